@@ -0,0 +1,29 @@
+//! Record the git commit the KMS is built from, so it can report which
+//! build is running (`KMS_GIT_COMMIT`, or "unknown" outside a git checkout)
+
+use std::fs;
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_owned())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    println!("cargo:rustc-env=KMS_GIT_COMMIT={}", commit);
+
+    // Rebuild when the checked-out commit changes: HEAD itself changes when
+    // switching branches, and the branch's ref when committing to it
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    if let Ok(head) = fs::read_to_string(".git/HEAD") {
+        if let Some(branch_ref) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", branch_ref);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Sign a message with the keys from a KMS configuration file, without
+//! connecting to any validators
+//!
+//! Usage: `cargo run --example sign -- kms.toml "message to sign"`
+
+extern crate cosmos_kms;
+
+use std::env;
+use std::path::Path;
+use std::process::exit;
+
+use cosmos_kms::config::Config;
+use cosmos_kms::ed25519::Keyring;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 {
+        eprintln!("Usage: {} CONFIG MESSAGE", args[0]);
+        exit(2);
+    }
+
+    let config = Config::load(Path::new(&args[1])).unwrap_or_else(|e| {
+        eprintln!("error reading {}: {}", args[1], e);
+        exit(1);
+    });
+
+    let keyring = Keyring::from_config(config.providers).unwrap_or_else(|e| {
+        eprintln!("signer error: {}", e);
+        exit(1);
+    });
+
+    // Use the only key which isn't restricted to particular chains
+    let public_key = keyring.default_pubkey(None).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+
+    let msg = args[2].as_bytes();
+
+    let signature = keyring.sign(None, &public_key, msg).unwrap_or_else(|e| {
+        eprintln!("signing failed: {}", e);
+        exit(1);
+    });
+
+    public_key.verify(msg, &signature).unwrap_or_else(|e| {
+        eprintln!("signature didn't verify: {}", e);
+        exit(1);
+    });
+
+    let key = keyring.key_info(None, &public_key).unwrap();
+
+    println!("key: {}:{} {}", key.provider_name, key.key_id, public_key);
+    println!(
+        "signature: {}",
+        signature
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    );
+}
@@ -0,0 +1,927 @@
+//! Amino encoding of Tendermint's privval (remote signer) messages
+//!
+//! This is the wire format spoken by unmodified `tendermint` nodes. Each
+//! message is a registered Amino type: a 4-byte prefix derived from the
+//! type's name followed by its protobuf-style fields, framed by the same
+//! varint length prefix as the legacy format.
+//!
+//! Votes and proposals are signed over their Amino-encoded canonical forms,
+//! which include the chain ID (taken from the validator's config, since the
+//! requests themselves don't carry it).
+
+use sha2::{Digest, Sha256};
+
+use codec;
+use ed25519::{KeyAlgorithm, PublicKey};
+use error::Error;
+use state::{self, HeightRoundStep, STEP_PRECOMMIT, STEP_PREVOTE, STEP_PROPOSAL};
+
+/// Amino name of `PubKeyRequest`
+const PUBKEY_REQUEST_NAME: &str = "tendermint/remotesigner/PubKeyRequest";
+
+/// Amino name of `PubKeyResponse`
+const PUBKEY_RESPONSE_NAME: &str = "tendermint/remotesigner/PubKeyResponse";
+
+/// Amino name of `SignVoteRequest`
+const SIGN_VOTE_REQUEST_NAME: &str = "tendermint/remotesigner/SignVoteRequest";
+
+/// Amino name of `SignedVoteResponse`
+const SIGNED_VOTE_RESPONSE_NAME: &str = "tendermint/remotesigner/SignedVoteResponse";
+
+/// Amino name of `SignProposalRequest`
+const SIGN_PROPOSAL_REQUEST_NAME: &str = "tendermint/remotesigner/SignProposalRequest";
+
+/// Amino name of `SignedProposalResponse`
+const SIGNED_PROPOSAL_RESPONSE_NAME: &str = "tendermint/remotesigner/SignedProposalResponse";
+
+/// Amino name of `PingRequest`
+const PING_REQUEST_NAME: &str = "tendermint/remotesigner/PingRequest";
+
+/// Amino name of `PingResponse`
+const PING_RESPONSE_NAME: &str = "tendermint/remotesigner/PingResponse";
+
+/// Amino name of Ed25519 public keys
+const PUBKEY_ED25519_NAME: &str = "tendermint/PubKeyEd25519";
+
+/// Amino name of secp256k1 public keys
+const PUBKEY_SECP256K1_NAME: &str = "tendermint/PubKeySecp256k1";
+
+/// Signed message type for prevotes
+pub const MSG_TYPE_PREVOTE: u64 = 0x01;
+
+/// Signed message type for precommits
+pub const MSG_TYPE_PRECOMMIT: u64 = 0x02;
+
+/// Signed message type for proposals
+pub const MSG_TYPE_PROPOSAL: u64 = 0x20;
+
+/// Requests sent from a Tendermint node to the KMS
+#[derive(Debug)]
+pub enum Request {
+    /// Obtain the public key the KMS signs with
+    PubKey,
+
+    /// Sign a consensus vote
+    SignVote(Vote),
+
+    /// Sign a block proposal
+    SignProposal(Proposal),
+
+    /// Keep the connection alive
+    Ping,
+}
+
+impl Request {
+    /// Decode a request from its registered Amino encoding
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        ensure!(bytes.len() >= 4, RpcError, "Amino message too short");
+        let (prefix, body) = bytes.split_at(4);
+
+        if prefix == type_prefix(PUBKEY_REQUEST_NAME) {
+            Ok(Request::PubKey)
+        } else if prefix == type_prefix(SIGN_VOTE_REQUEST_NAME) {
+            let vote = match parse_fields(body)?.remove_message(1)? {
+                Some(vote) => Vote::decode(vote)?,
+                None => {
+                    fail!(RpcError, "SignVoteRequest is missing its vote");
+                }
+            };
+            Ok(Request::SignVote(vote))
+        } else if prefix == type_prefix(SIGN_PROPOSAL_REQUEST_NAME) {
+            let proposal = match parse_fields(body)?.remove_message(1)? {
+                Some(proposal) => Proposal::decode(proposal)?,
+                None => {
+                    fail!(RpcError, "SignProposalRequest is missing its proposal");
+                }
+            };
+            Ok(Request::SignProposal(proposal))
+        } else if prefix == type_prefix(PING_REQUEST_NAME) {
+            Ok(Request::Ping)
+        } else {
+            fail!(RpcError, "unknown Amino message prefix: {:02X?}", prefix);
+        }
+    }
+}
+
+/// Responses sent from the KMS to a Tendermint node
+#[derive(Debug)]
+pub enum Response {
+    /// Public key the KMS signs with
+    PubKey(PublicKey),
+
+    /// Vote with its signature filled in, or the reason it wasn't signed
+    SignedVote(Result<Vote, RemoteError>),
+
+    /// Proposal with its signature filled in, or the reason it wasn't signed
+    SignedProposal(Result<Proposal, RemoteError>),
+
+    /// Reply to a keepalive
+    Ping,
+}
+
+impl Response {
+    /// Serialize this response, ready to be written to the connection
+    pub fn to_vec(&self) -> Vec<u8> {
+        let (name, body) = match *self {
+            Response::PubKey(ref public_key) => {
+                let mut body = Encoder::new();
+                body.bytes(1, &encode_public_key(public_key));
+                (PUBKEY_RESPONSE_NAME, body)
+            }
+            Response::SignedVote(ref result) => {
+                let mut body = Encoder::new();
+                match *result {
+                    Ok(ref vote) => body.message(1, &vote.encode()),
+                    Err(ref error) => body.message(2, &error.encode()),
+                }
+                (SIGNED_VOTE_RESPONSE_NAME, body)
+            }
+            Response::SignedProposal(ref result) => {
+                let mut body = Encoder::new();
+                match *result {
+                    Ok(ref proposal) => body.message(1, &proposal.encode()),
+                    Err(ref error) => body.message(2, &error.encode()),
+                }
+                (SIGNED_PROPOSAL_RESPONSE_NAME, body)
+            }
+            Response::Ping => (PING_RESPONSE_NAME, Encoder::new()),
+        };
+
+        let mut msg = type_prefix(name).to_vec();
+        msg.extend_from_slice(&body.0);
+        codec::encode(&msg)
+    }
+}
+
+/// Error reported back to the node in place of a signature
+#[derive(Debug)]
+pub struct RemoteError {
+    /// Error code
+    pub code: i64,
+
+    /// Description of the error
+    pub description: String,
+}
+
+impl From<Error> for RemoteError {
+    fn from(error: Error) -> Self {
+        RemoteError {
+            code: 1,
+            description: error.to_string(),
+        }
+    }
+}
+
+impl RemoteError {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.varint(1, self.code as u64);
+        encoder.bytes(2, self.description.as_bytes());
+        encoder.0
+    }
+}
+
+/// Identity key and signature over the connection's challenge, which each
+/// end of a Secret Connection sends the other to authenticate itself
+#[derive(Debug)]
+pub struct AuthSigMessage {
+    /// Identity key of the sender
+    pub public_key: PublicKey,
+
+    /// Signature over the challenge
+    pub signature: Vec<u8>,
+}
+
+impl AuthSigMessage {
+    /// Decode a message (without its length prefix). Only Ed25519 identity
+    /// keys are accepted, as Tendermint only uses those.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut fields = parse_fields(bytes)?;
+
+        let key = match fields.remove_message(1)? {
+            Some(key) => key,
+            None => {
+                fail!(RpcError, "AuthSigMessage is missing its key");
+            }
+        };
+
+        ensure!(
+            key.len() >= 4 && key[..4] == type_prefix(PUBKEY_ED25519_NAME),
+            RpcError,
+            "AuthSigMessage key isn't an Ed25519 key"
+        );
+
+        let mut key_bytes = &key[4..];
+        let len = read_varint(&mut key_bytes)?;
+        ensure!(
+            len == key_bytes.len() as u64,
+            RpcError,
+            "AuthSigMessage key has the wrong length"
+        );
+
+        Ok(AuthSigMessage {
+            public_key: PublicKey::from_bytes(key_bytes)?,
+            signature: fields.remove_bytes(2)?.to_vec(),
+        })
+    }
+
+    /// Serialize this message, ready to be written to the connection
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut body = Encoder::new();
+        body.bytes(1, &encode_public_key(&self.public_key));
+        body.bytes(2, &self.signature);
+        codec::encode(&body.0)
+    }
+}
+
+/// Consensus vote
+#[derive(Debug, Default)]
+pub struct Vote {
+    /// Signed message type (prevote or precommit)
+    pub vote_type: u64,
+
+    /// Block height
+    pub height: i64,
+
+    /// Consensus round
+    pub round: i64,
+
+    /// Block being voted on (`None` for nil votes)
+    pub block_id: Option<BlockId>,
+
+    /// Time the vote was cast
+    pub timestamp: Option<Timestamp>,
+
+    /// Address of the validator casting the vote
+    pub validator_address: Vec<u8>,
+
+    /// Index of the validator in the validator set
+    pub validator_index: i64,
+
+    /// Signature over the canonical vote
+    pub signature: Vec<u8>,
+}
+
+impl Vote {
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut fields = parse_fields(bytes)?;
+
+        Ok(Vote {
+            vote_type: fields.remove_varint(1)?,
+            height: fields.remove_varint(2)? as i64,
+            round: fields.remove_varint(3)? as i64,
+            block_id: fields.remove_message(4)?.map(BlockId::decode).transpose()?,
+            timestamp: fields.remove_message(5)?.map(Timestamp::decode).transpose()?,
+            validator_address: fields.remove_bytes(6)?.to_vec(),
+            validator_index: fields.remove_varint(7)? as i64,
+            signature: fields.remove_bytes(8)?.to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.varint(1, self.vote_type);
+        encoder.varint(2, self.height as u64);
+        encoder.varint(3, self.round as u64);
+        if let Some(ref block_id) = self.block_id {
+            encoder.message(4, &block_id.encode());
+        }
+        if let Some(ref timestamp) = self.timestamp {
+            encoder.message(5, &timestamp.encode());
+        }
+        encoder.bytes(6, &self.validator_address);
+        encoder.varint(7, self.validator_index as u64);
+        encoder.bytes(8, &self.signature);
+        encoder.0
+    }
+
+    /// Position in the consensus process this vote would be signed at
+    pub fn height_round_step(&self) -> Result<HeightRoundStep, Error> {
+        let step = match self.vote_type {
+            MSG_TYPE_PREVOTE => STEP_PREVOTE,
+            MSG_TYPE_PRECOMMIT => STEP_PRECOMMIT,
+            other => {
+                fail!(RpcError, "invalid vote type: {}", other);
+            }
+        };
+
+        Ok(HeightRoundStep {
+            height: self.height,
+            round: self.round,
+            step,
+        })
+    }
+
+    /// Time the vote was cast, if given
+    pub fn vote_time(&self) -> Option<state::Timestamp> {
+        self.timestamp.as_ref().map(|timestamp| state::Timestamp {
+            seconds: timestamp.seconds,
+            nanos: timestamp.nanos,
+        })
+    }
+
+    /// Length-prefixed Amino encoding of the canonical vote, i.e. the bytes
+    /// to be signed
+    pub fn sign_bytes(&self, chain_id: &str) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.varint(1, self.vote_type);
+        encoder.sfixed64(2, self.height);
+        encoder.sfixed64(3, self.round);
+        if let Some(ref block_id) = self.block_id {
+            encoder.message(4, &block_id.encode());
+        }
+        if let Some(ref timestamp) = self.timestamp {
+            encoder.message(5, &timestamp.encode());
+        }
+        encoder.bytes(6, chain_id.as_bytes());
+        codec::encode(&encoder.0)
+    }
+}
+
+/// Block proposal
+#[derive(Debug, Default)]
+pub struct Proposal {
+    /// Signed message type (always proposal)
+    pub msg_type: u64,
+
+    /// Block height
+    pub height: i64,
+
+    /// Consensus round
+    pub round: i64,
+
+    /// Proof-of-lock round (-1 if none)
+    pub pol_round: i64,
+
+    /// Block being proposed
+    pub block_id: Option<BlockId>,
+
+    /// Time the proposal was made
+    pub timestamp: Option<Timestamp>,
+
+    /// Signature over the canonical proposal
+    pub signature: Vec<u8>,
+}
+
+impl Proposal {
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut fields = parse_fields(bytes)?;
+
+        Ok(Proposal {
+            msg_type: fields.remove_varint(1)?,
+            height: fields.remove_varint(2)? as i64,
+            round: fields.remove_varint(3)? as i64,
+            pol_round: fields.remove_varint(4)? as i64,
+            block_id: fields.remove_message(5)?.map(BlockId::decode).transpose()?,
+            timestamp: fields.remove_message(6)?.map(Timestamp::decode).transpose()?,
+            signature: fields.remove_bytes(7)?.to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.varint(1, self.msg_type);
+        encoder.varint(2, self.height as u64);
+        encoder.varint(3, self.round as u64);
+        encoder.varint(4, self.pol_round as u64);
+        if let Some(ref block_id) = self.block_id {
+            encoder.message(5, &block_id.encode());
+        }
+        if let Some(ref timestamp) = self.timestamp {
+            encoder.message(6, &timestamp.encode());
+        }
+        encoder.bytes(7, &self.signature);
+        encoder.0
+    }
+
+    /// Position in the consensus process this proposal would be signed at
+    pub fn height_round_step(&self) -> HeightRoundStep {
+        HeightRoundStep {
+            height: self.height,
+            round: self.round,
+            step: STEP_PROPOSAL,
+        }
+    }
+
+    /// Length-prefixed Amino encoding of the canonical proposal, i.e. the
+    /// bytes to be signed
+    pub fn sign_bytes(&self, chain_id: &str) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.varint(1, MSG_TYPE_PROPOSAL);
+        encoder.sfixed64(2, self.height);
+        encoder.sfixed64(3, self.round);
+        encoder.sfixed64(4, self.pol_round);
+        if let Some(ref block_id) = self.block_id {
+            encoder.message(5, &block_id.encode());
+        }
+        if let Some(ref timestamp) = self.timestamp {
+            encoder.message(6, &timestamp.encode());
+        }
+        encoder.bytes(7, chain_id.as_bytes());
+        codec::encode(&encoder.0)
+    }
+}
+
+/// Identifies a block by its hash and part set header
+#[derive(Debug, Default)]
+pub struct BlockId {
+    /// Block hash
+    pub hash: Vec<u8>,
+
+    /// Number of parts in the block's part set
+    pub parts_total: i64,
+
+    /// Merkle root of the block's part set
+    pub parts_hash: Vec<u8>,
+}
+
+impl BlockId {
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut fields = parse_fields(bytes)?;
+        let hash = fields.remove_bytes(1)?.to_vec();
+
+        let (parts_total, parts_hash) = match fields.remove_message(2)? {
+            Some(parts) => {
+                let mut parts = parse_fields(parts)?;
+                (parts.remove_varint(1)? as i64, parts.remove_bytes(2)?.to_vec())
+            }
+            None => (0, vec![]),
+        };
+
+        Ok(BlockId {
+            hash,
+            parts_total,
+            parts_hash,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut parts = Encoder::new();
+        parts.varint(1, self.parts_total as u64);
+        parts.bytes(2, &self.parts_hash);
+
+        let mut encoder = Encoder::new();
+        encoder.bytes(1, &self.hash);
+        encoder.message(2, &parts.0);
+        encoder.0
+    }
+}
+
+/// Point in time, as seconds and nanoseconds since the Unix epoch
+#[derive(Debug, Default)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch
+    pub seconds: i64,
+
+    /// Nanoseconds within the second
+    pub nanos: i64,
+}
+
+impl Timestamp {
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut fields = parse_fields(bytes)?;
+
+        Ok(Timestamp {
+            seconds: fields.remove_varint(1)? as i64,
+            nanos: fields.remove_varint(2)? as i64,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.varint(1, self.seconds as u64);
+        encoder.varint(2, self.nanos as u64);
+        encoder.0
+    }
+}
+
+/// Encode a public key as the registered Amino type for its algorithm
+fn encode_public_key(public_key: &PublicKey) -> Vec<u8> {
+    let mut key = Encoder::new();
+    key.raw(&type_prefix(match public_key.algorithm() {
+        KeyAlgorithm::Ed25519 => PUBKEY_ED25519_NAME,
+        KeyAlgorithm::Secp256k1 => PUBKEY_SECP256K1_NAME,
+    }));
+    key.raw_bytes(public_key.as_bytes());
+    key.0
+}
+
+/// Compute the prefix which identifies a registered Amino type: the first
+/// four bytes of its name's SHA-256 digest, after skipping leading zero
+/// bytes, three "disambiguation" bytes, and any zero bytes following those
+fn type_prefix(name: &str) -> [u8; 4] {
+    let digest = Sha256::digest(name.as_bytes());
+    let mut bytes = digest.iter().skip_while(|byte| **byte == 0).skip(3);
+    let mut bytes = bytes.by_ref().skip_while(|byte| **byte == 0);
+
+    let mut prefix = [0u8; 4];
+    for byte in prefix.iter_mut() {
+        *byte = *bytes.next().unwrap();
+    }
+
+    prefix
+}
+
+/// Builds the protobuf-style encoding of a message. Fields with default
+/// (zero or empty) values are omitted, as Amino does.
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder(vec![])
+    }
+
+    /// Append a field key (tag and wire type)
+    fn key(&mut self, tag: u64, wire_type: u64) {
+        codec::encode_varint(tag << 3 | wire_type, &mut self.0);
+    }
+
+    /// Append a varint field (used for all integer types except `sfixed64`)
+    fn varint(&mut self, tag: u64, value: u64) {
+        if value != 0 {
+            self.key(tag, WIRE_TYPE_VARINT);
+            codec::encode_varint(value, &mut self.0);
+        }
+    }
+
+    /// Append a little endian 64-bit field
+    fn sfixed64(&mut self, tag: u64, value: i64) {
+        if value != 0 {
+            self.key(tag, WIRE_TYPE_FIXED64);
+            self.0.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// Append a length-delimited bytes or string field
+    fn bytes(&mut self, tag: u64, value: &[u8]) {
+        if !value.is_empty() {
+            self.key(tag, WIRE_TYPE_LENGTH_DELIMITED);
+            self.raw_bytes(value);
+        }
+    }
+
+    /// Append an embedded message field
+    fn message(&mut self, tag: u64, encoded: &[u8]) {
+        self.bytes(tag, encoded);
+    }
+
+    /// Append length-prefixed bytes without a field key
+    fn raw_bytes(&mut self, value: &[u8]) {
+        codec::encode_varint(value.len() as u64, &mut self.0);
+        self.0.extend_from_slice(value);
+    }
+
+    /// Append bytes verbatim
+    fn raw(&mut self, value: &[u8]) {
+        self.0.extend_from_slice(value);
+    }
+}
+
+/// Varint wire type
+const WIRE_TYPE_VARINT: u64 = 0;
+
+/// Little endian 64-bit wire type
+const WIRE_TYPE_FIXED64: u64 = 1;
+
+/// Length-delimited wire type
+const WIRE_TYPE_LENGTH_DELIMITED: u64 = 2;
+
+/// Little endian 32-bit wire type
+const WIRE_TYPE_FIXED32: u64 = 5;
+
+/// Value of a decoded field
+enum FieldValue<'a> {
+    Varint(u64),
+    Fixed(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The fields of a decoded message, by tag
+struct Fields<'a>(Vec<(u64, FieldValue<'a>)>);
+
+impl<'a> Fields<'a> {
+    fn remove(&mut self, tag: u64) -> Option<FieldValue<'a>> {
+        // Later occurrences of a field take precedence
+        let index = self.0.iter().rposition(|&(t, _)| t == tag)?;
+        Some(self.0.remove(index).1)
+    }
+
+    fn remove_varint(&mut self, tag: u64) -> Result<u64, Error> {
+        match self.remove(tag) {
+            Some(FieldValue::Varint(value)) | Some(FieldValue::Fixed(value)) => Ok(value),
+            Some(FieldValue::Bytes(_)) => {
+                fail!(RpcError, "field {}: expected integer, got bytes", tag);
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn remove_bytes(&mut self, tag: u64) -> Result<&'a [u8], Error> {
+        match self.remove(tag) {
+            Some(FieldValue::Bytes(value)) => Ok(value),
+            Some(_) => {
+                fail!(RpcError, "field {}: expected bytes, got integer", tag);
+            }
+            None => Ok(&[]),
+        }
+    }
+
+    fn remove_message(&mut self, tag: u64) -> Result<Option<&'a [u8]>, Error> {
+        match self.remove(tag) {
+            Some(FieldValue::Bytes(value)) => Ok(Some(value)),
+            Some(_) => {
+                fail!(RpcError, "field {}: expected message, got integer", tag);
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Split a protobuf-style message into its fields
+fn parse_fields(mut bytes: &[u8]) -> Result<Fields<'_>, Error> {
+    let mut fields = vec![];
+
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let tag = key >> 3;
+
+        let value = match key & 0x7 {
+            WIRE_TYPE_VARINT => FieldValue::Varint(read_varint(&mut bytes)?),
+            WIRE_TYPE_FIXED64 => FieldValue::Fixed(read_fixed(&mut bytes, 8)?),
+            WIRE_TYPE_LENGTH_DELIMITED => {
+                let len = read_varint(&mut bytes)?;
+                ensure!(len <= bytes.len() as u64, RpcError, "truncated Amino field");
+                let (value, rest) = bytes.split_at(len as usize);
+                bytes = rest;
+                FieldValue::Bytes(value)
+            }
+            WIRE_TYPE_FIXED32 => FieldValue::Fixed(read_fixed(&mut bytes, 4)?),
+            other => {
+                fail!(RpcError, "unsupported Amino wire type: {}", other);
+            }
+        };
+
+        fields.push((tag, value));
+    }
+
+    Ok(Fields(fields))
+}
+
+/// Read a varint from the front of a byte slice
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    match codec::decode_varint(bytes) {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) | Err(Error::UnexpectedEof { .. }) => {
+            Err(err!(RpcError, "truncated Amino varint"))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Read a little endian fixed size integer from the front of a byte slice
+fn read_fixed(bytes: &mut &[u8], size: usize) -> Result<u64, Error> {
+    ensure!(bytes.len() >= size, RpcError, "truncated Amino field");
+
+    let (value, rest) = bytes.split_at(size);
+    *bytes = rest;
+
+    Ok(value
+        .iter()
+        .rev()
+        .fold(0u64, |acc, byte| acc << 8 | u64::from(*byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Decode a hex string (ignoring any whitespace in it, for layout)
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: String = s.split_whitespace().collect();
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Decode a request from a whole frame, as read from the connection
+    fn decode_frame(frame: &[u8]) -> Request {
+        let msg = codec::decode(&mut Cursor::new(frame), 1024).unwrap();
+        Request::decode(&msg).unwrap()
+    }
+
+    /// Block ID (field 4 of a vote, or 5 of a proposal, once keyed) shared by
+    /// the vote and proposal frames below
+    const BLOCK_ID: &str = "480a20 1111111111111111111111111111111111111111111111111111111111111111
+                            1224 0801 1220 2222222222222222222222222222222222222222222222222222222222222222";
+
+    /// 2017-12-25T03:00:01.234Z
+    const TIMESTAMP: &str = "0b 08b1d381d205 10809dca6f";
+
+    /// Signature filled in by the KMS
+    const SIGNATURE: &str = "40 55555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555555";
+
+    fn assert_block_id(block_id: &Option<BlockId>) {
+        let block_id = block_id.as_ref().unwrap();
+        assert_eq!(block_id.hash, vec![0x11; 32]);
+        assert_eq!(block_id.parts_total, 1);
+        assert_eq!(block_id.parts_hash, vec![0x22; 32]);
+    }
+
+    fn assert_timestamp(timestamp: &Option<Timestamp>) {
+        let timestamp = timestamp.as_ref().unwrap();
+        assert_eq!((timestamp.seconds, timestamp.nanos), (1_514_170_801, 234_000_000));
+    }
+
+    #[test]
+    fn type_prefixes_match_tendermint() {
+        // As seen in every `cosmosvalconspub`/`cosmospub` key
+        assert_eq!(type_prefix(PUBKEY_ED25519_NAME), [0x16, 0x24, 0xde, 0x64]);
+        assert_eq!(type_prefix(PUBKEY_SECP256K1_NAME), [0xeb, 0x5a, 0xe9, 0x87]);
+
+        assert_eq!(type_prefix(PUBKEY_REQUEST_NAME), [0xcb, 0x94, 0xd6, 0x20]);
+        assert_eq!(type_prefix(PUBKEY_RESPONSE_NAME), [0x17, 0x0e, 0xd5, 0x7c]);
+        assert_eq!(type_prefix(SIGN_VOTE_REQUEST_NAME), [0xf3, 0xf4, 0x12, 0x04]);
+        assert_eq!(type_prefix(SIGNED_VOTE_RESPONSE_NAME), [0xb2, 0x48, 0xa6, 0x16]);
+        assert_eq!(type_prefix(SIGN_PROPOSAL_REQUEST_NAME), [0xbd, 0xe4, 0x98, 0xe2]);
+        assert_eq!(type_prefix(SIGNED_PROPOSAL_RESPONSE_NAME), [0x4a, 0x04, 0xfb, 0x87]);
+        assert_eq!(type_prefix(PING_REQUEST_NAME), [0x1b, 0xad, 0xb6, 0x0d]);
+        assert_eq!(type_prefix(PING_RESPONSE_NAME), [0xa2, 0x9a, 0x03, 0x1a]);
+    }
+
+    #[test]
+    fn pubkey_request_and_response() {
+        match decode_frame(&hex("04 cb94d620")) {
+            Request::PubKey => (),
+            other => panic!("expected PubKey, got {:?}", other),
+        }
+
+        let public_key = PublicKey::from_hex(
+            "2d65efaf84f360a32ef19ca7a4196bd37c6c63f8cbf0f2bf0201e73f05a5acc3",
+        ).unwrap();
+
+        assert_eq!(
+            Response::PubKey(public_key).to_vec(),
+            hex("2b 170ed57c 0a25 1624de64 20
+                 2d65efaf84f360a32ef19ca7a4196bd37c6c63f8cbf0f2bf0201e73f05a5acc3")
+        );
+    }
+
+    #[test]
+    fn ping_request_and_response() {
+        match decode_frame(&hex("04 1badb60d")) {
+            Request::Ping => (),
+            other => panic!("expected Ping, got {:?}", other),
+        }
+
+        assert_eq!(Response::Ping.to_vec(), hex("04 a29a031a"));
+    }
+
+    #[test]
+    fn sign_vote_request_and_response() {
+        let vote_fields = format!(
+            "0802 10b960 1802 22{} 2a{} 3214 a0a1a2a3a4a5a6a7a8a9aaabacadaeafb0b1b2b3 38d5bb03",
+            BLOCK_ID, TIMESTAMP
+        );
+
+        let mut vote = match decode_frame(&hex(&format!("7e f3f41204 0a78 {}", vote_fields))) {
+            Request::SignVote(vote) => vote,
+            other => panic!("expected SignVote, got {:?}", other),
+        };
+
+        assert_eq!(vote.vote_type, MSG_TYPE_PRECOMMIT);
+        assert_eq!((vote.height, vote.round), (12345, 2));
+        assert_block_id(&vote.block_id);
+        assert_timestamp(&vote.timestamp);
+        assert_eq!(vote.validator_address, (0xa0..0xb4).collect::<Vec<u8>>());
+        assert_eq!(vote.validator_index, 56789);
+        assert!(vote.signature.is_empty());
+
+        vote.signature = vec![0x55; 64];
+
+        assert_eq!(
+            Response::SignedVote(Ok(vote)).to_vec(),
+            hex(&format!("c101 b248a616 0aba01 {} 42{}", vote_fields, SIGNATURE))
+        );
+    }
+
+    #[test]
+    fn sign_proposal_request_and_response() {
+        // The proof-of-lock round is -1, encoded as a 10-byte varint
+        let proposal_fields = format!(
+            "0820 10b960 1802 20ffffffffffffffffff01 2a{} 32{}",
+            BLOCK_ID, TIMESTAMP
+        );
+
+        let mut proposal =
+            match decode_frame(&hex(&format!("6f bde498e2 0a69 {}", proposal_fields))) {
+                Request::SignProposal(proposal) => proposal,
+                other => panic!("expected SignProposal, got {:?}", other),
+            };
+
+        assert_eq!(proposal.msg_type, MSG_TYPE_PROPOSAL);
+        assert_eq!((proposal.height, proposal.round), (12345, 2));
+        assert_eq!(proposal.pol_round, -1);
+        assert_block_id(&proposal.block_id);
+        assert_timestamp(&proposal.timestamp);
+        assert!(proposal.signature.is_empty());
+
+        proposal.signature = vec![0x55; 64];
+
+        assert_eq!(
+            Response::SignedProposal(Ok(proposal)).to_vec(),
+            hex(&format!("b201 4a04fb87 0aab01 {} 3a{}", proposal_fields, SIGNATURE))
+        );
+    }
+
+    #[test]
+    fn signed_vote_response_with_error() {
+        let error = RemoteError {
+            code: 1,
+            description: "double sign".to_owned(),
+        };
+
+        assert_eq!(
+            Response::SignedVote(Err(error)).to_vec(),
+            hex("15 b248a616 120f 0801 120b 646f75626c65207369676e")
+        );
+    }
+
+    #[test]
+    fn auth_sig_message_round_trip() {
+        let msg = AuthSigMessage {
+            public_key: PublicKey::from_bytes(&[0x33; 32]).unwrap(),
+            signature: vec![0x44; 64],
+        };
+
+        let bytes = msg.to_vec();
+        assert_eq!(
+            bytes,
+            hex(&format!("69 0a25 1624de64 20{} 1240{}", "33".repeat(32), "44".repeat(64)))
+        );
+
+        let decoded = AuthSigMessage::decode(&bytes[1..]).unwrap();
+        assert_eq!(decoded.public_key.as_bytes(), &[0x33; 32][..]);
+        assert_eq!(decoded.signature, vec![0x44; 64]);
+    }
+
+    #[test]
+    fn auth_sig_message_with_secp256k1_key_is_rejected() {
+        let key = format!("{} 21{}", "eb5ae987", "02".repeat(33));
+        let bytes = hex(&format!("0a26 {} 1240{}", key, "44".repeat(64)));
+
+        match AuthSigMessage::decode(&bytes) {
+            Err(Error::RpcError { .. }) => (),
+            other => panic!("expected RpcError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_prefix_is_rejected() {
+        match Request::decode(&hex("01020304")) {
+            Err(Error::RpcError { .. }) => (),
+            other => panic!("expected RpcError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn vote_sign_bytes_match_tendermint_vectors() {
+        // Go's zero time, which is what votes without one are signed with
+        let zero_time = || {
+            Some(Timestamp {
+                seconds: -62_135_596_800,
+                nanos: 0,
+            })
+        };
+
+        // From Tendermint's `TestVoteSignBytesTestVectors`
+        let precommit = Vote {
+            vote_type: MSG_TYPE_PRECOMMIT,
+            height: 1,
+            round: 1,
+            timestamp: zero_time(),
+            ..Vote::default()
+        };
+
+        assert_eq!(
+            precommit.sign_bytes(""),
+            hex("21 0802 11 0100000000000000 19 0100000000000000
+                 2a 0b 088092b8c398feffffff01")
+        );
+
+        let with_chain_id = Vote {
+            height: 1,
+            round: 1,
+            timestamp: zero_time(),
+            ..Vote::default()
+        };
+
+        assert_eq!(
+            with_chain_id.sign_bytes("test_chain_id"),
+            hex("2e 11 0100000000000000 19 0100000000000000
+                 2a 0b 088092b8c398feffffff01
+                 32 0d 746573745f636861696e5f6964")
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Sessions with validators run as tasks on a shared tokio runtime, rather
+//! than each on a thread of its own (for validators configured with
+//! `async-session = true`, with the `tokio` cargo feature)
+//!
+//! Requests are handled by the same `RequestHandler` as the blocking
+//! `Session`. Signing can block (e.g. waiting on an HSM), so it's done in
+//! `block_in_place`, which lets the runtime move the worker's other tasks
+//! elsewhere meanwhile. The crate is built as Rust 2015, which has no
+//! `async`/`await`, so the futures here are written out by hand.
+
+use futures::{Sink, Stream};
+use socket2::{SockRef, TcpKeepalive};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task;
+use tokio::time::{self, Sleep};
+use tokio_util::codec::Framed;
+
+use codec::RpcCodec;
+use config::{Chains, ValidatorConfig};
+use ed25519::SharedKeyring;
+use error::Error;
+use handler::{Reply, RequestHandler};
+use metrics;
+use secret_connection::{self, AsyncSecretConnection, Handshake};
+use session::{CONNECT_HINT, SHUTDOWN_POLL_MS};
+use state::SignState;
+
+/// Number of worker threads in the shared runtime, however many validators
+/// use it
+pub const WORKER_THREADS: usize = 2;
+
+/// Runtime shared by all async sessions
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Obtain the shared runtime, starting it on first use
+pub fn runtime() -> Result<&'static Runtime, Error> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .thread_name("kms-async")
+        .enable_all()
+        .build()
+        .map_err(|e| err!(IoError, "couldn't start tokio runtime: {}", e))?;
+
+    // If another client got there first, ours is dropped unused
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+/// Secret connection to a validator, framed into messages
+type Connection = Framed<AsyncSecretConnection<TcpStream>, RpcCodec>;
+
+/// Future which connects to a validator and performs the handshake,
+/// resolving to a session ready to handle its requests
+pub struct Connect {
+    /// Identifier for this validator from the config
+    label: String,
+
+    /// Address being connected to, and the validator's port
+    addr: String,
+    port: u16,
+
+    /// Idle time before sending TCP keepalive probes (if enabled)
+    keepalive: Option<Duration>,
+
+    /// File holding the key we prove our identity with (if not made up)
+    secret_key: Option<PathBuf>,
+
+    /// Peer IDs the validator may connect as (if restricted)
+    peer_ids: Option<Vec<String>>,
+
+    /// Largest request the validator may send (in bytes)
+    max_request_bytes: usize,
+
+    /// Time a write may block for before giving up (if limited)
+    write_timeout: Option<Duration>,
+
+    /// Handler for the session's requests, until the session starts
+    handler: Option<RequestHandler>,
+
+    /// Flag which is set when the client has been asked to shut down
+    shutdown: Arc<AtomicBool>,
+
+    /// How far connecting has got
+    stage: ConnectStage,
+}
+
+/// Stages of connecting to a validator
+enum ConnectStage {
+    /// Establishing the TCP connection, until the deadline (if any)
+    Connecting(
+        Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>,
+        Option<Pin<Box<Sleep>>>,
+    ),
+
+    /// Exchanging ephemeral keys, then proving our identities to each other
+    Handshaking(Box<Handshake<TcpStream>>),
+}
+
+impl Connect {
+    /// Start connecting to the validator described by the given config, at
+    /// the given one of its addresses (see `ValidatorConfig::endpoints`)
+    pub fn new(
+        label: &str,
+        config: &ValidatorConfig,
+        endpoint: &str,
+        keyring: SharedKeyring,
+        state: Arc<Mutex<SignState>>,
+        chains: Arc<Chains>,
+        shutdown: Arc<AtomicBool>,
+    ) -> Result<Self, Error> {
+        let handler = RequestHandler::new(label, config, endpoint, keyring, state, chains)?;
+
+        debug!(
+            validator = label, addr = endpoint, port = config.port, event = "connecting";
+            "connecting to {}...", endpoint
+        );
+
+        let connect = Box::pin(TcpStream::connect(config.host_port(endpoint)));
+        let deadline = config
+            .connect_timeout()
+            .map(|timeout| Box::pin(time::sleep(timeout)));
+
+        Ok(Self {
+            label: label.to_owned(),
+            addr: endpoint.to_owned(),
+            port: config.port,
+            keepalive: config.keepalive(),
+            secret_key: config.secret_key.clone(),
+            peer_ids: config.peer_ids(),
+            max_request_bytes: config.max_request_bytes(),
+            write_timeout: config.write_timeout(),
+            handler: Some(handler),
+            shutdown,
+            stage: ConnectStage::Connecting(connect, deadline),
+        })
+    }
+
+    /// Set the same socket options as the blocking session does
+    fn configure(&self, socket: &TcpStream) -> Result<(), Error> {
+        socket
+            .set_nodelay(true)
+            .map_err(|e| err!(IoError, "couldn't set TCP_NODELAY: {}", e))?;
+
+        if let Some(idle) = self.keepalive {
+            SockRef::from(socket)
+                .set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))
+                .map_err(|e| err!(IoError, "couldn't enable TCP keepalive: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the validator's identity is one of the allowed peer IDs
+    fn check_peer(&self, public_key: &::ed25519::PublicKey) -> Result<(), Error> {
+        let peer_id = secret_connection::peer_id(public_key);
+
+        if let Some(ref peer_ids) = self.peer_ids {
+            ensure!(
+                peer_ids.contains(&peer_id),
+                UntrustedPeer,
+                "validator {}: peer {} is not in peer-ids",
+                self.label,
+                peer_id
+            );
+        }
+
+        debug!(
+            validator = self.label.as_str(), addr = self.addr.as_str(), port = self.port,
+            event = "peer_authenticated", peer_id = peer_id.as_str();
+            "authenticated peer {}", peer_id
+        );
+
+        Ok(())
+    }
+
+    /// Start the session over the established connection
+    fn session(&mut self, connection: AsyncSecretConnection<TcpStream>) -> AsyncSession {
+        metrics::connection_opened();
+
+        AsyncSession {
+            connection: Framed::new(connection, RpcCodec::new(self.max_request_bytes)),
+            handler: self.handler.take().expect("session already started"),
+            shutdown: Arc::clone(&self.shutdown),
+            write_timeout: self.write_timeout,
+            outgoing: VecDeque::new(),
+            sending_since: None,
+            closing: None,
+            last_activity: Instant::now(),
+            poll_timer: Box::pin(time::sleep(Duration::from_millis(SHUTDOWN_POLL_MS))),
+        }
+    }
+}
+
+impl Future for Connect {
+    type Output = Result<AsyncSession, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        loop {
+            let next_stage = match this.stage {
+                ConnectStage::Connecting(ref mut connect, ref mut deadline) => {
+                    let socket = match connect.as_mut().poll(cx) {
+                        Poll::Ready(Ok(socket)) => socket,
+                        Poll::Ready(Err(e)) => {
+                            return Poll::Ready(Err(err!(
+                                IoError,
+                                "couldn't connect to {}:{}: {} ({})",
+                                this.addr,
+                                this.port,
+                                e,
+                                CONNECT_HINT
+                            )));
+                        }
+                        Poll::Pending => {
+                            if let Some(ref mut deadline) = *deadline {
+                                if deadline.as_mut().poll(cx).is_ready() {
+                                    return Poll::Ready(Err(err!(
+                                        Timeout,
+                                        "timed out connecting to {}:{} ({})",
+                                        this.addr,
+                                        this.port,
+                                        CONNECT_HINT
+                                    )));
+                                }
+                            }
+
+                            return Poll::Pending;
+                        }
+                    };
+
+                    this.configure(&socket)?;
+                    let identity_key =
+                        secret_connection::identity_key(this.secret_key.as_deref())?;
+                    ConnectStage::Handshaking(Box::new(AsyncSecretConnection::handshake(
+                        socket,
+                        identity_key,
+                    )?))
+                }
+                ConnectStage::Handshaking(ref mut handshake) => {
+                    let connection = ready!(Pin::new(handshake).poll(cx))?;
+
+                    let remote_eph_pubkey = connection
+                        .remote_eph_pubkey()
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<String>();
+
+                    debug!(
+                        validator = this.label.as_str(), addr = this.addr.as_str(),
+                        port = this.port, event = "connected",
+                        remote_eph_pubkey = remote_eph_pubkey.as_str();
+                        "established secret connection"
+                    );
+
+                    this.check_peer(connection.remote_pubkey())?;
+                    return Poll::Ready(Ok(this.session(connection)));
+                }
+            };
+
+            this.stage = next_stage;
+        }
+    }
+}
+
+/// Future which handles requests from the validator until the session ends,
+/// like `Session::handle_requests`
+pub struct AsyncSession {
+    /// Secret connection to the validator
+    connection: Connection,
+
+    /// Signs requests and decides what to send back
+    handler: RequestHandler,
+
+    /// Flag which is set when the client has been asked to shut down
+    shutdown: Arc<AtomicBool>,
+
+    /// Time a write may block for before giving up (if limited)
+    write_timeout: Option<Duration>,
+
+    /// Responses waiting to be sent
+    outgoing: VecDeque<Vec<u8>>,
+
+    /// When the responses being sent started waiting, if there are any
+    sending_since: Option<Instant>,
+
+    /// Error to end the session with once the responses have been sent
+    closing: Option<Error>,
+
+    /// When the validator last sent something
+    last_activity: Instant,
+
+    /// Wakes the session every `SHUTDOWN_POLL_MS`, to check whether it has
+    /// been asked to shut down or has timed out
+    poll_timer: Pin<Box<Sleep>>,
+}
+
+impl AsyncSession {
+    /// Queue a response to be sent
+    fn send(&mut self, response: Vec<u8>) {
+        self.outgoing.push_back(response);
+
+        if self.sending_since.is_none() {
+            self.sending_since = Some(Instant::now());
+        }
+    }
+
+    /// Write out the queued responses, finishing once all of them have been
+    /// handed to the socket
+    fn poll_send(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        if self.sending_since.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+
+        while !self.outgoing.is_empty() {
+            ready!(Sink::<Vec<u8>>::poll_ready(Pin::new(&mut self.connection), cx))?;
+            let response = self.outgoing.pop_front().unwrap();
+            Pin::new(&mut self.connection).start_send(response)?;
+        }
+
+        ready!(Sink::<Vec<u8>>::poll_flush(Pin::new(&mut self.connection), cx))?;
+        self.sending_since = None;
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Check for timeouts (and send any idle ping), as the blocking session
+    /// does whenever it has waited `SHUTDOWN_POLL_MS` for a request
+    fn check_timeouts(&mut self) -> Result<(), Error> {
+        if let Some(ping) = self.handler.check_idle(self.last_activity)? {
+            self.send(ping);
+        }
+
+        if let (Some(timeout), Some(since)) = (self.write_timeout, self.sending_since) {
+            ensure!(
+                since.elapsed() < timeout,
+                Timeout,
+                "validator {} stopped reading responses (write timed out)",
+                self.handler.label()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Future for AsyncSession {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        loop {
+            if this.shutdown.load(Ordering::SeqCst) {
+                debug!(
+                    validator = this.handler.label(), peer = this.handler.peer_addr(),
+                    event = "session_shutdown";
+                    "shutdown requested; closing session"
+                );
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.poll_send(cx)?.is_ready() {
+                if let Some(e) = this.closing.take() {
+                    return Poll::Ready(Err(e));
+                }
+            }
+
+            // Stop reading once the session is closing
+            if this.closing.is_none() {
+                match Pin::new(&mut this.connection).poll_next(cx) {
+                    Poll::Ready(Some(msg)) => {
+                        let msg = msg?;
+                        let handler = &mut this.handler;
+                        let reply = task::block_in_place(|| handler.handle_message(&msg))?;
+
+                        match reply {
+                            Reply::None => (),
+                            Reply::Send(response) => this.send(response),
+                            Reply::SendAndClose(response, e) => {
+                                this.send(response);
+                                this.closing = Some(e);
+                            }
+                        }
+
+                        this.last_activity = Instant::now();
+                        continue;
+                    }
+                    Poll::Ready(None) => {
+                        return Poll::Ready(Err(err!(ConnectionClosed, "connection closed by peer")));
+                    }
+                    Poll::Pending => (),
+                }
+            }
+
+            ready!(this.poll_timer.as_mut().poll(cx));
+
+            let next_poll = time::Instant::now() + Duration::from_millis(SHUTDOWN_POLL_MS);
+            this.poll_timer.as_mut().reset(next_poll);
+            this.check_timeouts()?;
+        }
+    }
+}
+
+impl Drop for AsyncSession {
+    fn drop(&mut self) {
+        metrics::connection_closed();
+    }
+}
@@ -0,0 +1,124 @@
+//! In-memory record of recent signing operations, for incident response
+//!
+//! The last few signatures are kept (without the signed messages themselves)
+//! so there's a forensic trail without writing every signature to disk. The
+//! log is served as JSON from `/audit` on the metrics endpoint.
+
+use chrono::{SecondsFormat, Utc};
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+use ed25519::PublicKey;
+use state::{HeightRoundStep, STEP_PRECOMMIT, STEP_PREVOTE, STEP_PROPOSAL};
+
+/// Global audit log (only present if enabled in the config)
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// A single signing operation
+#[derive(Serialize, Clone, Debug)]
+pub struct AuditEntry {
+    /// When the signature was made (RFC 3339)
+    pub time: String,
+
+    /// Validator which requested the signature
+    pub validator: String,
+
+    /// Chain the signature is for (if given)
+    pub chain_id: Option<String>,
+
+    /// Block height (consensus messages only)
+    pub height: Option<i64>,
+
+    /// Consensus round (consensus messages only)
+    pub round: Option<i64>,
+
+    /// Kind of message signed: `proposal`, `prevote`, `precommit`, or
+    /// `message` for arbitrary messages
+    #[serde(rename = "type")]
+    pub msg_type: &'static str,
+
+    /// Key the message was signed with (in hex)
+    pub public_key: String,
+}
+
+impl AuditEntry {
+    /// Describe a signature made just now for the given validator
+    pub fn new(
+        validator: &str,
+        chain_id: Option<&str>,
+        hrs: Option<HeightRoundStep>,
+        public_key: &PublicKey,
+    ) -> Self {
+        let msg_type = match hrs.map(|hrs| hrs.step) {
+            Some(STEP_PROPOSAL) => "proposal",
+            Some(STEP_PREVOTE) => "prevote",
+            Some(STEP_PRECOMMIT) => "precommit",
+            _ => "message",
+        };
+
+        Self {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            validator: validator.to_owned(),
+            chain_id: chain_id.map(str::to_owned),
+            height: hrs.map(|hrs| hrs.height),
+            round: hrs.map(|hrs| hrs.round),
+            msg_type,
+            public_key: public_key
+                .as_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+}
+
+/// Ring buffer of the most recent signing operations
+struct AuditLog {
+    /// Maximum number of entries retained
+    capacity: usize,
+
+    /// Retained entries, oldest first
+    entries: Mutex<VecDeque<AuditEntry>>,
+}
+
+/// Start keeping the last `capacity` signing operations. Has no effect if
+/// the capacity is zero or the log has already been enabled.
+pub fn init(capacity: usize) {
+    if capacity > 0 {
+        AUDIT_LOG.get_or_init(|| AuditLog {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        });
+    }
+}
+
+/// Record a signature made for the given validator, if the log is enabled
+pub fn record(
+    validator: &str,
+    chain_id: Option<&str>,
+    hrs: Option<HeightRoundStep>,
+    public_key: &PublicKey,
+) {
+    let log = match AUDIT_LOG.get() {
+        Some(log) => log,
+        None => return,
+    };
+
+    let entry = AuditEntry::new(validator, chain_id, hrs, public_key);
+    let mut entries = log.entries.lock().unwrap();
+
+    if entries.len() == log.capacity {
+        entries.pop_front();
+    }
+
+    entries.push_back(entry);
+}
+
+/// Recent signing operations, oldest first (empty if the log is disabled)
+#[cfg(any(test, feature = "metrics"))]
+pub fn entries() -> Vec<AuditEntry> {
+    AUDIT_LOG
+        .get()
+        .map(|log| log.entries.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
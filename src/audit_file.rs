@@ -0,0 +1,342 @@
+//! Append-only record of every signing operation on disk, for compliance
+//!
+//! Unlike the in-memory audit log, nothing is forgotten: each attempt to
+//! sign (successful or not) is appended to the configured file as a line of
+//! JSON. Lines are written by a background thread from a bounded queue, so
+//! a slow disk never holds up signing; if the queue fills, entries are
+//! dropped (and counted) rather than waited for. Full files are renamed
+//! aside with a timestamp suffix, and are never removed or rewritten.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use audit::AuditEntry;
+use config::AuditFileConfig;
+use ed25519::PublicKey;
+use error::Error;
+use metrics;
+use state::HeightRoundStep;
+
+/// How long the writer waits for another entry before syncing those it has
+/// already written (in milliseconds)
+const IDLE_SYNC_MS: u64 = 100;
+
+/// How long `flush` waits for queued entries to be written (in seconds)
+const FLUSH_TIMEOUT_SECS: u64 = 5;
+
+/// Queue of entries waiting to be written (only present if configured)
+static QUEUE: OnceLock<SyncSender<Command>> = OnceLock::new();
+
+/// Entries dropped because the queue was full, since the writer last
+/// reported them
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// A line of the audit file
+#[derive(Serialize)]
+struct Entry {
+    /// What was signed (or was to be)
+    #[serde(flatten)]
+    entry: AuditEntry,
+
+    /// `signed`, or `failed` if no signature was produced
+    outcome: &'static str,
+
+    /// Why no signature was produced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Work for the writer thread
+enum Command {
+    /// Append an entry to the file
+    Write(Entry),
+
+    /// Sync everything written so far, then acknowledge
+    Flush(SyncSender<()>),
+}
+
+/// Open the configured audit file and start the thread which writes to it.
+/// The file is opened here, so a bad path is reported at startup.
+pub fn start(config: &AuditFileConfig) -> Result<(), Error> {
+    let mut writer = AuditFile::open(config)?;
+    let (sender, receiver) = mpsc::sync_channel(config.queue_size());
+
+    thread::Builder::new()
+        .name("audit-file".to_owned())
+        .spawn(move || writer.run(&receiver))?;
+
+    ensure!(
+        QUEUE.set(sender).is_ok(),
+        ConfigError,
+        "audit file already started"
+    );
+
+    info!("appending signing operations to {}", config.path.display());
+    Ok(())
+}
+
+/// Queue an entry describing an attempt to sign for the given validator,
+/// which failed with `error` if given, if an audit file is configured.
+/// Never blocks.
+pub fn record(
+    validator: &str,
+    chain_id: Option<&str>,
+    hrs: Option<HeightRoundStep>,
+    public_key: &PublicKey,
+    error: Option<&Error>,
+) {
+    let queue = match QUEUE.get() {
+        Some(queue) => queue,
+        None => return,
+    };
+
+    let entry = Entry {
+        entry: AuditEntry::new(validator, chain_id, hrs, public_key),
+        outcome: if error.is_some() { "failed" } else { "signed" },
+        error: error.map(ToString::to_string),
+    };
+
+    match queue.try_send(Command::Write(entry)) {
+        Ok(()) => (),
+        Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            metrics::audit_file_entry_dropped();
+        }
+    }
+}
+
+/// Wait (for at most `FLUSH_TIMEOUT_SECS`) until every queued entry has been
+/// written and synced, e.g. before exiting. Does nothing if no audit file is
+/// configured.
+pub fn flush() {
+    let queue = match QUEUE.get() {
+        Some(queue) => queue,
+        None => return,
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(FLUSH_TIMEOUT_SECS);
+    let (ack, flushed) = mpsc::sync_channel(1);
+    let mut command = Command::Flush(ack);
+
+    loop {
+        match queue.try_send(command) {
+            Ok(()) => break,
+            Err(TrySendError::Full(unsent)) if Instant::now() < deadline => {
+                command = unsent;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(_) => {
+                warn!("timed out flushing the audit file");
+                return;
+            }
+        }
+    }
+
+    if flushed.recv_timeout(deadline.saturating_duration_since(Instant::now())).is_err() {
+        warn!("timed out flushing the audit file");
+    }
+}
+
+/// Audit file being appended to, rotated by size or date
+struct AuditFile {
+    /// Path of the current file
+    path: PathBuf,
+
+    /// Current file
+    file: File,
+
+    /// Number of bytes in the current file
+    size: u64,
+
+    /// Day (UTC) the current file was started
+    date: NaiveDate,
+
+    /// Size at which to start a new file (in bytes), if any
+    max_size: Option<u64>,
+
+    /// Start a new file each day?
+    rotate_daily: bool,
+
+    /// Number of entries written between each fsync
+    sync_every: usize,
+
+    /// Number of entries written since the last fsync
+    unsynced: usize,
+}
+
+impl AuditFile {
+    /// Open (or create) the configured file, appending to it
+    fn open(config: &AuditFileConfig) -> Result<Self, Error> {
+        let (file, size, date) = open_audit_file(&config.path).map_err(|e| {
+            err!(ConfigError, "couldn't open audit file {}: {}", config.path.display(), e)
+        })?;
+
+        Ok(Self {
+            path: config.path.clone(),
+            file,
+            size,
+            date,
+            max_size: config.max_size,
+            rotate_daily: config.rotate_daily(),
+            sync_every: config.sync_every(),
+            unsynced: 0,
+        })
+    }
+
+    /// Write queued entries until the queue is closed. Failures are logged
+    /// when writing starts failing and when it recovers, rather than for
+    /// every entry.
+    fn run(&mut self, receiver: &Receiver<Command>) {
+        let mut failing = false;
+
+        loop {
+            let dropped = DROPPED.swap(0, Ordering::Relaxed);
+
+            if dropped > 0 {
+                warn!(
+                    event = "audit_file_dropped";
+                    "dropped {} audit file entries (queue full)", dropped
+                );
+            }
+
+            let result = match receiver.recv_timeout(Duration::from_millis(IDLE_SYNC_MS)) {
+                Ok(Command::Write(entry)) => self.write(&entry).inspect_err(|_| {
+                    metrics::audit_file_entry_dropped();
+                }),
+                Ok(Command::Flush(ack)) => {
+                    let result = self.sync();
+                    ack.try_send(()).ok();
+                    result
+                }
+                // Nothing written, so nothing to say about whether writing works
+                Err(RecvTimeoutError::Timeout) if self.unsynced == 0 => continue,
+                Err(RecvTimeoutError::Timeout) => self.sync(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            match result {
+                Ok(()) if failing => {
+                    info!(event = "audit_file_recovered"; "audit file is being written again");
+                    failing = false;
+                }
+                Ok(()) => (),
+                Err(e) if !failing => {
+                    error!(
+                        event = "audit_file_failed";
+                        "couldn't write audit file {}: {}", self.path.display(), e
+                    );
+                    failing = true;
+                }
+                Err(e) => debug!("couldn't write audit file: {}", e),
+            }
+        }
+    }
+
+    /// Append an entry, starting a new file first if one is due, and sync
+    /// the file if enough entries have been written since it last was
+    fn write(&mut self, entry: &Entry) -> Result<(), Error> {
+        let mut line = serde_json::to_string(entry).unwrap();
+        line.push('\n');
+        let len = line.len() as u64;
+
+        self.rotate_if_due(len)?;
+        self.file.write_all(line.as_bytes())?;
+        self.size += len;
+        self.unsynced += 1;
+
+        if self.unsynced >= self.sync_every {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sync any entries written since the last sync to disk
+    fn sync(&mut self) -> Result<(), Error> {
+        if self.unsynced > 0 {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Move the current file aside and start a new one, if adding `len`
+    /// bytes would take it beyond the maximum size or it was started on an
+    /// earlier day
+    fn rotate_if_due(&mut self, len: u64) -> Result<(), Error> {
+        let today = Utc::now().naive_utc().date();
+
+        let full = self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + len > max_size);
+        let stale = self.rotate_daily && self.date != today;
+
+        if !full && !stale {
+            return Ok(());
+        }
+
+        self.sync()?;
+
+        let rotated_path = rotated_path(&self.path);
+        fs::rename(&self.path, &rotated_path)?;
+
+        let (file, size, _) = open_audit_file(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.date = today;
+
+        info!(
+            event = "audit_file_rotated";
+            "moved full audit file to {}", rotated_path.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// Open an audit file for appending, creating it if need be, along with its
+/// size and the day it was started. An existing file is taken to have been
+/// started on the day it was last written.
+fn open_audit_file(path: &Path) -> Result<(File, u64, NaiveDate), Error> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+
+    let date = metadata
+        .modified()
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now())
+        .naive_utc()
+        .date();
+
+    Ok((file, metadata.len(), date))
+}
+
+/// Path to move a full audit file to: its path with the current time
+/// appended (e.g. `audit.log.20190102T030405.678Z`), plus a counter should
+/// that already be taken, so no earlier file is ever overwritten
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut base = OsString::from(path.as_os_str());
+    base.push(format!(".{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ")));
+
+    let mut rotated_path = PathBuf::from(&base);
+    let mut n = 1;
+
+    while rotated_path.exists() {
+        let mut numbered = base.clone();
+        numbered.push(format!(".{}", n));
+        rotated_path = numbered.into();
+        n += 1;
+    }
+
+    rotated_path
+}
@@ -0,0 +1,97 @@
+//! Decoding of Bech32 strings (BIP 173), e.g. `cosmosvalconspub1...` keys
+
+use error::Error;
+
+/// Characters used to encode 5-bit groups, in order of value
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Number of characters in the checksum
+const CHECKSUM_SIZE: usize = 6;
+
+/// Coefficients of the BCH code the checksum is computed with
+const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+
+/// Decode a Bech32 string into its human-readable part and data, checking
+/// the checksum
+pub fn decode(s: &str) -> Result<(String, Vec<u8>), Error> {
+    ensure!(
+        s.is_ascii() && (s == s.to_lowercase() || s == s.to_uppercase()),
+        InvalidKey,
+        "invalid bech32 string (mixed case or non-ASCII): {:?}",
+        s
+    );
+
+    let s = s.to_lowercase();
+
+    let (hrp, data) = match s.rfind('1') {
+        Some(pos) if pos > 0 && s.len() - pos > CHECKSUM_SIZE => (&s[..pos], &s[pos + 1..]),
+        _ => {
+            fail!(InvalidKey, "invalid bech32 string (missing separator): {:?}", s);
+        }
+    };
+
+    let values = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|&d| d == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| err!(InvalidKey, "invalid character in bech32 string: {:?}", s))?;
+
+    let mut checked = hrp.bytes().map(|c| c >> 5).collect::<Vec<u8>>();
+    checked.push(0);
+    checked.extend(hrp.bytes().map(|c| c & 0x1f));
+    checked.extend_from_slice(&values);
+
+    ensure!(
+        polymod(&checked) == 1,
+        InvalidKey,
+        "invalid bech32 checksum in {:?}",
+        s
+    );
+
+    let bytes = convert_bits(&values[..values.len() - CHECKSUM_SIZE])
+        .ok_or_else(|| err!(InvalidKey, "invalid bech32 padding in {:?}", s))?;
+
+    Ok((hrp.to_owned(), bytes))
+}
+
+/// Compute the BCH checksum over the given 5-bit values
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+
+    for &value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ u32::from(value);
+
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+
+    chk
+}
+
+/// Regroup 5-bit values into bytes, returning `None` if the padding isn't
+/// all zeroes (or is longer than it needs to be)
+fn convert_bits(values: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(values.len() * 5 / 8);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for &value in values {
+        acc = ((acc << 5) | u32::from(value)) & 0xfff;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    if bits >= 5 || (acc & ((1 << bits) - 1)) != 0 {
+        return None;
+    }
+
+    Some(bytes)
+}
@@ -0,0 +1,255 @@
+//! Canonical JSON encoding of votes and proposals, i.e. the bytes which are
+//! signed for them
+//!
+//! Tendermint signs a compact JSON object with keys in alphabetical order,
+//! wrapping the vote or proposal together with the chain ID so a signature
+//! made for one chain is never valid on another.
+
+use serde::Serialize;
+use serde_json;
+
+use rpc::{BlockId, PartSetHeader, Proposal, Vote};
+
+/// Bytes to be signed for a vote on the given chain
+pub fn canonical_vote_bytes(chain_id: &str, vote: &Vote) -> Vec<u8> {
+    canonical_bytes(&CanonicalVoteOnce {
+        chain_id,
+        vote: CanonicalVote {
+            block_id: &vote.block_id,
+            height: vote.height,
+            round: vote.round,
+            timestamp: &vote.timestamp,
+            vote_type: vote.vote_type,
+        },
+    })
+}
+
+/// Bytes to be signed for a proposal on the given chain
+pub fn canonical_proposal_bytes(chain_id: &str, proposal: &Proposal) -> Vec<u8> {
+    canonical_bytes(&CanonicalProposalOnce {
+        chain_id,
+        proposal: CanonicalProposal {
+            block_parts_header: &proposal.block_parts_header,
+            height: proposal.height,
+            pol_block_id: &proposal.pol_block_id,
+            pol_round: proposal.pol_round,
+            round: proposal.round,
+            timestamp: &proposal.timestamp,
+        },
+    })
+}
+
+/// Vote or proposal decoded from its sign bytes
+#[derive(Debug)]
+pub enum SignedMessage {
+    /// Vote, with the chain it's for
+    Vote {
+        /// Chain ID
+        chain_id: String,
+
+        /// Vote
+        vote: Vote,
+    },
+
+    /// Proposal, with the chain it's for
+    Proposal {
+        /// Chain ID
+        chain_id: String,
+
+        /// Proposal
+        proposal: Proposal,
+    },
+}
+
+/// Decode the sign bytes of a vote or proposal. Returns `None` unless the
+/// bytes are exactly what `canonical_vote_bytes` or `canonical_proposal_bytes`
+/// would produce for what they decode as.
+pub fn parse_sign_bytes(bytes: &[u8]) -> Option<SignedMessage> {
+    if let Ok(decoded) = serde_json::from_slice::<DecodedVoteOnce>(bytes) {
+        if canonical_vote_bytes(&decoded.chain_id, &decoded.vote) == bytes {
+            return Some(SignedMessage::Vote {
+                chain_id: decoded.chain_id,
+                vote: decoded.vote,
+            });
+        }
+    }
+
+    if let Ok(decoded) = serde_json::from_slice::<DecodedProposalOnce>(bytes) {
+        if canonical_proposal_bytes(&decoded.chain_id, &decoded.proposal) == bytes {
+            return Some(SignedMessage::Proposal {
+                chain_id: decoded.chain_id,
+                proposal: decoded.proposal,
+            });
+        }
+    }
+
+    None
+}
+
+// Fields are declared in alphabetical order to match Tendermint's encoding.
+
+#[derive(Serialize)]
+struct CanonicalVoteOnce<'a> {
+    chain_id: &'a str,
+    vote: CanonicalVote<'a>,
+}
+
+#[derive(Serialize)]
+struct CanonicalVote<'a> {
+    block_id: &'a BlockId,
+    height: i64,
+    round: i64,
+    timestamp: &'a str,
+    #[serde(rename = "type")]
+    vote_type: u8,
+}
+
+#[derive(Serialize)]
+struct CanonicalProposalOnce<'a> {
+    chain_id: &'a str,
+    proposal: CanonicalProposal<'a>,
+}
+
+#[derive(Serialize)]
+struct CanonicalProposal<'a> {
+    block_parts_header: &'a PartSetHeader,
+    height: i64,
+    pol_block_id: &'a BlockId,
+    pol_round: i64,
+    round: i64,
+    timestamp: &'a str,
+}
+
+#[derive(Deserialize)]
+struct DecodedVoteOnce {
+    chain_id: String,
+    vote: Vote,
+}
+
+#[derive(Deserialize)]
+struct DecodedProposalOnce {
+    chain_id: String,
+    proposal: Proposal,
+}
+
+/// Serialize a canonical structure to JSON
+fn canonical_bytes<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sign bytes of Tendermint's example precommit (from `TestVoteSignable`)
+    const VOTE_SIGN_BYTES: &str = "{\"chain_id\":\"test_chain_id\",\"vote\":{\"block_id\":{\"hash\":\
+        \"68617368\",\"parts\":{\"hash\":\"70617274735F68617368\",\"total\":1000000}},\"height\":\
+        12345,\"round\":2,\"timestamp\":\"2017-12-25T03:00:01.234Z\",\"type\":2}}";
+
+    /// Sign bytes of Tendermint's example proposal (from
+    /// `TestProposalSignable`)
+    const PROPOSAL_SIGN_BYTES: &str = "{\"chain_id\":\"test_chain_id\",\"proposal\":{\
+        \"block_parts_header\":{\"hash\":\"626C6F636B7061727473\",\"total\":111},\"height\":12345,\
+        \"pol_block_id\":{},\"pol_round\":-1,\"round\":23456,\"timestamp\":\
+        \"2018-02-11T07:09:22.765Z\"}}";
+
+    fn example_vote() -> Vote {
+        Vote {
+            height: 12345,
+            round: 2,
+            vote_type: 2,
+            block_id: BlockId {
+                hash: b"hash".to_vec(),
+                parts_header: PartSetHeader {
+                    hash: b"parts_hash".to_vec(),
+                    total: 1_000_000,
+                },
+            },
+            timestamp: "2017-12-25T03:00:01.234Z".to_owned(),
+        }
+    }
+
+    fn example_proposal() -> Proposal {
+        Proposal {
+            height: 12345,
+            round: 23456,
+            block_parts_header: PartSetHeader {
+                hash: b"blockparts".to_vec(),
+                total: 111,
+            },
+            pol_round: -1,
+            pol_block_id: BlockId::default(),
+            timestamp: "2018-02-11T07:09:22.765Z".to_owned(),
+        }
+    }
+
+    #[test]
+    fn vote_bytes_match_tendermint() {
+        assert_eq!(
+            String::from_utf8(canonical_vote_bytes("test_chain_id", &example_vote())).unwrap(),
+            VOTE_SIGN_BYTES
+        );
+    }
+
+    #[test]
+    fn proposal_bytes_match_tendermint() {
+        assert_eq!(
+            String::from_utf8(canonical_proposal_bytes("test_chain_id", &example_proposal()))
+                .unwrap(),
+            PROPOSAL_SIGN_BYTES
+        );
+    }
+
+    #[test]
+    fn parses_tendermint_vote_sign_bytes() {
+        match parse_sign_bytes(VOTE_SIGN_BYTES.as_bytes()) {
+            Some(SignedMessage::Vote { chain_id, vote }) => {
+                let expected = example_vote();
+                assert_eq!(chain_id, "test_chain_id");
+                assert_eq!((vote.height, vote.round, vote.vote_type), (12345, 2, 2));
+                assert_eq!(vote.block_id.hash, expected.block_id.hash);
+                assert_eq!(vote.block_id.parts_header.hash, expected.block_id.parts_header.hash);
+                assert_eq!(vote.block_id.parts_header.total, 1_000_000);
+                assert_eq!(vote.timestamp, expected.timestamp);
+            }
+            other => panic!("expected a vote, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_tendermint_proposal_sign_bytes() {
+        match parse_sign_bytes(PROPOSAL_SIGN_BYTES.as_bytes()) {
+            Some(SignedMessage::Proposal { chain_id, proposal }) => {
+                assert_eq!(chain_id, "test_chain_id");
+                assert_eq!((proposal.height, proposal.round), (12345, 23456));
+                assert_eq!(proposal.block_parts_header.hash, b"blockparts");
+                assert_eq!(proposal.block_parts_header.total, 111);
+                assert_eq!(proposal.pol_round, -1);
+                assert!(proposal.pol_block_id.hash.is_empty());
+                assert_eq!(proposal.timestamp, "2018-02-11T07:09:22.765Z");
+            }
+            other => panic!("expected a proposal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_canonical_encodings_are_not_parsed() {
+        // Same vote, with whitespace
+        let spaced = VOTE_SIGN_BYTES.replace(",\"round\"", ", \"round\"");
+        assert!(parse_sign_bytes(spaced.as_bytes()).is_none());
+
+        // Same vote, with keys out of order
+        let reordered = VOTE_SIGN_BYTES.replace(
+            "\"round\":2,\"timestamp\":\"2017-12-25T03:00:01.234Z\"",
+            "\"timestamp\":\"2017-12-25T03:00:01.234Z\",\"round\":2",
+        );
+        assert_ne!(reordered, VOTE_SIGN_BYTES);
+        assert!(parse_sign_bytes(reordered.as_bytes()).is_none());
+
+        // Lowercase hex
+        let lowercase = PROPOSAL_SIGN_BYTES.replace("626C6F636B7061727473", "626c6f636b7061727473");
+        assert!(parse_sign_bytes(lowercase.as_bytes()).is_none());
+
+        assert!(parse_sign_bytes(b"not a vote").is_none());
+    }
+}
@@ -0,0 +1,87 @@
+//! Tendermint chain IDs
+
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use error::Error;
+
+/// Maximum length of a chain ID (in bytes), as enforced by Tendermint
+pub const MAX_CHAIN_ID_LEN: usize = 50;
+
+/// Identifier of a blockchain (e.g. `cosmoshub-1`), checked to be one
+/// Tendermint would accept: nonempty, at most `MAX_CHAIN_ID_LEN` bytes, and
+/// free of control characters
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChainId(String);
+
+impl ChainId {
+    /// Borrow the chain ID as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for ChainId {
+    type Error = Error;
+
+    fn try_from(id: String) -> Result<Self, Error> {
+        ensure!(!id.is_empty(), InvalidChainId, "chain ID is empty");
+
+        ensure!(
+            id.len() <= MAX_CHAIN_ID_LEN,
+            InvalidChainId,
+            "chain ID is {} bytes (maximum is {}): {:?}",
+            id.len(),
+            MAX_CHAIN_ID_LEN,
+            id
+        );
+
+        ensure!(
+            !id.chars().any(char::is_control),
+            InvalidChainId,
+            "chain ID contains control characters: {:?}",
+            id
+        );
+
+        Ok(ChainId(id))
+    }
+}
+
+impl FromStr for ChainId {
+    type Err = Error;
+
+    fn from_str(id: &str) -> Result<Self, Error> {
+        Self::try_from(id.to_owned())
+    }
+}
+
+impl From<ChainId> for String {
+    fn from(id: ChainId) -> String {
+        id.0
+    }
+}
+
+// Lets maps keyed by chain ID be looked up with a plain `&str`
+impl Borrow<str> for ChainId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for ChainId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ChainId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
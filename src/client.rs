@@ -5,17 +5,46 @@
 //! To dance around the fact the KMS isn't actually a service, we refer to it
 //! as a "Key Management System".
 
+use rand::{self, Rng};
+use std::cmp;
+#[cfg(feature = "tokio")]
+use std::future::Future;
 use std::panic;
-use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+#[cfg(feature = "tokio")]
+use tokio::runtime::Runtime;
+#[cfg(feature = "tokio")]
+use tokio::time::{self, Sleep};
 
-use ed25519::Keyring;
-use session::Session;
+#[cfg(feature = "tokio")]
+use async_session::{self, AsyncSession, Connect};
+use config::{Chains, ValidatorConfig};
+use ed25519::SharedKeyring;
+use error::Error;
+use metrics;
+use session::{Session, SHUTDOWN_POLL_MS};
+use state::SignState;
 
-/// How long to wait after a crash before respawning (in seconds)
+/// Default delay before reconnecting after a failure, unless overridden by
+/// `reconnect-base-secs` (in seconds)
 pub const RESPAWN_DELAY: u64 = 5;
 
+/// Default maximum delay between reconnect attempts (in seconds)
+pub const MAX_RESPAWN_DELAY: u64 = 300;
+
+/// Sessions which stay up at least this long reset the backoff (in seconds)
+pub const BACKOFF_RESET_SECS: u64 = 60;
+
+/// Warn once a client has failed to hold a session this many times in a row
+pub const RECONNECT_WARN_FAILURES: u32 = 5;
+
 /// Client connections: wraps a thread which makes a connection to a particular
 /// validator node and then receives RPCs.
 ///
@@ -26,52 +55,694 @@ pub struct Client {
     /// Identifier for this validator from the config
     label: String,
 
-    /// Handle to the client thread
-    handle: JoinHandle<()>,
+    /// Configuration for this validator
+    config: ValidatorConfig,
+
+    /// Double-signing protection state for this validator
+    state: Arc<Mutex<SignState>>,
+
+    /// Handle to the client thread (or task), which returns an error if it
+    /// stopped because of one that reconnecting can't fix
+    handle: ClientHandle,
+
+    /// Flag which asks the client thread to exit
+    shutdown: Arc<AtomicBool>,
 }
 
 impl Client {
-    /// Spawn a new client, returning a handle so it can be joined
-    pub fn spawn(label: String, addr: String, port: u16, keyring: Arc<Keyring>) -> Self {
-        Self {
+    /// Spawn a new client in a thread named after its label (`kms-<label>`),
+    /// returning a handle so it can be joined
+    pub fn spawn(
+        label: String,
+        config: ValidatorConfig,
+        keyring: SharedKeyring,
+        state: Arc<Mutex<SignState>>,
+        chains: Arc<Chains>,
+    ) -> Result<Self, Error> {
+        if config.async_session.unwrap_or(false) {
+            return Self::spawn_task(label, config, keyring, state, chains);
+        }
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = Arc::clone(&shutdown);
+        let thread_label = label.clone();
+        let thread_config = config.clone();
+        let thread_state = Arc::clone(&state);
+
+        let mut builder = thread::Builder::new().name(format!("kms-{}", label));
+
+        if let Some(stack_size) = config.thread_stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let handle = builder.spawn(move || {
+            client_loop(
+                &thread_label,
+                &thread_config,
+                keyring,
+                thread_state,
+                chains,
+                &thread_shutdown,
+            )
+        })?;
+
+        Ok(Self {
             label,
-            handle: thread::spawn(move || client_loop(&addr, port, keyring)),
+            config,
+            state,
+            handle: ClientHandle::Thread(handle),
+            shutdown,
+        })
+    }
+
+    /// Spawn a new client as a task on the shared tokio runtime (for
+    /// validators with `async-session = true`)
+    #[cfg(feature = "tokio")]
+    fn spawn_task(
+        label: String,
+        config: ValidatorConfig,
+        keyring: SharedKeyring,
+        state: Arc<Mutex<SignState>>,
+        chains: Arc<Chains>,
+    ) -> Result<Self, Error> {
+        let runtime = async_session::runtime()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let client_loop = AsyncClientLoop {
+            label: label.clone(),
+            config: config.clone(),
+            keyring,
+            state: Arc::clone(&state),
+            chains,
+            shutdown: Arc::clone(&shutdown),
+            reconnect: Reconnect::new(&config),
+            session_start: Instant::now(),
+            stage: AsyncClientStage::Starting,
+        };
+
+        let handle = runtime.spawn(client_loop);
+
+        Ok(Self {
+            label,
+            config,
+            state,
+            handle: ClientHandle::Task(runtime, handle),
+            shutdown,
+        })
+    }
+
+    /// Async sessions need the `tokio` feature (which config validation
+    /// checks for)
+    #[cfg(not(feature = "tokio"))]
+    fn spawn_task(
+        label: String,
+        _config: ValidatorConfig,
+        _keyring: SharedKeyring,
+        _state: Arc<Mutex<SignState>>,
+        _chains: Arc<Chains>,
+    ) -> Result<Self, Error> {
+        fail!(
+            ConfigError,
+            "validator {}: async-session requires the KMS to be built with the tokio feature",
+            label
+        );
+    }
+
+    /// Identifier for this validator from the config
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Configuration for this validator
+    pub fn config(&self) -> &ValidatorConfig {
+        &self.config
+    }
+
+    /// Double-signing protection state for this validator
+    pub fn state(&self) -> Arc<Mutex<SignState>> {
+        Arc::clone(&self.state)
+    }
+
+    /// Ask the client to close its session and exit its reconnect loop
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    /// Has the client been asked to shut down?
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Has the client thread (or task) exited?
+    pub fn is_finished(&self) -> bool {
+        match self.handle {
+            ClientHandle::Thread(ref handle) => handle.is_finished(),
+            #[cfg(feature = "tokio")]
+            ClientHandle::Task(_, ref handle) => handle.is_finished(),
         }
     }
 
-    /// Wait for a running client to finish
-    pub fn join(self) {
-        self.handle.join().unwrap();
+    /// Wait for a running client to finish, returning the error which
+    /// stopped it (if any)
+    pub fn join(self) -> Result<(), Error> {
+        match self.handle {
+            ClientHandle::Thread(handle) => match handle.join() {
+                Ok(result) => result,
+                Err(_) => {
+                    error!(
+                        validator = self.label.as_str(), event = "client_panic";
+                        "client thread panicked"
+                    );
+                    Ok(())
+                }
+            },
+            #[cfg(feature = "tokio")]
+            ClientHandle::Task(runtime, handle) => match runtime.block_on(handle) {
+                Ok(result) => result,
+                Err(_) => {
+                    error!(
+                        validator = self.label.as_str(), event = "client_panic";
+                        "client task panicked"
+                    );
+                    Ok(())
+                }
+            },
+        }
     }
 }
 
-/// Main loop for all clients. Handles reconnecting in the event of an error
-fn client_loop(addr: &str, port: u16, keyring: Arc<Keyring>) {
-    loop {
+/// Where a client is running
+enum ClientHandle {
+    /// On a thread of its own
+    Thread(JoinHandle<Result<(), Error>>),
+
+    /// As a task on the shared tokio runtime
+    #[cfg(feature = "tokio")]
+    Task(&'static Runtime, tokio::task::JoinHandle<Result<(), Error>>),
+}
+
+/// Main loop for all clients. Handles reconnecting in the event of an error,
+/// unless it's one reconnecting can't fix
+fn client_loop(
+    label: &str,
+    config: &ValidatorConfig,
+    keyring: SharedKeyring,
+    state: Arc<Mutex<SignState>>,
+    chains: Arc<Chains>,
+    shutdown: &Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let mut reconnect = Reconnect::new(config);
+
+    while !shutdown.load(Ordering::SeqCst) {
+        let addr = reconnect.addr(label, config);
+        let session_start = Instant::now();
+
         let catch_unwind_result = panic::catch_unwind(|| {
-            Session::new(addr, port, Arc::clone(&keyring))?.handle_requests()
+            Session::new(
+                label,
+                config,
+                &addr,
+                keyring.clone(),
+                Arc::clone(&state),
+                Arc::clone(&chains),
+                Arc::clone(shutdown),
+            )?.handle_requests()
         });
 
+        let ended = reconnect.session_ended(label, config, catch_unwind_result, session_start);
+
+        let delay = match ended? {
+            Some(delay) => delay,
+            None => return Ok(()),
+        };
+
+        sleep_unless_shutdown(delay, shutdown);
+        reconnect.advance(shutdown);
+    }
+
+    reconnect.log_shutdown(label, config);
+    Ok(())
+}
+
+/// Async equivalent of `client_loop`, for clients run as tasks: connects,
+/// handles requests, and reconnects in the same way
+#[cfg(feature = "tokio")]
+struct AsyncClientLoop {
+    /// Identifier for this validator from the config
+    label: String,
+
+    /// Configuration for this validator
+    config: ValidatorConfig,
+
+    /// Keys to sign with
+    keyring: SharedKeyring,
+
+    /// Double-signing protection state for this validator
+    state: Arc<Mutex<SignState>>,
+
+    /// Settings for particular chains
+    chains: Arc<Chains>,
+
+    /// Flag which asks the client to exit
+    shutdown: Arc<AtomicBool>,
+
+    /// Address and backoff for the next session
+    reconnect: Reconnect,
+
+    /// When the current session started
+    session_start: Instant,
+
+    /// What the client is doing
+    stage: AsyncClientStage,
+}
+
+/// Stages of the async client loop
+#[cfg(feature = "tokio")]
+enum AsyncClientStage {
+    /// About to start a session
+    Starting,
+
+    /// Connecting to the validator
+    Connecting(Box<Connect>),
+
+    /// Handling requests
+    Running(Box<AsyncSession>),
+
+    /// Waiting until the given time to reconnect, waking every
+    /// `SHUTDOWN_POLL_MS` to check for shutdown
+    Waiting(Instant, Pin<Box<Sleep>>),
+}
+
+#[cfg(feature = "tokio")]
+impl Future for AsyncClientLoop {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        loop {
+            let session_result = match this.stage {
+                AsyncClientStage::Starting => {
+                    if this.shutdown.load(Ordering::SeqCst) {
+                        this.reconnect.log_shutdown(&this.label, &this.config);
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    let addr = this.reconnect.addr(&this.label, &this.config);
+                    this.session_start = Instant::now();
+
+                    let connect = Connect::new(
+                        &this.label,
+                        &this.config,
+                        &addr,
+                        this.keyring.clone(),
+                        Arc::clone(&this.state),
+                        Arc::clone(&this.chains),
+                        Arc::clone(&this.shutdown),
+                    );
+
+                    match connect {
+                        Ok(connect) => {
+                            this.stage = AsyncClientStage::Connecting(Box::new(connect));
+                            continue;
+                        }
+                        Err(e) => Ok(Err(e)),
+                    }
+                }
+                AsyncClientStage::Connecting(ref mut connect) => {
+                    let poll_result =
+                        panic::catch_unwind(panic::AssertUnwindSafe(|| Pin::new(&mut **connect).poll(cx)));
+
+                    match poll_result {
+                        Ok(Poll::Pending) => return Poll::Pending,
+                        Ok(Poll::Ready(Ok(session))) => {
+                            this.stage = AsyncClientStage::Running(Box::new(session));
+                            continue;
+                        }
+                        Ok(Poll::Ready(Err(e))) => Ok(Err(e)),
+                        Err(panic) => Err(panic),
+                    }
+                }
+                AsyncClientStage::Running(ref mut session) => {
+                    let poll_result =
+                        panic::catch_unwind(panic::AssertUnwindSafe(|| Pin::new(&mut **session).poll(cx)));
+
+                    match poll_result {
+                        Ok(Poll::Pending) => return Poll::Pending,
+                        Ok(Poll::Ready(result)) => Ok(result),
+                        Err(panic) => Err(panic),
+                    }
+                }
+                AsyncClientStage::Waiting(until, ref mut timer) => {
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+
+                    let now = Instant::now();
+
+                    if now < until && !this.shutdown.load(Ordering::SeqCst) {
+                        let poll_interval = Duration::from_millis(SHUTDOWN_POLL_MS);
+                        let next_poll = cmp::min(until - now, poll_interval);
+                        timer.as_mut().reset(time::Instant::now() + next_poll);
+                        continue;
+                    }
+
+                    this.reconnect.advance(&this.shutdown);
+                    this.stage = AsyncClientStage::Starting;
+                    continue;
+                }
+            };
+
+            // Drop the session (closing its connection) before waiting
+            this.stage = AsyncClientStage::Starting;
+
+            let ended = this.reconnect.session_ended(
+                &this.label,
+                &this.config,
+                session_result,
+                this.session_start,
+            );
+
+            let delay = match ended? {
+                Some(delay) => delay,
+                None => return Poll::Ready(Ok(())),
+            };
+
+            let first_poll = cmp::min(delay, Duration::from_millis(SHUTDOWN_POLL_MS));
+            let timer = Box::pin(time::sleep(first_poll));
+            this.stage = AsyncClientStage::Waiting(Instant::now() + delay, timer);
+        }
+    }
+}
+
+/// Which of a validator's addresses a client is using, and how long to back
+/// off before reconnecting when sessions fail
+struct Reconnect {
+    /// Every address the validator can be reached at
+    endpoints: Vec<String>,
+
+    /// Index of the address in use
+    active: usize,
+
+    /// Index of the address last announced in the log (if any)
+    announced: Option<usize>,
+
+    /// Delay before reconnecting
+    backoff: Backoff,
+}
+
+impl Reconnect {
+    /// Start with the validator's first address and no failures
+    fn new(config: &ValidatorConfig) -> Self {
+        Self {
+            endpoints: config.endpoints(),
+            active: 0,
+            announced: None,
+            backoff: Backoff::new(
+                config.reconnect_base_secs.unwrap_or(RESPAWN_DELAY),
+                config.reconnect_max_secs.unwrap_or(MAX_RESPAWN_DELAY),
+            ),
+        }
+    }
+
+    /// Address to connect to for the next session, logged whenever it
+    /// changes (if there's more than one)
+    fn addr(&mut self, label: &str, config: &ValidatorConfig) -> String {
+        let addr = self.endpoints[self.active].clone();
+
+        if self.endpoints.len() > 1 && self.announced != Some(self.active) {
+            info!(
+                validator = label, addr = addr.as_str(), port = config.port,
+                event = "address_selected";
+                "using address {} ({} of {})", addr, self.active + 1, self.endpoints.len()
+            );
+            self.announced = Some(self.active);
+        }
+
+        addr
+    }
+
+    /// Decide what to do once a session has ended with the given result (or
+    /// panicked): `None` means the client is done, while `Some` gives the
+    /// delay before reconnecting. Errors mean the client should give up.
+    fn session_ended(
+        &mut self,
+        label: &str,
+        config: &ValidatorConfig,
+        catch_unwind_result: thread::Result<Result<(), Error>>,
+        session_start: Instant,
+    ) -> Result<Option<Duration>, Error> {
+        let addr = self.endpoints[self.active].as_str();
+        let port = config.port;
+
         match catch_unwind_result {
             Ok(result) => match result {
                 Ok(_) => {
-                    info!("[{}:{}] session closed gracefully", addr, port);
-                    return;
+                    info!(
+                        validator = label, addr = addr, port = port, event = "session_closed";
+                        "session closed gracefully"
+                    );
+                    return Ok(None);
                 }
-                Err(e) => error!("[{}:{}] {}", addr, port, e),
-            },
-            Err(val) => {
-                if let Some(e) = val.downcast_ref::<String>() {
-                    error!("[{}:{}] client panic! {}", addr, port, e);
-                } else if let Some(e) = val.downcast_ref::<&str>() {
-                    error!("[{}:{}] client panic! {}", addr, port, e);
-                } else {
-                    error!("[{}:{}] client panic! (unknown cause)", addr, port);
+                // e.g. the validator restarting, so it's worth reconnecting
+                Err(Error::ConnectionClosed { .. }) => info!(
+                    validator = label, addr = addr, port = port, event = "session_closed";
+                    "validator closed the connection; reconnecting"
+                ),
+                Err(Error::UnexpectedEof { description }) => warn!(
+                    validator = label, addr = addr, port = port, event = "session_eof";
+                    "{}; reconnecting", description
+                ),
+                Err(Error::Timeout { description }) => warn!(
+                    validator = label, addr = addr, port = port, event = "session_timeout";
+                    "{}; reconnecting", description
+                ),
+                Err(e) => {
+                    if !e.is_retryable() {
+                        error!(
+                            validator = label, addr = addr, port = port, event = "session_fatal";
+                            "{}; not reconnecting", e
+                        );
+                        return Err(e);
+                    }
+
+                    error!(
+                        validator = label, addr = addr, port = port, event = "session_error";
+                        "{}", e
+                    );
                 }
+            },
+            Err(_) => {
+                // The panic hook has already logged the details
+                error!(
+                    validator = label, addr = addr, port = port, event = "client_panic";
+                    "client panicked; reconnecting"
+                );
+            }
+        }
+
+        let backoff = &mut self.backoff;
+
+        // The schedule's transitions (starting, reaching the maximum delay,
+        // and being reset) are logged, so its state can be followed from the
+        // logs rather than inferred from the delays
+        if session_start.elapsed() >= Duration::from_secs(BACKOFF_RESET_SECS) {
+            if backoff.failures > 0 {
+                info!(
+                    validator = label, addr = addr, port = port, event = "backoff_reset",
+                    failures = backoff.failures;
+                    "session lasted over {}s; reconnect delay reset after {} failure(s)",
+                    BACKOFF_RESET_SECS, backoff.failures
+                );
             }
+
+            backoff.reset();
+            metrics::reconnect_backoff(label, 0, Duration::from_secs(0));
         }
 
-        // TODO: exponential backoff?
-        thread::sleep(Duration::from_secs(RESPAWN_DELAY))
+        if let Some(max_attempts) = config.max_reconnect_attempts.filter(|n| *n > 0) {
+            if backoff.failures >= max_attempts {
+                error!(
+                    validator = label, addr = addr, port = port, event = "reconnect_limit_reached",
+                    attempts = backoff.failures;
+                    "giving up after {} failed reconnect attempts", backoff.failures
+                );
+                fail!(
+                    IoError,
+                    "gave up after {} failed attempts to reconnect to {}",
+                    backoff.failures,
+                    addr
+                );
+            }
+        }
+
+        let was_capped = backoff.capped;
+        let delay = backoff.next_delay();
+        metrics::reconnect_backoff(label, backoff.failures, delay);
+
+        if backoff.failures == 1 {
+            info!(
+                validator = label, addr = addr, port = port, event = "backoff_started",
+                delay_ms = delay.as_millis() as u64;
+                "reconnecting in {:?}, then backing off from {}s up to {}s",
+                delay, backoff.base_secs, backoff.max_secs
+            );
+        }
+
+        if backoff.capped && !was_capped {
+            info!(
+                validator = label, addr = addr, port = port, event = "backoff_capped",
+                failures = backoff.failures;
+                "reconnect delay reached its maximum of {}s after {} failure(s); retrying at \
+                 that interval", backoff.max_secs, backoff.failures
+            );
+        }
+
+        debug!(
+            validator = label, addr = addr, port = port, event = "reconnect_scheduled",
+            delay_ms = delay.as_millis() as u64, failures = backoff.failures;
+            "reconnecting in {:?}", delay
+        );
+
+        if backoff.failures == RECONNECT_WARN_FAILURES {
+            warn!(
+                validator = label, addr = addr, port = port, event = "reconnect_struggling",
+                failures = backoff.failures;
+                "{} consecutive failures connecting to {}; still retrying", backoff.failures, addr
+            );
+        }
+
+        Ok(Some(delay))
+    }
+
+    /// Once the delay before reconnecting is over, move on to the next
+    /// address (if there's more than one)
+    fn advance(&mut self, shutdown: &AtomicBool) {
+        self.active = (self.active + 1) % self.endpoints.len();
+
+        if !shutdown.load(Ordering::SeqCst) {
+            metrics::reconnect_attempt();
+        }
+    }
+
+    /// Log that the client has been shut down
+    fn log_shutdown(&self, label: &str, config: &ValidatorConfig) {
+        let addr = self.endpoints[self.active].as_str();
+
+        info!(
+            validator = label, addr = addr, port = config.port, event = "client_shutdown";
+            "client shut down"
+        );
+    }
+}
+
+/// Sleep for the given duration, waking early if a shutdown is requested
+fn sleep_unless_shutdown(duration: Duration, shutdown: &AtomicBool) {
+    let start = Instant::now();
+    let poll_interval = Duration::from_millis(SHUTDOWN_POLL_MS);
+
+    while !shutdown.load(Ordering::SeqCst) {
+        let elapsed = start.elapsed();
+
+        if elapsed >= duration {
+            return;
+        }
+
+        thread::sleep(cmp::min(duration - elapsed, poll_interval));
+    }
+}
+
+/// Exponential backoff with jitter: the delay doubles after each consecutive
+/// failure (up to a maximum), and a random amount is added so many KMS
+/// instances restarting together don't all reconnect at once
+struct Backoff {
+    /// Delay after the first failure (in seconds)
+    base_secs: u64,
+
+    /// Maximum delay (in seconds)
+    max_secs: u64,
+
+    /// Number of consecutive failures
+    failures: u32,
+
+    /// Has the delay reached its maximum?
+    capped: bool,
+}
+
+impl Backoff {
+    /// Create a new backoff schedule
+    fn new(base_secs: u64, max_secs: u64) -> Self {
+        Self {
+            base_secs,
+            max_secs: cmp::max(base_secs, max_secs),
+            failures: 0,
+            capped: false,
+        }
+    }
+
+    /// Compute the delay before the next attempt, and count another failure
+    fn next_delay(&mut self) -> Duration {
+        let multiplier = 1u64.checked_shl(self.failures).unwrap_or(u64::MAX);
+        let delay_secs = cmp::min(self.base_secs.saturating_mul(multiplier), self.max_secs);
+        self.failures = self.failures.saturating_add(1);
+        self.capped = delay_secs == self.max_secs;
+
+        // Sleep for somewhere between half and all of the computed delay
+        let delay_ms = delay_secs.saturating_mul(1000);
+        let jitter_ms = if delay_ms >= 2 {
+            rand::thread_rng().gen_range(0, delay_ms / 2)
+        } else {
+            0
+        };
+
+        Duration::from_millis(delay_ms / 2 + jitter_ms)
+    }
+
+    /// Reset the schedule after a long-lived session
+    fn reset(&mut self) {
+        self.failures = 0;
+        self.capped = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[test]
+    fn backoff_doubles_up_to_its_maximum() {
+        let mut backoff = Backoff::new(1, 4);
+
+        for &max_secs in &[1, 2, 4, 4] {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_millis(max_secs * 500), "{:?}", delay);
+            assert!(delay <= Duration::from_secs(max_secs), "{:?}", delay);
+        }
+
+        assert!(backoff.capped);
+    }
+
+    #[test]
+    fn huge_backoff_saturates_rather_than_overflowing() {
+        let mut backoff = Backoff::new(u64::MAX / 2, u64::MAX);
+
+        for _ in 0..3 {
+            assert!(backoff.next_delay() >= Duration::from_millis(u64::MAX / 2));
+        }
+
+        assert!(backoff.capped);
+    }
+
+    #[test]
+    fn validator_closing_the_connection_leads_to_a_reconnect() {
+        let config: ValidatorConfig = toml::from_str("port = 26658").unwrap();
+        let mut reconnect = Reconnect::new(&config);
+        let closed = Err(err!(ConnectionClosed, "connection closed by peer"));
+
+        match reconnect.session_ended("test", &config, Ok(closed), Instant::now()) {
+            Ok(Some(delay)) => assert!(delay <= Duration::from_secs(RESPAWN_DELAY)),
+            other => panic!("expected a reconnect delay, got {:?}", other),
+        }
     }
 }
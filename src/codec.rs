@@ -0,0 +1,378 @@
+//! Length-delimited framing for RPC messages
+//!
+//! Each message is preceded by its length, encoded as an unsigned varint
+//! (i.e. protobuf-style base 128, least significant group first).
+
+#[cfg(feature = "tokio")]
+use bytes::{Buf, BytesMut};
+use std::cmp;
+use std::io::{self, Read};
+#[cfg(feature = "tokio")]
+use tokio_util::codec::{Decoder, Encoder};
+
+use error::Error;
+
+/// Maximum number of bytes in an encoded length prefix (enough for a `u64`)
+pub const MAX_VARINT_SIZE: usize = 10;
+
+/// Size of the chunks the body of a message is read in
+const READ_CHUNK_SIZE: usize = 1024;
+
+/// Prefix the given message with its length
+pub fn encode(msg: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAX_VARINT_SIZE + msg.len());
+    encode_varint(msg.len() as u64, &mut bytes);
+    bytes.extend_from_slice(msg);
+    bytes
+}
+
+/// Read the next length-prefixed message. If the stream ends cleanly
+/// between messages the error is `ConnectionClosed`, whereas ending partway
+/// through one is an `UnexpectedEof`. Messages longer than `max_len` are
+/// rejected before any of their body is read.
+///
+/// Nothing is read beyond the end of the message, so a reader which buffers
+/// (as `SecretConnection` does) still holds any requests sent along with it,
+/// e.g. pipelined in the same segment, for the next call.
+pub fn decode<R: Read>(reader: &mut R, max_len: usize) -> Result<Vec<u8>, Error> {
+    let len = match decode_varint(reader)? {
+        Some(len) => len,
+        None => {
+            fail!(ConnectionClosed, "connection closed by peer");
+        }
+    };
+
+    ensure!(
+        len <= max_len as u64,
+        MessageTooLarge,
+        "message is {} bytes (maximum is {})",
+        len,
+        max_len
+    );
+
+    let mut msg = vec![];
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+
+    // Grow the buffer as data arrives rather than trusting the length
+    // prefix enough to allocate it all up front
+    while (msg.len() as u64) < len {
+        let wanted = cmp::min(len - msg.len() as u64, READ_CHUNK_SIZE as u64) as usize;
+
+        match reader.read(&mut chunk[..wanted]) {
+            Ok(0) => {
+                fail!(
+                    UnexpectedEof,
+                    "connection closed mid-message ({} of {} bytes read)",
+                    msg.len(),
+                    len
+                );
+            }
+            Ok(n) => msg.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(msg)
+}
+
+/// Append the varint encoding of the given value
+pub fn encode_varint(mut value: u64, bytes: &mut Vec<u8>) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+
+    bytes.push(value as u8);
+}
+
+/// Read a varint one byte at a time, returning `None` on EOF before its
+/// first byte (and an `UnexpectedEof` error on EOF after it)
+pub fn decode_varint<R: Read>(reader: &mut R) -> Result<Option<u64>, Error> {
+    let mut value = 0u64;
+
+    for i in 0..MAX_VARINT_SIZE {
+        let byte = match read_byte(reader)? {
+            Some(byte) => byte,
+            None if i == 0 => return Ok(None),
+            None => {
+                fail!(UnexpectedEof, "connection closed mid-length-prefix");
+            }
+        };
+
+        value |= u64::from(byte & 0x7f) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    fail!(RpcError, "length prefix longer than {} bytes", MAX_VARINT_SIZE);
+}
+
+/// Read a single byte, returning `None` on EOF
+fn read_byte<R: Read>(reader: &mut R) -> Result<Option<u8>, Error> {
+    let mut byte = [0u8];
+
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Framing for sessions run on tokio (see `async_session`): decodes
+/// length-prefixed messages as they arrive, and writes out responses, which
+/// their `to_vec` methods have already prefixed, as they are
+#[cfg(feature = "tokio")]
+pub struct RpcCodec {
+    /// Largest message which will be accepted (in bytes)
+    max_len: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl RpcCodec {
+    /// Create a codec which rejects messages longer than `max_len`
+    pub fn new(max_len: usize) -> Self {
+        Self { max_len }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Decoder for RpcCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>, Error> {
+        let (len, prefix_len) = match parse_varint(src)? {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+
+        ensure!(
+            len <= self.max_len as u64,
+            MessageTooLarge,
+            "message is {} bytes (maximum is {})",
+            len,
+            self.max_len
+        );
+
+        // The buffer grows as data arrives, so a large length prefix doesn't
+        // make us allocate anything up front
+        if ((src.len() - prefix_len) as u64) < len {
+            return Ok(None);
+        }
+
+        src.advance(prefix_len);
+        Ok(Some(src.split_to(len as usize).to_vec()))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>, Error> {
+        match self.decode(src)? {
+            Some(msg) => Ok(Some(msg)),
+            None if src.is_empty() => Ok(None),
+            None => {
+                fail!(
+                    UnexpectedEof,
+                    "connection closed mid-message ({} bytes of it read)",
+                    src.len()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Encoder<Vec<u8>> for RpcCodec {
+    type Error = Error;
+
+    fn encode(&mut self, response: Vec<u8>, dst: &mut BytesMut) -> Result<(), Error> {
+        dst.extend_from_slice(&response);
+        Ok(())
+    }
+}
+
+/// Parse a varint from the start of the given bytes, returning its value
+/// and length, or `None` if more bytes are needed
+#[cfg(feature = "tokio")]
+fn parse_varint(bytes: &[u8]) -> Result<Option<(u64, usize)>, Error> {
+    let mut value = 0u64;
+
+    for (i, byte) in bytes.iter().take(MAX_VARINT_SIZE).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    ensure!(
+        bytes.len() < MAX_VARINT_SIZE,
+        RpcError,
+        "length prefix longer than {} bytes",
+        MAX_VARINT_SIZE
+    );
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::{PingRequest, Request};
+    use secret_connection::{IdentityKey, SecretConnection, DATA_MAX_SIZE};
+    use signatory::ed25519::FromSeed;
+    use std::io::{Cursor, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    /// Reader which hands out at most one byte per `read`, as a slow or
+    /// fragmenting connection might
+    struct ByteAtATime(Cursor<Vec<u8>>);
+
+    impl Read for ByteAtATime {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = cmp::min(buf.len(), 1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn decodes_messages_read_a_byte_at_a_time() {
+        // Long enough for a two-byte prefix and several read chunks
+        let first: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let second = b"second".to_vec();
+
+        let mut bytes = encode(&first);
+        bytes.extend_from_slice(&encode(&second));
+
+        let mut reader = ByteAtATime(Cursor::new(bytes));
+        assert_eq!(decode(&mut reader, 4096).unwrap(), first);
+        assert_eq!(decode(&mut reader, 4096).unwrap(), second);
+    }
+
+    #[test]
+    fn eof_mid_message_is_unexpected() {
+        let mut bytes = encode(b"truncated message");
+        bytes.truncate(5);
+
+        match decode(&mut Cursor::new(bytes), 1024) {
+            Err(Error::UnexpectedEof { .. }) => (),
+            other => panic!("expected UnexpectedEof, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn eof_mid_length_prefix_is_unexpected() {
+        let mut bytes = vec![];
+        encode_varint(300, &mut bytes);
+        bytes.truncate(1);
+
+        match decode(&mut Cursor::new(bytes), 1024) {
+            Err(Error::UnexpectedEof { .. }) => (),
+            other => panic!("expected UnexpectedEof, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn eof_between_messages_is_a_clean_close() {
+        let mut reader = Cursor::new(encode(b"only message"));
+        assert_eq!(decode(&mut reader, 1024).unwrap(), b"only message");
+
+        match decode(&mut reader, 1024) {
+            Err(Error::ConnectionClosed { .. }) => (),
+            other => panic!("expected ConnectionClosed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_message_is_rejected_before_its_body_is_read() {
+        // A prefix claiming far more than could ever be allocated
+        let mut bytes = vec![];
+        encode_varint(1 << 60, &mut bytes);
+        let prefix_len = bytes.len();
+        bytes.extend_from_slice(&[0u8; 64]);
+
+        let mut reader = Cursor::new(bytes);
+
+        match decode(&mut reader, 1024) {
+            Err(Error::MessageTooLarge { .. }) => (),
+            other => panic!("expected MessageTooLarge, got {:?}", other),
+        }
+
+        assert_eq!(reader.position(), prefix_len as u64);
+    }
+
+    #[test]
+    fn message_of_exactly_max_len_is_accepted() {
+        let msg = vec![0xab; 1024];
+        assert_eq!(decode(&mut Cursor::new(encode(&msg)), 1024).unwrap(), msg);
+
+        match decode(&mut Cursor::new(encode(&msg)), 1023) {
+            Err(Error::MessageTooLarge { .. }) => (),
+            other => panic!("expected MessageTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn requests_sealed_in_one_frame_are_both_read() {
+        let mut frames = Request::Ping(PingRequest { nonce: 1 }).to_vec();
+        frames.extend_from_slice(&Request::Ping(PingRequest { nonce: 2 }).to_vec());
+        assert!(frames.len() <= DATA_MAX_SIZE);
+
+        let (kms_socket, validator_socket) = UnixStream::pair().unwrap();
+        let validator = thread::spawn(move || {
+            let identity = IdentityKey::from_seed(&[2u8; 32]).unwrap();
+            let mut connection = SecretConnection::new(validator_socket, &identity).unwrap();
+
+            // A single write of less than DATA_MAX_SIZE is sealed into a
+            // single frame, as pipelined requests would be
+            connection.write_all(&frames).unwrap();
+        });
+
+        let identity = IdentityKey::from_seed(&[1u8; 32]).unwrap();
+        let mut connection = SecretConnection::new(kms_socket, &identity).unwrap();
+
+        for expected in 1..=2 {
+            match Request::read(&mut connection, 1024).unwrap() {
+                Request::Ping(PingRequest { nonce }) => assert_eq!(nonce, expected),
+                other => panic!("expected Ping, got {:?}", other),
+            }
+        }
+
+        validator.join().unwrap();
+
+        match Request::read(&mut connection, 1024) {
+            Err(Error::ConnectionClosed { .. }) => (),
+            other => panic!("expected ConnectionClosed, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn rpc_codec_decodes_frames_received_together() {
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&encode(b"first"));
+        src.extend_from_slice(&encode(b"second"));
+
+        let mut codec = RpcCodec::new(1024);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap(), b"first");
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap(), b"second");
+        assert!(codec.decode(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for &value in &[0, 1, 127, 128, 300, 1 << 32, u64::MAX] {
+            let mut bytes = vec![];
+            encode_varint(value, &mut bytes);
+            assert!(bytes.len() <= MAX_VARINT_SIZE);
+
+            let decoded = decode_varint(&mut Cursor::new(bytes)).unwrap();
+            assert_eq!(decoded, Some(value));
+        }
+    }
+}
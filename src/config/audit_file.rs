@@ -0,0 +1,68 @@
+//! Configuration for the append-only log of signing operations on disk
+
+use std::path::PathBuf;
+
+/// Default number of entries which may wait to be written
+pub const DEFAULT_QUEUE_SIZE: usize = 10_000;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct AuditFileConfig {
+    /// File to append entries to (created if it doesn't exist)
+    pub path: PathBuf,
+
+    /// Number of entries written between each fsync. Entries are also
+    /// synced whenever no more are waiting. Defaults to 1 (every entry).
+    #[serde(rename = "sync-every")]
+    pub sync_every: Option<usize>,
+
+    /// Most entries which may wait to be written. Once the queue is full,
+    /// new entries are dropped (and counted) rather than delaying signing.
+    /// Defaults to `DEFAULT_QUEUE_SIZE`.
+    #[serde(rename = "queue-size")]
+    pub queue_size: Option<usize>,
+
+    /// Start a new file once the current one reaches this size (in bytes).
+    /// Absent means files aren't rotated by size.
+    #[serde(rename = "max-size")]
+    pub max_size: Option<u64>,
+
+    /// Start a new file each day (UTC). Defaults to false.
+    #[serde(rename = "rotate-daily")]
+    pub rotate_daily: Option<bool>,
+}
+
+impl AuditFileConfig {
+    /// Report any problems with the audit file configuration
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        if self.path.as_os_str().is_empty() {
+            problems.push("audit-file: path must not be empty".to_owned());
+        }
+
+        if self.sync_every == Some(0) {
+            problems.push("audit-file: sync-every must be nonzero".to_owned());
+        }
+
+        if self.queue_size == Some(0) {
+            problems.push("audit-file: queue-size must be nonzero".to_owned());
+        }
+
+        if self.max_size == Some(0) {
+            problems.push("audit-file: max-size must be nonzero".to_owned());
+        }
+    }
+
+    /// Number of entries written between each fsync
+    pub fn sync_every(&self) -> usize {
+        self.sync_every.unwrap_or(1)
+    }
+
+    /// Most entries which may wait to be written
+    pub fn queue_size(&self) -> usize {
+        self.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE)
+    }
+
+    /// Should a new file be started each day?
+    pub fn rotate_daily(&self) -> bool {
+        self.rotate_daily.unwrap_or(false)
+    }
+}
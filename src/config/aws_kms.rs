@@ -0,0 +1,60 @@
+//! Configuration for the AWS KMS backend
+
+use std::collections::BTreeMap;
+
+use super::SigningPolicy;
+
+#[derive(Default, Deserialize, Debug)]
+pub struct AwsKmsConfig {
+    /// AWS region the keys live in (e.g. `us-east-1`)
+    #[serde(default)]
+    pub region: String,
+
+    /// URL of the KMS API, if not the region's standard endpoint
+    /// (e.g. a VPC endpoint)
+    pub endpoint: Option<String>,
+
+    /// Ed25519 keys held by AWS KMS
+    #[serde(default)]
+    pub keys: BTreeMap<String, AwsKmsKey>,
+}
+
+impl AwsKmsConfig {
+    /// Check a region is given if any keys are, and that no two keys refer
+    /// to the same ARN
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        if self.keys.is_empty() {
+            return;
+        }
+
+        if self.region.is_empty() {
+            problems.push("aws-kms: region is required".to_owned());
+        }
+
+        let mut arns = BTreeMap::new();
+
+        for (key_id, key_config) in &self.keys {
+            if let Some(other_key_id) = arns.insert(&key_config.key_arn, key_id) {
+                problems.push(format!(
+                    "aws-kms keys {} and {} are the same key",
+                    other_key_id, key_id
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AwsKmsKey {
+    /// ARN of the key (e.g. `arn:aws:kms:us-east-1:111122223333:key/...`)
+    #[serde(rename = "key-arn")]
+    pub key_arn: String,
+
+    /// Chains this key is authorized to sign for (any chain if empty)
+    #[serde(default)]
+    pub chains: Vec<String>,
+
+    /// Kinds of consensus message this key may sign
+    #[serde(flatten)]
+    pub policy: SigningPolicy,
+}
@@ -1,16 +1,139 @@
 //! Configuration for the ed25519-dalek backend
 
+use signatory::ed25519::{FromSeed, Signer};
+use signatory::providers::dalek::Ed25519Signer as DalekSigner;
 use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
 use std::path::PathBuf;
 
-#[derive(Deserialize, Debug)]
+use clear_on_drop::ClearOnDrop;
+use config::{declare_public_key, PublicKeys, SigningPolicy};
+use error::Error;
+
+/// Extension of key files loaded from `key-dir`
+const KEY_FILE_EXTENSION: &str = "key";
+
+#[derive(Default, Deserialize, Debug)]
 pub struct DalekConfig {
     /// Ed25519 private key configurations
+    #[serde(default)]
     pub keys: BTreeMap<String, DalekPrivateKey>,
+
+    /// Directory whose `*.key` files are each loaded as a key (optional)
+    #[serde(rename = "key-dir")]
+    pub key_dir: Option<PathBuf>,
+}
+
+impl DalekConfig {
+    /// All configured keys: those listed explicitly, plus one for each
+    /// `*.key` file in `key-dir` (identified by its filename, minus the
+    /// extension). Hidden files are skipped.
+    pub fn all_keys(&self) -> Result<BTreeMap<String, DalekPrivateKey>, Error> {
+        let mut keys = self.keys.clone();
+
+        let key_dir = match self.key_dir {
+            Some(ref key_dir) => key_dir,
+            None => return Ok(keys),
+        };
+
+        let entries = fs::read_dir(key_dir)
+            .map_err(|e| err!(ConfigError, "dalek key-dir: can't read {}: {}", key_dir.display(), e))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            let key_id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) if !stem.starts_with('.') => stem.to_owned(),
+                _ => continue,
+            };
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(KEY_FILE_EXTENSION)
+                || !path.is_file()
+            {
+                continue;
+            }
+
+            ensure!(
+                !keys.contains_key(&key_id),
+                ConfigError,
+                "dalek key {} is configured explicitly and also found in {}",
+                key_id,
+                key_dir.display()
+            );
+
+            keys.insert(
+                key_id,
+                DalekPrivateKey {
+                    path,
+                    chains: vec![],
+                    policy: SigningPolicy::default(),
+                },
+            );
+        }
+
+        Ok(keys)
+    }
+
+    /// Check every key file is readable and holds a valid seed, and that no
+    /// two keys (here or in `public_keys`) are the same
+    pub fn validate(&self, public_keys: &mut PublicKeys, problems: &mut Vec<String>) {
+        let keys = match self.all_keys() {
+            Ok(keys) => keys,
+            Err(e) => {
+                problems.push(e.to_string());
+                return;
+            }
+        };
+
+        for (key_id, key_config) in &keys {
+            let mut seed = ClearOnDrop::new(vec![]);
+
+            if let Err(e) = File::open(&key_config.path)
+                .and_then(|mut file| file.read_to_end(seed.as_mut()))
+            {
+                problems.push(format!(
+                    "dalek key {}: can't read {}: {} (check that the path exists and is readable)",
+                    key_id,
+                    key_config.path.display(),
+                    e
+                ));
+                continue;
+            }
+
+            let public_key = match DalekSigner::from_seed(&seed).and_then(|s| s.public_key()) {
+                Ok(public_key) => public_key,
+                Err(e) => {
+                    problems.push(format!(
+                        "dalek key {}: invalid key in {}: {}",
+                        key_id,
+                        key_config.path.display(),
+                        e
+                    ));
+                    continue;
+                }
+            };
+
+            declare_public_key(
+                public_key.into(),
+                format!("dalek key {}", key_id),
+                public_keys,
+                problems,
+            );
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug)]
 pub struct DalekPrivateKey {
     /// Path to a file containing a cryptographic key
     pub path: PathBuf,
+
+    /// Chains this key is authorized to sign for (any chain if empty)
+    #[serde(default)]
+    pub chains: Vec<String>,
+
+    /// Kinds of consensus message this key may sign
+    #[serde(flatten)]
+    pub policy: SigningPolicy,
 }
@@ -0,0 +1,43 @@
+//! Configuration for the Ledger hardware wallet backend
+
+use std::collections::BTreeMap;
+
+use super::SigningPolicy;
+
+#[derive(Default, Deserialize, Debug)]
+pub struct LedgerConfig {
+    /// Ed25519 keys held by the Ledger
+    #[serde(default)]
+    pub keys: BTreeMap<String, LedgerPrivateKey>,
+}
+
+impl LedgerConfig {
+    /// Check that no two keys use the same derivation path
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        let mut paths = BTreeMap::new();
+
+        for (key_id, key_config) in &self.keys {
+            if let Some(other_key_id) = paths.insert(&key_config.derivation_path, key_id) {
+                problems.push(format!(
+                    "ledger keys {} and {} are the same key",
+                    other_key_id, key_id
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LedgerPrivateKey {
+    /// BIP32 derivation path of the key (e.g. `44'/118'/0'/0'/0'`)
+    #[serde(rename = "derivation-path")]
+    pub derivation_path: String,
+
+    /// Chains this key is authorized to sign for (any chain if empty)
+    #[serde(default)]
+    pub chains: Vec<String>,
+
+    /// Kinds of consensus message this key may sign
+    #[serde(flatten)]
+    pub policy: SigningPolicy,
+}
@@ -0,0 +1,66 @@
+//! Configuration for where log output goes
+
+use std::path::PathBuf;
+
+/// Default number of rotated log files to keep
+pub const DEFAULT_MAX_LOG_FILES: usize = 5;
+
+/// `[logging]` section: where log output goes
+#[derive(Clone, Default, Deserialize, Debug)]
+pub struct LoggingConfig {
+    /// Where to send log output
+    #[serde(default)]
+    pub target: LogTarget,
+
+    /// File to log to (`file` target only)
+    pub path: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this size (in bytes). Absent or
+    /// zero means never rotate.
+    #[serde(rename = "max-size")]
+    pub max_size: Option<u64>,
+
+    /// Number of rotated log files to keep (`path.1` being the newest),
+    /// default 5
+    #[serde(rename = "max-files")]
+    pub max_files: Option<usize>,
+}
+
+impl LoggingConfig {
+    /// Check a path is given for the file target, and that the syslog
+    /// target is available
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        match self.target {
+            LogTarget::File if self.path.is_none() => {
+                problems.push("logging: path is required with the file target".to_owned())
+            }
+            LogTarget::Syslog if !cfg!(unix) => problems
+                .push("logging: the syslog target isn't supported on this platform".to_owned()),
+            _ => (),
+        }
+    }
+
+    /// Number of rotated log files to keep
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_MAX_LOG_FILES)
+    }
+}
+
+/// Destination of log output
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// The terminal: text on stdout/stderr (with color, if supported), or
+    /// JSON on stdout
+    #[default]
+    Terminal,
+
+    /// Standard error, without color
+    Stderr,
+
+    /// A file, optionally rotated by size
+    File,
+
+    /// The local syslog daemon (via `/dev/log`)
+    Syslog,
+}
@@ -0,0 +1,8 @@
+//! Configuration for the Prometheus metrics endpoint
+
+#[derive(Deserialize, Debug)]
+pub struct MetricsConfig {
+    /// Address to serve metrics on (e.g. `127.0.0.1:9100`)
+    #[serde(rename = "listen-addr")]
+    pub listen_addr: String,
+}
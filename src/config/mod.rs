@@ -1,26 +1,86 @@
 //! Configuration file structures (with serde-derived parser)
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+
+mod audit_file;
+
+#[cfg(feature = "aws-kms")]
+mod aws_kms;
 
 #[cfg(feature = "dalek-provider")]
 mod dalek;
 
+#[cfg(feature = "ledger-provider")]
+mod ledger;
+
+mod logging;
+
+#[cfg(feature = "metrics")]
+mod metrics;
+
+mod observer;
+
+mod proxy;
+
+#[cfg(feature = "secp256k1-provider")]
+mod secp256k1;
+
+#[cfg(feature = "webhook")]
+mod webhook;
+
 #[cfg(feature = "yubihsm-provider")]
 mod yubihsm;
 
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::iter;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use toml;
 
+use chain_id::ChainId;
+use ed25519::PublicKey;
 use error::Error;
+use rpc::hex_bytes;
+
+/// Default maximum size of a request from a validator (1 MiB)
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 1_048_576;
+
+/// Default time allowed for connecting to a validator (in seconds)
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+pub use self::audit_file::AuditFileConfig;
+
+#[cfg(feature = "aws-kms")]
+pub use self::aws_kms::AwsKmsConfig;
 
 #[cfg(feature = "dalek-provider")]
 pub use self::dalek::DalekConfig;
 
+#[cfg(feature = "ledger-provider")]
+pub use self::ledger::LedgerConfig;
+
+pub use self::logging::{LogTarget, LoggingConfig};
+
+#[cfg(feature = "metrics")]
+pub use self::metrics::MetricsConfig;
+
+pub use self::observer::ObserverConfig;
+pub use self::proxy::ProxyConfig;
+
+#[cfg(feature = "secp256k1-provider")]
+pub use self::secp256k1::Secp256k1Config;
+
+#[cfg(feature = "webhook")]
+pub use self::webhook::WebhookConfig;
+
 #[cfg(feature = "yubihsm-provider")]
 pub use self::yubihsm::YubihsmConnectorConfig;
 
+/// Commented example configuration, printed by the `generate-config` command
+pub const EXAMPLE_CONFIG: &str = include_str!("../../kms.toml.example");
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
     /// Addresses of validator nodes
@@ -28,6 +88,87 @@ pub struct Config {
 
     /// Cryptographic signature provider configuration
     pub providers: ProviderConfig,
+
+    /// Separate keyrings, by name, for validators which name one with
+    /// `keyring` rather than using the keys from `providers`
+    #[serde(default)]
+    pub keyrings: BTreeMap<String, ProviderConfig>,
+
+    /// Directory where signing state is kept, one file per chain. Shared by
+    /// validators which don't have their own `state-file` (which share state
+    /// in memory if this isn't given).
+    #[serde(rename = "state-dir")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Number of recent signing operations to keep in memory for auditing
+    /// (served from `/audit` on the metrics endpoint). Absent or zero
+    /// disables the audit log.
+    #[serde(rename = "audit-log-size")]
+    pub audit_log_size: Option<usize>,
+
+    /// Append-only log of every signing operation on disk (optional)
+    #[serde(rename = "audit-file")]
+    pub audit_file: Option<AuditFileConfig>,
+
+    /// Most requests which may be signed at once, across all validators.
+    /// Requests beyond the limit wait briefly for a slot, then are rejected.
+    /// Absent or zero means unlimited.
+    #[serde(rename = "max-in-flight-requests")]
+    pub max_in_flight_requests: Option<usize>,
+
+    /// What to do when some keys fail their self-test at startup (`strict`
+    /// or `lenient`)
+    #[serde(default, rename = "self-test-mode")]
+    pub self_test_mode: SelfTestMode,
+
+    /// How often to check each hardware signer is still reachable (in
+    /// seconds), so a lost connection shows up before a block needs signing.
+    /// Absent or zero disables the check.
+    #[serde(rename = "hsm-health-interval-secs")]
+    pub hsm_health_interval_secs: Option<u64>,
+
+    /// Reload the keyring (reconnecting to the hardware signers) when a
+    /// health check fails. Defaults to false.
+    #[serde(rename = "hsm-health-reconnect")]
+    pub hsm_health_reconnect: Option<bool>,
+
+    /// Run even if no keys are configured (e.g. to watch what validators
+    /// request), rather than refusing to start. Defaults to false.
+    #[serde(rename = "observer-mode")]
+    pub observer_mode: Option<bool>,
+
+    /// Format of log output (`text` or `json`)
+    #[serde(default, rename = "log-format")]
+    pub log_format: LogFormat,
+
+    /// Where log output goes (the terminal, unless configured otherwise)
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    /// Prometheus metrics endpoint configuration (optional)
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<MetricsConfig>,
+
+    /// Webhook to send signing events to (optional)
+    #[cfg(feature = "webhook")]
+    pub webhook: Option<WebhookConfig>,
+
+    /// Settings for particular chains
+    #[serde(default)]
+    pub chains: Chains,
+}
+
+/// Settings for each chain which has any, by chain ID
+pub type Chains = BTreeMap<ChainId, ChainConfig>;
+
+/// Settings for signing on behalf of a particular chain
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct ChainConfig {
+    /// Domain separator (in hex) prepended to every message signed for the
+    /// chain, so signatures can't be replayed on another chain. Absent means
+    /// messages are signed as they are.
+    #[serde(default, rename = "sign-prefix", with = "hex_bytes")]
+    pub sign_prefix: Vec<u8>,
 }
 
 impl Config {
@@ -38,26 +179,1032 @@ impl Config {
         let mut data = String::new();
         file.read_to_string(&mut data).unwrap();
 
-        toml::from_str(&data).map_err(|e| err!(ConfigError, "parse error: {}", e))
+        let config: Config =
+            toml::from_str(&data).map_err(|e| err!(ConfigError, "parse error: {}", e))?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the configuration for problems which would otherwise surface
+    /// later (and more confusingly), reporting all of them at once
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = vec![];
+
+        if self.validators.is_empty() {
+            problems.push("no validators configured".to_owned());
+        }
+
+        for (label, validator) in &self.validators {
+            match validator.transport {
+                Transport::Tcp => {
+                    check_addr(
+                        &format!("validator {}", label),
+                        &validator.addr,
+                        validator.port,
+                        &mut problems,
+                    );
+
+                    for addr in validator.fallback_addrs.iter().flatten() {
+                        check_addr(
+                            &format!("validator {}: fallback-addrs", label),
+                            addr,
+                            validator.port,
+                            &mut problems,
+                        );
+                    }
+                }
+                Transport::Unix if !cfg!(unix) => problems.push(format!(
+                    "validator {}: the unix transport isn't supported on this platform",
+                    label
+                )),
+                Transport::Unix if validator.socket_path.is_none() => problems.push(format!(
+                    "validator {}: socket-path is required with the unix transport",
+                    label
+                )),
+                Transport::Unix => (),
+            }
+
+            if validator.transport == Transport::Unix && validator.fallback_addrs.is_some() {
+                problems.push(format!(
+                    "validator {}: fallback-addrs can only be used with the tcp transport",
+                    label
+                ));
+            }
+
+            if validator.transport == Transport::Unix && validator.bind_addr.is_some() {
+                problems.push(format!(
+                    "validator {}: bind-addr can only be used with the tcp transport",
+                    label
+                ));
+            }
+
+            if let Some(ref proxy) = validator.proxy {
+                if validator.transport == Transport::Unix {
+                    problems.push(format!(
+                        "validator {}: proxy can only be used with the tcp transport",
+                        label
+                    ));
+                } else {
+                    proxy.validate(label, &mut problems);
+                }
+            }
+
+            for (name, limits) in &[
+                ("sign-msg-len", validator.sign_msg_len),
+                ("vote-msg-len", validator.vote_msg_len),
+                ("proposal-msg-len", validator.proposal_msg_len),
+            ] {
+                if let (Some(min), Some(max)) = (limits.min, limits.max) {
+                    if min > max {
+                        problems.push(format!(
+                            "validator {}: {} min ({}) is greater than its max ({})",
+                            label, name, min, max
+                        ));
+                    }
+                }
+            }
+
+            if validator.async_session.unwrap_or(false) {
+                if !cfg!(feature = "tokio") {
+                    problems.push(format!(
+                        "validator {}: async-session requires the KMS to be built with the tokio feature",
+                        label
+                    ));
+                }
+
+                if validator.transport != Transport::Tcp {
+                    problems.push(format!(
+                        "validator {}: async-session can only be used with the tcp transport",
+                        label
+                    ));
+                }
+
+                if validator.bind_addr.is_some() {
+                    problems.push(format!(
+                        "validator {}: bind-addr can't be used with async-session",
+                        label
+                    ));
+                }
+
+                if validator.proxy.is_some() {
+                    problems.push(format!(
+                        "validator {}: proxy can't be used with async-session",
+                        label
+                    ));
+                }
+            }
+
+            if validator.reconnect_base_secs == Some(0) {
+                problems.push(format!(
+                    "validator {}: reconnect-base-secs must be at least 1 (0 would reconnect \
+                     in a tight loop)",
+                    label
+                ));
+            }
+
+            if validator.enabled_operations.as_ref().is_some_and(Vec::is_empty) {
+                problems.push(format!(
+                    "validator {}: enabled-operations must not be empty (leave it out to enable \
+                     every operation)",
+                    label
+                ));
+            }
+
+            if validator.wire_format == WireFormat::Amino && validator.idle_timeout().is_some() {
+                problems.push(format!(
+                    "validator {}: idle-timeout-secs can't be used with the amino wire format",
+                    label
+                ));
+            }
+
+            if validator.wire_format == WireFormat::Amino && validator.chain_id.is_none() {
+                problems.push(format!(
+                    "validator {}: chain-id is required with the amino wire format",
+                    label
+                ));
+            }
+
+            for key in validator.authorized_keys.iter().flatten() {
+                if let Err(e) = key.parse::<PublicKey>() {
+                    problems.push(format!("validator {}: authorized-keys: {}", label, e));
+                }
+            }
+
+            for peer_id in validator.peer_ids.iter().flatten() {
+                if peer_id.len() != 40 || !peer_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    problems.push(format!("validator {}: invalid peer ID: {:?}", label, peer_id));
+                }
+            }
+
+            if let Some(ref name) = validator.keyring {
+                if !self.keyrings.contains_key(name) {
+                    problems.push(format!("validator {}: no such keyring: {:?}", label, name));
+                }
+            }
+        }
+
+        // A state file written by two validators would have each overwrite
+        // the other's high-water marks
+        let mut state_files = BTreeMap::new();
+
+        for (label, validator) in &self.validators {
+            if let Some(ref state_file) = validator.state_file {
+                if let Some(other) = state_files.insert(state_file, label) {
+                    problems.push(format!(
+                        "validators {} and {} have the same state-file ({}); give each its own, \
+                         or leave them out to share state-dir",
+                        other,
+                        label,
+                        state_file.display()
+                    ));
+                }
+            }
+        }
+
+        // Validators signing for the same chain with separate state would
+        // each let the other sign the same height/round/step again
+        let mut chain_validators: BTreeMap<&ChainId, Vec<(&String, &ValidatorConfig)>> =
+            BTreeMap::new();
+
+        for (label, validator) in &self.validators {
+            if let Some(ref chain_id) = validator.chain_id {
+                chain_validators.entry(chain_id).or_default().push((label, validator));
+            }
+        }
+
+        for (chain_id, validators) in chain_validators {
+            if validators.len() > 1 && validators.iter().any(|(_, v)| v.state_file.is_some()) {
+                let labels: Vec<&str> = validators.iter().map(|(l, _)| l.as_str()).collect();
+                problems.push(format!(
+                    "validators {} all sign for chain {} but keep separate signing state; \
+                     leave out their state-file so they share state-dir",
+                    labels.join(", "),
+                    chain_id
+                ));
+            }
+        }
+
+        if self.hsm_health_reconnect() && self.hsm_health_interval().is_none() {
+            problems.push("hsm-health-reconnect needs hsm-health-interval-secs".to_owned());
+        }
+
+        self.logging.validate(&mut problems);
+
+        if let Some(ref audit_file) = self.audit_file {
+            audit_file.validate(&mut problems);
+        }
+
+        #[cfg(feature = "webhook")]
+        {
+            if let Some(ref webhook) = self.webhook {
+                webhook.validate(&mut problems);
+            }
+        }
+        self.providers.validate(&mut problems);
+
+        for (name, providers) in &self.keyrings {
+            let mut keyring_problems = vec![];
+            providers.validate(&mut keyring_problems);
+
+            problems.extend(
+                keyring_problems
+                    .into_iter()
+                    .map(|problem| format!("keyring {}: {}", name, problem)),
+            );
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        let description = format!(
+            "invalid configuration:\n{}",
+            problems
+                .iter()
+                .map(|problem| format!("  - {}", problem))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+
+        Err(Error::InvalidConfig {
+            description,
+            problems,
+        })
+    }
+
+    /// How often hardware signers are health-checked, if at all
+    pub fn hsm_health_interval(&self) -> Option<Duration> {
+        timeout_from_secs(self.hsm_health_interval_secs)
+    }
+
+    /// Should the keyring be reloaded when a health check fails?
+    pub fn hsm_health_reconnect(&self) -> bool {
+        self.hsm_health_reconnect.unwrap_or(false)
+    }
+
+    /// May the KMS run with an empty keyring?
+    pub fn observer_mode(&self) -> bool {
+        self.observer_mode.unwrap_or(false)
+    }
+
+    /// Do any validators sign with the keys from `providers` (rather than a
+    /// keyring of their own)?
+    pub fn uses_default_keyring(&self) -> bool {
+        self.validators.values().any(|validator| validator.keyring.is_none())
     }
 }
 
-#[derive(Deserialize, Debug)]
+/// Format of log output
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+/// What to do when some keys fail their self-test
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SelfTestMode {
+    /// Refuse to start (or reload) if any key fails
+    #[default]
+    Strict,
+
+    /// Leave out the keys which fail, as long as at least one key which can
+    /// sign passes
+    Lenient,
+}
+
+#[derive(Clone, Deserialize, Debug)]
 pub struct ValidatorConfig {
-    /// Validator hostname or IP address
+    /// How to connect to the validator (`tcp` or `unix`)
+    #[serde(default)]
+    pub transport: Transport,
+
+    /// Validator hostname or IP address (TCP only). IPv6 addresses may be
+    /// given with or without brackets (e.g. `[::1]` or `::1`).
+    #[serde(default)]
     pub addr: String,
 
-    /// Validator port
+    /// Validator port (TCP only)
+    #[serde(default)]
     pub port: u16,
+
+    /// Other addresses the validator can be reached at (on the same port),
+    /// tried in turn after `addr` when connecting fails (TCP only)
+    #[serde(rename = "fallback-addrs")]
+    pub fallback_addrs: Option<Vec<String>>,
+
+    /// Path to the validator's Unix domain socket (Unix only)
+    #[serde(rename = "socket-path")]
+    pub socket_path: Option<PathBuf>,
+
+    /// File where the last signed height/round/step is persisted, used to
+    /// prevent double signing across restarts
+    #[serde(rename = "state-file")]
+    pub state_file: Option<PathBuf>,
+
+    /// Initial delay before reconnecting after a failure (in seconds, at
+    /// least 1). Also accepted as `respawn-delay-secs`.
+    #[serde(rename = "reconnect-base-secs", alias = "respawn-delay-secs")]
+    pub reconnect_base_secs: Option<u64>,
+
+    /// Maximum delay between reconnect attempts (in seconds)
+    #[serde(rename = "reconnect-max-secs")]
+    pub reconnect_max_secs: Option<u64>,
+
+    /// Stop the client after this many consecutive failed sessions (e.g. so
+    /// an orchestrator can restart the KMS). Absent or zero means retry
+    /// forever.
+    #[serde(rename = "max-reconnect-attempts")]
+    pub max_reconnect_attempts: Option<u32>,
+
+    /// Give up connecting to the validator after this long (in seconds), so
+    /// an unreachable validator doesn't hold up reconnecting for as long as
+    /// the OS would wait. Defaults to 10; zero means leave it to the OS.
+    #[serde(rename = "connect-timeout-secs")]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// Give up on the connection if nothing is received for this long (in
+    /// seconds). Absent or zero means no timeout.
+    #[serde(rename = "read-timeout-secs")]
+    pub read_timeout_secs: Option<u64>,
+
+    /// Give up on the connection if a write blocks for this long (in
+    /// seconds). Absent or zero means no timeout.
+    #[serde(rename = "write-timeout-secs")]
+    pub write_timeout_secs: Option<u64>,
+
+    /// Ping the validator if nothing is received for this long (in seconds),
+    /// reconnecting if it doesn't answer promptly. Legacy wire format only.
+    /// Absent or zero means never ping.
+    #[serde(rename = "idle-timeout-secs")]
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Respawn the client if it exits without being asked to. Defaults to
+    /// false. (A validator closing the connection is reconnected to either
+    /// way.)
+    #[serde(rename = "respawn-on-exit")]
+    pub respawn_on_exit: Option<bool>,
+
+    /// Largest request the validator may send (in bytes). Defaults to 1 MiB.
+    #[serde(rename = "max-request-bytes")]
+    pub max_request_bytes: Option<usize>,
+
+    /// Encoding of requests and responses (`legacy` or `amino`)
+    #[serde(default, rename = "wire-format")]
+    pub wire_format: WireFormat,
+
+    /// Chain the validator signs for. Required with the `amino` wire format,
+    /// whose sign requests don't carry a chain ID.
+    #[serde(rename = "chain-id")]
+    pub chain_id: Option<ChainId>,
+
+    /// Name of the keyring (from `keyrings`) this validator signs with,
+    /// kept entirely apart from other validators' keys. Absent means the
+    /// keys from `providers`, shared with other validators which don't name
+    /// a keyring.
+    pub keyring: Option<String>,
+
+    /// Public keys (in hex, base64, or bech32) this validator may request
+    /// signatures from. Absent means any key in the keyring.
+    #[serde(rename = "authorized-keys")]
+    pub authorized_keys: Option<Vec<String>>,
+
+    /// Log a warning whenever signing takes longer than this (in
+    /// milliseconds). Absent or zero means never warn.
+    #[serde(rename = "slow-sign-warn-ms")]
+    pub slow_sign_warn_ms: Option<u64>,
+
+    /// Send TCP keepalive probes once the connection has been idle this long
+    /// (in seconds). Absent or zero disables keepalives.
+    #[serde(rename = "keepalive-secs")]
+    pub keepalive_secs: Option<u64>,
+
+    /// Check each signature against the signer's public key before sending
+    /// it to the validator, to catch faulty signing hardware. Defaults to
+    /// true.
+    #[serde(rename = "verify-signatures")]
+    pub verify_signatures: Option<bool>,
+
+    /// Most sign requests per second this validator may make, so a
+    /// misbehaving validator can't hammer the signing hardware. A request
+    /// signed by several keys counts once. Absent or zero means unlimited.
+    #[serde(rename = "max-sign-rate")]
+    pub max_sign_rate: Option<u32>,
+
+    /// Accept `Sign` requests for arbitrary messages, which could be crafted
+    /// to look like a vote. With this off, only votes and proposals (whose
+    /// sign bytes the KMS builds itself) are signed. Defaults to true.
+    #[serde(rename = "allow-raw-sign")]
+    pub allow_raw_sign: Option<bool>,
+
+    /// Only accept `Sign` requests whose message is the sign bytes of a vote
+    /// or proposal, exactly as the KMS would build them, so the request type
+    /// can't be used to sign arbitrary data. Such messages are checked and
+    /// recorded against the chain's signing state like any other vote or
+    /// proposal. Defaults to false.
+    #[serde(rename = "require-known-message")]
+    pub require_known_message: Option<bool>,
+
+    /// Kinds of request the validator may make, so its access to the KMS
+    /// can be kept to what it needs (e.g. only votes and proposals). Hello
+    /// messages are always allowed. Absent means all of them. Amino pubkey
+    /// and ping responses can't carry an error, so with that wire format a
+    /// disabled one ends the session instead.
+    #[serde(rename = "enabled-operations")]
+    pub enabled_operations: Option<Vec<Operation>>,
+
+    /// Bounds on the length of arbitrary messages sent to be signed
+    #[serde(default, rename = "sign-msg-len")]
+    pub sign_msg_len: MsgLenLimits,
+
+    /// Bounds on the length of votes' sign bytes
+    #[serde(default, rename = "vote-msg-len")]
+    pub vote_msg_len: MsgLenLimits,
+
+    /// Bounds on the length of proposals' sign bytes
+    #[serde(default, rename = "proposal-msg-len")]
+    pub proposal_msg_len: MsgLenLimits,
+
+    /// Local IP address to connect from, e.g. so connections on a
+    /// multi-homed host leave through a particular interface (TCP only)
+    #[serde(rename = "bind-addr")]
+    pub bind_addr: Option<IpAddr>,
+
+    /// SOCKS5 proxy to reach the validator through (e.g. a bastion host in
+    /// front of a private network), which resolves the validator's address
+    /// itself. TCP only.
+    pub proxy: Option<ProxyConfig>,
+
+    /// Stack size of the client thread (in bytes), for signing providers
+    /// whose SDKs need more than the default
+    #[serde(rename = "thread-stack-size")]
+    pub thread_stack_size: Option<usize>,
+
+    /// Run the session as a task on a runtime shared with other validators,
+    /// rather than on a thread of its own, so many validators don't need as
+    /// many threads. Requires the `tokio` cargo feature, the tcp transport,
+    /// and no bind-addr or proxy. Defaults to false.
+    #[serde(rename = "async-session")]
+    pub async_session: Option<bool>,
+
+    /// File holding the Ed25519 key (a raw 32-byte seed, as for dalek keys)
+    /// the KMS proves its identity to the validator with. Absent means a new
+    /// key is made up for each connection, which is enough for validators
+    /// which don't check the KMS's identity.
+    #[serde(rename = "secret-key")]
+    pub secret_key: Option<PathBuf>,
+
+    /// Peer IDs (hex-encoded truncated SHA-256 hashes of identity keys, as
+    /// for Tendermint node IDs) the validator may connect as. The validator
+    /// always has to prove it holds the key it presents; absent means any
+    /// key is accepted.
+    #[serde(rename = "peer-ids")]
+    pub peer_ids: Option<Vec<String>>,
+}
+
+/// Which kinds of consensus message a key may sign, as a safety policy
+/// (e.g. a key which only ever votes). Both are allowed by default.
+#[derive(Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+pub struct SigningPolicy {
+    /// May the key sign prevotes and precommits?
+    #[serde(default = "default_true", rename = "allow-vote")]
+    pub allow_vote: bool,
+
+    /// May the key sign block proposals?
+    #[serde(default = "default_true", rename = "allow-proposal")]
+    pub allow_proposal: bool,
+}
+
+impl Default for SigningPolicy {
+    fn default() -> Self {
+        Self {
+            allow_vote: true,
+            allow_proposal: true,
+        }
+    }
+}
+
+/// Shortest and longest messages (in bytes) which may be signed for a
+/// particular kind of request. Either bound may be left out.
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+pub struct MsgLenLimits {
+    /// Shortest message allowed
+    pub min: Option<usize>,
+
+    /// Longest message allowed
+    pub max: Option<usize>,
+}
+
+/// Default for settings which are on unless turned off
+fn default_true() -> bool {
+    true
+}
+
+/// Kind of socket to connect to a validator over
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// TCP to `addr` and `port`
+    #[default]
+    Tcp,
+
+    /// Unix domain socket at `socket-path`
+    Unix,
+}
+
+/// Kinds of request a validator can make, as listed in `enabled-operations`
+#[derive(Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// Sign an arbitrary message (`Sign`)
+    Sign,
+
+    /// Sign a prevote or precommit (`SignVote`)
+    SignVote,
+
+    /// Sign a block proposal (`SignProposal`)
+    SignProposal,
+
+    /// Obtain the public key (`ShowPublicKey`, or Amino's `PubKey`)
+    #[serde(rename = "pubkey")]
+    PubKey,
+
+    /// Keepalive (`Ping`)
+    Ping,
+}
+
+impl Operation {
+    /// Name of this operation, as written in the config
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Sign => "sign",
+            Operation::SignVote => "sign-vote",
+            Operation::SignProposal => "sign-proposal",
+            Operation::PubKey => "pubkey",
+            Operation::Ping => "ping",
+        }
+    }
+}
+
+/// Encoding of requests and responses on a validator connection
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// JSON messages specific to this KMS
+    #[default]
+    Legacy,
+
+    /// Tendermint's Amino-encoded privval messages
+    Amino,
+}
+
+impl ValidatorConfig {
+    /// Hostname or IP address to connect to, without any brackets around an
+    /// IPv6 address
+    pub fn host(&self) -> &str {
+        strip_brackets(&self.addr)
+    }
+
+    /// Every address the validator can be reached at, `addr` first and then
+    /// any `fallback-addrs`, without brackets around IPv6 addresses
+    pub fn hosts(&self) -> Vec<&str> {
+        iter::once(&self.addr)
+            .chain(self.fallback_addrs.iter().flatten())
+            .map(|addr| strip_brackets(addr))
+            .collect()
+    }
+
+    /// The given host and the validator's port in `host:port` form,
+    /// bracketing IPv6 addresses (e.g. `[::1]:26658`)
+    pub fn host_port(&self, host: &str) -> String {
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, self.port),
+            _ => format!("{}:{}", host, self.port),
+        }
+    }
+
+    /// Where the validator is, for log messages: its address for TCP, or
+    /// its socket path for Unix domain sockets
+    pub fn endpoint(&self) -> String {
+        match self.transport {
+            Transport::Tcp => self.host().to_owned(),
+            Transport::Unix => self.socket_path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Who a session with the validator at the given one of its endpoints is
+    /// talking to, for log messages: `host:port` for TCP, or the socket path
+    /// for Unix domain sockets
+    pub fn peer_addr(&self, endpoint: &str) -> String {
+        match self.transport {
+            Transport::Tcp => self.host_port(endpoint),
+            Transport::Unix => endpoint.to_owned(),
+        }
+    }
+
+    /// Every place the validator can be reached, in the order to try them:
+    /// its addresses for TCP, or just its socket path for Unix domain sockets
+    pub fn endpoints(&self) -> Vec<String> {
+        match self.transport {
+            Transport::Tcp => self.hosts().into_iter().map(str::to_owned).collect(),
+            Transport::Unix => vec![self.endpoint()],
+        }
+    }
+
+    /// How long to wait for a TCP connection to be established, if limited
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        timeout_from_secs(Some(
+            self.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ))
+    }
+
+    /// Configured read timeout, if any
+    pub fn read_timeout(&self) -> Option<Duration> {
+        timeout_from_secs(self.read_timeout_secs)
+    }
+
+    /// How long the connection may be idle before pinging the validator, if
+    /// configured
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        timeout_from_secs(self.idle_timeout_secs)
+    }
+
+    /// Configured write timeout, if any
+    pub fn write_timeout(&self) -> Option<Duration> {
+        timeout_from_secs(self.write_timeout_secs)
+    }
+
+    /// Largest request the validator may send (in bytes)
+    pub fn max_request_bytes(&self) -> usize {
+        self.max_request_bytes.unwrap_or(DEFAULT_MAX_REQUEST_BYTES)
+    }
+
+    /// Idle time before sending TCP keepalive probes, if enabled
+    pub fn keepalive(&self) -> Option<Duration> {
+        timeout_from_secs(self.keepalive_secs)
+    }
+
+    /// Signing duration beyond which to log a warning, if any
+    pub fn slow_sign_warn(&self) -> Option<Duration> {
+        self.slow_sign_warn_ms
+            .and_then(|ms| if ms > 0 { Some(Duration::from_millis(ms)) } else { None })
+    }
+
+    /// Most sign requests per second this validator may make, if limited
+    pub fn max_sign_rate(&self) -> Option<u32> {
+        self.max_sign_rate.filter(|rate| *rate > 0)
+    }
+
+    /// Parse the keys this validator may sign with, if restricted
+    pub fn authorized_keys(&self) -> Result<Option<Vec<PublicKey>>, Error> {
+        match self.authorized_keys {
+            Some(ref keys) => keys.iter()
+                .map(|key| key.parse())
+                .collect::<Result<Vec<_>, _>>()
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Peer IDs the validator may connect as (in lowercase), if restricted
+    pub fn peer_ids(&self) -> Option<Vec<String>> {
+        self.peer_ids
+            .as_ref()
+            .map(|ids| ids.iter().map(|id| id.to_lowercase()).collect())
+    }
+}
+
+/// Check that an address is an IP address or well-formed hostname, and
+/// that the port is nonzero
+pub(crate) fn check_addr(name: &str, addr: &str, port: u16, problems: &mut Vec<String>) {
+    let host = strip_brackets(addr);
+    let bracketed = host.len() != addr.len();
+
+    let valid = match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_ipv6() || !bracketed,
+        Err(_) => !bracketed && is_valid_hostname(host),
+    };
+
+    if !valid {
+        problems.push(format!("{}: invalid address: {:?}", name, addr));
+    }
+
+    if port == 0 {
+        problems.push(format!("{}: port must be nonzero", name));
+    }
+}
+
+/// Public keys declared by the providers checked so far, with the key each
+/// belongs to (e.g. `dalek key foo`)
+pub(crate) type PublicKeys = HashMap<PublicKey, String>;
+
+/// Note that `key` has the given public key, adding a problem if another
+/// key already has it (the keyring would otherwise refuse it with
+/// `DuplicateKey` at startup)
+pub(crate) fn declare_public_key(
+    public_key: PublicKey,
+    key: String,
+    public_keys: &mut PublicKeys,
+    problems: &mut Vec<String>,
+) {
+    match public_keys.get(&public_key) {
+        Some(other_key) => problems.push(format!(
+            "{} and {} are the same key ({})",
+            other_key, key, public_key
+        )),
+        None => {
+            public_keys.insert(public_key, key);
+        }
+    }
+}
+
+/// Remove the brackets from a bracketed (IPv6) address, e.g. `[::1]`
+fn strip_brackets(addr: &str) -> &str {
+    addr.strip_prefix('[')
+        .and_then(|addr| addr.strip_suffix(']'))
+        .unwrap_or(addr)
+}
+
+/// Is this a syntactically valid DNS hostname?
+fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty() && hostname.len() <= 253 && hostname.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63 && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// Convert an optional number of seconds into a timeout, treating zero as none
+fn timeout_from_secs(secs: Option<u64>) -> Option<Duration> {
+    secs.and_then(|s| if s > 0 { Some(Duration::from_secs(s)) } else { None })
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ProviderConfig {
+    /// AWS KMS configuration
+    #[cfg(feature = "aws-kms")]
+    #[serde(default, rename = "aws-kms")]
+    pub aws_kms: AwsKmsConfig,
+
     /// ed25519-dalek configuration
     #[cfg(feature = "dalek-provider")]
+    #[serde(default)]
     pub dalek: DalekConfig,
 
+    /// Ledger hardware wallet configuration
+    #[cfg(feature = "ledger-provider")]
+    #[serde(default)]
+    pub ledger: LedgerConfig,
+
+    /// Public keys which can only be used to verify signatures
+    #[serde(default)]
+    pub observer: ObserverConfig,
+
+    /// secp256k1 software key configuration
+    #[cfg(feature = "secp256k1-provider")]
+    #[serde(default)]
+    pub secp256k1: Secp256k1Config,
+
     /// Map of yubihsm-connector labels to their configurations
     #[cfg(feature = "yubihsm-provider")]
+    #[serde(default)]
     pub yubihsm: BTreeMap<String, YubihsmConnectorConfig>,
 }
+
+impl ProviderConfig {
+    /// Check each provider's configuration, adding any problems found.
+    /// Providers whose public keys are known without a device (software and
+    /// observer keys) must also not declare the same key as one another.
+    #[allow(unused_variables, unused_mut)]
+    fn validate(&self, problems: &mut Vec<String>) {
+        let mut public_keys = PublicKeys::new();
+
+        #[cfg(feature = "aws-kms")]
+        self.aws_kms.validate(problems);
+
+        #[cfg(feature = "dalek-provider")]
+        self.dalek.validate(&mut public_keys, problems);
+
+        #[cfg(feature = "ledger-provider")]
+        self.ledger.validate(problems);
+
+        self.observer.validate(&mut public_keys, problems);
+
+        #[cfg(feature = "secp256k1-provider")]
+        self.secp256k1.validate(&mut public_keys, problems);
+
+        #[cfg(feature = "yubihsm-provider")]
+        yubihsm::validate(&self.yubihsm, problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv6Addr, SocketAddr};
+
+    /// Test key, in hex and base64
+    const PUBLIC_KEY_HEX: &str = "2d65efaf84f360a32ef19ca7a4196bd37c6c63f8cbf0f2bf0201e73f05a5acc3";
+    const PUBLIC_KEY_BASE64: &str = "LWXvr4TzYKMu8ZynpBlr03xsY/jL8PK/AgHnPwWlrMM=";
+
+    /// Problems found in the given `[providers]` configuration
+    fn provider_problems(toml: &str) -> Vec<String> {
+        let providers: ProviderConfig = toml::from_str(toml).unwrap();
+        let mut problems = vec![];
+        providers.validate(&mut problems);
+        problems
+    }
+
+    #[test]
+    fn same_public_key_declared_twice_is_a_problem() {
+        let problems = provider_problems(&format!(
+            "[observer.keys.hex]\npublic-key = \"{}\"\n\
+             [observer.keys.base64]\npublic-key = \"{}\"\n",
+            PUBLIC_KEY_HEX, PUBLIC_KEY_BASE64
+        ));
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("observer key base64 and observer key hex are the same key"));
+    }
+
+    #[test]
+    fn unreadable_key_file_problem_says_what_to_check() {
+        let problems = provider_problems("[dalek.keys.missing]\npath = \"/nonexistent/kms.key\"\n");
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("dalek key missing: can't read /nonexistent/kms.key: "));
+        assert!(problems[0].ends_with(" (check that the path exists and is readable)"));
+    }
+
+    /// Problems found in a config with one validator at the given address
+    fn addr_problems(addr: &str) -> Vec<String> {
+        let config: Config = toml::from_str(&format!(
+            "[validators.example]\naddr = {:?}\nport = 26658\n[providers]\n",
+            addr
+        )).unwrap();
+
+        match config.validate() {
+            Ok(()) => vec![],
+            Err(Error::InvalidConfig { problems, .. }) => problems,
+            Err(e) => panic!("expected InvalidConfig, got {}", e),
+        }
+    }
+
+    #[test]
+    fn validators_sharing_a_state_file_are_a_problem() {
+        let config: Config = toml::from_str(
+            "[validators.a]\naddr = \"127.0.0.1\"\nport = 26658\nstate-file = \"state.json\"\n\
+             [validators.b]\naddr = \"127.0.0.1\"\nport = 26659\nstate-file = \"state.json\"\n\
+             [validators.c]\naddr = \"127.0.0.1\"\nport = 26660\nstate-file = \"other.json\"\n\
+             [providers]\n",
+        ).unwrap();
+
+        match config.validate() {
+            Err(Error::InvalidConfig { problems, .. }) => assert_eq!(
+                problems,
+                vec!["validators a and b have the same state-file (state.json); give each its \
+                      own, or leave them out to share state-dir"
+                    .to_owned()]
+            ),
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validators_for_one_chain_with_separate_state_are_a_problem() {
+        let config: Config = toml::from_str(
+            "[validators.a]\naddr = \"127.0.0.1\"\nport = 26658\nchain-id = \"test-chain\"\n\
+             state-file = \"a.json\"\n\
+             [validators.b]\naddr = \"127.0.0.1\"\nport = 26659\nchain-id = \"test-chain\"\n\
+             [providers]\n",
+        ).unwrap();
+
+        match config.validate() {
+            Err(Error::InvalidConfig { problems, .. }) => assert_eq!(
+                problems,
+                vec!["validators a, b all sign for chain test-chain but keep separate signing \
+                      state; leave out their state-file so they share state-dir"
+                    .to_owned()]
+            ),
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn zero_reconnect_base_is_a_problem() {
+        let config: Config = toml::from_str(
+            "[validators.example]\naddr = \"127.0.0.1\"\nport = 26658\n\
+             reconnect-base-secs = 0\n[providers]\n",
+        ).unwrap();
+
+        match config.validate() {
+            Err(Error::InvalidConfig { problems, .. }) => assert_eq!(
+                problems,
+                vec!["validator example: reconnect-base-secs must be at least 1 (0 would \
+                      reconnect in a tight loop)"
+                    .to_owned()]
+            ),
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ipv6_addresses_with_or_without_brackets() {
+        for &addr in &["::1", "[::1]"] {
+            assert!(addr_problems(addr).is_empty(), "{} should be valid", addr);
+
+            let config: ValidatorConfig =
+                toml::from_str(&format!("addr = {:?}\nport = 26658\n", addr)).unwrap();
+            assert_eq!(config.host(), "::1");
+            assert_eq!(config.host_port(config.host()), "[::1]:26658");
+            assert_eq!(config.peer_addr(config.host()), "[::1]:26658");
+
+            // What the session connects to
+            let socket_addr: SocketAddr = config.host_port(config.host()).parse().unwrap();
+            assert_eq!(socket_addr, SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 26658));
+        }
+
+        let config: ValidatorConfig =
+            toml::from_str("addr = \"10.0.0.1\"\nport = 26658\n").unwrap();
+        assert_eq!(config.host_port(config.host()), "10.0.0.1:26658");
+    }
+
+    #[test]
+    fn malformed_bracketed_addresses_are_problems() {
+        for &addr in &["[127.0.0.1]", "[::1", "::1]", "[validator.example.com]"] {
+            assert_eq!(
+                addr_problems(addr),
+                vec![format!("validator example: invalid address: {:?}", addr)]
+            );
+        }
+    }
+
+    #[test]
+    fn authorized_keys_may_be_given_in_any_encoding() {
+        let config: Config = toml::from_str(&format!(
+            "[validators.example]\naddr = \"127.0.0.1\"\nport = 26658\n\
+             authorized-keys = [\"{}\", \"{}\", \"{}\", \"{}\"]\n\
+             [providers]\n",
+            PUBLIC_KEY_HEX,
+            PUBLIC_KEY_BASE64,
+            "cosmosvalconspub1zcjduepq94j7ltuy7ds2xth3njn6gxtt6d7xcclce0c090czq8nn7pd94npspnykkc",
+            // The same, with its checksum's last character changed
+            "cosmosvalconspub1zcjduepq94j7ltuy7ds2xth3njn6gxtt6d7xcclce0c090czq8nn7pd94npspnykkd"
+        )).unwrap();
+
+        match config.validate() {
+            Err(Error::InvalidConfig { problems, .. }) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].starts_with("validator example: authorized-keys: "));
+                assert!(problems[0].contains("invalid bech32 checksum"));
+            }
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "dalek-provider")]
+    #[test]
+    fn same_public_key_under_different_providers_is_a_problem() {
+        use signatory::ed25519::{FromSeed, Signer};
+        use signatory::providers::dalek::Ed25519Signer;
+        use std::{env, fs};
+
+        let seed = [7u8; 32];
+        let public_key: PublicKey = Ed25519Signer::from_seed(&seed[..])
+            .and_then(|signer| signer.public_key())
+            .unwrap()
+            .into();
+
+        let path = env::temp_dir().join(format!("kms-config-test-{}.key", std::process::id()));
+        fs::write(&path, &seed[..]).unwrap();
+
+        let problems = provider_problems(&format!(
+            "[dalek.keys.software]\npath = {:?}\n\
+             [observer.keys.watched]\npublic-key = \"{}\"\n",
+            path.display().to_string(),
+            public_key.as_bytes().iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        ));
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            problems,
+            vec![format!(
+                "dalek key software and observer key watched are the same key ({})",
+                public_key
+            )]
+        );
+    }
+}
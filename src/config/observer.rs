@@ -0,0 +1,43 @@
+//! Configuration for observer keys: public keys without any private key,
+//! for running the KMS in a role where it only verifies signatures
+
+use std::collections::BTreeMap;
+
+use super::{declare_public_key, PublicKeys};
+use ed25519::PublicKey;
+
+#[derive(Default, Deserialize, Debug)]
+pub struct ObserverConfig {
+    /// Observer key configurations
+    #[serde(default)]
+    pub keys: BTreeMap<String, ObserverKey>,
+}
+
+impl ObserverConfig {
+    /// Check every public key can be parsed, and that none is already
+    /// among `public_keys`
+    pub fn validate(&self, public_keys: &mut PublicKeys, problems: &mut Vec<String>) {
+        for (key_id, key_config) in &self.keys {
+            match key_config.public_key.parse::<PublicKey>() {
+                Ok(public_key) => declare_public_key(
+                    public_key,
+                    format!("observer key {}", key_id),
+                    public_keys,
+                    problems,
+                ),
+                Err(e) => problems.push(format!("observer key {}: {}", key_id, e)),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ObserverKey {
+    /// Public key, in hex, base64, or bech32
+    #[serde(rename = "public-key")]
+    pub public_key: String,
+
+    /// Chains this key is used for (any chain if empty)
+    #[serde(default)]
+    pub chains: Vec<String>,
+}
@@ -0,0 +1,79 @@
+//! Configuration for reaching a validator through a SOCKS5 proxy
+
+use std::net::IpAddr;
+
+use super::{check_addr, strip_brackets};
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ProxyConfig {
+    /// Address of the proxy (IP or hostname)
+    pub addr: String,
+
+    /// Port the proxy listens on
+    pub port: u16,
+
+    /// Username to authenticate to the proxy with. Absent means connect
+    /// without authenticating.
+    pub username: Option<String>,
+
+    /// Password to authenticate to the proxy with (required with `username`)
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Report any problems with the given validator's proxy configuration
+    pub fn validate(&self, label: &str, problems: &mut Vec<String>) {
+        check_addr(
+            &format!("validator {}: proxy", label),
+            &self.addr,
+            self.port,
+            problems,
+        );
+
+        match (&self.username, &self.password) {
+            (Some(_), None) | (None, Some(_)) => problems.push(format!(
+                "validator {}: proxy username and password must be given together",
+                label
+            )),
+            (Some(username), Some(password)) => {
+                // RFC 1929 gives each a single length byte
+                if username.is_empty() || username.len() > 255 {
+                    problems.push(format!(
+                        "validator {}: proxy username must be 1-255 bytes",
+                        label
+                    ));
+                }
+
+                if password.is_empty() || password.len() > 255 {
+                    problems.push(format!(
+                        "validator {}: proxy password must be 1-255 bytes",
+                        label
+                    ));
+                }
+            }
+            (None, None) => (),
+        }
+    }
+
+    /// Username and password to authenticate with, if any
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            _ => None,
+        }
+    }
+
+    /// Hostname or IP address of the proxy, without any brackets around an
+    /// IPv6 address
+    pub fn host(&self) -> &str {
+        strip_brackets(&self.addr)
+    }
+
+    /// The proxy's `host:port`, bracketing IPv6 addresses
+    pub fn host_port(&self) -> String {
+        match self.host().parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, self.port),
+            _ => format!("{}:{}", self.host(), self.port),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Configuration for the secp256k1 software backend
+
+use signatory::ecdsa::Signer;
+use signatory::providers::secp256k1::ECDSASigner;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use clear_on_drop::ClearOnDrop;
+use config::{declare_public_key, PublicKeys, SigningPolicy};
+
+#[derive(Default, Deserialize, Debug)]
+pub struct Secp256k1Config {
+    /// secp256k1 private key configurations
+    #[serde(default)]
+    pub keys: BTreeMap<String, Secp256k1PrivateKey>,
+}
+
+impl Secp256k1Config {
+    /// Check every key file is readable and holds a valid secret key, and
+    /// that no two keys (here or in `public_keys`) are the same
+    pub fn validate(&self, public_keys: &mut PublicKeys, problems: &mut Vec<String>) {
+        for (key_id, key_config) in &self.keys {
+            let mut secret_key = ClearOnDrop::new(vec![]);
+
+            if let Err(e) = File::open(&key_config.path)
+                .and_then(|mut file| file.read_to_end(secret_key.as_mut()))
+            {
+                problems.push(format!(
+                    "secp256k1 key {}: can't read {}: {} (check that the path exists and is \
+                     readable)",
+                    key_id,
+                    key_config.path.display(),
+                    e
+                ));
+                continue;
+            }
+
+            let public_key =
+                match ECDSASigner::from_bytes(&secret_key).and_then(|s| s.public_key()) {
+                    Ok(public_key) => public_key,
+                    Err(e) => {
+                        problems.push(format!(
+                            "secp256k1 key {}: invalid key in {}: {}",
+                            key_id,
+                            key_config.path.display(),
+                            e
+                        ));
+                        continue;
+                    }
+                };
+
+            declare_public_key(
+                public_key.into(),
+                format!("secp256k1 key {}", key_id),
+                public_keys,
+                problems,
+            );
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct Secp256k1PrivateKey {
+    /// Path to a file containing the 32-byte secret key
+    pub path: PathBuf,
+
+    /// Chains this key is authorized to sign for (any chain if empty)
+    #[serde(default)]
+    pub chains: Vec<String>,
+
+    /// Kinds of consensus message this key may sign
+    #[serde(flatten)]
+    pub policy: SigningPolicy,
+}
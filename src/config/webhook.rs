@@ -0,0 +1,48 @@
+//! Configuration for the webhook signing events are sent to
+
+use std::time::Duration;
+
+/// Default number of events which may wait to be sent
+pub const DEFAULT_QUEUE_SIZE: usize = 1000;
+
+/// Default time allowed for sending each event (in seconds)
+pub const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct WebhookConfig {
+    /// URL to POST events to
+    pub url: String,
+
+    /// Most events which may wait to be sent. Once the queue is full, new
+    /// events are dropped. Defaults to `DEFAULT_QUEUE_SIZE`.
+    #[serde(rename = "queue-size")]
+    pub queue_size: Option<usize>,
+
+    /// Time allowed for sending each event (in seconds). Defaults to
+    /// `DEFAULT_TIMEOUT_SECS`.
+    #[serde(rename = "timeout-secs")]
+    pub timeout_secs: Option<u64>,
+}
+
+impl WebhookConfig {
+    /// Report any problems with the webhook configuration
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            problems.push(format!("webhook: url must be http(s): {:?}", self.url));
+        }
+
+        if self.queue_size == Some(0) {
+            problems.push("webhook: queue-size must be nonzero".to_owned());
+        }
+    }
+
+    /// Most events which may wait to be sent
+    pub fn queue_size(&self) -> usize {
+        self.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE)
+    }
+
+    /// Time allowed for sending each event
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS))
+    }
+}
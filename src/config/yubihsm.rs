@@ -1,6 +1,10 @@
 //! Configuration for the `YubiHSM` backend
 
 use std::collections::BTreeMap;
+use std::env;
+
+use super::{check_addr, SigningPolicy};
+use error::Error;
 
 /// Configuration for a particular yubihsm-connector process
 #[derive(Deserialize, Debug)]
@@ -8,21 +12,168 @@ pub struct YubihsmConnectorConfig {
     /// Address of yubihsm-connector (IP or hostname)
     pub addr: String,
 
+    /// Port yubihsm-connector is listening on
+    pub port: u16,
+
     /// Authentication key ID to use to authenticate to the YubiHSM
     #[serde(rename = "auth-key-id")]
     pub auth_key_id: u16,
 
     /// Password to use to authenticate to the YubiHSM
     // TODO: allow password to be read from an external password-file
-    pub password: String,
+    pub password: Option<String>,
+
+    /// Environment variable to read the password from instead, so it needn't
+    /// be kept in the config file
+    #[serde(rename = "password-env")]
+    pub password_env: Option<String>,
 
     /// Map of labels to private key configurations
     pub keys: BTreeMap<String, YubihsmPrivateKey>,
 }
 
+impl YubihsmConnectorConfig {
+    /// Password to authenticate with: `password` if given, otherwise the
+    /// value of the environment variable named by `password-env`
+    pub fn password(&self) -> Result<String, Error> {
+        if let Some(ref password) = self.password {
+            return Ok(password.clone());
+        }
+
+        let name = self.password_env
+            .as_ref()
+            .ok_or_else(|| err!(ConfigError, "yubihsm: no password or password-env given"))?;
+
+        env::var(name).map_err(|_| err!(ConfigError, "yubihsm: {} is not set", name))
+    }
+}
+
+/// Check each connector's address, that it's given exactly one of
+/// `password` and `password-env` (and that the latter is set), and that no
+/// two keys refer to the same key ID on the same HSM
+pub fn validate(connectors: &BTreeMap<String, YubihsmConnectorConfig>, problems: &mut Vec<String>) {
+    let mut hsm_keys = BTreeMap::new();
+
+    for (connector_label, connector_config) in connectors {
+        check_addr(
+            &format!("yubihsm connector {}", connector_label),
+            &connector_config.addr,
+            connector_config.port,
+            problems,
+        );
+
+        match (&connector_config.password, &connector_config.password_env) {
+            (Some(_), Some(_)) => problems.push(format!(
+                "yubihsm connector {}: give password or password-env, not both",
+                connector_label
+            )),
+            (None, None) => problems.push(format!(
+                "yubihsm connector {}: password or password-env is required",
+                connector_label
+            )),
+            (None, Some(name)) if env::var(name).is_err() => problems.push(format!(
+                "yubihsm connector {}: environment variable {} (password-env) is not set",
+                connector_label, name
+            )),
+            _ => (),
+        }
+
+        for (key_id, key_config) in &connector_config.keys {
+            let hsm_key = (
+                connector_config.addr.as_str(),
+                connector_config.port,
+                key_config.key_id,
+            );
+
+            if let Some(other_key_id) = hsm_keys.insert(hsm_key, key_id) {
+                problems.push(format!(
+                    "yubihsm keys {} and {} are the same key (key-id {})",
+                    other_key_id, key_id, key_config.key_id
+                ));
+            }
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct YubihsmPrivateKey {
     /// Signing key ID
     #[serde(rename = "key-id")]
     pub key_id: u16,
+
+    /// Chains this key is authorized to sign for (any chain if empty)
+    #[serde(default)]
+    pub chains: Vec<String>,
+
+    /// Kinds of consensus message this key may sign
+    #[serde(flatten)]
+    pub policy: SigningPolicy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use toml;
+
+    /// Connector whose password is read from the given environment variable
+    fn connector(password_env: &str) -> YubihsmConnectorConfig {
+        toml::from_str(&format!(
+            "addr = \"127.0.0.1\"\nport = 12345\nauth-key-id = 1\npassword-env = {:?}\n\
+             [keys]\n",
+            password_env
+        )).unwrap()
+    }
+
+    /// Restore an environment variable to the value it had before a test
+    fn restore(name: &str, saved: Option<OsString>) {
+        match saved {
+            Some(value) => env::set_var(name, value),
+            None => env::remove_var(name),
+        }
+    }
+
+    #[test]
+    fn password_is_read_from_password_env() {
+        let name = "COSMOS_KMS_TEST_YUBIHSM_PASSWORD";
+        let saved = env::var_os(name);
+        env::set_var(name, "from the environment");
+
+        let mut problems = vec![];
+        let connectors = vec![("example".to_owned(), connector(name))].into_iter().collect();
+        validate(&connectors, &mut problems);
+        let password = connectors["example"].password();
+
+        restore(name, saved);
+        assert_eq!(problems, Vec::<String>::new());
+        assert_eq!(password.unwrap(), "from the environment");
+    }
+
+    #[test]
+    fn unset_password_env_is_an_error() {
+        let name = "COSMOS_KMS_TEST_YUBIHSM_PASSWORD_UNSET";
+        let saved = env::var_os(name);
+        env::remove_var(name);
+
+        let mut problems = vec![];
+        let connectors = vec![("example".to_owned(), connector(name))].into_iter().collect();
+        validate(&connectors, &mut problems);
+        let password = connectors["example"].password();
+
+        restore(name, saved);
+        assert_eq!(
+            problems,
+            vec![format!(
+                "yubihsm connector example: environment variable {} (password-env) is not set",
+                name
+            )]
+        );
+
+        match password {
+            Err(Error::ConfigError { description, .. }) => {
+                assert_eq!(description, format!("yubihsm: {} is not set", name))
+            }
+            other => panic!("expected ConfigError, got {:?}", other),
+        }
+    }
 }
@@ -1,15 +1,61 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use config::ProviderConfig;
+use config::{ProviderConfig, SigningPolicy};
 use error::Error;
 use std::panic::RefUnwindSafe;
-use super::{PublicKey, Signer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use super::{PublicKey, Signature, Signer};
+
+#[cfg(feature = "aws-kms")]
+use super::signer::cloud;
 
 #[cfg(feature = "dalek-provider")]
-use super::signer::dalek;
+use super::signer::dalek::{self, DALEK_PROVIDER_LABEL};
+
+#[cfg(feature = "ledger-provider")]
+use super::signer::ledger::{self, LEDGER_PROVIDER_LABEL};
+
+use super::signer::observer;
+
+#[cfg(feature = "secp256k1-provider")]
+use super::signer::secp256k1;
+
+#[cfg(feature = "yubihsm-provider")]
+use super::signer::yubihsm;
+
+/// Message signed by each key when testing the keyring
+pub const SELF_TEST_MESSAGE: &[u8] = b"cosmos-kms self-test";
 
+/// Maximum number of providers whose keys are self-tested at once
+pub const MAX_SELF_TEST_THREADS: usize = 4;
+
+/// Keys are indexed by the chain they're authorized for and their public
+/// key. Keys which aren't restricted to particular chains are indexed under
+/// a chain ID of `None`.
+#[derive(Clone)]
 pub struct Keyring {
-    keys: HashMap<PublicKey, Signer>,
+    keys: HashMap<(Option<String>, PublicKey), Arc<Signer>>,
+}
+
+/// Description of a key held in the keyring
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyInfo {
+    /// Ed25519 public key
+    pub public_key: PublicKey,
+
+    /// Name of the signature provider holding the key
+    pub provider_name: &'static str,
+
+    /// ID of the key within its provider
+    pub key_id: String,
+
+    /// Chains the key is authorized to sign for (any chain if empty)
+    pub chain_ids: Vec<String>,
+
+    /// Kinds of consensus message the key may sign
+    pub policy: SigningPolicy,
 }
 
 impl Keyring {
@@ -17,29 +63,721 @@ impl Keyring {
     pub fn from_config(config: ProviderConfig) -> Result<Self, Error> {
         let mut signers = vec![];
 
+        #[cfg(feature = "aws-kms")]
+        cloud::create_signers(&mut signers, config.aws_kms)?;
+
         #[cfg(feature = "dalek-provider")]
         dalek::create_signers(&mut signers, config.dalek)?;
 
+        #[cfg(feature = "ledger-provider")]
+        ledger::create_signers(&mut signers, config.ledger)?;
+
+        observer::create_signers(&mut signers, config.observer)?;
+
+        #[cfg(feature = "secp256k1-provider")]
+        secp256k1::create_signers(&mut signers, config.secp256k1)?;
+
+        #[cfg(feature = "yubihsm-provider")]
+        yubihsm::create_signers(&mut signers, config.yubihsm)?;
+
+        Self::from_signers(signers)
+    }
+
+    /// Create a keyring from the given vector of signer objects. Fails if
+    /// two signers hold the same key, so one can't silently shadow the other.
+    /// Signers are added in order of provider name and key ID, so the logs
+    /// are the same from one run to the next.
+    pub fn from_signers(mut signers: Vec<Signer>) -> Result<Self, Error> {
+        signers.sort_by(|a, b| (a.provider_name, &a.key_id).cmp(&(b.provider_name, &b.key_id)));
+
+        let mut keyring = Self {
+            keys: HashMap::new(),
+        };
+
+        for signer in signers {
+            keyring.add_signer(signer)?;
+        }
+
+        Ok(keyring)
+    }
+
+    /// Create a keyring of public keys only, from (key ID, public key) pairs,
+    /// e.g. for a node which verifies signatures but never makes them. The
+    /// keys can be used for any chain, and every attempt to sign with them
+    /// fails with `SigningDisabled`.
+    pub fn from_public_keys(keys: Vec<(String, PublicKey)>) -> Result<Self, Error> {
+        Self::from_signers(
+            keys.into_iter()
+                .map(|(key_id, public_key)| Signer::observer(key_id, vec![], public_key))
+                .collect(),
+        )
+    }
+
+    /// Add a signer's key to the keyring, returning its public key. Fails if
+    /// another signer already holds the same key.
+    pub fn add_signer(&mut self, signer: Signer) -> Result<PublicKey, Error> {
+        let public_key = signer.public_key()?;
+        self.insert(signer, public_key.clone())?;
+        Ok(public_key)
+    }
+
+    /// Remove the given key from the keyring (for every chain it can sign
+    /// for), returning a description of it. Requests already using the
+    /// signer hold a reference to it, so they can finish.
+    pub fn remove_key(&mut self, public_key: &PublicKey) -> Result<KeyInfo, Error> {
+        let key_info = self.keys
+            .iter()
+            .find(|((_, key), _)| key == public_key)
+            .map(|(_, signer)| KeyInfo {
+                public_key: public_key.clone(),
+                provider_name: signer.provider_name,
+                key_id: signer.key_id.clone(),
+                chain_ids: signer.chain_ids.clone(),
+                policy: signer.policy,
+            })
+            .ok_or_else(|| err!(UnknownKey, "not in keyring: {}", public_key))?;
+
+        self.keys.retain(|(_, key), _| key != public_key);
+
+        debug!(
+            "Removed {}:{} {}",
+            key_info.provider_name, key_info.key_id, public_key
+        );
+
+        Ok(key_info)
+    }
+
+    /// Index a signer under each of the chains it can sign for, unless
+    /// another signer already holds its key
+    fn insert(&mut self, signer: Signer, public_key: PublicKey) -> Result<(), Error> {
+        if let Some(existing) = self.keys
+            .iter()
+            .find(|((_, key), _)| *key == public_key)
+            .map(|(_, existing)| existing)
+        {
+            return Err(Error::DuplicateKey {
+                description: format!(
+                    "{}:{} and {}:{} are the same key ({})",
+                    existing.provider_name,
+                    existing.key_id,
+                    signer.provider_name,
+                    signer.key_id,
+                    public_key
+                ),
+                public_key,
+            });
+        }
+
+        debug!(
+            "Added {}:{} {}",
+            signer.provider_name, signer.key_id, &public_key
+        );
+
+        let chain_ids: Vec<Option<String>> = if signer.chain_ids.is_empty() {
+            vec![None]
+        } else {
+            signer.chain_ids.iter().cloned().map(Some).collect()
+        };
+
+        let signer = Arc::new(signer);
+
+        for chain_id in chain_ids {
+            self.keys
+                .insert((chain_id, public_key.clone()), Arc::clone(&signer));
+        }
+
+        Ok(())
+    }
+
+    /// Create a keyring of software keys from (key ID, seed) pairs, without
+    /// touching the filesystem. Only for tests: real keyrings are always
+    /// built from the config.
+    #[cfg(all(test, feature = "dalek-provider"))]
+    pub fn from_seed_bytes(seeds: &[(&str, &[u8])]) -> Result<Self, Error> {
+        let signers = seeds
+            .iter()
+            .map(|&(key_id, seed)| {
+                dalek::signer_from_seed(key_id.to_owned(), vec![], SigningPolicy::default(), seed)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         Self::from_signers(signers)
     }
 
-    /// Create a keyring from the given vector of signer objects
-    pub fn from_signers(signers: Vec<Signer>) -> Result<Self, Error> {
-        let mut keys = HashMap::new();
+    /// Does the keyring hold no keys at all?
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// List the keys in the keyring, ordered by provider name and key ID
+    pub fn public_keys(&self) -> Vec<KeyInfo> {
+        let mut keys = BTreeMap::new();
+
+        for ((_, public_key), signer) in &self.keys {
+            keys.entry((signer.provider_name, signer.key_id.clone()))
+                .or_insert_with(|| KeyInfo {
+                    public_key: public_key.clone(),
+                    provider_name: signer.provider_name,
+                    key_id: signer.key_id.clone(),
+                    chain_ids: signer.chain_ids.clone(),
+                    policy: signer.policy,
+                });
+        }
+
+        keys.into_values().collect()
+    }
+
+    /// Signers whose keys are held in hardware devices, with their public
+    /// keys, ordered by provider name and key ID
+    pub fn hardware_signers(&self) -> Vec<(PublicKey, Arc<Signer>)> {
+        let mut signers = BTreeMap::new();
 
-        for mut signer in signers {
-            let public_key = signer.public_key()?;
-            debug!(
-                "Added {}:{} {}",
-                signer.provider_name, signer.key_id, &public_key
-            );
-            keys.insert(public_key, signer);
+        for ((_, public_key), signer) in &self.keys {
+            if signer.is_hardware() {
+                signers
+                    .entry((signer.provider_name, signer.key_id.clone()))
+                    .or_insert_with(|| (public_key.clone(), Arc::clone(signer)));
+            }
         }
 
-        Ok(Self { keys })
+        signers.into_values().collect()
+    }
+
+    /// Check every key can produce a valid signature, so a dead HSM or bad
+    /// key file is caught before any validator traffic is accepted.
+    ///
+    /// Providers are tested concurrently (up to `MAX_SELF_TEST_THREADS` at a
+    /// time), but the keys of any one provider are tested in turn, since
+    /// they may share a single connection to a device. Every key is tested
+    /// even if some fail, and all failures are reported in one error.
+    pub fn self_test(&self) -> Result<(), Error> {
+        let failures = self.self_test_failures();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(self_test_error(failures))
+        }
+    }
+
+    /// Check every key as `self_test` does, but remove any which fail rather
+    /// than failing outright, so the rest can still be used. Returns an
+    /// error describing each key removed, and only fails if none of the keys
+    /// left can sign (observer keys don't count). Validators asking to sign
+    /// with a removed key are refused as they would be for any key not in
+    /// the keyring.
+    pub fn self_test_lenient(&mut self) -> Result<Vec<Error>, Error> {
+        let failures = self.self_test_failures();
+
+        for (public_key, _) in &failures {
+            self.keys.retain(|(_, key), _| key != public_key);
+        }
+
+        if !failures.is_empty() && !self.keys.values().any(|signer| signer.can_sign()) {
+            return Err(self_test_error(failures));
+        }
+
+        Ok(failures
+            .into_iter()
+            .map(|(public_key, description)| Error::SelfTestFailed {
+                description,
+                public_key,
+            })
+            .collect())
+    }
+
+    /// Test every key, returning those which failed (ordered by their
+    /// descriptions)
+    fn self_test_failures(&self) -> Vec<SelfTestFailure> {
+        let mut tested = HashSet::new();
+        let mut providers: BTreeMap<&'static str, Vec<(&Signer, &PublicKey)>> = BTreeMap::new();
+
+        let mut keys: Vec<_> = self.keys.iter().collect();
+        keys.sort_by_key(|&(_, signer)| (signer.provider_name, &signer.key_id));
+
+        for ((_, public_key), signer) in keys {
+            if !tested.insert(public_key) {
+                continue;
+            }
+
+            if !signer.can_sign() {
+                debug!("skipping self-test of {}:{}", signer.provider_name, signer.key_id);
+                continue;
+            }
+
+            // The Ledger validator app only signs consensus messages
+            #[cfg(feature = "ledger-provider")]
+            {
+                if signer.provider_name == LEDGER_PROVIDER_LABEL {
+                    debug!("skipping self-test of {}:{}", signer.provider_name, signer.key_id);
+                    continue;
+                }
+            }
+
+            providers
+                .entry(signer.provider_name)
+                .or_default()
+                .push((signer.as_ref(), public_key));
+        }
+
+        let providers: Vec<_> = providers.into_iter().collect();
+        let next_provider = AtomicUsize::new(0);
+        let num_threads = providers.len().min(MAX_SELF_TEST_THREADS);
+
+        let mut failures: Vec<SelfTestFailure> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..num_threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut failures = vec![];
+
+                        loop {
+                            let i = next_provider.fetch_add(1, Ordering::SeqCst);
+
+                            match providers.get(i) {
+                                Some(&(provider_name, ref keys)) => {
+                                    failures.extend(self_test_provider(provider_name, keys))
+                                }
+                                None => return failures,
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("self-test thread panicked"))
+                .collect()
+        });
+
+        failures.sort_by(|a, b| a.1.cmp(&b.1));
+        failures
+    }
+
+    /// Obtain the public key to sign with when the validator doesn't specify
+    /// one. Only succeeds if exactly one key can sign for the given chain.
+    pub fn default_pubkey(&self, chain_id: Option<&str>) -> Result<PublicKey, Error> {
+        let public_keys: HashSet<&PublicKey> = self.keys
+            .keys()
+            .filter(|(key_chain_id, _)| {
+                key_chain_id.is_none() || key_chain_id.as_deref() == chain_id
+            })
+            .map(|(_, public_key)| public_key)
+            .collect();
+
+        ensure!(
+            public_keys.len() == 1,
+            InvalidKey,
+            "expected exactly one key in keyring (found {})",
+            public_keys.len()
+        );
+
+        Ok(public_keys.into_iter().next().unwrap().clone())
+    }
+
+    /// Describe the key which would be used to sign for the given chain ID
+    /// and public key
+    pub fn key_info(
+        &self,
+        chain_id: Option<&str>,
+        public_key: &PublicKey,
+    ) -> Result<KeyInfo, Error> {
+        let signer = self.signer(chain_id, public_key)?;
+
+        Ok(KeyInfo {
+            public_key: public_key.clone(),
+            provider_name: signer.provider_name,
+            key_id: signer.key_id.clone(),
+            chain_ids: signer.chain_ids.clone(),
+            policy: signer.policy,
+        })
+    }
+
+    /// Is there a key which can sign for the given chain ID with the given
+    /// public key? Every key in the keyring is compared (in constant time)
+    /// regardless of where or whether a match is found, so how long this
+    /// takes doesn't reveal which keys the keyring holds.
+    pub fn contains(&self, chain_id: Option<&str>, public_key: &PublicKey) -> bool {
+        self.keys
+            .keys()
+            .fold(false, |found, (key_chain_id, key)| {
+                let chain_ok = key_chain_id.is_none() || key_chain_id.as_deref() == chain_id;
+                found | (chain_ok & key.ct_eq(public_key))
+            })
+    }
+
+    /// Sign a message using the secret key associated with the given chain
+    /// ID and public key. If no chain ID is given, only keys which aren't
+    /// restricted to particular chains can be used.
+    pub fn sign(
+        &self,
+        chain_id: Option<&str>,
+        public_key: &PublicKey,
+        msg: &[u8],
+    ) -> Result<Signature, Error> {
+        self.signer(chain_id, public_key)?.sign(msg)
+    }
+
+    /// Verify a signature over a message was made with the given key, which
+    /// must be in the keyring for the given chain ID (as with `sign`)
+    pub fn verify(
+        &self,
+        chain_id: Option<&str>,
+        public_key: &PublicKey,
+        msg: &[u8],
+        signature: &Signature,
+    ) -> Result<(), Error> {
+        self.signer(chain_id, public_key)?;
+        public_key.verify(msg, signature)
+    }
+
+    /// Find the signer for the given chain ID and public key, preferring one
+    /// restricted to that chain over one which can sign for any chain
+    fn signer(
+        &self,
+        chain_id: Option<&str>,
+        public_key: &PublicKey,
+    ) -> Result<&Arc<Signer>, Error> {
+        chain_id
+            .and_then(|id| self.keys.get(&(Some(id.to_owned()), public_key.clone())))
+            .or_else(|| self.keys.get(&(None, public_key.clone())))
+            .ok_or_else(|| {
+                err!(
+                    UnknownKey,
+                    "not in keyring: {} (chain: {})",
+                    public_key,
+                    chain_id.unwrap_or("none")
+                )
+            })
+    }
+}
+
+/// A key which failed its self-test, and a description of what went wrong
+type SelfTestFailure = (PublicKey, String);
+
+/// Test each of a provider's keys in turn, returning any which failed.
+/// Signatures from software keys are verified as a batch, since large
+/// keyrings would otherwise be slow to start.
+#[cfg_attr(not(feature = "dalek-provider"), allow(unused_variables))]
+fn self_test_provider(
+    provider_name: &str,
+    keys: &[(&Signer, &PublicKey)],
+) -> Vec<SelfTestFailure> {
+    let mut failures = vec![];
+    let mut signed = vec![];
+
+    for &(signer, public_key) in keys {
+        match signer.sign(SELF_TEST_MESSAGE) {
+            Ok(signature) => signed.push((signer, public_key, signature)),
+            Err(e) => failures.push(self_test_failure(signer, public_key, &e)),
+        }
+    }
+
+    #[cfg(feature = "dalek-provider")]
+    {
+        if provider_name == DALEK_PROVIDER_LABEL {
+            let triples: Vec<_> = signed
+                .iter()
+                .map(|&(_, public_key, ref signature)| {
+                    (public_key, SELF_TEST_MESSAGE, signature.clone())
+                })
+                .collect();
+
+            // A failed batch doesn't say which signature is bad, so fall
+            // through to checking them one by one
+            if !triples.is_empty() && dalek::verify_batch(&triples).is_ok() {
+                debug!("self-test of {} {} keys passed", triples.len(), DALEK_PROVIDER_LABEL);
+                return failures;
+            }
+        }
+    }
+
+    for (signer, public_key, signature) in signed {
+        match public_key.verify(SELF_TEST_MESSAGE, &signature) {
+            Ok(()) => debug!("self-test of {}:{} passed", signer.provider_name, signer.key_id),
+            Err(e) => failures.push(self_test_failure(signer, public_key, &e)),
+        }
+    }
+
+    failures
+}
+
+/// Combine the failures of one or more keys into a single error
+fn self_test_error(mut failures: Vec<SelfTestFailure>) -> Error {
+    let description = if failures.len() == 1 {
+        failures[0].1.clone()
+    } else {
+        format!(
+            "{} keys failed their self-test: {}",
+            failures.len(),
+            failures
+                .iter()
+                .map(|(_, description)| description.as_str())
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+    };
+
+    Error::SelfTestFailed {
+        description,
+        public_key: failures.swap_remove(0).0,
+    }
+}
+
+/// Describe a key which failed its self-test
+fn self_test_failure(signer: &Signer, public_key: &PublicKey, error: &Error) -> SelfTestFailure {
+    (
+        public_key.clone(),
+        format!(
+            "self-test of {}:{} ({}) failed: {}",
+            signer.provider_name, signer.key_id, public_key, error
+        ),
+    )
+}
+
+/// Handle to the current keyring, which can be replaced (e.g. on SIGHUP)
+/// while sessions are using it. Requests which are already being handled
+/// keep using the keyring they started with.
+#[derive(Clone)]
+pub struct SharedKeyring(Arc<RwLock<Arc<Keyring>>>);
+
+impl SharedKeyring {
+    /// Share the given keyring
+    pub fn new(keyring: Keyring) -> Self {
+        SharedKeyring(Arc::new(RwLock::new(Arc::new(keyring))))
+    }
+
+    /// Obtain the current keyring
+    pub fn current(&self) -> Arc<Keyring> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    /// Replace the keyring used for subsequent requests. The old keyring is
+    /// dropped once the last request using it has finished.
+    pub fn replace(&self, keyring: Keyring) {
+        *self.0.write().unwrap() = Arc::new(keyring);
+    }
+
+    /// Add a signer's key for subsequent requests, returning its public key
+    /// (see `Keyring::add_signer`). The key is looked up before taking the
+    /// lock, since that may mean talking to a device.
+    pub fn add_signer(&self, signer: Signer) -> Result<PublicKey, Error> {
+        let public_key = signer.public_key()?;
+
+        let mut current = self.0.write().unwrap();
+        let mut keyring = Keyring::clone(&current);
+        keyring.insert(signer, public_key.clone())?;
+        *current = Arc::new(keyring);
+
+        Ok(public_key)
+    }
+
+    /// Remove a key for subsequent requests (see `Keyring::remove_key`).
+    /// Requests which are already signing with it aren't affected.
+    pub fn remove_key(&self, public_key: &PublicKey) -> Result<KeyInfo, Error> {
+        let mut current = self.0.write().unwrap();
+        let mut keyring = Keyring::clone(&current);
+        let key_info = keyring.remove_key(public_key)?;
+        *current = Arc::new(keyring);
+
+        Ok(key_info)
     }
 }
 
 // TODO: push this down and enforce it inside of Signatory.
 // Right now it just "happens to be true"
 impl RefUnwindSafe for Keyring {}
+
+#[cfg(all(test, feature = "dalek-provider"))]
+pub(crate) mod tests {
+    use super::*;
+    use signatory::ed25519::{
+        PublicKey as SignatoryKey, Signature as SignatorySignature, Signer as SignerTrait,
+    };
+    use signatory::error::{Error as SignatoryError, ErrorKind as SignatoryErrorKind};
+
+    /// Signer whose device has gone away
+    struct UnavailableSigner(SignatoryKey);
+
+    impl SignerTrait for UnavailableSigner {
+        fn public_key(&self) -> Result<SignatoryKey, SignatoryError> {
+            Ok(self.0)
+        }
+
+        fn sign(&self, _msg: &[u8]) -> Result<SignatorySignature, SignatoryError> {
+            Err(SignatoryError::new(SignatoryErrorKind::ProviderError, Some("device unplugged")))
+        }
+    }
+
+    /// Signer whose every signature fails, as if its device had gone away
+    pub(crate) fn unavailable_signer(key_id: &str, public_key: [u8; 32]) -> Signer {
+        Signer::new(
+            "unavailable",
+            key_id.to_owned(),
+            vec![],
+            SigningPolicy::default(),
+            Box::new(UnavailableSigner(SignatoryKey::from_bytes(public_key).unwrap())),
+        )
+    }
+
+    fn observer_signer(key_id: &str, public_key: [u8; 32]) -> Signer {
+        Signer::observer(key_id.to_owned(), vec![], PublicKey::from_bytes(&public_key).unwrap())
+    }
+
+    #[test]
+    fn same_seed_twice_is_a_duplicate_key() {
+        let seed = [1u8; 32];
+
+        match Keyring::from_seed_bytes(&[("first", &seed), ("second", &seed)]) {
+            Err(Error::DuplicateKey { description, .. }) => {
+                assert!(description.starts_with("dalek:first and dalek:second are the same key"))
+            }
+            Err(e) => panic!("expected DuplicateKey, got {}", e),
+            Ok(_) => panic!("expected DuplicateKey, got a keyring"),
+        }
+    }
+
+    #[test]
+    fn keys_are_listed_in_a_stable_order() {
+        let seeds: Vec<(String, [u8; 32])> =
+            (0..16u8).map(|i| (format!("key-{:02}", 15 - i), [i; 32])).collect();
+
+        let key_ids = |seeds: &[(String, [u8; 32])]| -> Vec<String> {
+            let seeds: Vec<(&str, &[u8])> = seeds
+                .iter()
+                .map(|(key_id, seed)| (key_id.as_str(), &seed[..]))
+                .collect();
+
+            Keyring::from_seed_bytes(&seeds)
+                .unwrap()
+                .public_keys()
+                .into_iter()
+                .map(|key_info| key_info.key_id)
+                .collect()
+        };
+
+        let expected: Vec<String> = (0..16).map(|i| format!("key-{:02}", i)).collect();
+        assert_eq!(key_ids(&seeds), expected);
+
+        let mut reversed = seeds.clone();
+        reversed.reverse();
+        assert_eq!(key_ids(&reversed), expected);
+    }
+
+    #[test]
+    fn sign_and_verify_round_trip() {
+        let keyring = Keyring::from_seed_bytes(&[("signer", &[2u8; 32])]).unwrap();
+        let public_key = keyring.default_pubkey(None).unwrap();
+        let msg = b"round trip";
+
+        let signature = keyring.sign(None, &public_key, msg).unwrap();
+        keyring.verify(None, &public_key, msg, &signature).unwrap();
+
+        assert!(keyring
+            .verify(None, &public_key, b"another message", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn lenient_self_test_leaves_out_failing_keys() {
+        let working = dalek::signer_from_seed(
+            "working".to_owned(),
+            vec![],
+            SigningPolicy::default(),
+            &[3u8; 32],
+        ).unwrap();
+        let working_key = working.public_key().unwrap();
+
+        let mut keyring =
+            Keyring::from_signers(vec![working, unavailable_signer("broken", [4u8; 32])]).unwrap();
+        let broken_key = PublicKey::from_bytes(&[4u8; 32]).unwrap();
+
+        let excluded = keyring.self_test_lenient().unwrap();
+        assert_eq!(excluded.len(), 1);
+
+        match excluded[0] {
+            Error::SelfTestFailed { ref public_key, .. } => assert_eq!(*public_key, broken_key),
+            ref other => panic!("expected SelfTestFailed, got {:?}", other),
+        }
+
+        assert!(keyring.contains(None, &working_key));
+        assert!(!keyring.contains(None, &broken_key));
+    }
+
+    #[test]
+    fn signing_while_keys_are_reloaded_sees_one_keyring_per_request() {
+        let seed_signer = |key_id: &str, seed: &[u8]| {
+            dalek::signer_from_seed(key_id.to_owned(), vec![], SigningPolicy::default(), seed)
+                .unwrap()
+        };
+        let stable = seed_signer("stable", &[6u8; 32]);
+        let stable_key = stable.public_key().unwrap();
+        let toggled_key = seed_signer("toggled", &[7u8; 32]).public_key().unwrap();
+
+        let shared = SharedKeyring::new(Keyring::from_signers(vec![stable]).unwrap());
+        let reloading = Arc::new(AtomicUsize::new(1));
+        let msg = b"signed during a reload";
+
+        let signers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let reloading = Arc::clone(&reloading);
+                let (stable_key, toggled_key) = (stable_key.clone(), toggled_key.clone());
+
+                thread::spawn(move || {
+                    let mut requests = 0;
+
+                    while reloading.load(Ordering::SeqCst) != 0 || requests == 0 {
+                        let keyring = shared.current();
+                        let has_toggled = keyring.contains(None, &toggled_key);
+                        let listed = keyring
+                            .public_keys()
+                            .iter()
+                            .any(|key_info| key_info.public_key == toggled_key);
+                        assert_eq!(has_toggled, listed);
+
+                        let signature = keyring.sign(None, &stable_key, msg).unwrap();
+                        stable_key.verify(msg, &signature).unwrap();
+
+                        match keyring.sign(None, &toggled_key, msg) {
+                            Ok(signature) => {
+                                assert!(has_toggled);
+                                toggled_key.verify(msg, &signature).unwrap();
+                            }
+                            Err(Error::UnknownKey { .. }) => assert!(!has_toggled),
+                            Err(e) => panic!("expected a signature or UnknownKey, got {}", e),
+                        }
+
+                        requests += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..100 {
+            shared.add_signer(seed_signer("toggled", &[7u8; 32])).unwrap();
+            shared.remove_key(&toggled_key).unwrap();
+        }
+        reloading.store(0, Ordering::SeqCst);
+
+        for signer in signers {
+            signer.join().unwrap();
+        }
+
+        assert!(!shared.current().contains(None, &toggled_key));
+        assert!(shared.current().contains(None, &stable_key));
+    }
+
+    #[test]
+    fn lenient_self_test_needs_a_key_which_can_sign() {
+        let mut keyring = Keyring::from_signers(vec![
+            unavailable_signer("broken", [4u8; 32]),
+            observer_signer("observer", [5u8; 32]),
+        ]).unwrap();
+
+        match keyring.self_test_lenient() {
+            Err(Error::SelfTestFailed { .. }) => (),
+            other => panic!("expected SelfTestFailed, got {:?}", other),
+        }
+    }
+}
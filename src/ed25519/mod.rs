@@ -1,7 +1,15 @@
+//! Signing keys: Ed25519, and (with the `secp256k1-provider` cargo feature)
+//! secp256k1 ECDSA keys as used by some chains
+
 mod keyring;
 mod public_key;
+mod signature;
 mod signer;
 
-pub use self::keyring::Keyring;
-pub use self::public_key::{PublicKey, PUBLIC_KEY_SIZE};
+pub use self::keyring::{KeyInfo, Keyring, SharedKeyring};
+pub use self::public_key::{KeyAlgorithm, PublicKey, PUBLIC_KEY_SIZE, SECP256K1_PUBLIC_KEY_SIZE};
+pub use self::signature::Signature;
 pub use self::signer::Signer;
+
+#[cfg(all(test, feature = "dalek-provider"))]
+pub(crate) use self::keyring::tests::unavailable_signer;
@@ -1,40 +1,351 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use signatory::ed25519::PublicKey as SignatoryKey;
 pub use signatory::ed25519::PUBLIC_KEY_SIZE;
+#[cfg(feature = "secp256k1-provider")]
+use signatory::ecdsa::curve::secp256k1::PublicKey as Secp256k1Key;
 use std::fmt;
+use std::hint;
+use std::str::FromStr;
 
+use bech32;
+use error::Error;
+use super::Signature;
+
+/// Size of a (compressed) secp256k1 public key
+pub const SECP256K1_PUBLIC_KEY_SIZE: usize = 33;
+
+/// Amino prefix of an Ed25519 public key, as found in `cosmosvalconspub`
+/// and similar bech32 keys
+const AMINO_PREFIX: [u8; 5] = [0x16, 0x24, 0xde, 0x64, 0x20];
+
+/// Amino prefix of a secp256k1 public key
+const SECP256K1_AMINO_PREFIX: [u8; 5] = [0xeb, 0x5a, 0xe9, 0x87, 0x21];
+
+/// Signature algorithms keys can be used with
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    /// Ed25519 (used by most chains)
+    #[default]
+    Ed25519,
+
+    /// ECDSA over secp256k1 with SHA-256 (requires the `secp256k1-provider`
+    /// cargo feature)
+    Secp256k1,
+}
+
+impl fmt::Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::Secp256k1 => "secp256k1",
+        })
+    }
+}
+
+/// Public key of any supported algorithm, tagged with which one it is
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
-pub struct PublicKey(SignatoryKey);
+pub enum PublicKey {
+    /// Ed25519 public key
+    Ed25519(SignatoryKey),
+
+    /// Compressed secp256k1 public key
+    #[cfg(feature = "secp256k1-provider")]
+    Secp256k1(Secp256k1Key),
+}
 
 impl PublicKey {
-    /// Obtain public key as a byte array reference
+    /// Create an Ed25519 public key from its serialized form
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        SignatoryKey::from_bytes(bytes)
+            .map(PublicKey::Ed25519)
+            .map_err(|e| err!(InvalidKey, "{}", e))
+    }
+
+    /// Create a public key for the given algorithm from its serialized form
+    pub fn new(algorithm: KeyAlgorithm, bytes: &[u8]) -> Result<Self, Error> {
+        match algorithm {
+            KeyAlgorithm::Ed25519 => Self::from_bytes(bytes),
+            KeyAlgorithm::Secp256k1 => Self::from_secp256k1_bytes(bytes),
+        }
+    }
+
+    /// Create a secp256k1 public key from its compressed serialized form
+    #[cfg(feature = "secp256k1-provider")]
+    pub fn from_secp256k1_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Secp256k1Key::from_bytes(bytes)
+            .map(PublicKey::Secp256k1)
+            .map_err(|e| err!(InvalidKey, "{}", e))
+    }
+
+    /// Create a secp256k1 public key (unsupported in this build)
+    #[cfg(not(feature = "secp256k1-provider"))]
+    pub fn from_secp256k1_bytes(_bytes: &[u8]) -> Result<Self, Error> {
+        fail!(
+            InvalidKey,
+            "secp256k1 keys require the `secp256k1-provider` cargo feature"
+        );
+    }
+
+    /// Parse a hex-encoded public key
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        ensure!(
+            hex.len().is_multiple_of(2) && hex.is_ascii(),
+            InvalidKey,
+            "expected an even number of hex characters, got {:?}",
+            hex
+        );
+
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|e| err!(InvalidKey, "invalid hex in {:?}: {}", hex, e))?;
+
+        Self::from_untagged_bytes(&bytes)
+    }
+
+    /// Parse a base64-encoded public key (as in Tendermint's
+    /// `priv_validator.json`)
+    pub fn from_base64(encoded: &str) -> Result<Self, Error> {
+        let bytes = BASE64
+            .decode(encoded)
+            .map_err(|e| err!(InvalidKey, "invalid base64 in {:?}: {}", encoded, e))?;
+
+        Self::from_untagged_bytes(&bytes)
+    }
+
+    /// Parse a bech32-encoded public key (e.g. `cosmosvalconspub1...`), with
+    /// or without its Amino prefix
+    pub fn from_bech32(encoded: &str) -> Result<Self, Error> {
+        let (_, bytes) = bech32::decode(encoded)?;
+
+        if bytes.len() == AMINO_PREFIX.len() + PUBLIC_KEY_SIZE && bytes.starts_with(&AMINO_PREFIX) {
+            Self::from_bytes(&bytes[AMINO_PREFIX.len()..])
+        } else if bytes.len() == SECP256K1_AMINO_PREFIX.len() + SECP256K1_PUBLIC_KEY_SIZE
+            && bytes.starts_with(&SECP256K1_AMINO_PREFIX)
+        {
+            Self::from_secp256k1_bytes(&bytes[SECP256K1_AMINO_PREFIX.len()..])
+        } else {
+            Self::from_untagged_bytes(&bytes)
+        }
+    }
+
+    /// Create a public key from bytes which don't say which algorithm it's
+    /// for. Keys of each algorithm differ in length, so the length says.
+    fn from_untagged_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.len() {
+            PUBLIC_KEY_SIZE => Self::from_bytes(bytes),
+            SECP256K1_PUBLIC_KEY_SIZE => Self::from_secp256k1_bytes(bytes),
+            len => {
+                fail!(
+                    InvalidKey,
+                    "expected a {}-byte ed25519 or {}-byte secp256k1 key, got {} bytes",
+                    PUBLIC_KEY_SIZE,
+                    SECP256K1_PUBLIC_KEY_SIZE,
+                    len
+                );
+            }
+        }
+    }
+
+    /// Algorithm this key is used with
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match *self {
+            PublicKey::Ed25519(_) => KeyAlgorithm::Ed25519,
+            #[cfg(feature = "secp256k1-provider")]
+            PublicKey::Secp256k1(_) => KeyAlgorithm::Secp256k1,
+        }
+    }
+
+    /// Obtain public key as a byte slice reference
     #[inline]
-    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_SIZE] {
-        self.0.as_bytes()
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            PublicKey::Ed25519(ref key) => key.as_bytes(),
+            #[cfg(feature = "secp256k1-provider")]
+            PublicKey::Secp256k1(ref key) => key.as_bytes(),
+        }
     }
 
-    /// Convert public key into owned byte array
+    /// Convert public key into an owned byte vector
     #[inline]
-    pub fn into_bytes(self) -> [u8; PUBLIC_KEY_SIZE] {
-        self.0.into_bytes()
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    /// Compare with another key in constant time, so how long the comparison
+    /// takes doesn't reveal how much of the key matched. Keys of different
+    /// algorithms never match.
+    pub fn ct_eq(&self, other: &PublicKey) -> bool {
+        if self.algorithm() != other.algorithm() {
+            return false;
+        }
+
+        let diff = self.as_bytes()
+            .iter()
+            .zip(other.as_bytes().iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+
+        hint::black_box(diff) == 0
+    }
+
+    /// Verify a signature over the given message was made with this key
+    pub fn verify(&self, msg: &[u8], signature: &Signature) -> Result<(), Error> {
+        match (self, signature) {
+            (PublicKey::Ed25519(key), Signature::Ed25519(signature)) => key
+                .verify(msg, signature)
+                .map_err(|e| err!(SigningError, "{}", e)),
+            #[cfg(feature = "secp256k1-provider")]
+            (PublicKey::Secp256k1(key), Signature::Secp256k1(signature)) => key
+                .verify_sha2_raw_signature(msg, signature)
+                .map_err(|e| err!(SigningError, "{}", e)),
+            #[cfg(feature = "secp256k1-provider")]
+            _ => {
+                fail!(
+                    SigningError,
+                    "{} signature can't be checked against {}",
+                    signature.algorithm(),
+                    self
+                );
+            }
+        }
     }
 }
 
-// TODO: public key serialization formats (cosmos-bech32)
 impl fmt::Display for PublicKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ed25519(")?;
+        write!(f, "{}(", self.algorithm())?;
 
-        for (i, byte) in self.as_bytes().iter().enumerate() {
+        let bytes = self.as_bytes();
+
+        for (i, byte) in bytes.iter().enumerate() {
             write!(f, "{:02x}", byte)?;
-            write!(f, "{}", if i == PUBLIC_KEY_SIZE - 1 { ")" } else { ":" })?;
+            write!(f, "{}", if i == bytes.len() - 1 { ")" } else { ":" })?;
         }
 
         Ok(())
     }
 }
 
+/// Parse a public key given in hex, base64, or bech32 (e.g. in the config
+/// file). The encodings of a 32-byte Ed25519 key and a 33-byte secp256k1 key
+/// all differ in length, so the length says which one it is.
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.len() {
+            64 | 66 => Self::from_hex(s),
+            43 | 44 => Self::from_base64(s),
+            _ => Self::from_bech32(s),
+        }
+    }
+}
+
 impl From<SignatoryKey> for PublicKey {
     fn from(key: SignatoryKey) -> PublicKey {
-        PublicKey(key)
+        PublicKey::Ed25519(key)
+    }
+}
+
+#[cfg(feature = "secp256k1-provider")]
+impl From<Secp256k1Key> for PublicKey {
+    fn from(key: Secp256k1Key) -> PublicKey {
+        PublicKey::Secp256k1(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test key, and its encodings
+    const PUBLIC_KEY_HEX: &str = "2d65efaf84f360a32ef19ca7a4196bd37c6c63f8cbf0f2bf0201e73f05a5acc3";
+    const PUBLIC_KEY_BECH32: &str =
+        "cosmosvalconspub1zcjduepq94j7ltuy7ds2xth3njn6gxtt6d7xcclce0c090czq8nn7pd94npspnykkc";
+    const PUBLIC_KEY_BECH32_UNTAGGED: &str =
+        "cosmosvalconspub194j7ltuy7ds2xth3njn6gxtt6d7xcclce0c090czq8nn7pd94npswtaf5q";
+
+    fn test_key() -> PublicKey {
+        PublicKey::from_hex(PUBLIC_KEY_HEX).unwrap()
+    }
+
+    fn assert_invalid_key(result: Result<PublicKey, Error>, expected: &str) {
+        match result {
+            Err(Error::InvalidKey { description }) => assert!(
+                description.contains(expected),
+                "{:?} doesn't mention {:?}",
+                description,
+                expected
+            ),
+            other => panic!("expected InvalidKey, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        let encoded = BASE64.encode(test_key().as_bytes());
+        assert_eq!(encoded, "LWXvr4TzYKMu8ZynpBlr03xsY/jL8PK/AgHnPwWlrMM=");
+
+        assert_eq!(PublicKey::from_base64(&encoded).unwrap(), test_key());
+        assert_eq!(encoded.parse::<PublicKey>().unwrap(), test_key());
+    }
+
+    #[test]
+    fn invalid_base64_is_rejected() {
+        assert_invalid_key(
+            PublicKey::from_base64("LWXvr4TzYKMu8ZynpBlr03xsY/jL8PK/AgHnPwWlrM!="),
+            "invalid base64",
+        );
+
+        // Valid base64, but of a 31-byte key
+        assert_invalid_key(
+            PublicKey::from_base64(&BASE64.encode([0u8; 31])),
+            "got 31 bytes",
+        );
+    }
+
+    #[test]
+    fn bech32_round_trip() {
+        assert_eq!(PublicKey::from_bech32(PUBLIC_KEY_BECH32).unwrap(), test_key());
+        assert_eq!(PUBLIC_KEY_BECH32.parse::<PublicKey>().unwrap(), test_key());
+
+        // Uppercase is as valid as lowercase, and the Amino prefix is optional
+        let uppercase = PUBLIC_KEY_BECH32.to_uppercase();
+        assert_eq!(PublicKey::from_bech32(&uppercase).unwrap(), test_key());
+        assert_eq!(
+            PublicKey::from_bech32(PUBLIC_KEY_BECH32_UNTAGGED).unwrap(),
+            test_key()
+        );
+    }
+
+    #[test]
+    fn bech32_with_bad_checksum_is_rejected() {
+        // Last character changed
+        let mut corrupted = PUBLIC_KEY_BECH32.to_owned();
+        corrupted.pop();
+        corrupted.push('d');
+        assert_invalid_key(PublicKey::from_bech32(&corrupted), "invalid bech32 checksum");
+
+        // A character of the key itself changed
+        let corrupted = PUBLIC_KEY_BECH32.replacen("94j7", "95j7", 1);
+        assert_invalid_key(corrupted.parse::<PublicKey>(), "invalid bech32 checksum");
+    }
+
+    #[test]
+    fn malformed_bech32_is_rejected() {
+        let mixed_case = format!("COSMOS{}", &PUBLIC_KEY_BECH32[6..]);
+        assert_invalid_key(PublicKey::from_bech32(&mixed_case), "mixed case");
+
+        assert_invalid_key(
+            PublicKey::from_bech32("cosmosvalconspub"),
+            "missing separator",
+        );
+        assert_invalid_key(
+            PublicKey::from_bech32(&PUBLIC_KEY_BECH32.replacen('q', "b", 1)),
+            "invalid character",
+        );
     }
 }
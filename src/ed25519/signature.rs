@@ -0,0 +1,50 @@
+use signatory::ed25519::Signature as Ed25519Signature;
+#[cfg(feature = "secp256k1-provider")]
+use signatory::ecdsa::curve::secp256k1::RawSignature as Secp256k1Signature;
+
+use super::KeyAlgorithm;
+
+/// Signature made by a key of any supported algorithm
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Signature {
+    /// Ed25519 signature
+    Ed25519(Ed25519Signature),
+
+    /// secp256k1 ECDSA signature in compact (`r || s`) form, over the
+    /// SHA-256 digest of the message
+    #[cfg(feature = "secp256k1-provider")]
+    Secp256k1(Secp256k1Signature),
+}
+
+impl Signature {
+    /// Algorithm of the key which made this signature
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match *self {
+            Signature::Ed25519(_) => KeyAlgorithm::Ed25519,
+            #[cfg(feature = "secp256k1-provider")]
+            Signature::Secp256k1(_) => KeyAlgorithm::Secp256k1,
+        }
+    }
+
+    /// Obtain signature as a byte slice reference
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            Signature::Ed25519(ref signature) => signature.as_bytes(),
+            #[cfg(feature = "secp256k1-provider")]
+            Signature::Secp256k1(ref signature) => signature.as_bytes(),
+        }
+    }
+}
+
+impl From<Ed25519Signature> for Signature {
+    fn from(signature: Ed25519Signature) -> Signature {
+        Signature::Ed25519(signature)
+    }
+}
+
+#[cfg(feature = "secp256k1-provider")]
+impl From<Secp256k1Signature> for Signature {
+    fn from(signature: Secp256k1Signature) -> Signature {
+        Signature::Secp256k1(signature)
+    }
+}
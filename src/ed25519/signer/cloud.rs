@@ -0,0 +1,388 @@
+//! Ed25519 signing with keys held by AWS KMS, via its Sign API
+//!
+//! Requests are signed with AWS Signature Version 4 using credentials from
+//! the standard `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and (optionally)
+//! `AWS_SESSION_TOKEN` environment variables.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::{self, json, Value};
+use sha2::{Digest, Sha256};
+use signatory::ed25519::{PublicKey as SignatoryKey, Signature, Signer as SignerTrait};
+use signatory::error::{Error as SignatoryError, ErrorKind as SignatoryErrorKind};
+use std::env;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use ureq;
+
+use config::AwsKmsConfig;
+use error::Error;
+use super::Signer;
+
+/// Label for AWS KMS providers
+pub const AWS_KMS_PROVIDER_LABEL: &str = "aws-kms";
+
+/// Service name used when signing requests
+const SERVICE: &str = "kms";
+
+/// Content type of KMS API requests
+const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+
+/// Signing algorithm for Ed25519 keys (`ECC_NIST_EDWARDS25519` key spec)
+const SIGNING_ALGORITHM: &str = "ED25519_SHA_512";
+
+/// DER prefix of an Ed25519 `SubjectPublicKeyInfo`, as returned by
+/// `GetPublicKey`
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// How many times to try a request before giving up
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry (in milliseconds), doubling on each retry
+const RETRY_DELAY_MS: u64 = 100;
+
+/// How long to wait for a response from KMS (in seconds)
+const REQUEST_TIMEOUT_SECS: u64 = 5;
+
+/// Create AWS KMS-backed Ed25519 signers from the given configuration
+pub fn create_signers(signers: &mut Vec<Signer>, config: AwsKmsConfig) -> Result<(), Error> {
+    if config.keys.is_empty() {
+        return Ok(());
+    }
+
+    let client = Arc::new(KmsClient::new(&config)?);
+
+    for (key_id, key_config) in config.keys {
+        // Ensure the key exists and we're allowed to use it before continuing
+        let public_key = client.public_key(&key_config.key_arn)?;
+
+        let signer = AwsKmsSigner {
+            client: Arc::clone(&client),
+            key_arn: key_config.key_arn,
+            public_key,
+        };
+
+        signers.push(Signer::new(
+            AWS_KMS_PROVIDER_LABEL,
+            key_id,
+            key_config.chains,
+            key_config.policy,
+            Box::new(signer),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Ed25519 signer which uses a key held by AWS KMS
+struct AwsKmsSigner {
+    /// KMS API client (shared by all keys in the same region)
+    client: Arc<KmsClient>,
+
+    /// ARN of the key
+    key_arn: String,
+
+    /// Public key, fetched when the signer was created
+    public_key: [u8; 32],
+}
+
+impl SignerTrait for AwsKmsSigner {
+    fn public_key(&self) -> Result<SignatoryKey, SignatoryError> {
+        SignatoryKey::from_bytes(self.public_key)
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<Signature, SignatoryError> {
+        let bytes = self.client
+            .sign(&self.key_arn, msg)
+            .map_err(provider_error)?;
+
+        Signature::from_bytes(&bytes)
+    }
+}
+
+/// Convert our errors into Signatory's
+fn provider_error(error: Error) -> SignatoryError {
+    SignatoryError::new(
+        SignatoryErrorKind::ProviderError,
+        Some(&error.to_string()),
+    )
+}
+
+/// Credentials used to sign requests
+struct Credentials {
+    /// Access key ID
+    access_key_id: String,
+
+    /// Secret access key
+    secret_access_key: String,
+
+    /// Session token (for temporary credentials)
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Read credentials from the standard environment variables
+    fn from_env() -> Result<Self, Error> {
+        let var = |name| {
+            env::var(name).map_err(|_| err!(ConfigError, "aws-kms: {} is not set", name))
+        };
+
+        Ok(Self {
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// Minimal client for the parts of the KMS API needed for signing
+struct KmsClient {
+    /// AWS region
+    region: String,
+
+    /// URL of the KMS API
+    endpoint: String,
+
+    /// Host (and port, if given) of the endpoint, as sent in the `Host` header
+    host: String,
+
+    /// Credentials used to sign requests
+    credentials: Credentials,
+
+    /// HTTP agent (reuses connections between requests)
+    agent: ureq::Agent,
+}
+
+impl KmsClient {
+    /// Create a client for the configured region and endpoint
+    fn new(config: &AwsKmsConfig) -> Result<Self, Error> {
+        let endpoint = config
+            .endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://kms.{}.amazonaws.com/", config.region));
+
+        let host = endpoint
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split('/').next())
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| err!(ConfigError, "aws-kms: invalid endpoint: {}", endpoint))?
+            .to_owned();
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build();
+
+        Ok(Self {
+            region: config.region.clone(),
+            endpoint,
+            host,
+            credentials: Credentials::from_env()?,
+            agent,
+        })
+    }
+
+    /// Obtain the Ed25519 public key for the given key ARN
+    fn public_key(&self, key_arn: &str) -> Result<[u8; 32], Error> {
+        let response = self.call("GetPublicKey", &json!({ "KeyId": key_arn }))?;
+        let spki = decode_field(&response, "PublicKey")?;
+
+        ensure!(
+            spki.len() == ED25519_SPKI_PREFIX.len() + 32 && spki.starts_with(&ED25519_SPKI_PREFIX),
+            InvalidKey,
+            "aws-kms: {} is not an Ed25519 key",
+            key_arn
+        );
+
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(&spki[ED25519_SPKI_PREFIX.len()..]);
+        Ok(public_key)
+    }
+
+    /// Sign the given message with the key for the given ARN
+    fn sign(&self, key_arn: &str, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let request = json!({
+            "KeyId": key_arn,
+            "Message": BASE64.encode(msg),
+            "MessageType": "RAW",
+            "SigningAlgorithm": SIGNING_ALGORITHM,
+        });
+
+        let signature = decode_field(&self.call("Sign", &request)?, "Signature")?;
+
+        ensure!(
+            signature.len() == 64,
+            ProviderError,
+            "unexpected signature length from AWS KMS: {}",
+            signature.len()
+        );
+
+        Ok(signature)
+    }
+
+    /// Call the given API action, retrying (with backoff) if KMS is
+    /// throttling us or having trouble of its own
+    fn call(&self, action: &str, request: &Value) -> Result<Value, Error> {
+        let body = serde_json::to_vec(request).unwrap();
+        let mut delay = Duration::from_millis(RETRY_DELAY_MS);
+        let mut attempt = 1;
+
+        loop {
+            match self.send(action, &body) {
+                Err(Error::Throttled { ref description }) if attempt < MAX_ATTEMPTS => {
+                    warn!("{}; retrying in {:?}", description, delay);
+                }
+                result => return result,
+            }
+
+            thread::sleep(delay);
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// Make a single signed request to the KMS API
+    fn send(&self, action: &str, body: &[u8]) -> Result<Value, Error> {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let target = format!("TrentService.{}", action);
+
+        let mut request = self.agent
+            .post(&self.endpoint)
+            .set("Content-Type", CONTENT_TYPE)
+            .set("X-Amz-Date", &amz_date)
+            .set("X-Amz-Target", &target)
+            .set(
+                "Authorization",
+                &self.authorization(&amz_date, &target, body),
+            );
+
+        if let Some(ref token) = self.credentials.session_token {
+            request = request.set("X-Amz-Security-Token", token);
+        }
+
+        let (status, response) = match request.send_bytes(body) {
+            Ok(response) => (response.status(), response),
+            Err(ureq::Error::Status(status, response)) => (status, response),
+            Err(ureq::Error::Transport(e)) => {
+                fail!(ProviderError, "error calling AWS KMS {}: {}", action, e);
+            }
+        };
+
+        let response: Value = serde_json::from_reader(response.into_reader())
+            .map_err(|e| err!(ProviderError, "malformed AWS KMS {} response: {}", action, e))?;
+
+        if status == 200 {
+            return Ok(response);
+        }
+
+        // Error types look like `ThrottlingException` or, in some cases,
+        // `com.amazonaws.kms#ThrottlingException`
+        let error_type = response["__type"]
+            .as_str()
+            .unwrap_or("")
+            .rsplit('#')
+            .next()
+            .unwrap_or("");
+
+        let description = format!(
+            "AWS KMS {} failed ({} {}): {}",
+            action,
+            status,
+            error_type,
+            response["message"]
+                .as_str()
+                .or_else(|| response["Message"].as_str())
+                .unwrap_or("no message")
+        );
+
+        if status == 429 || status >= 500 || error_type == "ThrottlingException" {
+            Err(Error::Throttled { description })
+        } else {
+            Err(Error::ProviderError { description })
+        }
+    }
+
+    /// Compute the SigV4 `Authorization` header for a request
+    fn authorization(&self, amz_date: &str, target: &str, body: &[u8]) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, SERVICE);
+
+        let mut headers = vec![
+            ("content-type", CONTENT_TYPE),
+            ("host", self.host.as_str()),
+            ("x-amz-date", amz_date),
+        ];
+
+        if let Some(ref token) = self.credentials.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+
+        headers.push(("x-amz-target", target));
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "POST\n/\n\n{}\n{}\n{}",
+            canonical_headers,
+            signed_headers,
+            hex(&Sha256::digest(body))
+        );
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let secret = format!("AWS4{}", self.credentials.secret_access_key);
+        let mut key = hmac_sha256(secret.as_bytes(), date.as_bytes());
+
+        for part in &[self.region.as_str(), SERVICE, "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id,
+            scope,
+            signed_headers,
+            hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+        )
+    }
+}
+
+/// Decode a base64-encoded field of a KMS API response
+fn decode_field(response: &Value, name: &str) -> Result<Vec<u8>, Error> {
+    response[name]
+        .as_str()
+        .and_then(|value| BASE64.decode(value).ok())
+        .ok_or_else(|| err!(ProviderError, "AWS KMS response is missing {}", name))
+}
+
+/// Compute HMAC-SHA256 of the given message
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key size");
+    mac.update(msg);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Encode bytes as lowercase hex
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,10 +1,12 @@
+use ed25519_dalek::{self, VerifyingKey};
 use signatory::ed25519::FromSeed;
 use signatory::providers::dalek::Ed25519Signer as DalekSigner;
 use std::fs::File;
 use std::io::Read;
 
 use clear_on_drop::ClearOnDrop;
-use config::DalekConfig;
+use config::{DalekConfig, SigningPolicy};
+use ed25519::{PublicKey, Signature};
 use error::Error;
 use super::Signer;
 
@@ -13,11 +15,11 @@ pub const DALEK_PROVIDER_LABEL: &str = "dalek";
 
 /// Create software-backed Ed25519 signers from the given configuration
 pub fn create_signers(signers: &mut Vec<Signer>, config: DalekConfig) -> Result<(), Error> {
-    for (key_id, key_config) in config.keys {
+    for (key_id, key_config) in config.all_keys()? {
         let mut file = File::open(&key_config.path).map_err(|e| {
             err!(
                 ConfigError,
-                "can't open {}: {}",
+                "can't open {}: {} (check that the key path exists and is readable)",
                 key_config.path.display(),
                 e
             )
@@ -26,9 +28,63 @@ pub fn create_signers(signers: &mut Vec<Signer>, config: DalekConfig) -> Result<
         let mut key_material = ClearOnDrop::new(vec![]);
         file.read_to_end(key_material.as_mut())?;
 
-        let signer = Box::new(DalekSigner::from_seed(&key_material).unwrap());
-        signers.push(Signer::new(DALEK_PROVIDER_LABEL, key_id, signer));
+        signers.push(signer_from_seed(
+            key_id,
+            key_config.chains,
+            key_config.policy,
+            &key_material,
+        )?);
     }
 
     Ok(())
 }
+
+/// Create a software-backed Ed25519 signer from a raw seed
+pub fn signer_from_seed(
+    key_id: String,
+    chain_ids: Vec<String>,
+    policy: SigningPolicy,
+    seed: &[u8],
+) -> Result<Signer, Error> {
+    let provider = DalekSigner::from_seed(seed)
+        .map_err(|e| err!(InvalidKey, "invalid seed for dalek key {}: {}", key_id, e))?;
+
+    Ok(Signer::new(
+        DALEK_PROVIDER_LABEL,
+        key_id,
+        chain_ids,
+        policy,
+        Box::new(provider),
+    ))
+}
+
+/// Check a batch of (public key, message, signature) triples all at once,
+/// which is much faster than verifying each in turn. Only tells whether every
+/// signature is valid, not which ones aren't.
+pub fn verify_batch(batch: &[(&PublicKey, &[u8], Signature)]) -> Result<(), Error> {
+    let mut messages = Vec::with_capacity(batch.len());
+    let mut signatures = Vec::with_capacity(batch.len());
+    let mut verifying_keys = Vec::with_capacity(batch.len());
+
+    for &(public_key, msg, ref signature) in batch {
+        let (key_bytes, signature_bytes) = match (public_key, signature) {
+            (PublicKey::Ed25519(key), Signature::Ed25519(signature)) => {
+                (key.as_bytes(), signature.as_bytes())
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                fail!(InvalidKey, "{} isn't an ed25519 key", public_key);
+            }
+        };
+
+        messages.push(msg);
+        signatures.push(ed25519_dalek::Signature::from_bytes(signature_bytes));
+        verifying_keys.push(
+            VerifyingKey::from_bytes(key_bytes)
+                .map_err(|e| err!(InvalidKey, "{}: {}", public_key, e))?,
+        );
+    }
+
+    ed25519_dalek::verify_batch(&messages, &signatures, &verifying_keys)
+        .map_err(|e| err!(SigningError, "batch verification failed: {}", e))
+}
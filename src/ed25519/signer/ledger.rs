@@ -0,0 +1,324 @@
+use hidapi::{HidApi, HidDevice};
+use signatory::ed25519::{PublicKey as SignatoryKey, Signature, Signer as SignerTrait};
+use signatory::error::{Error as SignatoryError, ErrorKind as SignatoryErrorKind};
+use std::sync::{Arc, Mutex};
+
+use config::LedgerConfig;
+use error::Error;
+use super::Signer;
+
+/// Label for Ledger providers
+pub const LEDGER_PROVIDER_LABEL: &str = "ledger";
+
+/// USB vendor ID for Ledger devices
+const LEDGER_VENDOR_ID: u16 = 0x2c97;
+
+/// HID usage page Ledger devices expose their APDU interface on
+const LEDGER_USAGE_PAGE: u16 = 0xffa0;
+
+/// HID channel used for APDU exchanges
+const LEDGER_CHANNEL: u16 = 0x0101;
+
+/// HID packet tag for APDU data
+const LEDGER_TAG_APDU: u8 = 0x05;
+
+/// Size of a HID report
+const HID_PACKET_SIZE: usize = 64;
+
+/// How long to wait for the device to respond (in milliseconds)
+const HID_TIMEOUT_MS: i32 = 10_000;
+
+/// APDU class byte for the Tendermint validator app
+const CLA_VALIDATOR: u8 = 0x56;
+
+/// Instruction to obtain an Ed25519 public key
+const INS_PUBLIC_KEY_ED25519: u8 = 0x01;
+
+/// Instruction to compute an Ed25519 signature
+const INS_SIGN_ED25519: u8 = 0x02;
+
+/// Maximum payload carried by a single APDU
+const APDU_CHUNK_SIZE: usize = 250;
+
+/// Status word returned on success
+const SW_OK: u16 = 0x9000;
+
+/// Create Ledger-backed Ed25519 signers from the given configuration
+pub fn create_signers(signers: &mut Vec<Signer>, config: LedgerConfig) -> Result<(), Error> {
+    if config.keys.is_empty() {
+        return Ok(());
+    }
+
+    let device = Arc::new(Mutex::new(LedgerDevice::open()?));
+
+    for (key_id, key_config) in config.keys {
+        let derivation_path = parse_derivation_path(&key_config.derivation_path)?;
+
+        // Ensure the device is unlocked and the app is open before continuing
+        device.lock().unwrap().public_key(&derivation_path)?;
+
+        let signer = LedgerSigner {
+            device: Arc::clone(&device),
+            derivation_path,
+        };
+
+        signers.push(Signer::new(
+            LEDGER_PROVIDER_LABEL,
+            key_id,
+            key_config.chains,
+            key_config.policy,
+            Box::new(signer),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Ed25519 signer which uses a key held by a Ledger device
+struct LedgerSigner {
+    /// Ledger device (shared by all of its signers, since it can only
+    /// process a single request at a time)
+    device: Arc<Mutex<LedgerDevice>>,
+
+    /// Serialized BIP32 derivation path for this key
+    derivation_path: Vec<u8>,
+}
+
+impl SignerTrait for LedgerSigner {
+    fn public_key(&self) -> Result<SignatoryKey, SignatoryError> {
+        let bytes = self.device
+            .lock()
+            .unwrap()
+            .public_key(&self.derivation_path)
+            .map_err(provider_error)?;
+
+        SignatoryKey::from_bytes(&bytes)
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<Signature, SignatoryError> {
+        let bytes = self.device
+            .lock()
+            .unwrap()
+            .sign(&self.derivation_path, msg)
+            .map_err(provider_error)?;
+
+        Signature::from_bytes(&bytes)
+    }
+}
+
+/// Convert our errors into Signatory's
+fn provider_error(error: Error) -> SignatoryError {
+    SignatoryError::new(
+        SignatoryErrorKind::ProviderError,
+        Some(&error.to_string()),
+    )
+}
+
+/// Connection to a Ledger device running the Tendermint validator app
+struct LedgerDevice {
+    /// HID handle for the device
+    hid: HidDevice,
+}
+
+impl LedgerDevice {
+    /// Open the first Ledger device attached to the system
+    fn open() -> Result<Self, Error> {
+        let api = HidApi::new().map_err(|e| err!(ProviderError, "can't initialize HID: {}", e))?;
+
+        let device_info = api.device_list()
+            .find(|info| {
+                info.vendor_id() == LEDGER_VENDOR_ID
+                    && (info.usage_page() == LEDGER_USAGE_PAGE || info.interface_number() == 0)
+            })
+            .ok_or_else(|| err!(ProviderError, "no Ledger device found"))?;
+
+        let hid = api.open_path(device_info.path())
+            .map_err(|e| err!(ProviderError, "can't open Ledger device: {}", e))?;
+
+        Ok(Self { hid })
+    }
+
+    /// Obtain the Ed25519 public key for the given derivation path
+    fn public_key(&self, derivation_path: &[u8]) -> Result<Vec<u8>, Error> {
+        let response = self.exchange(INS_PUBLIC_KEY_ED25519, 0, 0, derivation_path)?;
+
+        ensure!(
+            response.len() == 32,
+            ProviderError,
+            "unexpected public key length from Ledger: {}",
+            response.len()
+        );
+
+        Ok(response)
+    }
+
+    /// Sign a message with the key for the given derivation path. The path
+    /// is sent in the first APDU and the message in those which follow it.
+    fn sign(&self, derivation_path: &[u8], msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut chunks = vec![derivation_path];
+        chunks.extend(msg.chunks(APDU_CHUNK_SIZE));
+
+        ensure!(
+            chunks.len() <= 0xff,
+            ProviderError,
+            "message too long to sign with Ledger ({} bytes)",
+            msg.len()
+        );
+
+        let total = chunks.len() as u8;
+        let mut response = vec![];
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            response = self.exchange(INS_SIGN_ED25519, i as u8 + 1, total, chunk)?;
+        }
+
+        ensure!(
+            response.len() == 64,
+            ProviderError,
+            "unexpected signature length from Ledger: {}",
+            response.len()
+        );
+
+        Ok(response)
+    }
+
+    /// Send an APDU to the device and return the response data
+    fn exchange(&self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut apdu = vec![CLA_VALIDATOR, ins, p1, p2, data.len() as u8];
+        apdu.extend_from_slice(data);
+
+        self.write_apdu(&apdu)?;
+        let mut response = self.read_apdu()?;
+
+        ensure!(response.len() >= 2, ProviderError, "truncated Ledger response");
+
+        let sw_offset = response.len() - 2;
+        let status = (u16::from(response[sw_offset]) << 8) | u16::from(response[sw_offset + 1]);
+        response.truncate(sw_offset);
+
+        match status {
+            SW_OK => Ok(response),
+            0x5515 | 0x6982 => {
+                fail!(
+                    DeviceLocked,
+                    "Ledger device is locked (status {:04x})",
+                    status
+                );
+            }
+            0x6d00 | 0x6e00 | 0x6e01 => {
+                fail!(
+                    AppNotOpen,
+                    "Tendermint validator app is not open on the Ledger (status {:04x})",
+                    status
+                );
+            }
+            0x6985 | 0x6986 => {
+                fail!(ProviderError, "request rejected by the Ledger device");
+            }
+            _ => {
+                fail!(ProviderError, "unexpected Ledger status: {:04x}", status);
+            }
+        }
+    }
+
+    /// Split an APDU into HID packets and write them to the device
+    fn write_apdu(&self, apdu: &[u8]) -> Result<(), Error> {
+        let mut data = vec![(apdu.len() >> 8) as u8, apdu.len() as u8];
+        data.extend_from_slice(apdu);
+
+        for (seq, chunk) in data.chunks(HID_PACKET_SIZE - 5).enumerate() {
+            // The first byte is the HID report number, which is always zero
+            let mut packet = vec![0u8; HID_PACKET_SIZE + 1];
+            packet[1..3].copy_from_slice(&LEDGER_CHANNEL.to_be_bytes());
+            packet[3] = LEDGER_TAG_APDU;
+            packet[4..6].copy_from_slice(&(seq as u16).to_be_bytes());
+            packet[6..6 + chunk.len()].copy_from_slice(chunk);
+
+            self.hid
+                .write(&packet)
+                .map_err(|e| err!(ProviderError, "error writing to Ledger: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Read HID packets from the device and reassemble the response APDU
+    fn read_apdu(&self) -> Result<Vec<u8>, Error> {
+        let mut response: Vec<u8> = vec![];
+        let mut expected_len = None;
+        let mut seq = 0u16;
+
+        loop {
+            let mut packet = [0u8; HID_PACKET_SIZE];
+            let n = self.hid
+                .read_timeout(&mut packet, HID_TIMEOUT_MS)
+                .map_err(|e| err!(ProviderError, "error reading from Ledger: {}", e))?;
+
+            ensure!(n >= 5, ProviderError, "timed out waiting for Ledger response");
+            ensure!(
+                packet[..2] == LEDGER_CHANNEL.to_be_bytes()
+                    && packet[2] == LEDGER_TAG_APDU
+                    && packet[3..5] == seq.to_be_bytes(),
+                ProviderError,
+                "malformed HID packet from Ledger"
+            );
+
+            let payload = if expected_len.is_none() {
+                expected_len = Some((usize::from(packet[5]) << 8) | usize::from(packet[6]));
+                &packet[7..n]
+            } else {
+                &packet[5..n]
+            };
+
+            response.extend_from_slice(payload);
+            let len = expected_len.unwrap();
+
+            if response.len() >= len {
+                response.truncate(len);
+                return Ok(response);
+            }
+
+            seq = seq.wrapping_add(1);
+        }
+    }
+}
+
+/// Parse and serialize a BIP32 derivation path (e.g. `44'/118'/0'/0'/0'`)
+fn parse_derivation_path(path: &str) -> Result<Vec<u8>, Error> {
+    let components: Vec<&str> = path.trim_start_matches("m/").split('/').collect();
+
+    ensure!(
+        !components.is_empty() && components.len() <= 10,
+        ConfigError,
+        "invalid derivation path: {}",
+        path
+    );
+
+    let mut serialized = vec![components.len() as u8];
+
+    for component in components {
+        let (index, hardened) = match component.chars().last() {
+            Some('\'') | Some('h') => (&component[..component.len() - 1], true),
+            _ => (component, false),
+        };
+
+        let mut index: u32 = index
+            .parse()
+            .map_err(|_| err!(ConfigError, "invalid derivation path: {}", path))?;
+
+        ensure!(
+            index < 0x8000_0000,
+            ConfigError,
+            "invalid derivation path: {}",
+            path
+        );
+
+        if hardened {
+            index |= 0x8000_0000;
+        }
+
+        serialized.extend_from_slice(&index.to_be_bytes());
+    }
+
+    Ok(serialized)
+}
@@ -1,14 +1,41 @@
+#[cfg(feature = "secp256k1-provider")]
+use signatory::ecdsa::curve::Secp256k1;
+#[cfg(feature = "secp256k1-provider")]
+use signatory::ecdsa::Signer as EcdsaSignerTrait;
 use signatory::ed25519::Signer as SignerTrait;
+use signatory::error::{Error as SignatoryError, ErrorKind as SignatoryErrorKind};
+
+#[cfg(feature = "aws-kms")]
+pub mod cloud;
 
 #[cfg(feature = "dalek-provider")]
 pub mod dalek;
 
-// TODO: #[cfg(feature = "yubihsm-provider")] pub mod yubihsm;
+#[cfg(feature = "ledger-provider")]
+pub mod ledger;
+
+pub mod observer;
+
+#[cfg(feature = "secp256k1-provider")]
+pub mod secp256k1;
 
+#[cfg(feature = "yubihsm-provider")]
+pub mod yubihsm;
+
+use config::SigningPolicy;
 use error::Error;
-use super::PublicKey;
+use super::{PublicKey, Signature};
+
+/// Providers which hold their keys in a hardware device, whose connection
+/// can be lost while the KMS is running
+const HARDWARE_PROVIDERS: &[&str] = &[
+    #[cfg(feature = "ledger-provider")]
+    ledger::LEDGER_PROVIDER_LABEL,
+    #[cfg(feature = "yubihsm-provider")]
+    yubihsm::YUBIHSM_PROVIDER_LABEL,
+];
 
-/// Wrapper for an Ed25519 signing provider (i.e. trait object)
+/// Wrapper for a signing provider (i.e. trait object) of any algorithm
 pub struct Signer {
     /// Name of the signature provider for this key
     pub provider_name: &'static str,
@@ -16,25 +43,181 @@ pub struct Signer {
     /// ID which identifies this key (should be unique-per-provider)
     pub key_id: String,
 
+    /// Chains this key is authorized to sign for (any chain if empty)
+    pub chain_ids: Vec<String>,
+
+    /// Kinds of consensus message this key may sign
+    pub policy: SigningPolicy,
+
     /// Signer trait object
-    provider: Box<SignerTrait>,
+    provider: Provider,
+}
+
+/// Signing provider for each supported algorithm
+enum Provider {
+    Ed25519(Box<SignerTrait>),
+    #[cfg(feature = "secp256k1-provider")]
+    Secp256k1(Box<dyn EcdsaSignerTrait<Secp256k1>>),
+    Observer(PublicKey),
 }
 
 impl Signer {
-    /// Create a new signer
-    pub fn new(provider_name: &'static str, key_id: String, provider: Box<SignerTrait>) -> Self {
+    /// Create a new Ed25519 signer
+    pub fn new(
+        provider_name: &'static str,
+        key_id: String,
+        chain_ids: Vec<String>,
+        policy: SigningPolicy,
+        provider: Box<SignerTrait>,
+    ) -> Self {
+        Self {
+            provider_name,
+            key_id,
+            chain_ids,
+            policy,
+            provider: Provider::Ed25519(provider),
+        }
+    }
+
+    /// Create a new secp256k1 signer
+    #[cfg(feature = "secp256k1-provider")]
+    pub fn new_secp256k1(
+        provider_name: &'static str,
+        key_id: String,
+        chain_ids: Vec<String>,
+        policy: SigningPolicy,
+        provider: Box<dyn EcdsaSignerTrait<Secp256k1>>,
+    ) -> Self {
         Self {
             provider_name,
             key_id,
-            provider,
+            chain_ids,
+            policy,
+            provider: Provider::Secp256k1(provider),
+        }
+    }
+
+    /// Create a signer which only has a public key, and refuses to sign
+    pub fn observer(key_id: String, chain_ids: Vec<String>, public_key: PublicKey) -> Self {
+        Self {
+            provider_name: observer::OBSERVER_PROVIDER_LABEL,
+            key_id,
+            chain_ids,
+            policy: SigningPolicy::default(),
+            provider: Provider::Observer(public_key),
         }
     }
 
-    /// Obtain the Ed25519 public key which corresponds to this signer's private key
-    pub fn public_key(&mut self) -> Result<PublicKey, Error> {
-        Ok(self.provider
-            .public_key()
-            .map_err(|e| err!(InvalidKey, "{}", e))?
-            .into())
+    /// Obtain the public key which corresponds to this signer's private key
+    pub fn public_key(&self) -> Result<PublicKey, Error> {
+        match self.provider {
+            Provider::Ed25519(ref provider) => provider.public_key().map(PublicKey::from),
+            #[cfg(feature = "secp256k1-provider")]
+            Provider::Secp256k1(ref provider) => provider.public_key().map(PublicKey::from),
+            Provider::Observer(ref public_key) => return Ok(public_key.clone()),
+        }.map_err(|e| self.provider_error(e, |description| Error::InvalidKey { description }))
+    }
+
+    /// Does this signer have a private key to sign with?
+    pub fn can_sign(&self) -> bool {
+        !matches!(self.provider, Provider::Observer(_))
+    }
+
+    /// Is this signer's key held in a hardware device?
+    pub fn is_hardware(&self) -> bool {
+        HARDWARE_PROVIDERS.contains(&self.provider_name)
+    }
+
+    /// Sign the given message using this signer's private key. secp256k1
+    /// keys sign the SHA-256 digest of the message, as Tendermint expects.
+    pub fn sign(&self, msg: &[u8]) -> Result<Signature, Error> {
+        match self.provider {
+            Provider::Ed25519(ref provider) => provider.sign(msg).map(Signature::from),
+            #[cfg(feature = "secp256k1-provider")]
+            Provider::Secp256k1(ref provider) => provider.sign_sha2_raw(msg).map(Signature::from),
+            Provider::Observer(_) => {
+                fail!(
+                    SigningDisabled,
+                    "{}:{} is an observer key, which can only verify signatures",
+                    self.provider_name,
+                    self.key_id
+                );
+            }
+        }.map_err(|e| self.provider_error(e, |description| Error::SigningError { description }))
+    }
+
+    /// Convert an error from the provider into ours. An error inside the
+    /// provider (e.g. a device or service which can't be reached) means the
+    /// signer is unavailable, rather than anything being wrong with the key,
+    /// so it's reported as such; any other error is made by `other`.
+    fn provider_error<F>(&self, error: SignatoryError, other: F) -> Error
+    where
+        F: FnOnce(String) -> Error,
+    {
+        match error.kind() {
+            SignatoryErrorKind::ProviderError => err!(
+                SignerUnavailable,
+                "{}:{} is unavailable: {}",
+                self.provider_name,
+                self.key_id,
+                error
+            ),
+            _ => other(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::{ErrorCode, ErrorResponse};
+    use signatory::ed25519::{PublicKey as SignatoryKey, Signature as SignatorySignature};
+
+    /// Provider which fails every signature with an error of the given kind
+    struct FailingSigner(SignatoryErrorKind);
+
+    impl SignerTrait for FailingSigner {
+        fn public_key(&self) -> Result<SignatoryKey, SignatoryError> {
+            SignatoryKey::from_bytes([1u8; 32])
+        }
+
+        fn sign(&self, _msg: &[u8]) -> Result<SignatorySignature, SignatoryError> {
+            Err(SignatoryError::new(self.0, Some("it broke")))
+        }
+    }
+
+    fn failing_signer(kind: SignatoryErrorKind) -> Signer {
+        Signer::new(
+            "failing",
+            "key-1".to_owned(),
+            vec![],
+            SigningPolicy::default(),
+            Box::new(FailingSigner(kind)),
+        )
+    }
+
+    /// Code the validator would be sent for the given error
+    fn error_code(error: &Error) -> ErrorCode {
+        ErrorResponse::for_error(error).unwrap().code
+    }
+
+    #[test]
+    fn provider_error_means_the_signer_is_unavailable() {
+        match failing_signer(SignatoryErrorKind::ProviderError).sign(b"msg") {
+            Err(e @ Error::SignerUnavailable { .. }) => {
+                assert_eq!(error_code(&e), ErrorCode::SignerUnavailable)
+            }
+            other => panic!("expected SignerUnavailable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn other_errors_are_signing_errors() {
+        match failing_signer(SignatoryErrorKind::SignatureInvalid).sign(b"msg") {
+            Err(e @ Error::SigningError { .. }) => {
+                assert_eq!(error_code(&e), ErrorCode::SigningFailed)
+            }
+            other => panic!("expected SigningError, got {:?}", other),
+        }
     }
 }
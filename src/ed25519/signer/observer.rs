@@ -0,0 +1,20 @@
+use config::ObserverConfig;
+use error::Error;
+use super::Signer;
+
+/// Label for observer keys, which can't sign
+pub const OBSERVER_PROVIDER_LABEL: &str = "observer";
+
+/// Create verify-only signers for the configured public keys
+pub fn create_signers(signers: &mut Vec<Signer>, config: ObserverConfig) -> Result<(), Error> {
+    for (key_id, key_config) in config.keys {
+        let public_key = key_config
+            .public_key
+            .parse()
+            .map_err(|e| err!(InvalidKey, "observer key {}: {}", key_id, e))?;
+
+        signers.push(Signer::observer(key_id, key_config.chains, public_key));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use signatory::providers::secp256k1::ECDSASigner;
+use std::fs::File;
+use std::io::Read;
+
+use clear_on_drop::ClearOnDrop;
+use config::Secp256k1Config;
+use error::Error;
+use super::Signer;
+
+/// Label for secp256k1 software providers
+pub const SECP256K1_PROVIDER_LABEL: &str = "secp256k1";
+
+/// Create software-backed secp256k1 signers from the given configuration
+pub fn create_signers(signers: &mut Vec<Signer>, config: Secp256k1Config) -> Result<(), Error> {
+    for (key_id, key_config) in config.keys {
+        let mut file = File::open(&key_config.path).map_err(|e| {
+            err!(
+                ConfigError,
+                "can't open {}: {} (check that the key path exists and is readable)",
+                key_config.path.display(),
+                e
+            )
+        })?;
+
+        let mut secret_key = ClearOnDrop::new(vec![]);
+        file.read_to_end(secret_key.as_mut())?;
+
+        let provider = ECDSASigner::from_bytes(&secret_key).map_err(|e| {
+            err!(InvalidKey, "invalid secret key for secp256k1 key {}: {}", key_id, e)
+        })?;
+
+        signers.push(Signer::new_secp256k1(
+            SECP256K1_PROVIDER_LABEL,
+            key_id,
+            key_config.chains,
+            key_config.policy,
+            Box::new(provider),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use signatory::providers::yubihsm::{Config as HttpConfig, Session};
+use std::collections::BTreeMap;
+
+use config::YubihsmConnectorConfig;
+use error::Error;
+use super::Signer;
+
+/// Label for YubiHSM providers
+pub const YUBIHSM_PROVIDER_LABEL: &str = "yubihsm";
+
+/// Create hardware-backed Ed25519 signers from the given configuration
+pub fn create_signers(
+    signers: &mut Vec<Signer>,
+    config: BTreeMap<String, YubihsmConnectorConfig>,
+) -> Result<(), Error> {
+    for (connector_label, connector_config) in config {
+        let http_config = HttpConfig {
+            addr: connector_config.addr.clone(),
+            port: connector_config.port,
+            ..Default::default()
+        };
+
+        let session = Session::new(
+            http_config,
+            connector_config.auth_key_id,
+            &connector_config.password()?,
+        ).map_err(|e| {
+            err!(
+                ProviderError,
+                "couldn't connect to YubiHSM via {} ({}:{}): {} (check that \
+                 yubihsm-connector is running there and auth-key-id and the password are right)",
+                connector_label,
+                connector_config.addr,
+                connector_config.port,
+                e
+            )
+        })?;
+
+        for (key_id, key_config) in connector_config.keys {
+            let signer = session.ed25519_signer(key_config.key_id).map_err(|e| {
+                err!(
+                    InvalidKey,
+                    "{}: can't use key-id {} for {}: {}",
+                    connector_label,
+                    key_config.key_id,
+                    key_id,
+                    e
+                )
+            })?;
+
+            signers.push(Signer::new(
+                YUBIHSM_PROVIDER_LABEL,
+                key_id,
+                key_config.chains,
+                key_config.policy,
+                Box::new(signer),
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,39 @@
 //! Error types
+//!
+//! Errors an operator can act on say how in their message, either here for
+//! every error of a kind or where the error is raised when the fix depends on
+//! the circumstances.
 
 use std::io;
 
+use ed25519::PublicKey;
+
 /// Error type
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
 pub enum Error {
+    /// Hardware device is running but not on the app the KMS talks to
+    #[fail(display = "{} (open the Tendermint validator app on the device)", description)]
+    AppNotOpen {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Couldn't bind a socket to the configured local address
+    #[fail(display = "{} (check that bind-addr is one of this host's addresses)", description)]
+    BindError {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Couldn't reach the validator because of the SOCKS5 proxy in the way:
+    /// connecting to it, authenticating, or it refusing the request (as
+    /// opposed to the validator itself being unreachable from the proxy)
+    #[fail(display = "{} (check the proxy's address and credentials)", description)]
+    ProxyError {
+        /// Description of the error
+        description: String,
+    },
+
     /// Error in configuration file
     #[fail(display = "{}", description)]
     ConfigError {
@@ -12,6 +41,62 @@ pub enum Error {
         description: String,
     },
 
+    /// Peer closed the connection between messages (e.g. a validator
+    /// restarting normally)
+    #[fail(display = "{}", description)]
+    ConnectionClosed {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Hardware device is locked and must be unlocked with a PIN
+    #[fail(display = "{} (unlock the device with its PIN)", description)]
+    DeviceLocked {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Signing would regress or duplicate a previous signature
+    #[fail(display = "{}", description)]
+    DoubleSign {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Two signers hold the same key
+    #[fail(display = "{} (remove one of them from the configuration)", description)]
+    DuplicateKey {
+        /// Description of the error
+        description: String,
+
+        /// The key both signers hold
+        public_key: PublicKey,
+    },
+
+    /// Secret Connection handshake failed (e.g. malformed ephemeral key)
+    #[fail(display = "{}", description)]
+    HandshakeError {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Chain ID Tendermint wouldn't accept (e.g. too long)
+    #[fail(display = "{}", description)]
+    InvalidChainId {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Configuration failed validation
+    #[fail(display = "{}", description)]
+    InvalidConfig {
+        /// Description of the error
+        description: String,
+
+        /// Every problem found with the configuration
+        problems: Vec<String>,
+    },
+
     /// Malformatted or otherwise invalid cryptographic key
     #[fail(display = "{}", description)]
     InvalidKey {
@@ -19,6 +104,182 @@ pub enum Error {
         description: String,
     },
 
+    /// Proposal violates one of Tendermint's invariants
+    #[fail(display = "{}", description)]
+    InvalidProposal {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Key's signing policy doesn't allow this kind of message
+    #[fail(display = "{}", description)]
+    PolicyViolation {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Keyring has no keys, so no request could ever be signed
+    #[fail(display = "{} (configure a key under [providers], or set observer-mode)", description)]
+    NoKeys {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Error inside a signing provider (e.g. couldn't connect to an HSM)
+    #[fail(display = "{}", description)]
+    ProviderError {
+        /// Description of the error
+        description: String,
+    },
+
+    /// RPC message exceeds the configured maximum size
+    #[fail(display = "{}", description)]
+    MessageTooLarge {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Validator is requesting signatures faster than it's allowed to
+    #[fail(display = "{}", description)]
+    RateLimited {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Validator sent a raw sign request, which it isn't allowed to
+    #[fail(display = "{}", description)]
+    RawSignDisabled {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Validator made a kind of request which isn't in its
+    /// `enabled-operations`
+    #[fail(display = "{}", description)]
+    OperationDisabled {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Too many requests are already being signed across all validators
+    #[fail(display = "{}", description)]
+    Overloaded {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Malformed or unexpected RPC message
+    #[fail(display = "{}", description)]
+    RpcError {
+        /// Description of the error
+        description: String,
+    },
+
+    /// A signer failed to produce a valid signature over a test message
+    #[fail(display = "{} (check the device or key file holding the key)", description)]
+    SelfTestFailed {
+        /// Description of the error
+        description: String,
+
+        /// Public key of the failing signer
+        public_key: PublicKey,
+    },
+
+    /// A signature didn't verify against the signer's public key (e.g. a
+    /// faulty HSM), so it was withheld from the validator
+    #[fail(display = "{} (the signing device may be faulty)", description)]
+    SignatureVerificationFailed {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Signer's key is in the keyring, but the device or service holding it
+    /// failed (e.g. an HSM which has been disconnected)
+    #[fail(display = "{} (check the device or service holding the key is reachable)", description)]
+    SignerUnavailable {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Key can't sign, only verify signatures (e.g. an observer key)
+    #[fail(display = "{}", description)]
+    SigningDisabled {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Error signing a message
+    #[fail(display = "{}", description)]
+    SigningError {
+        /// Description of the error
+        description: String,
+    },
+
+    /// A signing provider is rate limiting us (or temporarily unavailable)
+    #[fail(display = "{}", description)]
+    Throttled {
+        /// Description of the error
+        description: String,
+    },
+
+    /// A vote's timestamp is earlier than one already signed in the same
+    /// height and round
+    #[fail(display = "{}", description)]
+    TimestampRegression {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Timed out waiting on a validator connection
+    #[fail(display = "{}", description)]
+    Timeout {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Validator isn't authorized to sign with the requested key
+    #[fail(display = "{}", description)]
+    Unauthorized {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Stream ended partway through a message (or an encrypted frame)
+    #[fail(display = "{}", description)]
+    UnexpectedEof {
+        /// Description of the error
+        description: String,
+    },
+
+    /// No key in the keyring matches the requested chain and public key
+    #[fail(display = "{}", description)]
+    UnknownKey {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Message sent to be signed isn't a vote or proposal, and the validator
+    /// may only sign those
+    #[fail(display = "{}", description)]
+    UnrecognizedMessage {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Validator's identity key isn't in the configured allowlist
+    #[fail(display = "{} (add the validator's node ID to peer-ids if it's trusted)", description)]
+    UntrustedPeer {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Validator speaks an incompatible version of the RPC protocol
+    #[fail(display = "{} (upgrade the KMS or the validator to a matching version)", description)]
+    VersionMismatch {
+        /// Description of the error
+        description: String,
+    },
+
     /// Input/output error
     #[fail(display = "{}", description)]
     IoError {
@@ -27,8 +288,83 @@ pub enum Error {
     },
 }
 
+impl Error {
+    /// Could reconnecting to the validator plausibly make this error go away?
+    /// Configuration and key problems persist until an operator fixes them.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Error::ConfigError { .. }
+            | Error::DuplicateKey { .. }
+            | Error::InvalidConfig { .. }
+            | Error::InvalidKey { .. }
+            | Error::NoKeys { .. }
+            | Error::OperationDisabled { .. }
+            | Error::PolicyViolation { .. }
+            | Error::RawSignDisabled { .. }
+            | Error::SelfTestFailed { .. }
+            | Error::SigningDisabled { .. }
+            | Error::Unauthorized { .. }
+            | Error::UnknownKey { .. }
+            | Error::UnrecognizedMessage { .. } => false,
+            Error::AppNotOpen { .. }
+            | Error::BindError { .. }
+            | Error::ConnectionClosed { .. }
+            | Error::DeviceLocked { .. }
+            | Error::DoubleSign { .. }
+            | Error::HandshakeError { .. }
+            | Error::InvalidChainId { .. }
+            | Error::InvalidProposal { .. }
+            | Error::MessageTooLarge { .. }
+            | Error::Overloaded { .. }
+            | Error::ProviderError { .. }
+            | Error::ProxyError { .. }
+            | Error::RateLimited { .. }
+            | Error::RpcError { .. }
+            | Error::SignatureVerificationFailed { .. }
+            | Error::SignerUnavailable { .. }
+            | Error::SigningError { .. }
+            | Error::Throttled { .. }
+            | Error::TimestampRegression { .. }
+            | Error::Timeout { .. }
+            | Error::UnexpectedEof { .. }
+            | Error::UntrustedPeer { .. }
+            | Error::VersionMismatch { .. }
+            | Error::IoError { .. } => true,
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(other: io::Error) -> Self {
-        err!(IoError, "{}", other)
+        match other.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                err!(Timeout, "I/O timed out: {}", other)
+            }
+            io::ErrorKind::UnexpectedEof => {
+                err!(UnexpectedEof, "connection closed mid-frame: {}", other)
+            }
+            _ => err!(IoError, "{}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_with_a_fixed_remedy_say_what_it_is() {
+        let locked = Error::DeviceLocked {
+            description: "ledger is locked".to_owned(),
+        };
+        assert_eq!(locked.to_string(), "ledger is locked (unlock the device with its PIN)");
+
+        let app_not_open = Error::AppNotOpen {
+            description: "ledger is on the dashboard".to_owned(),
+        };
+        assert_eq!(
+            app_not_open.to_string(),
+            "ledger is on the dashboard (open the Tendermint validator app on the device)"
+        );
     }
 }
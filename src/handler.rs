@@ -0,0 +1,1359 @@
+//! Handling of requests from a validator, independent of how they arrive
+//!
+//! Sessions read messages off their connection and hand them to a
+//! `RequestHandler`, which does the signing and decides what to send back.
+//! This keeps the blocking `Session` and the tokio-based session (with the
+//! `tokio` feature) from drifting apart.
+
+use chrono::SecondsFormat;
+use log::Level;
+use std::convert::TryFrom;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use amino;
+use audit;
+use audit_file;
+use canonical::{self, SignedMessage};
+use chain_id::ChainId;
+use config::{Chains, MsgLenLimits, Operation, ValidatorConfig, WireFormat};
+use error::Error;
+use in_flight;
+use liveness;
+use ed25519::{Keyring, PublicKey, SharedKeyring};
+use metrics;
+use rpc::{ErrorResponse, HelloRequest, HelloResponse, PingRequest, PongResponse, PubKeyResponse,
+          Request, Response, SignResponse, PROTOCOL_VERSION};
+use state::{HeightRoundStep, SignState, Timestamp, STEP_PROPOSAL};
+use traffic::{self, RequestType};
+use webhook;
+
+/// How long the validator has to answer an idle ping (in seconds)
+pub const IDLE_PING_GRACE_SECS: u64 = 5;
+
+/// What to do after handling a message from the validator
+pub enum Reply {
+    /// Nothing needs sending back
+    None,
+
+    /// Send this encoded response
+    Send(Vec<u8>),
+
+    /// Send this encoded response, then end the session with the given error
+    SendAndClose(Vec<u8>, Error),
+}
+
+/// Signs on behalf of one validator, keeping track of the state of its
+/// session (e.g. whether it has said hello yet)
+pub struct RequestHandler {
+    /// Identifier for this validator from the config
+    label: String,
+
+    /// Where the validator is connected from (see
+    /// `ValidatorConfig::peer_addr`), included in every log message
+    peer_addr: String,
+
+    /// Keyring of signature keys
+    keyring: SharedKeyring,
+
+    /// Double-signing protection state for this validator
+    state: Arc<Mutex<SignState>>,
+
+    /// Settings for particular chains (e.g. sign prefixes)
+    chains: Arc<Chains>,
+
+    /// Maximum time to wait for data from the validator (if any)
+    read_timeout: Option<Duration>,
+
+    /// Idle time after which to ping the validator (if any)
+    idle_timeout: Option<Duration>,
+
+    /// Nonce of the idle ping we're waiting for a reply to, and when it was
+    /// sent
+    pending_ping: Option<(u64, Instant)>,
+
+    /// Nonce to send with the next idle ping
+    next_ping_nonce: u64,
+
+    /// Encoding of requests and responses
+    wire_format: WireFormat,
+
+    /// Chain the validator signs for (if configured)
+    chain_id: Option<ChainId>,
+
+    /// Keys the validator may sign with (`None` means any key)
+    authorized_keys: Option<Vec<PublicKey>>,
+
+    /// Signing duration beyond which to log a warning (if any)
+    slow_sign_warn: Option<Duration>,
+
+    /// Limits how fast the validator may request signatures (if configured)
+    sign_rate_limit: Option<TokenBucket>,
+
+    /// Check signatures against the public key before sending them
+    verify_signatures: bool,
+
+    /// Accept `Sign` requests for arbitrary messages
+    allow_raw_sign: bool,
+
+    /// Only accept `Sign` requests for the sign bytes of votes and proposals
+    require_known_message: bool,
+
+    /// Kinds of request the validator may make (`None` means any)
+    enabled_operations: Option<Vec<Operation>>,
+
+    /// Bounds on the length of arbitrary messages, votes, and proposals
+    sign_msg_len: MsgLenLimits,
+    vote_msg_len: MsgLenLimits,
+    proposal_msg_len: MsgLenLimits,
+
+    /// Has the validator negotiated a protocol version with us yet?
+    hello_received: bool,
+}
+
+impl RequestHandler {
+    /// Create a handler for a new session with the validator described by
+    /// the given config
+    pub fn new(
+        label: &str,
+        config: &ValidatorConfig,
+        endpoint: &str,
+        keyring: SharedKeyring,
+        state: Arc<Mutex<SignState>>,
+        chains: Arc<Chains>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            label: label.to_owned(),
+            peer_addr: config.peer_addr(endpoint),
+            keyring,
+            state,
+            chains,
+            read_timeout: config.read_timeout(),
+            idle_timeout: config.idle_timeout(),
+            pending_ping: None,
+            next_ping_nonce: 0,
+            wire_format: config.wire_format,
+            chain_id: config.chain_id.clone(),
+            authorized_keys: config.authorized_keys()?,
+            slow_sign_warn: config.slow_sign_warn(),
+            sign_rate_limit: config.max_sign_rate().map(TokenBucket::new),
+            verify_signatures: config.verify_signatures.unwrap_or(true),
+            allow_raw_sign: config.allow_raw_sign.unwrap_or(true),
+            require_known_message: config.require_known_message.unwrap_or(false),
+            enabled_operations: config.enabled_operations.clone(),
+            sign_msg_len: config.sign_msg_len,
+            vote_msg_len: config.vote_msg_len,
+            proposal_msg_len: config.proposal_msg_len,
+            hello_received: false,
+        })
+    }
+
+    /// Identifier for this validator from the config
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Where the validator is connected from
+    pub fn peer_addr(&self) -> &str {
+        &self.peer_addr
+    }
+
+    /// Handle a message from the validator (without its length prefix)
+    pub fn handle_message(&mut self, msg: &[u8]) -> Result<Reply, Error> {
+        match self.wire_format {
+            WireFormat::Legacy => match Request::decode(msg)? {
+                Request::Hello(req) => {
+                    // Answer even if we're incompatible, so the validator
+                    // can report what we speak
+                    let response = Response::Hello(HelloResponse::new()).to_vec();
+
+                    Ok(match self.check_hello(&req) {
+                        Ok(()) => Reply::Send(response),
+                        Err(e) => Reply::SendAndClose(response, e),
+                    })
+                }
+                Request::Pong(pong) => {
+                    self.pong_received(&pong);
+                    Ok(Reply::None)
+                }
+                request => Ok(Reply::Send(self.handle_request(request)?.to_vec())),
+            },
+            WireFormat::Amino => {
+                let request = amino::Request::decode(msg)?;
+                Ok(Reply::Send(self.handle_amino_request(request)?.to_vec()))
+            }
+        }
+    }
+
+    /// Called periodically while nothing is arriving from the validator.
+    /// Gives up on the connection if it has been quiet for longer than the
+    /// read timeout, or if a ping we already sent hasn't been answered in
+    /// time. Otherwise returns a ping to send if it has been idle too long.
+    pub fn check_idle(&mut self, last_activity: Instant) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(timeout) = self.read_timeout {
+            ensure!(
+                last_activity.elapsed() < timeout,
+                Timeout,
+                "no requests received in {} seconds",
+                timeout.as_secs()
+            );
+        }
+
+        let idle_timeout = match self.idle_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(None),
+        };
+
+        if let Some((nonce, sent_at)) = self.pending_ping {
+            ensure!(
+                sent_at.elapsed() < Duration::from_secs(IDLE_PING_GRACE_SECS),
+                Timeout,
+                "no reply to ping {} within {} seconds",
+                nonce,
+                IDLE_PING_GRACE_SECS
+            );
+
+            return Ok(None);
+        }
+
+        if last_activity.elapsed() < idle_timeout {
+            return Ok(None);
+        }
+
+        let nonce = self.next_ping_nonce;
+        self.next_ping_nonce = self.next_ping_nonce.wrapping_add(1);
+
+        debug!(
+            validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+            event = "idle_ping", nonce = nonce;
+            "no requests in {} seconds; pinging validator", idle_timeout.as_secs()
+        );
+
+        self.pending_ping = Some((nonce, Instant::now()));
+        Ok(Some(Response::Ping(PingRequest { nonce }).to_vec()))
+    }
+
+    /// Handle the validator's reply to an idle ping
+    fn pong_received(&mut self, pong: &PongResponse) {
+        match self.pending_ping {
+            Some((nonce, sent_at)) if nonce == pong.nonce => {
+                debug!(
+                    validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                    event = "idle_pong", nonce = nonce,
+                    elapsed_us = sent_at.elapsed().as_micros() as u64;
+                    "validator answered ping"
+                );
+                self.pending_ping = None;
+            }
+            _ => debug!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "unexpected_pong", nonce = pong.nonce;
+                "ignoring unexpected pong"
+            ),
+        }
+    }
+
+    /// Check the validator speaks our version of the protocol
+    fn check_hello(&mut self, hello: &HelloRequest) -> Result<(), Error> {
+        if hello.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "version_mismatch",
+                protocol_version = hello.protocol_version,
+                software_version = hello.software_version.as_str();
+                "validator speaks protocol version {} but this KMS speaks version {}",
+                hello.protocol_version,
+                PROTOCOL_VERSION
+            );
+
+            fail!(
+                VersionMismatch,
+                "incompatible protocol versions (validator: {}, KMS: {})",
+                hello.protocol_version,
+                PROTOCOL_VERSION
+            );
+        }
+
+        debug!(
+            validator = self.label.as_str(), peer = self.peer_addr.as_str(), event = "hello",
+            protocol_version = hello.protocol_version,
+            software_version = hello.software_version.as_str();
+            "negotiated protocol version"
+        );
+
+        self.hello_received = true;
+        Ok(())
+    }
+
+    /// Handle a single request, producing the response to send back. Errors
+    /// which only affect this request (e.g. an unknown key) are reported to
+    /// the validator, while any others end the session.
+    fn handle_request(&self, request: Request) -> Result<Response, Error> {
+        match request {
+            Request::Sign(_) => traffic::record(RequestType::Sign),
+            Request::SignVote(_) => traffic::record(RequestType::SignVote),
+            Request::SignProposal(_) => traffic::record(RequestType::SignProposal),
+            Request::ShowPublicKey(_) => traffic::record(RequestType::PubKey),
+            Request::Ping(_) => traffic::record(RequestType::Ping),
+            Request::Hello(_) | Request::Pong(_) => (),
+        }
+
+        self.dispatch_request(request).or_else(|e| match ErrorResponse::for_error(&e) {
+            Some(response) => {
+                debug!(
+                    validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                    event = "request_rejected";
+                    "{}", e
+                );
+                Ok(Response::Error(response))
+            }
+            None => Err(e),
+        })
+    }
+
+    /// Perform the work a request asks for
+    fn dispatch_request(&self, request: Request) -> Result<Response, Error> {
+        let operation = match request {
+            Request::Sign(_) => Some(Operation::Sign),
+            Request::SignVote(_) => Some(Operation::SignVote),
+            Request::SignProposal(_) => Some(Operation::SignProposal),
+            Request::ShowPublicKey(_) => Some(Operation::PubKey),
+            Request::Ping(_) => Some(Operation::Ping),
+            Request::Hello(_) | Request::Pong(_) => None,
+        };
+
+        if let Some(operation) = operation {
+            self.check_operation(operation)?;
+        }
+
+        match request {
+            Request::Hello(_)
+            | Request::ShowPublicKey(_)
+            | Request::Ping(_)
+            | Request::Pong(_) => (),
+            _ => {
+                ensure!(
+                    self.hello_received,
+                    VersionMismatch,
+                    "validator sent a sign request without negotiating a protocol version"
+                );
+                metrics::sign_request();
+            }
+        }
+
+        // Use the same keyring for the whole request, even if it's reloaded
+        // partway through
+        let keyring = self.keyring.current();
+
+        let signature = match request {
+            Request::Hello(_) => return Ok(Response::Hello(HelloResponse::new())),
+            Request::Sign(req) => {
+                ensure!(
+                    self.allow_raw_sign,
+                    RawSignDisabled,
+                    "validator {} may only sign votes and proposals (allow-raw-sign is off)",
+                    self.label
+                );
+
+                let public_keys = req.keys()?;
+                let chain_id = req.chain_id.as_deref();
+                let mut signatures = if self.require_known_message {
+                    self.sign_known_message(&keyring, chain_id, &public_keys, &req.msg)?
+                } else {
+                    self.sign(&keyring, chain_id, None, &public_keys, &req.msg)?
+                };
+
+                if req.public_keys.is_none() {
+                    signatures.swap_remove(0)
+                } else {
+                    return Ok(Response::Sign(SignResponse {
+                        signature: signatures[0].clone(),
+                        signatures: Some(signatures),
+                    }));
+                }
+            }
+            Request::SignVote(req) => self.sign_consensus(
+                &keyring,
+                &req.chain_id,
+                req.height_round_step()?,
+                Some(req.vote_time()?),
+                &req.public_key()?,
+                &req.sign_bytes(),
+            )?,
+            Request::SignProposal(req) => {
+                check_pol_round(req.proposal.round, req.proposal.pol_round)?;
+
+                self.sign_consensus(
+                    &keyring,
+                    &req.chain_id,
+                    req.height_round_step(),
+                    None,
+                    &req.public_key()?,
+                    &req.sign_bytes(),
+                )?
+            }
+            Request::ShowPublicKey(req) => {
+                let chain_id = req.chain_id.as_deref();
+                let key = keyring.key_info(chain_id, &keyring.default_pubkey(chain_id)?)?;
+                let last_signed = chain_id.and_then(|id| self.last_signed(id));
+
+                return Ok(Response::PublicKey(PubKeyResponse {
+                    public_key: key.public_key.as_bytes().to_vec(),
+                    algorithm: key.public_key.algorithm(),
+                    provider: Some(key.provider_name.to_owned()),
+                    key_id: Some(key.key_id),
+                    last_signed_height: last_signed.map(|hrs| hrs.height),
+                    last_signed_round: last_signed.map(|hrs| hrs.round),
+                    last_signed_at: liveness::last_signed(&key.public_key)
+                        .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+                }));
+            }
+            Request::Ping(req) => return Ok(Response::Pong(PongResponse { nonce: req.nonce })),
+            Request::Pong(_) => {
+                fail!(RpcError, "unexpected pong");
+            }
+        };
+
+        Ok(Response::Sign(SignResponse {
+            signature,
+            signatures: None,
+        }))
+    }
+
+    /// Handle a single Amino-encoded request, producing the response to send
+    /// back. Failures to sign are reported to the validator rather than
+    /// ending the session.
+    fn handle_amino_request(&self, request: amino::Request) -> Result<amino::Response, Error> {
+        traffic::record(match request {
+            amino::Request::PubKey => RequestType::PubKey,
+            amino::Request::SignVote(_) => RequestType::SignVote,
+            amino::Request::SignProposal(_) => RequestType::SignProposal,
+            amino::Request::Ping => RequestType::Ping,
+        });
+
+        let operation = match request {
+            amino::Request::PubKey => Operation::PubKey,
+            amino::Request::SignVote(_) => Operation::SignVote,
+            amino::Request::SignProposal(_) => Operation::SignProposal,
+            amino::Request::Ping => Operation::Ping,
+        };
+
+        if let Err(e) = self.check_operation(operation) {
+            return match request {
+                amino::Request::SignVote(_) => Ok(amino::Response::SignedVote(Err(e.into()))),
+                amino::Request::SignProposal(_) => {
+                    Ok(amino::Response::SignedProposal(Err(e.into())))
+                }
+                // Neither response can carry an error, so the session ends
+                amino::Request::PubKey | amino::Request::Ping => Err(e),
+            };
+        }
+
+        let chain_id = self.chain_id
+            .as_deref()
+            .ok_or_else(|| err!(ConfigError, "chain-id is required with the amino wire format"))?;
+
+        // Use the same keyring for the whole request, even if it's reloaded
+        // partway through
+        let keyring = self.keyring.current();
+
+        match request {
+            amino::Request::PubKey => Ok(amino::Response::PubKey(
+                keyring.default_pubkey(Some(chain_id))?,
+            )),
+            amino::Request::SignVote(mut vote) => {
+                metrics::sign_request();
+
+                let result = vote.height_round_step().and_then(|hrs| {
+                    let public_key = keyring.default_pubkey(Some(chain_id))?;
+                    self.sign_consensus(
+                        &keyring,
+                        chain_id,
+                        hrs,
+                        vote.vote_time(),
+                        &public_key,
+                        &vote.sign_bytes(chain_id),
+                    )
+                });
+
+                Ok(amino::Response::SignedVote(
+                    result
+                        .map(|signature| {
+                            vote.signature = signature;
+                            vote
+                        })
+                        .map_err(amino::RemoteError::from),
+                ))
+            }
+            amino::Request::SignProposal(mut proposal) => {
+                metrics::sign_request();
+
+                let result = check_pol_round(proposal.round, proposal.pol_round)
+                    .and_then(|()| keyring.default_pubkey(Some(chain_id)))
+                    .and_then(|public_key| {
+                        self.sign_consensus(
+                            &keyring,
+                            chain_id,
+                            proposal.height_round_step(),
+                            None,
+                            &public_key,
+                            &proposal.sign_bytes(chain_id),
+                        )
+                    });
+
+                Ok(amino::Response::SignedProposal(
+                    result
+                        .map(|signature| {
+                            proposal.signature = signature;
+                            proposal
+                        })
+                        .map_err(amino::RemoteError::from),
+                ))
+            }
+            amino::Request::Ping => Ok(amino::Response::Ping),
+        }
+    }
+
+    /// Sign the message of a `Sign` request (with require-known-message on),
+    /// which must be the sign bytes of a vote or proposal. It's signed as
+    /// that vote or proposal would be, so the same double-sign and policy
+    /// checks apply.
+    fn sign_known_message(
+        &self,
+        keyring: &Keyring,
+        chain_id: Option<&str>,
+        public_keys: &[PublicKey],
+        msg: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let (msg_chain_id, hrs, timestamp) = match canonical::parse_sign_bytes(msg) {
+            Some(SignedMessage::Vote { chain_id, vote }) => {
+                (chain_id, vote.height_round_step()?, Some(vote.time()?))
+            }
+            Some(SignedMessage::Proposal { chain_id, proposal }) => {
+                check_pol_round(proposal.round, proposal.pol_round)?;
+                (chain_id, proposal.height_round_step(), None)
+            }
+            None => {
+                fail!(
+                    UnrecognizedMessage,
+                    "validator {} sent a message which isn't a vote or proposal \
+                     (require-known-message is on)",
+                    self.label
+                );
+            }
+        };
+
+        // Held to the same rules as the chain ID of a `SignVote` request
+        let msg_chain_id = ChainId::try_from(msg_chain_id)?;
+
+        if let Some(chain_id) = chain_id {
+            ensure!(
+                chain_id == msg_chain_id.as_str(),
+                RpcError,
+                "sign request for chain {} is for a message on chain {}",
+                chain_id,
+                msg_chain_id
+            );
+        }
+
+        self.sign_consensus_with_keys(keyring, &msg_chain_id, hrs, timestamp, public_keys, msg)
+    }
+
+    /// Sign a consensus message, refusing to regress or duplicate a previous
+    /// signature on the same chain. Votes also give their timestamp, which
+    /// mustn't go back in time within a round.
+    fn sign_consensus(
+        &self,
+        keyring: &Keyring,
+        chain_id: &str,
+        hrs: HeightRoundStep,
+        timestamp: Option<Timestamp>,
+        public_key: &PublicKey,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let public_keys = slice::from_ref(public_key);
+        self.sign_consensus_with_keys(keyring, chain_id, hrs, timestamp, public_keys, msg)
+            .map(|mut signatures| signatures.swap_remove(0))
+    }
+
+    /// Sign a consensus message with each of the given keys, as
+    /// `sign_consensus` does. The position is checked and recorded once, so
+    /// signing with several keys isn't taken for a double sign.
+    fn sign_consensus_with_keys(
+        &self,
+        keyring: &Keyring,
+        chain_id: &str,
+        hrs: HeightRoundStep,
+        timestamp: Option<Timestamp>,
+        public_keys: &[PublicKey],
+        msg: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Err(e) = state.check(chain_id, hrs, timestamp) {
+            metrics::double_sign_rejected();
+            debug!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "double_sign_rejected",
+                chain_id = chain_id, height = hrs.height, round = hrs.round, step = hrs.step;
+                "{}", e
+            );
+            for public_key in public_keys {
+                audit_file::record(&self.label, Some(chain_id), Some(hrs), public_key, Some(&e));
+            }
+            return Err(e);
+        }
+
+        let signatures = self.sign(keyring, Some(chain_id), Some(hrs), public_keys, msg)?;
+        state.record(chain_id, hrs, timestamp)?;
+
+        debug!(
+            validator = self.label.as_str(), peer = self.peer_addr.as_str(), event = "signed",
+            chain_id = chain_id, height = hrs.height, round = hrs.round, step = hrs.step;
+            "signed consensus message"
+        );
+
+        Ok(signatures)
+    }
+
+    /// Ensure the validator may sign with the given key, and that it's in the
+    /// keyring. Authorization is checked first (in constant time), so a
+    /// validator restricted by `authorized-keys` can't probe for other keys
+    /// the KMS holds; a key it may use but which isn't in the keyring (e.g.
+    /// because it failed a lenient self-test) is reported as unknown.
+    fn check_key(
+        &self,
+        keyring: &Keyring,
+        chain_id: Option<&str>,
+        public_key: &PublicKey,
+    ) -> Result<(), Error> {
+        let authorized = match self.authorized_keys {
+            Some(ref authorized_keys) => authorized_keys
+                .iter()
+                .fold(false, |found, key| found | key.ct_eq(public_key)),
+            None => true,
+        };
+
+        let known = authorized && keyring.contains(chain_id, public_key);
+
+        if !known {
+            debug!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "key_rejected",
+                chain_id = chain_id.unwrap_or(""), public_key:% = public_key,
+                authorized = authorized;
+                "rejected request to sign with {}", public_key
+            );
+        }
+
+        ensure!(
+            authorized,
+            Unauthorized,
+            "validator {} isn't authorized to sign with {}",
+            self.label,
+            public_key
+        );
+
+        ensure!(
+            known,
+            UnknownKey,
+            "not in keyring: {}{}",
+            public_key,
+            chain_id.map(|id| format!(" (for chain {})", id)).unwrap_or_default()
+        );
+
+        Ok(())
+    }
+
+    /// Last position signed on the given chain, for reporting to the
+    /// validator. Failing to read the state isn't fatal here (signing will
+    /// report it), so it's logged and treated as unknown.
+    fn last_signed(&self, chain_id: &str) -> Option<HeightRoundStep> {
+        self.state
+            .lock()
+            .unwrap()
+            .last_signed(chain_id)
+            .unwrap_or_else(|e| {
+                warn!(
+                    validator = self.label.as_str(), peer = self.peer_addr.as_str();
+                    "couldn't read signing state: {}", e
+                );
+                None
+            })
+    }
+
+    /// Sign a message with each of the keys identified by the given chain ID
+    /// and public keys, recording it in the audit log. Consensus messages
+    /// give their position, which is included in the log. Each attempt is
+    /// recorded in the audit file whether or not it succeeds.
+    ///
+    /// The request takes a single token from the rate limit however many
+    /// keys sign it, and fails as a whole, before anything is signed, if the
+    /// validator may not use any one of the keys.
+    fn sign(
+        &self,
+        keyring: &Keyring,
+        chain_id: Option<&str>,
+        hrs: Option<HeightRoundStep>,
+        public_keys: &[PublicKey],
+        msg: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let checked = self.check_rate_limit().and_then(|()| {
+            public_keys
+                .iter()
+                .try_for_each(|public_key| self.check_key(keyring, chain_id, public_key))
+        });
+
+        if let Err(e) = checked {
+            for public_key in public_keys {
+                audit_file::record(&self.label, chain_id, hrs, public_key, Some(&e));
+            }
+            return Err(e);
+        }
+
+        public_keys
+            .iter()
+            .map(|public_key| {
+                let result = self.try_sign(keyring, chain_id, hrs, public_key, msg);
+                audit_file::record(&self.label, chain_id, hrs, public_key, result.as_ref().err());
+                result
+            })
+            .collect()
+    }
+
+    /// Take a token from the validator's signing rate limit, if it has one
+    fn check_rate_limit(&self) -> Result<(), Error> {
+        if let Some(ref limit) = self.sign_rate_limit {
+            ensure!(
+                limit.take(),
+                RateLimited,
+                "validator {} exceeded {} sign requests per second",
+                self.label,
+                limit.rate
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Check the message may be signed with a key `sign` has already checked
+    /// the validator may use, then sign it (without the audit file)
+    fn try_sign(
+        &self,
+        keyring: &Keyring,
+        chain_id: Option<&str>,
+        hrs: Option<HeightRoundStep>,
+        public_key: &PublicKey,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        self.check_msg_len(hrs, msg.len())?;
+
+        // Taken before the clock starts, so time spent waiting for a slot
+        // isn't counted as signing latency
+        let _slot = in_flight::acquire()?;
+
+        // Only read the clock if something is going to use the measurement
+        let started_at = if cfg!(feature = "metrics") || self.slow_sign_warn.is_some()
+            || log_enabled!(Level::Debug)
+        {
+            Some(Instant::now())
+        } else {
+            None
+        };
+
+        if let Some(hrs) = hrs {
+            let key = keyring.key_info(chain_id, public_key)?;
+            let (allowed, kind) = if hrs.step == STEP_PROPOSAL {
+                (key.policy.allow_proposal, "proposals")
+            } else {
+                (key.policy.allow_vote, "votes")
+            };
+
+            ensure!(
+                allowed,
+                PolicyViolation,
+                "{}:{} isn't allowed to sign {}",
+                key.provider_name,
+                key.key_id,
+                kind
+            );
+        }
+
+        // Chains with a sign prefix have it prepended to everything signed
+        // on their behalf
+        let prefixed;
+        let msg = match chain_id.and_then(|id| self.chains.get(id)) {
+            Some(chain) if !chain.sign_prefix.is_empty() => {
+                prefixed = [chain.sign_prefix.as_slice(), msg].concat();
+                prefixed.as_slice()
+            }
+            _ => msg,
+        };
+
+        let result = keyring.sign(chain_id, public_key, msg);
+
+        if let Some(started_at) = started_at {
+            self.record_signing_latency(started_at.elapsed(), public_key, msg.len());
+        }
+
+        let signature = result.inspect_err(|e| {
+            metrics::signing_error();
+            debug!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "signing_error",
+                chain_id = chain_id.unwrap_or(""), public_key:% = public_key;
+                "{}", e
+            );
+        })?;
+
+        if self.verify_signatures {
+            if let Err(e) = public_key.verify(msg, &signature) {
+                metrics::signing_error();
+                error!(
+                    validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                    event = "signature_verification_failed",
+                    chain_id = chain_id.unwrap_or(""), public_key:% = public_key;
+                    "withholding bad signature: {}", e
+                );
+                fail!(
+                    SignatureVerificationFailed,
+                    "signature from {} failed to verify (faulty signer?): {}",
+                    public_key,
+                    e
+                );
+            }
+        }
+
+        audit::record(&self.label, chain_id, hrs, public_key);
+        webhook::record(&self.label, chain_id, hrs, public_key);
+        liveness::record(public_key);
+        Ok(signature.as_bytes().to_vec())
+    }
+
+    /// Fail if the given kind of request isn't one the validator may make
+    fn check_operation(&self, operation: Operation) -> Result<(), Error> {
+        if let Some(ref enabled) = self.enabled_operations {
+            ensure!(
+                enabled.contains(&operation),
+                OperationDisabled,
+                "validator {} may not make {} requests (enabled-operations doesn't include it)",
+                self.label,
+                operation.as_str()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Check a message is within the length bounds for its kind of request
+    /// (an arbitrary message if there's no height/round/step)
+    fn check_msg_len(&self, hrs: Option<HeightRoundStep>, len: usize) -> Result<(), Error> {
+        let (kind, limits) = match hrs {
+            None => ("message", &self.sign_msg_len),
+            Some(hrs) if hrs.step == STEP_PROPOSAL => ("proposal", &self.proposal_msg_len),
+            Some(_) => ("vote", &self.vote_msg_len),
+        };
+
+        if let Some(min) = limits.min {
+            ensure!(
+                len >= min,
+                PolicyViolation,
+                "{} to sign is {} bytes (minimum is {})",
+                kind,
+                len,
+                min
+            );
+        }
+
+        if let Some(max) = limits.max {
+            ensure!(
+                len <= max,
+                PolicyViolation,
+                "{} to sign is {} bytes (maximum is {})",
+                kind,
+                len,
+                max
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Report how long signing took, warning if it was slower than configured
+    fn record_signing_latency(&self, elapsed: Duration, public_key: &PublicKey, msg_len: usize) {
+        metrics::observe_signing_latency(elapsed);
+
+        let elapsed_us = elapsed.as_micros() as u64;
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+
+        match self.slow_sign_warn {
+            Some(threshold) if elapsed > threshold => warn!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "slow_sign",
+                public_key:% = public_key, msg_len = msg_len, elapsed_us = elapsed_us;
+                "signing took {:.3}ms (warning threshold is {}ms)",
+                elapsed_ms,
+                threshold.as_millis()
+            ),
+            _ => debug!(
+                validator = self.label.as_str(), peer = self.peer_addr.as_str(),
+                event = "sign_latency",
+                public_key:% = public_key, msg_len = msg_len, elapsed_us = elapsed_us;
+                "signing took {:.3}ms", elapsed_ms
+            ),
+        }
+    }
+}
+
+/// Ensure a proposal's proof-of-lock round is either -1 (none) or an earlier
+/// round than the proposal's, as Tendermint requires
+fn check_pol_round(round: i64, pol_round: i64) -> Result<(), Error> {
+    ensure!(
+        pol_round == -1 || (0 <= pol_round && pol_round < round),
+        InvalidProposal,
+        "invalid proposal: pol_round {} must be -1 or less than round {}",
+        pol_round,
+        round
+    );
+
+    Ok(())
+}
+
+/// Token bucket allowing bursts of up to one second's worth of sign
+/// requests, refilled continuously at the configured rate
+struct TokenBucket {
+    /// Sign requests allowed per second
+    rate: u32,
+
+    /// Available tokens, and when they were last refilled
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Create a full bucket for the given rate
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            bucket: Mutex::new((f64::from(rate), Instant::now())),
+        }
+    }
+
+    /// Take a token if one is available
+    fn take(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (ref mut tokens, ref mut refilled_at) = *bucket;
+
+        let now = Instant::now();
+        let refill = now.duration_since(*refilled_at).as_secs_f64() * f64::from(self.rate);
+        *tokens = (*tokens + refill).min(f64::from(self.rate));
+        *refilled_at = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(all(test, feature = "dalek-provider"))]
+mod tests {
+    use super::*;
+    use config::ChainConfig;
+    use ed25519::{unavailable_signer, Signature};
+    use rpc::{BlockId, ErrorCode, PartSetHeader, Proposal, PubKeyRequest, SignProposalRequest,
+              SignRequest, SignVoteRequest, Vote, VOTE_TYPE_PREVOTE};
+    use signatory::ed25519::Signature as Ed25519Signature;
+    use serde_json;
+    use std::{env, fs, io, process};
+    use toml;
+
+    /// Chain the test requests are for
+    const CHAIN_ID: &str = "test-chain";
+
+    /// Create a handler for a validator with the given config (TOML) which
+    /// has already said hello, and whose keyring holds a software key for
+    /// each of the given key settings (TOML, e.g. a signing policy). Returns
+    /// the handler and the keys' public keys, in the same order.
+    fn handler(
+        name: &str,
+        validator_config: &str,
+        key_configs: &[&str],
+    ) -> (RequestHandler, Vec<PublicKey>) {
+        let mut providers = String::new();
+        let mut paths = vec![];
+
+        for (i, key_config) in key_configs.iter().enumerate() {
+            let filename = format!("kms-handler-{}-{}-{}.key", name, i, process::id());
+            let path = env::temp_dir().join(filename);
+            fs::write(&path, [i as u8 + 1; 32]).unwrap();
+
+            providers.push_str(&format!(
+                "[dalek.keys.key{}]\npath = {:?}\n{}\n",
+                i,
+                path.display().to_string(),
+                key_config
+            ));
+            paths.push(path);
+        }
+
+        let keyring = Keyring::from_config(toml::from_str(&providers).unwrap());
+
+        for path in paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        let keyring = keyring.unwrap();
+        let public_keys = keyring
+            .public_keys()
+            .into_iter()
+            .map(|key_info| key_info.public_key)
+            .collect();
+
+        (handler_with_keyring(name, validator_config, keyring), public_keys)
+    }
+
+    /// Create a handler for a validator with the given config (TOML) which
+    /// has already said hello, and which signs with the given keyring
+    fn handler_with_keyring(name: &str, validator_config: &str, keyring: Keyring) -> RequestHandler {
+        let config: ValidatorConfig = toml::from_str(validator_config).unwrap();
+        let mut handler = RequestHandler::new(
+            name,
+            &config,
+            "127.0.0.1:26658",
+            SharedKeyring::new(keyring),
+            Arc::new(Mutex::new(SignState::default())),
+            Arc::new(Chains::new()),
+        ).unwrap();
+
+        handler.hello_received = true;
+        handler
+    }
+
+    /// Prevote at the given height
+    fn prevote(height: i64) -> Vote {
+        Vote {
+            height,
+            round: 0,
+            vote_type: VOTE_TYPE_PREVOTE,
+            block_id: BlockId::default(),
+            timestamp: "2018-01-01T00:00:00.000Z".to_owned(),
+        }
+    }
+
+    /// Proposal at the given height
+    fn proposal(height: i64) -> Proposal {
+        Proposal {
+            height,
+            round: 0,
+            block_parts_header: PartSetHeader::default(),
+            pol_round: -1,
+            pol_block_id: BlockId::default(),
+            timestamp: "2018-01-01T00:00:00.000Z".to_owned(),
+        }
+    }
+
+    /// Request to sign a prevote at the given height
+    fn sign_vote_request(public_key: &PublicKey, height: i64) -> Request {
+        Request::SignVote(SignVoteRequest {
+            public_key: public_key.as_bytes().to_vec(),
+            algorithm: public_key.algorithm(),
+            chain_id: CHAIN_ID.parse().unwrap(),
+            vote: prevote(height),
+        })
+    }
+
+    /// Request to sign a proposal at the given height
+    fn sign_proposal_request(public_key: &PublicKey, height: i64) -> Request {
+        Request::SignProposal(SignProposalRequest {
+            public_key: public_key.as_bytes().to_vec(),
+            algorithm: public_key.algorithm(),
+            chain_id: CHAIN_ID.parse().unwrap(),
+            proposal: proposal(height),
+        })
+    }
+
+    /// Request to sign the given message as it is
+    fn sign_request(public_key: &PublicKey, msg: Vec<u8>) -> Request {
+        Request::Sign(SignRequest {
+            public_key: public_key.as_bytes().to_vec(),
+            public_keys: None,
+            algorithm: public_key.algorithm(),
+            chain_id: Some(CHAIN_ID.parse().unwrap()),
+            msg,
+        })
+    }
+
+    /// Code of the error the given response reports (`None` if it's a
+    /// signature)
+    fn error_code(response: Response) -> Option<ErrorCode> {
+        match response {
+            Response::Sign(_) => None,
+            Response::Error(error) => Some(error.code),
+            other => panic!("expected Sign or Error response, got {:?}", other),
+        }
+    }
+
+    /// Signature the given response carries
+    fn signature(response: Response) -> Signature {
+        match response {
+            Response::Sign(response) => {
+                Ed25519Signature::from_bytes(&response.signature).unwrap().into()
+            }
+            other => panic!("expected Sign response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn signed_vote_verifies_over_its_canonical_bytes() {
+        let (handler, public_keys) = handler("vote-round-trip", "", &[""]);
+        let public_key = &public_keys[0];
+
+        let response = handler.handle_request(sign_vote_request(public_key, 1)).unwrap();
+        let signature = signature(response);
+
+        let sign_bytes = |height| canonical::canonical_vote_bytes(CHAIN_ID, &prevote(height));
+        public_key.verify(&sign_bytes(1), &signature).unwrap();
+        assert!(public_key.verify(&sign_bytes(2), &signature).is_err());
+    }
+
+    #[test]
+    fn show_public_key_returns_the_default_key() {
+        let (handler, public_keys) = handler("show-public-key", "", &[""]);
+        let request = Request::ShowPublicKey(PubKeyRequest {
+            chain_id: Some(CHAIN_ID.parse().unwrap()),
+        });
+
+        match handler.handle_request(request).unwrap() {
+            Response::PublicKey(response) => {
+                assert_eq!(response.public_key, public_keys[0].as_bytes());
+                assert_eq!(response.algorithm, public_keys[0].algorithm());
+                assert_eq!(response.provider.as_deref(), Some("dalek"));
+                assert_eq!(response.key_id.as_deref(), Some("key0"));
+            }
+            other => panic!("expected PublicKey response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pong_echoes_the_ping_nonce() {
+        let (handler, _) = handler("ping", "", &[""]);
+
+        for &nonce in &[0, 42, u64::MAX] {
+            match handler.handle_request(Request::Ping(PingRequest { nonce })).unwrap() {
+                Response::Pong(pong) => assert_eq!(pong.nonce, nonce),
+                other => panic!("expected Pong response, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn sign_request_for_an_unknown_key_is_answered_with_an_error() {
+        let (mut handler, _) = handler("unknown-key", "", &[""]);
+        let unknown_key = PublicKey::from_bytes(&[4u8; 32]).unwrap();
+        let request = sign_request(&unknown_key, b"some message".to_vec());
+
+        // The session is kept open, with the error sent back in place of a
+        // signature
+        let response = match handler.handle_message(&serde_json::to_vec(&request).unwrap()) {
+            Ok(Reply::Send(response)) => response,
+            Ok(_) => panic!("expected a response to send"),
+            Err(e) => panic!("expected a response to send, got {}", e),
+        };
+
+        let expected = ErrorResponse::for_error(&err!(UnknownKey, "")).unwrap();
+
+        match Response::read(&mut io::Cursor::new(response), 1024).unwrap() {
+            Response::Error(error) => assert_eq!(error.code, expected.code),
+            other => panic!("expected Error response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn key_left_out_by_lenient_self_test_is_unknown() {
+        let mut keyring = Keyring::from_seed_bytes(&[("working", &[1u8; 32])]).unwrap();
+        keyring.add_signer(unavailable_signer("broken", [4u8; 32])).unwrap();
+        assert_eq!(keyring.self_test_lenient().unwrap().len(), 1);
+
+        let handler = handler_with_keyring("lenient", "", keyring);
+        let broken_key = PublicKey::from_bytes(&[4u8; 32]).unwrap();
+
+        let response = handler.handle_request(sign_vote_request(&broken_key, 1)).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::UnknownKey));
+    }
+
+    #[test]
+    fn unauthorized_key_is_told_apart_from_an_unknown_one() {
+        let keyring =
+            Keyring::from_seed_bytes(&[("authorized", &[1u8; 32]), ("other", &[2u8; 32])]);
+        let keyring = keyring.unwrap();
+        let authorized_key = keyring.public_keys()[0].public_key.clone();
+        let other_key = keyring.public_keys()[1].public_key.clone();
+        let unknown_key = PublicKey::from_bytes(&[4u8; 32]).unwrap();
+
+        let hex = |key: &PublicKey| -> String {
+            key.as_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+        };
+        let config = format!(
+            "authorized-keys = [{:?}, {:?}]",
+            hex(&authorized_key),
+            hex(&unknown_key)
+        );
+        let handler = handler_with_keyring("authorized", &config, keyring);
+
+        let response = handler.handle_request(sign_vote_request(&authorized_key, 1)).unwrap();
+        assert_eq!(error_code(response), None);
+
+        let response = handler.handle_request(sign_vote_request(&other_key, 2)).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::Unauthorized));
+
+        let response = handler.handle_request(sign_vote_request(&unknown_key, 2)).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::UnknownKey));
+    }
+
+    #[test]
+    fn signed_vote_is_audited_with_its_validator_chain_position_and_key() {
+        audit::init(1024);
+        let (handler, public_keys) = handler("audited", "", &[""]);
+        let public_key = &public_keys[0];
+
+        let response = handler.handle_request(sign_vote_request(public_key, 7)).unwrap();
+        signature(response);
+
+        let entries: Vec<_> = audit::entries()
+            .into_iter()
+            .filter(|entry| entry.validator == "audited")
+            .collect();
+        assert_eq!(entries.len(), 1);
+
+        let entry = &entries[0];
+        assert_eq!(entry.chain_id.as_deref(), Some(CHAIN_ID));
+        assert_eq!((entry.height, entry.round), (Some(7), Some(0)));
+        assert_eq!(entry.msg_type, "prevote");
+
+        let key_hex: String = public_key.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(entry.public_key, key_hex);
+    }
+
+    #[test]
+    fn sign_prefix_is_signed_along_with_the_message() {
+        let (mut handler, public_keys) = handler("sign-prefix", "", &[""]);
+        let public_key = &public_keys[0];
+
+        let mut chains = Chains::new();
+        let chain = ChainConfig {
+            sign_prefix: b"prefix:".to_vec(),
+        };
+        chains.insert(CHAIN_ID.parse().unwrap(), chain);
+        handler.chains = Arc::new(chains);
+
+        let msg = b"some message".to_vec();
+        let response = handler.handle_request(sign_request(public_key, msg.clone())).unwrap();
+        let signature = signature(response);
+
+        public_key.verify(&[&b"prefix:"[..], &msg].concat(), &signature).unwrap();
+        assert!(public_key.verify(&msg, &signature).is_err());
+    }
+
+    #[test]
+    fn vote_only_key_is_refused_a_proposal() {
+        let (handler, public_keys) = handler("vote-only", "", &["allow-proposal = false"]);
+        let public_key = &public_keys[0];
+
+        let response = handler.handle_request(sign_proposal_request(public_key, 1)).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::PolicyViolation));
+
+        let response = handler.handle_request(sign_vote_request(public_key, 1)).unwrap();
+        assert_eq!(error_code(response), None);
+    }
+
+    #[test]
+    fn known_vote_sent_as_raw_message_is_checked_for_double_signing() {
+        let (handler, public_keys) = handler("known-vote", "require-known-message = true", &[""]);
+        let public_key = &public_keys[0];
+        let sign_bytes = || canonical::canonical_vote_bytes(CHAIN_ID, &prevote(1));
+
+        let response = handler.handle_request(sign_request(public_key, sign_bytes())).unwrap();
+        assert_eq!(error_code(response), None);
+
+        let response = handler.handle_request(sign_request(public_key, sign_bytes())).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::DoubleSign));
+
+        let response = handler.handle_request(sign_vote_request(public_key, 1)).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::DoubleSign));
+    }
+
+    #[test]
+    fn known_proposal_sent_as_raw_message_follows_the_signing_policy() {
+        let (handler, public_keys) = handler(
+            "known-proposal",
+            "require-known-message = true",
+            &["allow-proposal = false"],
+        );
+
+        let sign_bytes = canonical::canonical_proposal_bytes(CHAIN_ID, &proposal(1));
+        let response = handler.handle_request(sign_request(&public_keys[0], sign_bytes)).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::PolicyViolation));
+    }
+
+    #[test]
+    fn unknown_raw_message_is_refused() {
+        let (handler, public_keys) = handler("unknown", "require-known-message = true", &[""]);
+
+        let response = handler
+            .handle_request(sign_request(&public_keys[0], b"not a vote".to_vec()))
+            .unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::UnrecognizedMessage));
+    }
+
+    #[test]
+    fn pol_round_of_none_or_an_earlier_round_is_valid() {
+        for &(round, pol_round) in &[(0, -1), (5, -1), (1, 0), (5, 4), (5, 0)] {
+            assert!(
+                check_pol_round(round, pol_round).is_ok(),
+                "round {} pol_round {}",
+                round,
+                pol_round
+            );
+        }
+    }
+
+    #[test]
+    fn pol_round_of_this_or_a_later_round_is_invalid() {
+        for &(round, pol_round) in &[(0, 0), (5, 5), (5, 6), (0, -2), (5, i64::MIN)] {
+            match check_pol_round(round, pol_round) {
+                Err(Error::InvalidProposal { .. }) => (),
+                other => panic!(
+                    "round {} pol_round {}: expected InvalidProposal, got {:?}",
+                    round, pol_round, other
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn multi_key_sign_request_takes_one_rate_limit_token() {
+        let (handler, public_keys) = handler("rate-limited", "max-sign-rate = 1", &["", ""]);
+
+        let request = || {
+            Request::Sign(SignRequest {
+                public_key: vec![],
+                public_keys: Some(public_keys.iter().map(|key| key.as_bytes().to_vec()).collect()),
+                algorithm: Default::default(),
+                chain_id: None,
+                msg: b"signed by both keys".to_vec(),
+            })
+        };
+
+        match handler.handle_request(request()).unwrap() {
+            Response::Sign(response) => assert_eq!(response.signatures.unwrap().len(), 2),
+            other => panic!("expected Sign response, got {:?}", other),
+        }
+
+        // The bucket holds one second's worth, which the first request used
+        let response = handler.handle_request(request()).unwrap();
+        assert_eq!(error_code(response), Some(ErrorCode::RateLimited));
+    }
+}
@@ -0,0 +1,112 @@
+//! Periodic health checks of hardware signers
+//!
+//! A device can lose its connection between blocks without anyone noticing
+//! until the next signature is needed. Every so often each hardware signer's
+//! public key is fetched from its device and compared with the one it was
+//! loaded with, so a lost device is logged (and recorded in the
+//! `kms_hsm_healthy` metric) while there's still time to fix it. Software
+//! signers aren't checked.
+//!
+//! Checks are skipped while any request is being signed, so they never hold
+//! up a signature.
+
+use std::collections::HashSet;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use ed25519::{PublicKey, SharedKeyring, Signer};
+use error::Error;
+use in_flight;
+use metrics;
+
+/// Start a background thread which checks every hardware signer in the
+/// keyring each `interval`. If a `reconnect` flag is given, it's set
+/// whenever a check fails, asking for the keyring to be reloaded.
+pub fn spawn_heartbeat(
+    keyring: SharedKeyring,
+    interval: Duration,
+    reconnect: Option<Arc<AtomicBool>>,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name("hsm-health".to_owned())
+        .spawn(move || {
+            let mut failing = HashSet::new();
+
+            loop {
+                thread::sleep(interval);
+
+                if !check_signers(&keyring, &mut failing) {
+                    continue;
+                }
+
+                if let Some(ref reconnect) = reconnect {
+                    reconnect.store(true, Ordering::SeqCst);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Check each hardware signer in turn, returning whether any failed. Keys
+/// which are failing are kept in `failing` (as `provider:key-id`), so a
+/// failure is only warned about when it starts and when it ends.
+fn check_signers(keyring: &SharedKeyring, failing: &mut HashSet<String>) -> bool {
+    let mut any_failed = false;
+
+    for (public_key, signer) in keyring.current().hardware_signers() {
+        let name = format!("{}:{}", signer.provider_name, signer.key_id);
+
+        if !in_flight::is_idle() {
+            debug!("skipping health check of {} while signing", name);
+            continue;
+        }
+
+        match check_signer(&public_key, &signer) {
+            Ok(()) => {
+                if failing.remove(&name) {
+                    info!(
+                        signer = name.as_str(), event = "hsm_recovered";
+                        "{} passed its health check again", name
+                    );
+                }
+
+                metrics::hsm_health_check(&name, true);
+            }
+            Err(e) => {
+                if failing.insert(name.clone()) {
+                    warn!(
+                        signer = name.as_str(), event = "hsm_unhealthy";
+                        "health check of {} failed: {}", name, e
+                    );
+                } else {
+                    debug!("health check of {} failed: {}", name, e);
+                }
+
+                metrics::hsm_health_check(&name, false);
+                any_failed = true;
+            }
+        }
+    }
+
+    any_failed
+}
+
+/// Fetch a signer's public key from its device, checking it's the key the
+/// signer was loaded with
+fn check_signer(public_key: &PublicKey, signer: &Signer) -> Result<(), Error> {
+    let device_key = signer.public_key()?;
+
+    ensure!(
+        device_key == *public_key,
+        ProviderError,
+        "device now has key {} (expected {})",
+        device_key,
+        public_key
+    );
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! Global limit on how many requests are signed at once, across all
+//! validators, to bound the load on the signing providers
+//!
+//! Each request takes a slot for as long as it's being signed. Requests
+//! which can't get one within `SLOT_WAIT_MS` are rejected as `Overloaded`,
+//! leaving the validator to retry.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use error::Error;
+use metrics;
+
+/// How long a request may wait for a slot (in milliseconds)
+pub const SLOT_WAIT_MS: u64 = 100;
+
+/// Most requests signed at once (zero means unlimited)
+static LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of requests being signed
+static IN_FLIGHT: Mutex<usize> = Mutex::new(0);
+
+/// Signalled whenever a slot is freed
+static SLOT_FREED: Condvar = Condvar::new();
+
+/// Set the most requests which may be signed at once (zero means unlimited)
+pub fn init(limit: usize) {
+    LIMIT.store(limit, Ordering::SeqCst);
+}
+
+/// Slot taken by a request while it's being signed, freed when dropped
+pub struct Slot(());
+
+/// Take a slot for a request, waiting up to `SLOT_WAIT_MS` if they're all in
+/// use
+pub fn acquire() -> Result<Slot, Error> {
+    let limit = LIMIT.load(Ordering::SeqCst);
+    let in_flight = IN_FLIGHT.lock().unwrap();
+
+    let (mut in_flight, wait) = SLOT_FREED
+        .wait_timeout_while(in_flight, Duration::from_millis(SLOT_WAIT_MS), |in_flight| {
+            limit > 0 && *in_flight >= limit
+        })
+        .unwrap();
+
+    ensure!(
+        !wait.timed_out(),
+        Overloaded,
+        "too many requests being signed at once (maximum is {})",
+        limit
+    );
+
+    *in_flight += 1;
+    metrics::in_flight_requests(*in_flight);
+    Ok(Slot(()))
+}
+
+/// Is no request being signed at the moment?
+pub fn is_idle() -> bool {
+    *IN_FLIGHT.lock().unwrap() == 0
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        *in_flight -= 1;
+        metrics::in_flight_requests(*in_flight);
+        SLOT_FREED.notify_one();
+    }
+}
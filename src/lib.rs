@@ -0,0 +1,95 @@
+//! Key Management System for Cosmos Validators
+//!
+//! Besides the `cosmos-kms` daemon, the crate can be used as a library to
+//! sign with the same keys without running the validator connection: load a
+//! `config::Config`, build an `ed25519::Keyring` from its providers, and sign
+//! with `Keyring::sign`. Consensus messages can be turned into the bytes to
+//! sign with `rpc::SignVoteRequest::sign_bytes` and
+//! `rpc::SignProposalRequest::sign_bytes`, and `state::SignState` guards
+//! against double signing as the daemon does (see `examples/sign.rs`).
+
+extern crate base64;
+#[cfg(feature = "tokio")]
+extern crate bytes;
+extern crate chacha20poly1305;
+extern crate chrono;
+extern crate clear_on_drop;
+#[cfg(feature = "dalek-provider")]
+extern crate ed25519_dalek;
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+#[cfg(feature = "tokio")]
+extern crate futures;
+#[cfg(feature = "ledger-provider")]
+extern crate hidapi;
+extern crate hkdf;
+#[cfg(feature = "aws-kms")]
+extern crate hmac;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "metrics")]
+extern crate prometheus;
+extern crate rand;
+extern crate serde;
+extern crate simplelog;
+extern crate socket2;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
+extern crate signatory;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio")]
+extern crate tokio_util;
+extern crate toml;
+#[cfg(any(feature = "aws-kms", feature = "webhook"))]
+extern crate ureq;
+extern crate x25519_dalek;
+
+#[macro_use]
+mod macros;
+
+mod amino;
+#[cfg(feature = "tokio")]
+mod async_session;
+mod bech32;
+mod client;
+mod codec;
+mod handler;
+mod secret_connection;
+mod socket;
+mod socks5;
+
+pub mod canonical;
+pub mod chain_id;
+pub mod config;
+pub mod ed25519;
+pub mod error;
+pub mod rpc;
+pub mod state;
+
+// Used by the daemon, but not intended for use as a library
+#[doc(hidden)]
+pub mod audit;
+#[doc(hidden)]
+pub mod audit_file;
+#[doc(hidden)]
+pub mod health;
+#[doc(hidden)]
+pub mod in_flight;
+#[doc(hidden)]
+pub mod liveness;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod session;
+#[doc(hidden)]
+pub mod supervisor;
+#[doc(hidden)]
+pub mod traffic;
+#[doc(hidden)]
+pub mod webhook;
@@ -0,0 +1,38 @@
+//! When each key last produced a signature, as a compact liveness signal
+//!
+//! Unlike the audit log, only the latest time is kept for each key, so it's
+//! always on. External monitoring can alert on a key which hasn't signed
+//! within a few block intervals, either from the
+//! `kms_last_signature_timestamp_seconds` metric or from `PubKey` responses.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use ed25519::PublicKey;
+use metrics;
+
+/// Time of the last signature made with each key (since startup)
+static LAST_SIGNED: OnceLock<RwLock<HashMap<PublicKey, DateTime<Utc>>>> = OnceLock::new();
+
+/// Obtain the map of last signature times, creating it on first use
+fn last_signed_times() -> &'static RwLock<HashMap<PublicKey, DateTime<Utc>>> {
+    LAST_SIGNED.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Record that the given key has just produced a (verified) signature
+pub fn record(public_key: &PublicKey) {
+    let now = Utc::now();
+    metrics::last_signature(public_key, now.timestamp_millis() as f64 / 1000.0);
+
+    last_signed_times()
+        .write()
+        .unwrap()
+        .insert(public_key.clone(), now);
+}
+
+/// When the given key last produced a signature, if it has since the KMS
+/// started
+pub fn last_signed(public_key: &PublicKey) -> Option<DateTime<Utc>> {
+    last_signed_times().read().unwrap().get(public_key).cloned()
+}
@@ -0,0 +1,457 @@
+//! Logging setup, either as human-readable text or as one JSON object per line
+//!
+//! Logs go to the terminal unless the `[logging]` section of the config says
+//! otherwise: to stderr, to a file (rotated once it reaches a given size), or
+//! to the local syslog daemon.
+//!
+//! Log call sites can attach structured key/values (e.g.
+//! `info!(validator = label; "session closed")`). In JSON format these become
+//! fields of the emitted object, while in text format they're appended to the
+//! message as `key=value` pairs.
+//!
+//! Panics are logged too (with a backtrace if `RUST_BACKTRACE` is set), since
+//! their default output on stderr bypasses the logger and loses the thread's
+//! context.
+
+use chrono::Utc;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{self, Map, Value as JsonValue};
+use simplelog::{Config as SimplelogConfig, TermLogger};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::ffi::OsString;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::panic::{self, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+
+use config::{LogFormat, LogTarget, LoggingConfig};
+use error::Error;
+
+/// Socket the local syslog daemon listens on
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Syslog facility logs are sent with (`LOG_DAEMON`)
+#[cfg(unix)]
+const SYSLOG_FACILITY: u8 = 3;
+
+/// Identifier logs are tagged with in syslog
+#[cfg(unix)]
+const SYSLOG_IDENT: &str = "cosmos-kms";
+
+/// Initialize the global logger
+pub fn init(verbose: bool, format: LogFormat, config: &LoggingConfig) -> Result<(), Error> {
+    let level_filter = if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+
+    let output = match config.target {
+        LogTarget::Terminal => None,
+        LogTarget::Stderr => Some(Output::Stderr),
+        LogTarget::File => Some(Output::File(Mutex::new(RotatingFile::open(config)?))),
+        #[cfg(unix)]
+        LogTarget::Syslog => Some(Output::Syslog(Syslog::connect()?)),
+        #[cfg(not(unix))]
+        LogTarget::Syslog => fail!(ConfigError, "syslog isn't supported on this platform"),
+    };
+
+    let logger: Box<dyn Log> = match (format, output) {
+        (LogFormat::Text, None) => Box::new(TextLogger {
+            inner: TermLogger::new(level_filter, SimplelogConfig::default())
+                .ok_or_else(|| err!(ConfigError, "couldn't open the terminal for logging"))?,
+        }),
+        (LogFormat::Text, Some(output)) => Box::new(PlainLogger {
+            level_filter,
+            output,
+        }),
+        (LogFormat::Json, output) => Box::new(JsonLogger {
+            level_filter,
+            output: output.unwrap_or(Output::Stdout),
+        }),
+    };
+
+    log::set_boxed_logger(logger)
+        .map_err(|e| err!(ConfigError, "couldn't install logger: {}", e))?;
+    log::set_max_level(level_filter);
+
+    panic::set_hook(Box::new(log_panic));
+    Ok(())
+}
+
+/// Panic hook which logs the panic's message and location, along with a
+/// backtrace when enabled via `RUST_BACKTRACE`
+fn log_panic(info: &PanicHookInfo) {
+    let current = thread::current();
+    let thread_name = current.name().unwrap_or("<unnamed>");
+
+    let message = info.payload_as_str().unwrap_or("Box<dyn Any>");
+
+    let location = info
+        .location()
+        .map(|location| location.to_string())
+        .unwrap_or_else(|| "unknown location".to_owned());
+
+    let backtrace = Backtrace::capture();
+    let backtrace = match backtrace.status() {
+        BacktraceStatus::Captured => format!("\nstack backtrace:\n{}", backtrace),
+        _ => String::new(),
+    };
+
+    error!(
+        thread = thread_name, location = location.as_str(), event = "panic";
+        "thread '{}' panicked at {}: {}{}", thread_name, location, message, backtrace
+    );
+}
+
+/// Logs to the terminal via simplelog, appending any key/values to the message
+struct TextLogger {
+    inner: Box<TermLogger>,
+}
+
+impl Log for TextLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let mut pairs = TextPairs(String::new());
+        record.key_values().visit(&mut pairs).ok();
+
+        if pairs.0.is_empty() {
+            return self.inner.log(record);
+        }
+
+        self.inner.log(&Record::builder()
+            .args(format_args!("{}{}", record.args(), pairs.0))
+            .metadata(record.metadata().clone())
+            .module_path(record.module_path())
+            .file(record.file())
+            .line(record.line())
+            .build());
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Formats key/values as ` key=value` pairs
+struct TextPairs(String);
+
+impl<'kvs> VisitSource<'kvs> for TextPairs {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        write!(self.0, " {}={}", key, value).map_err(|_| kv::Error::msg("formatting failed"))
+    }
+}
+
+/// Logs lines of plain text (without color) to somewhere other than the
+/// terminal, appending any key/values to the message
+struct PlainLogger {
+    level_filter: LevelFilter,
+    output: Output,
+}
+
+impl Log for PlainLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut pairs = TextPairs(String::new());
+        record.key_values().visit(&mut pairs).ok();
+
+        // Syslog records the time and level itself
+        let line = if self.output.is_syslog() {
+            format!("{}: {}{}", record.target(), record.args(), pairs.0)
+        } else {
+            format!(
+                "{} [{}] {}: {}{}",
+                Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                record.level(),
+                record.target(),
+                record.args(),
+                pairs.0
+            )
+        };
+
+        self.output.write_line(record.level(), &line);
+    }
+
+    fn flush(&self) {
+        self.output.flush()
+    }
+}
+
+/// Logs one JSON object per line (to stdout, unless configured otherwise)
+struct JsonLogger {
+    level_filter: LevelFilter,
+    output: Output,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = JsonFields(Map::new());
+        fields.insert(
+            "ts",
+            Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string().into(),
+        );
+        fields.insert("level", record.level().to_string().to_lowercase().into());
+        fields.insert("target", record.target().into());
+        fields.insert("msg", record.args().to_string().into());
+        record.key_values().visit(&mut fields).ok();
+
+        if let Ok(line) = serde_json::to_string(&fields.0) {
+            self.output.write_line(record.level(), &line);
+        }
+    }
+
+    fn flush(&self) {
+        self.output.flush()
+    }
+}
+
+/// Somewhere lines of log output can be written
+enum Output {
+    /// Standard output
+    Stdout,
+
+    /// Standard error
+    Stderr,
+
+    /// A log file
+    File(Mutex<RotatingFile>),
+
+    /// The local syslog daemon
+    #[cfg(unix)]
+    Syslog(Syslog),
+}
+
+impl Output {
+    /// Write a single line of log output. Errors are ignored, since there's
+    /// nowhere left to report them.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn write_line(&self, level: Level, line: &str) {
+        match *self {
+            Output::Stdout => {
+                writeln!(io::stdout().lock(), "{}", line).ok();
+            }
+            Output::Stderr => {
+                writeln!(io::stderr().lock(), "{}", line).ok();
+            }
+            Output::File(ref file) => {
+                file.lock().unwrap().write_line(line).ok();
+            }
+            #[cfg(unix)]
+            Output::Syslog(ref syslog) => syslog.send(level, line),
+        }
+    }
+
+    /// Flush any buffered output
+    fn flush(&self) {
+        match *self {
+            Output::Stdout => {
+                io::stdout().flush().ok();
+            }
+            Output::Stderr => {
+                io::stderr().flush().ok();
+            }
+            Output::File(ref file) => {
+                file.lock().unwrap().file.flush().ok();
+            }
+            #[cfg(unix)]
+            Output::Syslog(_) => (),
+        }
+    }
+
+    /// Is this the syslog daemon (which timestamps messages itself)?
+    fn is_syslog(&self) -> bool {
+        match *self {
+            #[cfg(unix)]
+            Output::Syslog(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Log file which is rotated once it reaches a maximum size: `path` is
+/// renamed to `path.1`, `path.1` to `path.2`, and so on, with the oldest
+/// beyond the configured number of files being removed
+struct RotatingFile {
+    /// Path of the current log file
+    path: PathBuf,
+
+    /// Size at which to rotate the file (in bytes). Zero means never.
+    max_size: u64,
+
+    /// Number of rotated files to keep
+    max_files: usize,
+
+    /// Current log file
+    file: File,
+
+    /// Number of bytes in the current log file
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Open (or create) the configured log file, appending to it
+    fn open(config: &LoggingConfig) -> Result<Self, Error> {
+        let path = config
+            .path
+            .clone()
+            .ok_or_else(|| err!(ConfigError, "logging: path is required with the file target"))?;
+
+        let file = open_log_file(&path).map_err(|e| {
+            err!(ConfigError, "couldn't open log file {}: {}", path.display(), e)
+        })?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_size: config.max_size.unwrap_or(0),
+            max_files: config.max_files(),
+            file,
+            size,
+        })
+    }
+
+    /// Append a line to the file, rotating it first if it would grow beyond
+    /// the maximum size
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Move the current file aside and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match fs::rename(self.rotated_path(n), self.rotated_path(n + 1)) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                    result => result?,
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = open_log_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Path of the nth most recent rotated file (e.g. `kms.log.1`)
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+/// Open a log file for appending, creating it if need be
+fn open_log_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Connection to the local syslog daemon
+#[cfg(unix)]
+struct Syslog {
+    /// Datagram socket connected to `SYSLOG_SOCKET`
+    socket: UnixDatagram,
+
+    /// Our process ID, included in each message
+    pid: u32,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    /// Connect to the syslog daemon
+    fn connect() -> Result<Self, Error> {
+        let socket = UnixDatagram::unbound()?;
+
+        socket.connect(SYSLOG_SOCKET).map_err(|e| {
+            err!(ConfigError, "couldn't connect to syslog at {}: {}", SYSLOG_SOCKET, e)
+        })?;
+
+        Ok(Self {
+            socket,
+            pid: process::id(),
+        })
+    }
+
+    /// Send a message with the syslog severity matching the given level
+    fn send(&self, level: Level, line: &str) {
+        let severity = match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+
+        let message = format!(
+            "<{}>{}[{}]: {}",
+            SYSLOG_FACILITY * 8 + severity,
+            SYSLOG_IDENT,
+            self.pid,
+            line
+        );
+
+        self.socket.send(message.as_bytes()).ok();
+    }
+}
+
+/// Collects key/values as JSON fields, preserving numbers and booleans
+struct JsonFields(Map<String, JsonValue>);
+
+impl JsonFields {
+    fn insert(&mut self, key: &str, value: JsonValue) {
+        self.0.insert(key.to_owned(), value);
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let json = if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+
+        self.insert(key.as_str(), json);
+        Ok(())
+    }
+}
@@ -1,54 +1,62 @@
 //! Key Management System for Cosmos Validators
 
+extern crate base64;
 extern crate clear_on_drop;
-extern crate failure;
-#[macro_use]
-extern crate failure_derive;
+extern crate cosmos_kms;
 extern crate gumdrop;
 #[macro_use]
 extern crate gumdrop_derive;
 #[macro_use]
 extern crate log;
 extern crate rand;
-extern crate simplelog;
-#[macro_use]
-extern crate serde_derive;
-extern crate signatory;
-extern crate toml;
+extern crate signal_hook;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use gumdrop::Options;
-use simplelog::{CombinedLogger, LevelFilter, TermLogger};
-use simplelog::Config as LoggingConfig;
 use std::collections::BTreeMap;
 use std::env;
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-
-#[macro_use]
-mod macros;
-
-mod client;
-mod config;
-mod error;
-mod ed25519;
-mod session;
+use std::thread;
+use std::time::Duration;
 
 use clear_on_drop::ClearOnDrop;
-use client::Client;
-use config::{Config, ProviderConfig, ValidatorConfig};
+use cosmos_kms::config::{
+    self, Config, LogFormat, LogTarget, LoggingConfig, ProviderConfig, SelfTestMode, Transport,
+    ValidatorConfig,
+};
+use cosmos_kms::ed25519::{Keyring, PublicKey, SharedKeyring};
+use cosmos_kms::error::Error;
+use cosmos_kms::session::SHUTDOWN_POLL_MS;
+use cosmos_kms::supervisor::ClientSupervisor;
+#[cfg(feature = "metrics")]
+use cosmos_kms::metrics;
+use cosmos_kms::{audit, audit_file, health, in_flight, logging, rpc, traffic};
 use rand::{OsRng, Rng};
-use ed25519::Keyring;
 
 /// Unix file permissions required for private keys (i.e. owner-readable only)
 pub const PRIVATE_KEY_PERMISSIONS: u32 = 0o600;
 
+/// Environment variable giving the path to the configuration file, used if
+/// none is given on the command line
+pub const CONFIG_ENV_VAR: &str = "KMS_CONFIG";
+
+/// Configuration file used if none is given on the command line or in
+/// `KMS_CONFIG`
+pub const DEFAULT_CONFIG_FILE: &str = "kms.toml";
+
 /// Command line arguments (using gumdrop as the parser)
 #[derive(Debug, Options)]
 enum Opts {
+    #[options(help = "print an example configuration file")]
+    GenerateConfig(GenerateConfigOpts),
+
     #[options(help = "show help for a command")]
     Help(HelpOpts),
 
@@ -58,8 +66,15 @@ enum Opts {
 
     #[options(help = "run the KMS application")]
     Run(RunOpts),
+
+    #[options(help = "sign a file's contents offline with a key from the keyring")]
+    Sign(SignOpts),
 }
 
+/// Options for the `generate-config` command
+#[derive(Debug, Default, Options)]
+struct GenerateConfigOpts {}
+
 /// Options for the `help` command
 #[derive(Debug, Default, Options)]
 struct HelpOpts {
@@ -76,24 +91,52 @@ struct KeygenOpts {
 }
 
 /// Options for the `run` command
-#[derive(Debug, Options)]
+#[derive(Debug, Default, Options)]
 struct RunOpts {
-    /// Path to configuration file
+    /// Path to configuration file (default is $KMS_CONFIG, or kms.toml)
     #[options(short = "c", long = "config")]
-    config: PathBuf,
+    config: Option<PathBuf>,
 
     /// Print debugging information
     #[options(short = "v", long = "verbose")]
     verbose: bool,
+
+    /// Don't check each key can sign before starting (for debugging)
+    #[options(no_short, long = "skip-self-test")]
+    skip_self_test: bool,
+
+    /// Load and self-test the keys, print them, and exit without connecting
+    /// to any validators
+    #[options(no_short, long = "check")]
+    check: bool,
 }
 
-impl Default for RunOpts {
-    fn default() -> Self {
-        Self {
-            config: "kms.toml".into(),
-            verbose: false,
-        }
-    }
+/// Options for the `sign` command
+#[derive(Debug, Default, Options)]
+struct SignOpts {
+    /// Path to configuration file (default is $KMS_CONFIG, or kms.toml)
+    #[options(short = "c", long = "config")]
+    config: Option<PathBuf>,
+
+    /// Public key to sign with (in hex, base64, or bech32)
+    #[options(short = "k", long = "key")]
+    key: Option<String>,
+
+    /// File whose contents are to be signed
+    #[options(short = "i", long = "input")]
+    input: Option<PathBuf>,
+
+    /// Chain to sign for (needed for keys restricted to particular chains)
+    #[options(no_short, long = "chain-id")]
+    chain_id: Option<String>,
+
+    /// Keyring (from [keyrings]) holding the key, rather than [providers]
+    #[options(no_short, long = "keyring")]
+    keyring: Option<String>,
+
+    /// Print debugging information
+    #[options(short = "v", long = "verbose")]
+    verbose: bool,
 }
 
 /// Main entry point
@@ -111,10 +154,12 @@ fn main() {
     });
 
     match opts {
+        Opts::GenerateConfig(_) => generate_config(),
         Opts::Help(_commands) => help(),
         #[cfg(feature = "dalek-provider")]
         Opts::Keygen(opts) => keygen(opts.path.as_ref()),
-        Opts::Run(opts) => run(&opts.config, opts.verbose),
+        Opts::Run(opts) => run(&opts),
+        Opts::Sign(opts) => sign(&opts),
     }
 
     exit(0);
@@ -130,10 +175,18 @@ fn help() {
     println!();
 }
 
+/// Print a commented example configuration, to be edited into a real one
+fn generate_config() {
+    print!("{}", config::EXAMPLE_CONFIG);
+}
+
 /// Generate an Ed25519 secret key for use with a software provider (i.e. ed25519-dalek)
 #[cfg(feature = "dalek-provider")]
 fn keygen(output_paths: &[PathBuf]) {
-    init_logging(true);
+    logging::init(true, LogFormat::Text, &LoggingConfig::default()).unwrap_or_else(|e| {
+        eprintln!("error initializing logging: {}", e);
+        exit(1);
+    });
 
     if output_paths.len() != 1 {
         eprintln!("Usage: {} keygen [PATH]", env::args().next().unwrap());
@@ -170,75 +223,676 @@ fn keygen(output_paths: &[PathBuf]) {
 }
 
 /// Run the KMS
-fn run(config_file: &Path, verbose: bool) {
-    init_logging(verbose);
+fn run(opts: &RunOpts) {
+    let config_file = &config_path(opts.config.as_deref());
+
+    // The config selects the log format and target, so it's loaded before
+    // logging is initialized (but any error loading it is reported after)
+    let config_result = Config::load(config_file);
+
+    let (log_format, log_config) = config_result
+        .as_ref()
+        .map(|config| (config.log_format, config.logging.clone()))
+        .unwrap_or_default();
+
+    logging::init(opts.verbose, log_format, &log_config).unwrap_or_else(|e| {
+        eprintln!("error initializing logging: {}", e);
+        exit(1);
+    });
 
     info!(
-        "{} {} starting up...",
+        "{} {} (commit {}) starting up...",
         env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION")
+        rpc::SOFTWARE_VERSION,
+        rpc::GIT_COMMIT
     );
 
+    let config = config_result.unwrap_or_else(|e| {
+        error!("error reading {}: {}", config_file.display(), e);
+        exit(1);
+    });
+
+    let (if_empty, named_if_empty) = empty_keyring_policies(&config);
+
+    if opts.check {
+        check_keyring(
+            None,
+            &init_keyring(
+                config.providers,
+                opts.skip_self_test,
+                config.self_test_mode,
+                if_empty,
+            ),
+        );
+
+        let named_keyrings = init_named_keyrings(
+            config.keyrings,
+            opts.skip_self_test,
+            config.self_test_mode,
+            named_if_empty,
+        );
+
+        for (name, keyring) in &named_keyrings {
+            check_keyring(Some(name), &keyring.current());
+        }
+
+        info!("configuration OK");
+        return;
+    }
+
+    #[cfg(feature = "metrics")]
+    init_metrics(config.metrics.as_ref());
+
+    #[cfg(feature = "webhook")]
+    init_webhook(config.webhook.as_ref());
+
+    audit::init(config.audit_log_size.unwrap_or(0));
+    init_audit_file(config.audit_file.as_ref());
+    in_flight::init(config.max_in_flight_requests.unwrap_or(0));
+
+    traffic::spawn_summary_logger().unwrap_or_else(|e| {
+        error!("couldn't start request summary thread: {}", e);
+        exit(1);
+    });
+
+    let hsm_health_interval = config.hsm_health_interval();
+    let hsm_health_reconnect = config.hsm_health_reconnect();
+
     let Config {
         validators,
         providers,
-    } = load_config(config_file);
-
-    let keyring = Arc::new(init_keyring(providers));
+        keyrings,
+        state_dir,
+        chains,
+        self_test_mode,
+        ..
+    } = config;
+
+    let keyring = SharedKeyring::new(init_keyring(
+        providers,
+        opts.skip_self_test,
+        self_test_mode,
+        if_empty,
+    ));
+    let named_keyrings =
+        init_named_keyrings(keyrings, opts.skip_self_test, self_test_mode, named_if_empty);
+    log_summary(&validators, &keyring.current(), &named_keyrings);
+    let shutdown = init_signal_handlers();
+    let reload = init_reload_handler();
+    let reconnect = init_health_check(
+        &keyring,
+        &named_keyrings,
+        hsm_health_interval,
+        hsm_health_reconnect,
+    );
 
     // Spawn the validator client threads
-    let validator_clients = spawn_validator_clients(validators, &keyring);
+    let mut supervisor = ClientSupervisor::spawn(
+        validators,
+        keyring.clone(),
+        named_keyrings.clone(),
+        state_dir.as_deref(),
+        chains,
+    ).unwrap_or_else(|e| {
+        error!("couldn't start validator clients: {}", e);
+        exit(1);
+    });
 
-    // Wait for the validator client threads to exit
-    // TODO: Find something more useful for this thread to do
-    for client in validator_clients {
-        client.join();
+    // Wait until we receive SIGTERM/SIGINT or all clients have exited
+    while !shutdown.load(Ordering::SeqCst) {
+        if reload.swap(false, Ordering::SeqCst) {
+            info!("received SIGHUP; reloading keyring from {}", config_file.display());
+            reload_keyring(config_file, &keyring, &named_keyrings, opts.skip_self_test);
+        }
+
+        if reconnect.swap(false, Ordering::SeqCst) {
+            warn!(
+                event = "hsm_reconnect";
+                "hardware signer failed its health check; reloading keyring from {}",
+                config_file.display()
+            );
+            reload_keyring(config_file, &keyring, &named_keyrings, opts.skip_self_test);
+        }
+
+        supervisor.check_clients();
+
+        if supervisor.is_finished() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(SHUTDOWN_POLL_MS));
     }
+
+    if shutdown.load(Ordering::SeqCst) {
+        info!("received shutdown signal; waiting for clients to exit...");
+        supervisor.shutdown_all();
+    }
+
+    // Signing state is persisted synchronously after every signature, so
+    // once the clients have exited only the audit file is left to flush
+    supervisor.join_all();
+    audit_file::flush();
 }
 
-/// Initialize the logger
-fn init_logging(verbose: bool) {
-    let level_filter = if verbose {
-        LevelFilter::Debug
-    } else {
-        LevelFilter::Info
+/// Sign the contents of a file with a key from the keyring, offline (no
+/// validator is connected to), and print the signature in base64 on stdout.
+/// Failures, including the key not being in the keyring, are reported on
+/// stderr with exit status 1; bad arguments exit with status 2.
+fn sign(opts: &SignOpts) {
+    // Log to stderr, so stdout carries nothing but the signature
+    let log_config = LoggingConfig {
+        target: LogTarget::Stderr,
+        ..LoggingConfig::default()
     };
 
-    CombinedLogger::init(vec![
-        TermLogger::new(level_filter, LoggingConfig::default()).unwrap(),
-    ]).unwrap();
-}
+    logging::init(opts.verbose, LogFormat::Text, &log_config).unwrap_or_else(|e| {
+        eprintln!("error initializing logging: {}", e);
+        exit(1);
+    });
 
-/// Load the configuration file
-fn load_config(config_file: &Path) -> Config {
-    Config::load(config_file).unwrap_or_else(|e| {
+    let (key, input) = match (&opts.key, &opts.input) {
+        (Some(key), Some(input)) => (key, input),
+        _ => {
+            eprintln!(
+                "Usage: {} sign --key PUBKEY --input PATH [--chain-id ID] [--keyring NAME] \
+                 [-c CONFIG]",
+                env::args().next().unwrap()
+            );
+            exit(2);
+        }
+    };
+
+    let public_key = key.parse::<PublicKey>().unwrap_or_else(|e| {
+        error!("invalid key {}: {}", key, e);
+        exit(2);
+    });
+
+    let msg = fs::read(input).unwrap_or_else(|e| {
+        error!("couldn't read {}: {}", input.display(), e);
+        exit(1);
+    });
+
+    let config_file = config_path(opts.config.as_deref());
+    let mut config = Config::load(&config_file).unwrap_or_else(|e| {
         error!("error reading {}: {}", config_file.display(), e);
         exit(1);
-    })
+    });
+
+    let providers = match opts.keyring {
+        Some(ref name) => config.keyrings.remove(name).unwrap_or_else(|| {
+            error!("no such keyring: {}", name);
+            exit(1);
+        }),
+        None => config.providers,
+    };
+
+    // The signature is verified below, which tests the key just as well
+    let keyring = load_keyring(providers, true, config.self_test_mode, EmptyKeyring::Allow)
+        .unwrap_or_else(|e| {
+            error!("signer error: {}", e);
+            exit(1);
+        });
+
+    let chain_id = opts.chain_id.as_deref();
+
+    let signature = keyring
+        .sign(chain_id, &public_key, &msg)
+        .and_then(|signature| {
+            keyring.verify(chain_id, &public_key, &msg, &signature)?;
+            Ok(signature)
+        })
+        .unwrap_or_else(|e| {
+            match e {
+                Error::UnknownKey { .. } => {
+                    error!("{} (`run --check` lists the keyring's keys)", e)
+                }
+                _ => error!("{}", e),
+            }
+
+            exit(1);
+        });
+
+    info!(
+        "signed {} ({} bytes) with {}",
+        input.display(),
+        msg.len(),
+        public_key
+    );
+    println!("{}", BASE64.encode(signature.as_bytes()));
+}
+
+/// Install SIGTERM/SIGINT handlers, returning a flag which is set when one
+/// of these signals is received
+fn init_signal_handlers() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    for signal in &[signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(*signal, Arc::clone(&shutdown)).unwrap_or_else(|e| {
+            error!("couldn't install signal handler: {}", e);
+            exit(1);
+        });
+    }
+
+    shutdown
+}
+
+/// Install a SIGHUP handler, returning a flag which is set when the keyring
+/// should be reloaded
+fn init_reload_handler() -> Arc<AtomicBool> {
+    let reload = Arc::new(AtomicBool::new(false));
+
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload)).unwrap_or_else(
+        |e| {
+            error!("couldn't install signal handler: {}", e);
+            exit(1);
+        },
+    );
+
+    reload
 }
 
-/// Initialize the keyring
-fn init_keyring(config: ProviderConfig) -> Keyring {
-    Keyring::from_config(config).unwrap_or_else(|e| {
+/// Start checking the hardware signers in every keyring each `interval`
+/// (if given), returning a flag which is set when the keyrings should be
+/// reloaded to reconnect to them (only if `reconnect` is enabled)
+fn init_health_check(
+    keyring: &SharedKeyring,
+    named_keyrings: &BTreeMap<String, SharedKeyring>,
+    interval: Option<Duration>,
+    reconnect: bool,
+) -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+
+    if let Some(interval) = interval {
+        for keyring in Some(keyring).into_iter().chain(named_keyrings.values()) {
+            let reconnect = if reconnect { Some(Arc::clone(&flag)) } else { None };
+
+            health::spawn_heartbeat(keyring.clone(), interval, reconnect).unwrap_or_else(|e| {
+                error!("couldn't start hardware signer health check thread: {}", e);
+                exit(1);
+            });
+        }
+    }
+
+    flag
+}
+
+/// Path to the configuration file: the one given with `-c`, otherwise the
+/// one named by `KMS_CONFIG` (if set and nonempty), otherwise `kms.toml`
+fn config_path(config: Option<&Path>) -> PathBuf {
+    if let Some(path) = config {
+        return path.to_owned();
+    }
+
+    match env::var_os(CONFIG_ENV_VAR) {
+        Some(ref path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(DEFAULT_CONFIG_FILE),
+    }
+}
+
+/// Re-read the provider configuration and replace the keyring, and each
+/// named keyring. If anything goes wrong the current keyrings are all kept.
+/// Keyrings can't be added or removed without restarting, since clients
+/// are only given their keyring when they start.
+fn reload_keyring(
+    config_file: &Path,
+    keyring: &SharedKeyring,
+    named_keyrings: &BTreeMap<String, SharedKeyring>,
+    skip_self_test: bool,
+) {
+    let result = Config::load(config_file).and_then(|config| {
+        let (if_empty, named_if_empty) = empty_keyring_policies(&config);
+        let mode = config.self_test_mode;
+        let mut configs = config.keyrings;
+
+        let new_keyring = load_keyring(config.providers, skip_self_test, mode, if_empty)?;
+        let mut new_named_keyrings = vec![];
+
+        for (name, shared_keyring) in named_keyrings {
+            let config = configs.remove(name).ok_or_else(|| Error::ConfigError {
+                description: format!("keyring {} was removed (restart to remove it)", name),
+            })?;
+
+            let new_keyring = load_keyring(config, skip_self_test, mode, named_if_empty)?;
+            new_named_keyrings.push((name, shared_keyring, new_keyring));
+        }
+
+        for name in configs.keys() {
+            warn!("keyring {} was added, but won't be used until the KMS is restarted", name);
+        }
+
+        Ok((new_keyring, new_named_keyrings))
+    });
+
+    match result {
+        Ok((new_keyring, new_named_keyrings)) => {
+            log_keys(&new_keyring);
+            keyring.replace(new_keyring);
+
+            for (name, shared_keyring, new_keyring) in new_named_keyrings {
+                info!("keyring {}:", name);
+                log_keys(&new_keyring);
+                shared_keyring.replace(new_keyring);
+            }
+
+            info!("keyring reloaded");
+        }
+        Err(e) => error!("couldn't reload keyring (keeping current keys): {}", e),
+    }
+}
+
+/// Load a keyring (as `init_keyring` does, though failures are returned
+/// rather than exiting)
+fn load_keyring(
+    config: ProviderConfig,
+    skip_self_test: bool,
+    mode: SelfTestMode,
+    if_empty: EmptyKeyring,
+) -> Result<Keyring, Error> {
+    let mut keyring = Keyring::from_config(config)?;
+
+    if !skip_self_test {
+        self_test_keyring(&mut keyring, mode)?;
+    }
+
+    ensure_keys(&keyring, if_empty)?;
+    Ok(keyring)
+}
+
+/// Start serving metrics, if configured
+#[cfg(feature = "metrics")]
+fn init_metrics(config: Option<&config::MetricsConfig>) {
+    if let Some(config) = config {
+        metrics::serve(config).unwrap_or_else(|e| {
+            error!("metrics error: {}", e);
+            exit(1);
+        });
+    }
+}
+
+/// Start appending signing operations to the audit file (if configured)
+fn init_audit_file(config: Option<&config::AuditFileConfig>) {
+    if let Some(config) = config {
+        audit_file::start(config).unwrap_or_else(|e| {
+            error!("audit file error: {}", e);
+            exit(1);
+        });
+    }
+}
+
+/// Start sending signing events to the webhook (if configured)
+#[cfg(feature = "webhook")]
+fn init_webhook(config: Option<&config::WebhookConfig>) {
+    if let Some(config) = config {
+        cosmos_kms::webhook::start(config).unwrap_or_else(|e| {
+            error!("webhook error: {}", e);
+            exit(1);
+        });
+    }
+}
+
+/// Initialize the keyring, checking every key works unless told not to.
+/// Exits if there are no keys, unless `if_empty` allows it.
+fn init_keyring(
+    config: ProviderConfig,
+    skip_self_test: bool,
+    mode: SelfTestMode,
+    if_empty: EmptyKeyring,
+) -> Keyring {
+    let mut keyring = Keyring::from_config(config).unwrap_or_else(|e| {
         error!("signer error: {}", e);
         exit(1);
-    })
+    });
+
+    if skip_self_test {
+        warn!("skipping key self-test");
+    } else {
+        self_test_keyring(&mut keyring, mode).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+    }
+
+    ensure_keys(&keyring, if_empty).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(1);
+    });
+
+    log_keys(&keyring);
+    keyring
 }
 
-/// Spawn the validator clients (which expose the KMS "service")
-fn spawn_validator_clients(
-    config: BTreeMap<String, ValidatorConfig>,
-    keyring: &Arc<Keyring>,
-) -> Vec<Client> {
-    config
-        .iter()
-        .map(|(label, validator_config)| {
-            Client::spawn(
-                label.clone(),
-                validator_config.addr.clone(),
-                validator_config.port,
-                Arc::clone(&keyring),
-            )
+/// Initialize each named keyring (as `init_keyring` does)
+fn init_named_keyrings(
+    configs: BTreeMap<String, ProviderConfig>,
+    skip_self_test: bool,
+    mode: SelfTestMode,
+    if_empty: EmptyKeyring,
+) -> BTreeMap<String, SharedKeyring> {
+    configs
+        .into_iter()
+        .map(|(name, config)| {
+            info!("keyring {}:", name);
+            let keyring = init_keyring(config, skip_self_test, mode, if_empty);
+            (name, SharedKeyring::new(keyring))
         })
         .collect()
 }
+
+/// What to do about a keyring with no keys
+#[derive(Clone, Copy)]
+enum EmptyKeyring {
+    /// Fail, since no request could be signed
+    Refuse,
+
+    /// Carry on with a warning (in observer mode)
+    Warn,
+
+    /// Carry on quietly, since no validator uses the keyring
+    Allow,
+}
+
+/// What to do about an empty keyring from [providers], and from [keyrings].
+/// The keys from [providers] may be missing if every validator has a
+/// keyring of its own.
+fn empty_keyring_policies(config: &Config) -> (EmptyKeyring, EmptyKeyring) {
+    let if_empty = if config.observer_mode() {
+        EmptyKeyring::Warn
+    } else {
+        EmptyKeyring::Refuse
+    };
+
+    if config.uses_default_keyring() {
+        (if_empty, if_empty)
+    } else {
+        (EmptyKeyring::Allow, if_empty)
+    }
+}
+
+/// Fail if the keyring is empty, since no request could then be signed,
+/// unless told an empty keyring is acceptable
+fn ensure_keys(keyring: &Keyring, if_empty: EmptyKeyring) -> Result<(), Error> {
+    if keyring.is_empty() {
+        match if_empty {
+            EmptyKeyring::Refuse => {
+                return Err(Error::NoKeys {
+                    description: "no keys are configured".to_owned(),
+                })
+            }
+            EmptyKeyring::Warn => warn!(
+                "no keys are configured; running in observer mode, so no requests will be signed"
+            ),
+            EmptyKeyring::Allow => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Check every key in the keyring works. In lenient mode, keys which don't
+/// are left out (with a warning) as long as at least one does.
+fn self_test_keyring(keyring: &mut Keyring, mode: SelfTestMode) -> Result<(), Error> {
+    match mode {
+        SelfTestMode::Strict => keyring.self_test(),
+        SelfTestMode::Lenient => {
+            for e in keyring.self_test_lenient()? {
+                warn!(event = "key_excluded"; "{} (leaving the key out)", e);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Print the keys in the keyring (as hex, for use in `authorized-keys`),
+/// for checking a configuration without running it. Keys in a named
+/// keyring are prefixed with its name (e.g. `tenant-a/dalek:key-1`).
+fn check_keyring(name: Option<&str>, keyring: &Keyring) {
+    let prefix = name.map(|name| format!("{}/", name)).unwrap_or_default();
+
+    for key in keyring.public_keys() {
+        let public_key: String = key.public_key
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        println!("{}{}:{} {}", prefix, key.provider_name, key.key_id, public_key);
+    }
+}
+
+/// Log what the KMS is about to do, as a sanity check for operators
+fn log_summary(
+    validators: &BTreeMap<String, ValidatorConfig>,
+    keyring: &Keyring,
+    named_keyrings: &BTreeMap<String, SharedKeyring>,
+) {
+    info!("configured {} validator(s):", validators.len());
+
+    for (label, config) in validators {
+        match config.transport {
+            Transport::Tcp => {
+                let mut hosts = config.hosts().into_iter().map(|host| config.host_port(host));
+                let primary = hosts.next().unwrap();
+                let fallbacks: Vec<_> = hosts.collect();
+
+                if fallbacks.is_empty() {
+                    info!("  {}: tcp {}", label, primary);
+                } else {
+                    info!("  {}: tcp {} (fallback {})", label, primary, fallbacks.join(", "));
+                }
+            }
+            Transport::Unix => info!("  {}: unix {}", label, config.endpoint()),
+        }
+
+        if let Some(ref proxy) = config.proxy {
+            info!("    through SOCKS5 proxy {}", proxy.host_port());
+        }
+
+        if let Some(ref name) = config.keyring {
+            info!("    signing with keyring {}", name);
+        }
+    }
+
+    info!("loaded {}", key_summary(keyring));
+
+    for (name, keyring) in named_keyrings {
+        info!("keyring {}: loaded {}", name, key_summary(&keyring.current()));
+    }
+}
+
+/// Number of keys in the keyring, and how many each provider holds (e.g.
+/// `3 key(s) (2 dalek, 1 yubihsm)`)
+fn key_summary(keyring: &Keyring) -> String {
+    let mut providers = BTreeMap::new();
+
+    for key in keyring.public_keys() {
+        *providers.entry(key.provider_name).or_insert(0) += 1;
+    }
+
+    let key_count: usize = providers.values().sum();
+    let providers = providers
+        .iter()
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("{} key(s) ({})", key_count, providers)
+}
+
+/// Log the keys in the keyring so operators can see what was loaded
+fn log_keys(keyring: &Keyring) {
+    for key in keyring.public_keys() {
+        let chains = if key.chain_ids.is_empty() {
+            "any chain".to_owned()
+        } else {
+            key.chain_ids.join(", ")
+        };
+
+        info!(
+            "loaded key {}:{} {} ({})",
+            key.provider_name, key.key_id, key.public_key, chains
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Load a configuration file with the given contents
+    fn config(name: &str, toml: &str) -> Config {
+        let path = env::temp_dir().join(format!("kms-main-{}-{}.toml", name, process::id()));
+        fs::write(&path, toml).unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        config.unwrap()
+    }
+
+    #[test]
+    fn empty_keyring_is_refused_with_no_keys() {
+        let config = config(
+            "no-keys",
+            "[validators.example]\naddr = \"127.0.0.1\"\nport = 26658\n[providers]\n",
+        );
+        let (if_empty, _) = empty_keyring_policies(&config);
+
+        match ensure_keys(&Keyring::from_signers(vec![]).unwrap(), if_empty) {
+            Err(Error::NoKeys { description }) => assert_eq!(description, "no keys are configured"),
+            other => panic!("expected NoKeys, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_keyring_is_allowed_in_observer_mode() {
+        let config = config(
+            "observer",
+            "observer-mode = true\n\
+             [validators.example]\naddr = \"127.0.0.1\"\nport = 26658\n[providers]\n",
+        );
+        let (if_empty, _) = empty_keyring_policies(&config);
+
+        ensure_keys(&Keyring::from_signers(vec![]).unwrap(), if_empty).unwrap();
+    }
+
+    #[test]
+    fn config_path_falls_back_from_the_flag_to_kms_config_to_kms_toml() {
+        let saved = env::var_os(CONFIG_ENV_VAR);
+        let flag = Path::new("/etc/kms/flag.toml");
+
+        env::set_var(CONFIG_ENV_VAR, "/etc/kms/env.toml");
+        assert_eq!(config_path(Some(flag)), flag);
+        assert_eq!(config_path(None), Path::new("/etc/kms/env.toml"));
+
+        env::set_var(CONFIG_ENV_VAR, "");
+        assert_eq!(config_path(None), Path::new(DEFAULT_CONFIG_FILE));
+
+        env::remove_var(CONFIG_ENV_VAR);
+        assert_eq!(config_path(None), Path::new(DEFAULT_CONFIG_FILE));
+
+        match saved {
+            Some(value) => env::set_var(CONFIG_ENV_VAR, value),
+            None => env::remove_var(CONFIG_ENV_VAR),
+        }
+    }
+}
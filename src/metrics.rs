@@ -0,0 +1,410 @@
+//! Prometheus metrics for signing activity
+//!
+//! When the `metrics` feature is disabled, all of the recording functions
+//! are no-ops and no HTTP server is started.
+
+#[cfg(feature = "metrics")]
+pub use self::prometheus_metrics::*;
+
+#[cfg(not(feature = "metrics"))]
+pub use self::noop_metrics::*;
+
+#[cfg(feature = "metrics")]
+mod prometheus_metrics {
+    use prometheus::{Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec,
+                     IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::OnceLock;
+    use std::thread;
+    use std::time::Duration;
+
+    use audit;
+    use config::MetricsConfig;
+    use ed25519::PublicKey;
+    use error::Error;
+    use serde_json;
+
+    /// Buckets for the signing latency histogram (in seconds). Software
+    /// signers take microseconds, while HSMs can take hundreds of milliseconds.
+    const LATENCY_BUCKETS: &[f64] = &[
+        0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+    ];
+
+    /// Global set of metrics
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+    /// Metrics exported by the KMS
+    struct Metrics {
+        registry: Registry,
+        requests: IntCounterVec,
+        sign_requests: IntCounter,
+        signing_errors: IntCounter,
+        double_sign_rejections: IntCounter,
+        active_connections: IntGauge,
+        in_flight_requests: IntGauge,
+        reconnect_attempts: IntCounter,
+        reconnect_failures: IntGaugeVec,
+        reconnect_delay: GaugeVec,
+        signing_latency: Histogram,
+        webhook_drops: IntCounter,
+        audit_file_drops: IntCounter,
+        hsm_healthy: IntGaugeVec,
+        hsm_health_failures: IntCounterVec,
+        last_signature: GaugeVec,
+    }
+
+    impl Metrics {
+        /// Create and register all metrics
+        fn new() -> Self {
+            let registry = Registry::new();
+
+            let requests = IntCounterVec::new(
+                Opts::new("kms_requests_total", "Requests from validators, by type"),
+                &["type"],
+            ).unwrap();
+            let sign_requests =
+                IntCounter::new("kms_sign_requests_total", "Total sign requests").unwrap();
+            let signing_errors =
+                IntCounter::new("kms_signing_errors_total", "Signing errors").unwrap();
+            let double_sign_rejections = IntCounter::new(
+                "kms_double_sign_rejections_total",
+                "Sign requests rejected by double-signing protection",
+            ).unwrap();
+            let active_connections = IntGauge::new(
+                "kms_active_validator_connections",
+                "Currently connected validators",
+            ).unwrap();
+            let in_flight_requests = IntGauge::new(
+                "kms_in_flight_requests",
+                "Requests currently being signed, across all validators",
+            ).unwrap();
+            let reconnect_attempts =
+                IntCounter::new("kms_reconnect_attempts_total", "Validator reconnect attempts")
+                    .unwrap();
+            let reconnect_failures = IntGaugeVec::new(
+                Opts::new(
+                    "kms_reconnect_consecutive_failures",
+                    "Consecutive failed sessions with each validator",
+                ),
+                &["validator"],
+            ).unwrap();
+            let reconnect_delay = GaugeVec::new(
+                Opts::new(
+                    "kms_reconnect_delay_seconds",
+                    "Delay before the next reconnect to each validator",
+                ),
+                &["validator"],
+            ).unwrap();
+            let signing_latency = Histogram::with_opts(
+                HistogramOpts::new("kms_signing_latency_seconds", "Time taken to sign")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+            ).unwrap();
+            let webhook_drops = IntCounter::new(
+                "kms_webhook_events_dropped_total",
+                "Signing events dropped because the webhook queue was full",
+            ).unwrap();
+            let audit_file_drops = IntCounter::new(
+                "kms_audit_file_entries_dropped_total",
+                "Audit file entries dropped because they couldn't be written in time",
+            ).unwrap();
+            let hsm_healthy = IntGaugeVec::new(
+                Opts::new(
+                    "kms_hsm_healthy",
+                    "Whether each hardware signer passed its last health check",
+                ),
+                &["key"],
+            ).unwrap();
+            let hsm_health_failures = IntCounterVec::new(
+                Opts::new(
+                    "kms_hsm_health_check_failures_total",
+                    "Failed health checks of each hardware signer",
+                ),
+                &["key"],
+            ).unwrap();
+            let last_signature = GaugeVec::new(
+                Opts::new(
+                    "kms_last_signature_timestamp_seconds",
+                    "When each key last produced a signature (Unix time)",
+                ),
+                &["public_key"],
+            ).unwrap();
+
+            registry.register(Box::new(requests.clone())).unwrap();
+            registry.register(Box::new(sign_requests.clone())).unwrap();
+            registry.register(Box::new(signing_errors.clone())).unwrap();
+            registry
+                .register(Box::new(double_sign_rejections.clone()))
+                .unwrap();
+            registry.register(Box::new(active_connections.clone())).unwrap();
+            registry.register(Box::new(in_flight_requests.clone())).unwrap();
+            registry.register(Box::new(reconnect_attempts.clone())).unwrap();
+            registry.register(Box::new(reconnect_failures.clone())).unwrap();
+            registry.register(Box::new(reconnect_delay.clone())).unwrap();
+            registry.register(Box::new(signing_latency.clone())).unwrap();
+            registry.register(Box::new(webhook_drops.clone())).unwrap();
+            registry.register(Box::new(audit_file_drops.clone())).unwrap();
+            registry.register(Box::new(hsm_healthy.clone())).unwrap();
+            registry.register(Box::new(hsm_health_failures.clone())).unwrap();
+            registry.register(Box::new(last_signature.clone())).unwrap();
+
+            Self {
+                registry,
+                requests,
+                sign_requests,
+                signing_errors,
+                double_sign_rejections,
+                active_connections,
+                in_flight_requests,
+                reconnect_attempts,
+                reconnect_failures,
+                reconnect_delay,
+                signing_latency,
+                webhook_drops,
+                audit_file_drops,
+                hsm_healthy,
+                hsm_health_failures,
+                last_signature,
+            }
+        }
+    }
+
+    /// Obtain the global metrics, creating them on first use
+    fn metrics() -> &'static Metrics {
+        METRICS.get_or_init(Metrics::new)
+    }
+
+    /// A request of the given type (e.g. `sign_vote`) was received
+    pub fn request_received(request_type: &str) {
+        metrics().requests.with_label_values(&[request_type]).inc();
+    }
+
+    /// A sign request was received
+    pub fn sign_request() {
+        metrics().sign_requests.inc();
+    }
+
+    /// Signing a message failed
+    pub fn signing_error() {
+        metrics().signing_errors.inc();
+    }
+
+    /// A sign request was rejected to prevent double signing
+    pub fn double_sign_rejected() {
+        metrics().double_sign_rejections.inc();
+    }
+
+    /// A session with a validator was established
+    pub fn connection_opened() {
+        metrics().active_connections.inc();
+    }
+
+    /// A session with a validator ended
+    pub fn connection_closed() {
+        metrics().active_connections.dec();
+    }
+
+    /// Record how many requests are being signed at the moment
+    pub fn in_flight_requests(count: usize) {
+        metrics().in_flight_requests.set(count as i64);
+    }
+
+    /// A client is about to reconnect to its validator
+    pub fn reconnect_attempt() {
+        metrics().reconnect_attempts.inc();
+    }
+
+    /// Record a client's consecutive failures and the delay before it next
+    /// reconnects
+    pub fn reconnect_backoff(validator: &str, failures: u32, delay: Duration) {
+        let metrics = metrics();
+        metrics
+            .reconnect_failures
+            .with_label_values(&[validator])
+            .set(i64::from(failures));
+        metrics
+            .reconnect_delay
+            .with_label_values(&[validator])
+            .set(delay.as_secs_f64());
+    }
+
+    /// Record how long a signing operation took
+    pub fn observe_signing_latency(duration: Duration) {
+        metrics().signing_latency.observe(duration.as_secs_f64());
+    }
+
+    /// A signing event was dropped because the webhook queue was full
+    pub fn webhook_event_dropped() {
+        metrics().webhook_drops.inc();
+    }
+
+    /// An audit file entry was dropped, because the queue was full or it
+    /// couldn't be written
+    pub fn audit_file_entry_dropped() {
+        metrics().audit_file_drops.inc();
+    }
+
+    /// Record the outcome of a health check of the given hardware signer
+    /// (as `provider:key-id`)
+    pub fn hsm_health_check(key: &str, healthy: bool) {
+        let metrics = metrics();
+        metrics
+            .hsm_healthy
+            .with_label_values(&[key])
+            .set(i64::from(healthy));
+
+        if !healthy {
+            metrics.hsm_health_failures.with_label_values(&[key]).inc();
+        }
+    }
+
+    /// Record when the given key last produced a signature (as Unix time)
+    pub fn last_signature(public_key: &PublicKey, unix_time: f64) {
+        let public_key: String = public_key
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        metrics()
+            .last_signature
+            .with_label_values(&[&public_key])
+            .set(unix_time);
+    }
+
+    /// Start the HTTP server Prometheus scrapes metrics from
+    pub fn serve(config: &MetricsConfig) -> Result<(), Error> {
+        let listener = TcpListener::bind(&config.listen_addr).map_err(|e| {
+            err!(
+                ConfigError,
+                "couldn't listen for metrics on {}: {}",
+                config.listen_addr,
+                e
+            )
+        })?;
+
+        info!("serving metrics on http://{}/metrics", config.listen_addr);
+
+        thread::Builder::new()
+            .name("metrics".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = handle_scrape(stream) {
+                                debug!("error serving metrics: {}", e);
+                            }
+                        }
+                        Err(e) => debug!("error accepting metrics connection: {}", e),
+                    }
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// Answer a single HTTP request
+    fn handle_scrape(mut stream: TcpStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        // Only the request line matters, which fits in the first read
+        let mut request = [0u8; 1024];
+        let n = stream.read(&mut request)?;
+
+        let response = if request[..n].starts_with(b"GET /metrics") {
+            let mut body = vec![];
+            let encoder = TextEncoder::new();
+            encoder
+                .encode(&metrics().registry.gather(), &mut body)
+                .map_err(|e| err!(IoError, "couldn't encode metrics: {}", e))?;
+
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                encoder.format_type(),
+                body.len()
+            ).into_bytes();
+            response.extend_from_slice(&body);
+            response
+        } else if request[..n].starts_with(b"GET /audit") {
+            let body = serde_json::to_vec(&audit::entries()).unwrap();
+
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            ).into_bytes();
+            response.extend_from_slice(&body);
+            response
+        } else {
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+        };
+
+        stream.write_all(&response)?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod noop_metrics {
+    use std::time::Duration;
+
+    use ed25519::PublicKey;
+
+    /// A request of the given type (e.g. `sign_vote`) was received
+    #[inline]
+    pub fn request_received(_request_type: &str) {}
+
+    /// A sign request was received
+    #[inline]
+    pub fn sign_request() {}
+
+    /// Signing a message failed
+    #[inline]
+    pub fn signing_error() {}
+
+    /// A sign request was rejected to prevent double signing
+    #[inline]
+    pub fn double_sign_rejected() {}
+
+    /// A session with a validator was established
+    #[inline]
+    pub fn connection_opened() {}
+
+    /// A session with a validator ended
+    #[inline]
+    pub fn connection_closed() {}
+
+    /// Record how many requests are being signed at the moment
+    #[inline]
+    pub fn in_flight_requests(_count: usize) {}
+
+    /// A client is about to reconnect to its validator
+    #[inline]
+    pub fn reconnect_attempt() {}
+
+    /// Record a client's consecutive failures and the delay before it next
+    /// reconnects
+    #[inline]
+    pub fn reconnect_backoff(_validator: &str, _failures: u32, _delay: Duration) {}
+
+    /// Record how long a signing operation took
+    #[inline]
+    pub fn observe_signing_latency(_duration: Duration) {}
+
+    /// A signing event was dropped because the webhook queue was full
+    #[inline]
+    pub fn webhook_event_dropped() {}
+
+    /// An audit file entry was dropped, because the queue was full or it
+    /// couldn't be written
+    #[inline]
+    pub fn audit_file_entry_dropped() {}
+
+    /// Record the outcome of a health check of the given hardware signer
+    /// (as `provider:key-id`)
+    #[inline]
+    pub fn hsm_health_check(_key: &str, _healthy: bool) {}
+
+    /// Record when the given key last produced a signature (as Unix time)
+    #[inline]
+    pub fn last_signature(_public_key: &PublicKey, _unix_time: f64) {}
+}
@@ -0,0 +1,594 @@
+//! Remote Procedure Calls between the KMS and validator nodes
+//!
+//! Messages are JSON sent over the Secret Connection, framed by the
+//! length-delimited codec, with each request answered by exactly one response.
+//! Each session starts with a `Hello` exchange so incompatible versions of
+//! the protocol are detected before anything is signed.
+
+use chrono::DateTime;
+use serde_json;
+use std::io::Read;
+
+use canonical;
+use chain_id::ChainId;
+use codec;
+use ed25519::{KeyAlgorithm, PublicKey};
+use error::Error;
+use state::{HeightRoundStep, Timestamp, STEP_PRECOMMIT, STEP_PREVOTE, STEP_PROPOSAL};
+
+/// Version of the protocol implemented here. Bump this whenever the wire
+/// format changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Version of this KMS
+pub const SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit this KMS was built from ("unknown" if it wasn't built from a
+/// git checkout)
+pub const GIT_COMMIT: &str = env!("KMS_GIT_COMMIT");
+
+/// Vote type for prevotes
+pub const VOTE_TYPE_PREVOTE: u8 = 0x01;
+
+/// Vote type for precommits
+pub const VOTE_TYPE_PRECOMMIT: u8 = 0x02;
+
+/// Requests sent from the validator to the KMS
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    /// Negotiate the protocol version (sent once, before any signing)
+    Hello(HelloRequest),
+
+    /// Sign an arbitrary message
+    Sign(SignRequest),
+
+    /// Sign a consensus vote
+    SignVote(SignVoteRequest),
+
+    /// Sign a block proposal
+    SignProposal(SignProposalRequest),
+
+    /// Obtain the public key the KMS will sign with
+    ShowPublicKey(PubKeyRequest),
+
+    /// Keep the connection alive
+    Ping(PingRequest),
+
+    /// Reply to a `Ping` the KMS sent while the connection was idle
+    Pong(PongResponse),
+}
+
+impl Request {
+    /// Read the next request (of at most `max_len` bytes). If the validator
+    /// closed the connection between requests, the error is
+    /// `ConnectionClosed`.
+    pub fn read<R: Read>(reader: &mut R, max_len: usize) -> Result<Self, Error> {
+        Self::decode(&codec::decode(reader, max_len)?)
+    }
+
+    /// Decode a request from its JSON encoding
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|e| err!(RpcError, "malformed request: {}", e))
+    }
+
+    /// Serialize this request, ready to be written to the connection
+    pub fn to_vec(&self) -> Vec<u8> {
+        codec::encode(&serde_json::to_vec(self).unwrap())
+    }
+}
+
+/// Announce the validator's protocol and software versions
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HelloRequest {
+    /// Protocol version the validator speaks
+    pub protocol_version: u32,
+
+    /// Validator software version (informational)
+    #[serde(default)]
+    pub software_version: String,
+}
+
+/// Sign an arbitrary message with the given key (or keys)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignRequest {
+    /// Public key identifying the signing key (omitted if `public_keys` is
+    /// given)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub public_key: Vec<u8>,
+
+    /// Several keys which should all sign the message, e.g. the old and new
+    /// keys while rotating from one to the other
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_keys: Option<Vec<Vec<u8>>>,
+
+    /// Algorithm of the signing key(s) (default `ed25519`)
+    #[serde(default)]
+    pub algorithm: KeyAlgorithm,
+
+    /// Chain the signature is for (optional, for compatibility with
+    /// validators which don't send it)
+    #[serde(default)]
+    pub chain_id: Option<ChainId>,
+
+    /// Message to be signed
+    pub msg: Vec<u8>,
+}
+
+impl SignRequest {
+    /// Every key the message should be signed with: `public_keys` if given,
+    /// otherwise just `public_key`
+    pub fn keys(&self) -> Result<Vec<PublicKey>, Error> {
+        let keys = match self.public_keys {
+            Some(ref public_keys) => {
+                ensure!(
+                    self.public_key.is_empty(),
+                    RpcError,
+                    "sign request can't have both public_key and public_keys"
+                );
+                ensure!(!public_keys.is_empty(), RpcError, "sign request has no public_keys");
+                public_keys.iter().map(Vec::as_slice).collect()
+            }
+            None => vec![self.public_key.as_slice()],
+        };
+
+        keys.into_iter()
+            .map(|key| PublicKey::new(self.algorithm, key))
+            .collect()
+    }
+}
+
+/// Sign a vote with the given key
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignVoteRequest {
+    /// Public key identifying the signing key
+    pub public_key: Vec<u8>,
+
+    /// Algorithm of the signing key (default `ed25519`)
+    #[serde(default)]
+    pub algorithm: KeyAlgorithm,
+
+    /// Chain the vote is being cast on
+    pub chain_id: ChainId,
+
+    /// Vote to be signed
+    pub vote: Vote,
+}
+
+impl SignVoteRequest {
+    /// Key the vote should be signed with
+    pub fn public_key(&self) -> Result<PublicKey, Error> {
+        PublicKey::new(self.algorithm, &self.public_key)
+    }
+
+    /// Position in the consensus process this vote would be signed at
+    pub fn height_round_step(&self) -> Result<HeightRoundStep, Error> {
+        self.vote.height_round_step()
+    }
+
+    /// Time the vote was cast
+    pub fn vote_time(&self) -> Result<Timestamp, Error> {
+        self.vote.time()
+    }
+
+    /// Canonical JSON encoding of this vote, i.e. the bytes to be signed
+    pub fn sign_bytes(&self) -> Vec<u8> {
+        canonical::canonical_vote_bytes(&self.chain_id, &self.vote)
+    }
+}
+
+/// Sign a proposal with the given key
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignProposalRequest {
+    /// Public key identifying the signing key
+    pub public_key: Vec<u8>,
+
+    /// Algorithm of the signing key (default `ed25519`)
+    #[serde(default)]
+    pub algorithm: KeyAlgorithm,
+
+    /// Chain the proposal is being made on
+    pub chain_id: ChainId,
+
+    /// Proposal to be signed
+    pub proposal: Proposal,
+}
+
+impl SignProposalRequest {
+    /// Key the proposal should be signed with
+    pub fn public_key(&self) -> Result<PublicKey, Error> {
+        PublicKey::new(self.algorithm, &self.public_key)
+    }
+
+    /// Position in the consensus process this proposal would be signed at
+    pub fn height_round_step(&self) -> HeightRoundStep {
+        self.proposal.height_round_step()
+    }
+
+    /// Canonical JSON encoding of this proposal, i.e. the bytes to be signed
+    pub fn sign_bytes(&self) -> Vec<u8> {
+        canonical::canonical_proposal_bytes(&self.chain_id, &self.proposal)
+    }
+}
+
+/// Request the public key the KMS signs with
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PubKeyRequest {
+    /// Chain the key will be used on (optional)
+    #[serde(default)]
+    pub chain_id: Option<ChainId>,
+}
+
+/// Keepalive sent while the connection is otherwise idle (by either side)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PingRequest {
+    /// Arbitrary value echoed back in the `PongResponse`
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+/// Consensus vote for a particular block (or nil)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Vote {
+    /// Block height
+    pub height: i64,
+
+    /// Consensus round
+    pub round: i64,
+
+    /// Vote type (prevote or precommit)
+    #[serde(rename = "type")]
+    pub vote_type: u8,
+
+    /// Block being voted on
+    pub block_id: BlockId,
+
+    /// Timestamp in Tendermint's canonical format (`2006-01-02T15:04:05.000Z`)
+    pub timestamp: String,
+}
+
+impl Vote {
+    /// Position in the consensus process this vote would be signed at
+    pub fn height_round_step(&self) -> Result<HeightRoundStep, Error> {
+        let step = match self.vote_type {
+            VOTE_TYPE_PREVOTE => STEP_PREVOTE,
+            VOTE_TYPE_PRECOMMIT => STEP_PRECOMMIT,
+            other => {
+                fail!(RpcError, "invalid vote type: {}", other);
+            }
+        };
+
+        Ok(HeightRoundStep {
+            height: self.height,
+            round: self.round,
+            step,
+        })
+    }
+
+    /// Time the vote was cast
+    pub fn time(&self) -> Result<Timestamp, Error> {
+        let time = DateTime::parse_from_rfc3339(&self.timestamp).map_err(|e| {
+            err!(RpcError, "invalid vote timestamp {:?}: {}", self.timestamp, e)
+        })?;
+
+        Ok(Timestamp {
+            seconds: time.timestamp(),
+            nanos: i64::from(time.timestamp_subsec_nanos()),
+        })
+    }
+}
+
+/// Block proposal
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Proposal {
+    /// Block height
+    pub height: i64,
+
+    /// Consensus round
+    pub round: i64,
+
+    /// Header of the part set for the proposed block
+    pub block_parts_header: PartSetHeader,
+
+    /// Proof-of-lock round (-1 if none)
+    pub pol_round: i64,
+
+    /// Block ID of the proof-of-lock
+    pub pol_block_id: BlockId,
+
+    /// Timestamp in Tendermint's canonical format (`2006-01-02T15:04:05.000Z`)
+    pub timestamp: String,
+}
+
+impl Proposal {
+    /// Position in the consensus process this proposal would be signed at
+    pub fn height_round_step(&self) -> HeightRoundStep {
+        HeightRoundStep {
+            height: self.height,
+            round: self.round,
+            step: STEP_PROPOSAL,
+        }
+    }
+}
+
+/// Identifies a block by its hash and part set header
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BlockId {
+    /// Block hash
+    #[serde(default, with = "hex_bytes", skip_serializing_if = "Vec::is_empty")]
+    pub hash: Vec<u8>,
+
+    /// Header of the block's part set
+    #[serde(default, rename = "parts", skip_serializing_if = "PartSetHeader::is_empty")]
+    pub parts_header: PartSetHeader,
+}
+
+/// Header of a block part set
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PartSetHeader {
+    /// Merkle root of the part set
+    #[serde(default, with = "hex_bytes", skip_serializing_if = "Vec::is_empty")]
+    pub hash: Vec<u8>,
+
+    /// Number of parts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub total: i64,
+}
+
+impl PartSetHeader {
+    /// Is this an empty (i.e. nil) part set header?
+    fn is_empty(&self) -> bool {
+        self.hash.is_empty() && self.total == 0
+    }
+}
+
+/// Responses sent from the KMS to the validator
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    /// The KMS's protocol and software versions
+    Hello(HelloResponse),
+
+    /// Signature over the requested message
+    Sign(SignResponse),
+
+    /// Public key the KMS signs with
+    PublicKey(PubKeyResponse),
+
+    /// Reply to a keepalive
+    Pong(PongResponse),
+
+    /// Check the validator is still there, after the connection has been
+    /// idle for a while (expects a `Pong` request in reply)
+    Ping(PingRequest),
+
+    /// The request was rejected, but the connection remains usable
+    Error(ErrorResponse),
+}
+
+impl Response {
+    /// Read the next response (of at most `max_len` bytes). If the
+    /// connection was closed between responses, the error is
+    /// `ConnectionClosed`.
+    pub fn read<R: Read>(reader: &mut R, max_len: usize) -> Result<Self, Error> {
+        serde_json::from_slice(&codec::decode(reader, max_len)?)
+            .map_err(|e| err!(RpcError, "malformed response: {}", e))
+    }
+
+    /// Serialize this response, ready to be written to the connection
+    pub fn to_vec(&self) -> Vec<u8> {
+        codec::encode(&serde_json::to_vec(self).unwrap())
+    }
+}
+
+/// Reply to a `HelloRequest`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HelloResponse {
+    /// Protocol version the KMS speaks
+    pub protocol_version: u32,
+
+    /// KMS software version
+    pub software_version: String,
+
+    /// Git commit the KMS was built from
+    #[serde(default)]
+    pub git_commit: String,
+}
+
+impl HelloResponse {
+    /// Describe this KMS
+    pub fn new() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            software_version: SOFTWARE_VERSION.to_owned(),
+            git_commit: GIT_COMMIT.to_owned(),
+        }
+    }
+}
+
+impl Default for HelloResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Signature produced in response to a sign request
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignResponse {
+    /// Ed25519 signature (by the first key, if several were requested)
+    pub signature: Vec<u8>,
+
+    /// Signatures by each of a request's `public_keys`, in the same order
+    /// (only sent in reply to multi-key requests)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signatures: Option<Vec<Vec<u8>>>,
+}
+
+/// Public key returned in response to a `PubKeyRequest`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PubKeyResponse {
+    /// Public key
+    pub public_key: Vec<u8>,
+
+    /// Algorithm the key is used with
+    #[serde(default)]
+    pub algorithm: KeyAlgorithm,
+
+    /// Name of the signature provider holding the key (e.g. `yubihsm`), so
+    /// operators can confirm which key the validator is bound to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+
+    /// ID of the key within its provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+
+    /// Height last signed on the requested chain, so a restarting validator
+    /// knows what the KMS has already committed to (absent if no chain was
+    /// given or nothing has been signed on it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_signed_height: Option<i64>,
+
+    /// Round last signed at `last_signed_height`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_signed_round: Option<i64>,
+
+    /// When the key last produced a signature (RFC 3339), on any chain and
+    /// for any validator (absent if it hasn't since the KMS started)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_signed_at: Option<String>,
+}
+
+/// Reply to a `PingRequest`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PongResponse {
+    /// Nonce from the corresponding `PingRequest`
+    pub nonce: u64,
+}
+
+/// Reason a request was rejected, sent in place of the usual response
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorResponse {
+    /// Kind of failure
+    pub code: ErrorCode,
+
+    /// Description of the failure
+    pub message: String,
+}
+
+impl ErrorResponse {
+    /// Describe the given error to the validator, if it only affects the
+    /// request which caused it. Returns `None` for errors which should close
+    /// the connection instead.
+    pub fn for_error(error: &Error) -> Option<Self> {
+        let code = match *error {
+            Error::UnknownKey { .. } => ErrorCode::UnknownKey,
+            Error::Unauthorized { .. } => ErrorCode::Unauthorized,
+            Error::PolicyViolation { .. } => ErrorCode::PolicyViolation,
+            Error::RawSignDisabled { .. } => ErrorCode::RawSignDisabled,
+            Error::OperationDisabled { .. } => ErrorCode::OperationDisabled,
+            Error::UnrecognizedMessage { .. } => ErrorCode::UnrecognizedMessage,
+            Error::SigningDisabled { .. } => ErrorCode::SigningDisabled,
+            Error::SignerUnavailable { .. } => ErrorCode::SignerUnavailable,
+            Error::DoubleSign { .. } => ErrorCode::DoubleSign,
+            Error::TimestampRegression { .. } => ErrorCode::TimestampRegression,
+            Error::RateLimited { .. } => ErrorCode::RateLimited,
+            Error::Overloaded { .. } => ErrorCode::Overloaded,
+            Error::InvalidChainId { .. }
+            | Error::InvalidKey { .. }
+            | Error::InvalidProposal { .. }
+            | Error::RpcError { .. } => ErrorCode::InvalidRequest,
+            Error::AppNotOpen { .. }
+            | Error::DeviceLocked { .. }
+            | Error::ProviderError { .. }
+            | Error::SignatureVerificationFailed { .. }
+            | Error::SigningError { .. }
+            | Error::Throttled { .. } => ErrorCode::SigningFailed,
+            _ => return None,
+        };
+
+        Some(Self {
+            code,
+            message: error.to_string(),
+        })
+    }
+}
+
+/// Kinds of rejected request
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub enum ErrorCode {
+    /// No key in the keyring matches the request
+    UnknownKey,
+
+    /// The validator isn't allowed to use the requested key
+    Unauthorized,
+
+    /// The key's policy doesn't allow signing this kind of message
+    PolicyViolation,
+
+    /// The validator isn't allowed to sign arbitrary messages
+    RawSignDisabled,
+
+    /// The validator isn't allowed to make this kind of request at all
+    OperationDisabled,
+
+    /// The message isn't a vote or proposal, and the validator may only sign
+    /// those
+    UnrecognizedMessage,
+
+    /// The key can only verify signatures (e.g. the KMS is an observer)
+    SigningDisabled,
+
+    /// The key is known, but the device or service holding it isn't
+    /// responding
+    SignerUnavailable,
+
+    /// Signing would regress or duplicate a previous signature
+    DoubleSign,
+
+    /// The validator is requesting signatures too quickly
+    RateLimited,
+
+    /// The KMS is busy signing for other validators
+    Overloaded,
+
+    /// A vote's timestamp is earlier than one already signed in its round
+    TimestampRegression,
+
+    /// The request was malformed
+    InvalidRequest,
+
+    /// The signing provider failed
+    SigningFailed,
+}
+
+/// Helper for `skip_serializing_if`
+fn is_zero(n: &i64) -> bool {
+    *n == 0
+}
+
+/// Hashes (and other byte strings) are encoded as uppercase hex strings
+pub(crate) mod hex_bytes {
+    use serde::de::Error as DeError;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd-length hex string"));
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| D::Error::custom("invalid hex string"))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,904 @@
+//! Secret Connection: an encrypted transport based on Tendermint's
+//! Station-to-Station (STS) handshake.
+//!
+//! Both sides exchange ephemeral X25519 public keys, compute a shared secret,
+//! and derive a pair of ChaCha20-Poly1305 keys from it (one per direction)
+//! using HKDF-SHA256. All subsequent traffic is sent as fixed-size sealed
+//! frames, each carrying up to `DATA_MAX_SIZE` bytes of plaintext.
+//!
+//! The key exchange alone doesn't say who the peer is, so each side then
+//! sends the other an Amino `AuthSigMessage` over the encrypted channel:
+//! its Ed25519 identity key, and a signature over a challenge derived from
+//! the same shared secret. The handshake only completes once the peer's
+//! signature has been verified.
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use clear_on_drop::ClearOnDrop;
+use clear_on_drop::clear::Clear;
+use hkdf::Hkdf;
+use rand::{OsRng, Rng};
+use sha2::{Digest, Sha256};
+use signatory::ed25519::{FromSeed, Signature, Signer};
+use signatory::providers::dalek::Ed25519Signer;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use x25519_dalek::{PublicKey as EphemeralPublic, StaticSecret};
+
+use amino::AuthSigMessage;
+use codec;
+use ed25519::PublicKey;
+use error::Error;
+
+#[cfg(feature = "tokio")]
+pub use self::nonblocking::{AsyncSecretConnection, Handshake};
+
+/// Size of an X25519 ephemeral public key
+pub const EPHEMERAL_KEY_SIZE: usize = 32;
+
+/// Maximum number of plaintext bytes carried in a single frame
+pub const DATA_MAX_SIZE: usize = 1024;
+
+/// Size of the little endian length prefix at the start of each frame
+const DATA_LEN_SIZE: usize = 4;
+
+/// Size of a plaintext frame (length prefix plus padded data)
+const TOTAL_FRAME_SIZE: usize = DATA_MAX_SIZE + DATA_LEN_SIZE;
+
+/// Size of a Poly1305 authentication tag
+const TAG_SIZE: usize = 16;
+
+/// Size of a frame as it appears on the wire
+const SEALED_FRAME_SIZE: usize = TOTAL_FRAME_SIZE + TAG_SIZE;
+
+/// Size of the challenge the remote peer signs to authenticate itself
+const CHALLENGE_SIZE: usize = 32;
+
+/// Size of a peer ID (truncated SHA-256 of the peer's identity key)
+const PEER_ID_SIZE: usize = 20;
+
+/// Largest `AuthSigMessage` accepted from the peer (an Ed25519 key and
+/// signature take 106 bytes)
+const MAX_AUTH_SIG_MSG_SIZE: usize = 1024;
+
+/// Size of the seed an identity key is made from
+const IDENTITY_SEED_SIZE: usize = 32;
+
+/// HKDF "info" parameter used when deriving the session keys
+const HKDF_INFO: &[u8] = b"TENDERMINT_SECRET_CONNECTION_KEY_AND_CHALLENGE_GEN";
+
+/// Ed25519 key the KMS proves its identity to validators with
+pub type IdentityKey = Ed25519Signer;
+
+/// Load the KMS's identity key from the given file (a raw 32-byte seed, as
+/// for dalek keys), or make up a new one if no file is given
+pub fn identity_key(path: Option<&Path>) -> Result<IdentityKey, Error> {
+    let mut seed = ClearOnDrop::new(vec![0u8; IDENTITY_SEED_SIZE]);
+
+    match path {
+        Some(path) => {
+            seed.clear();
+            File::open(path)
+                .and_then(|mut file| file.read_to_end(seed.as_mut()))
+                .map_err(|e| {
+                    err!(
+                        ConfigError,
+                        "can't read secret key {}: {} (check that the path exists and is \
+                         readable)",
+                        path.display(),
+                        e
+                    )
+                })?;
+        }
+        None => {
+            OsRng::new()
+                .map_err(|e| err!(HandshakeError, "couldn't initialize RNG: {}", e))?
+                .fill_bytes(seed.as_mut());
+        }
+    }
+
+    IdentityKey::from_seed(&seed).map_err(|e| err!(InvalidKey, "invalid secret key: {}", e))
+}
+
+/// Encrypted connection wrapping an underlying I/O handler (e.g. `TcpStream`)
+pub struct SecretConnection<IoHandler: Read + Write> {
+    /// Underlying I/O handler frames are read from and written to
+    io_handler: IoHandler,
+
+    /// Ephemeral public key sent by the remote peer during the handshake
+    remote_eph_pubkey: [u8; EPHEMERAL_KEY_SIZE],
+
+    /// Identity key the remote peer authenticated itself with (set once
+    /// the handshake is complete)
+    remote_pubkey: Option<PublicKey>,
+
+    /// Ciphers for sealing and opening frames
+    ciphers: FrameCiphers,
+
+    /// Decrypted data which has been received but not yet read. A frame
+    /// can carry the end of one request and the start of the next, so this
+    /// outlives each read rather than being discarded with it.
+    recv_buffer: Vec<u8>,
+}
+
+impl<IoHandler: Read + Write> SecretConnection<IoHandler> {
+    /// Perform the handshake over the given I/O handler, proving our
+    /// identity with the given key, and returning an encrypted connection
+    /// to the authenticated peer on success
+    pub fn new(mut io_handler: IoHandler, identity_key: &IdentityKey) -> Result<Self, Error> {
+        let (local_eph_secret, local_eph_pubkey) = ephemeral_keypair()?;
+        io_handler.write_all(local_eph_pubkey.as_bytes())?;
+
+        let mut remote_eph_pubkey = [0u8; EPHEMERAL_KEY_SIZE];
+        io_handler
+            .read_exact(&mut remote_eph_pubkey)
+            .map_err(|e| err!(HandshakeError, "couldn't read remote ephemeral key: {}", e))?;
+
+        let (ciphers, challenge) =
+            key_exchange(&local_eph_secret, &local_eph_pubkey, &remote_eph_pubkey)?;
+
+        let mut connection = Self {
+            io_handler,
+            remote_eph_pubkey,
+            remote_pubkey: None,
+            ciphers,
+            recv_buffer: vec![],
+        };
+
+        // Ours is sent before the peer's is read, as the peer does likewise
+        let auth_msg = auth_sig_message(identity_key, &challenge)?;
+        connection
+            .write_all(&auth_msg)
+            .and_then(|()| connection.flush())
+            .map_err(|e| err!(HandshakeError, "couldn't send our authentication: {}", e))?;
+
+        let remote_auth_msg = codec::decode(&mut connection, MAX_AUTH_SIG_MSG_SIZE)
+            .map_err(|e| err!(HandshakeError, "couldn't read peer authentication: {}", e))?;
+
+        connection.remote_pubkey = Some(verify_peer(&challenge, &remote_auth_msg)?);
+        Ok(connection)
+    }
+
+    /// Ephemeral public key the remote peer sent during the handshake
+    pub fn remote_eph_pubkey(&self) -> &[u8; EPHEMERAL_KEY_SIZE] {
+        &self.remote_eph_pubkey
+    }
+
+    /// Identity key the remote peer authenticated itself with
+    pub fn remote_pubkey(&self) -> &PublicKey {
+        self.remote_pubkey
+            .as_ref()
+            .expect("handshake completed without authenticating the peer")
+    }
+
+    /// Underlying I/O handler (e.g. for setting socket options)
+    pub fn io_handler(&self) -> &IoHandler {
+        &self.io_handler
+    }
+
+    /// Is there decrypted data waiting to be read?
+    pub fn has_buffered_data(&self) -> bool {
+        !self.recv_buffer.is_empty()
+    }
+
+    /// Read and decrypt the next frame into the receive buffer, returning
+    /// `false` if the peer closed the connection at a frame boundary
+    fn recv_frame(&mut self) -> io::Result<bool> {
+        let mut sealed_frame = vec![0u8; SEALED_FRAME_SIZE];
+        let mut pos = 0;
+
+        while pos < SEALED_FRAME_SIZE {
+            match self.io_handler.read(&mut sealed_frame[pos..])? {
+                0 if pos == 0 => return Ok(false),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => pos += n,
+            }
+        }
+
+        self.ciphers.open(&mut sealed_frame, &mut self.recv_buffer)?;
+        Ok(true)
+    }
+
+    /// Encrypt and send a single frame containing at most `DATA_MAX_SIZE` bytes
+    fn send_frame(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let sealed_frame = self.ciphers.seal(chunk)?;
+        self.io_handler.write_all(&sealed_frame)
+    }
+}
+
+impl<IoHandler: Read + Write> Read for SecretConnection<IoHandler> {
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        while self.recv_buffer.is_empty() {
+            if !self.recv_frame()? {
+                return Ok(0);
+            }
+        }
+
+        let n = cmp::min(data.len(), self.recv_buffer.len());
+        data[..n].copy_from_slice(&self.recv_buffer[..n]);
+        self.recv_buffer.drain(..n);
+
+        Ok(n)
+    }
+}
+
+impl<IoHandler: Read + Write> Write for SecretConnection<IoHandler> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        for chunk in data.chunks(DATA_MAX_SIZE) {
+            self.send_frame(chunk)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io_handler.flush()
+    }
+}
+
+/// The same connection over tokio's `AsyncRead`/`AsyncWrite`, for sessions
+/// run as tasks (see `async_session`). The futures are written out by hand,
+/// as the crate is built as Rust 2015, which has no `async`/`await`.
+#[cfg(feature = "tokio")]
+mod nonblocking {
+    use bytes::BytesMut;
+    use std::cmp;
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio_util::codec::Decoder;
+    use x25519_dalek::{PublicKey as EphemeralPublic, StaticSecret};
+
+    use super::{auth_sig_message, ephemeral_keypair, key_exchange, verify_peer, FrameCiphers,
+                IdentityKey, CHALLENGE_SIZE, EPHEMERAL_KEY_SIZE, DATA_MAX_SIZE,
+                MAX_AUTH_SIG_MSG_SIZE, SEALED_FRAME_SIZE};
+    use codec::RpcCodec;
+    use ed25519::PublicKey;
+    use error::Error;
+
+    /// Encrypted connection wrapping an asynchronous I/O handler (e.g.
+    /// tokio's `TcpStream`)
+    pub struct AsyncSecretConnection<IoHandler> {
+        /// Underlying I/O handler frames are read from and written to
+        io_handler: IoHandler,
+
+        /// Ephemeral public key sent by the remote peer during the handshake
+        remote_eph_pubkey: [u8; EPHEMERAL_KEY_SIZE],
+
+        /// Identity key the remote peer authenticated itself with (set once
+        /// the handshake is complete)
+        remote_pubkey: Option<PublicKey>,
+
+        /// Ciphers for sealing and opening frames
+        ciphers: FrameCiphers,
+
+        /// Decrypted data which has been received but not yet read
+        recv_buffer: Vec<u8>,
+
+        /// The sealed frame currently being received...
+        recv_frame: Vec<u8>,
+
+        /// ...and how much of it has arrived
+        recv_frame_pos: usize,
+
+        /// The sealed frame currently being sent...
+        send_frame: Vec<u8>,
+
+        /// ...and how much of it has been written
+        send_frame_pos: usize,
+    }
+
+    impl<IoHandler: AsyncRead + AsyncWrite + Unpin> AsyncSecretConnection<IoHandler> {
+        /// Perform the handshake over the given I/O handler, proving our
+        /// identity with the given key, and resolving to an encrypted
+        /// connection to the authenticated peer on success
+        pub fn handshake(
+            io_handler: IoHandler,
+            identity_key: IdentityKey,
+        ) -> Result<Handshake<IoHandler>, Error> {
+            let (local_eph_secret, local_eph_pubkey) = ephemeral_keypair()?;
+
+            Ok(Handshake {
+                io_handler: Some(io_handler),
+                identity_key,
+                local_eph_secret,
+                local_eph_pubkey,
+                sent: 0,
+                remote_eph_pubkey: [0u8; EPHEMERAL_KEY_SIZE],
+                received: 0,
+                connection: None,
+                challenge: [0u8; CHALLENGE_SIZE],
+                auth_msg: vec![],
+                auth_sent: 0,
+                remote_auth_msg: BytesMut::new(),
+            })
+        }
+
+        /// Ephemeral public key the remote peer sent during the handshake
+        pub fn remote_eph_pubkey(&self) -> &[u8; EPHEMERAL_KEY_SIZE] {
+            &self.remote_eph_pubkey
+        }
+
+        /// Identity key the remote peer authenticated itself with
+        pub fn remote_pubkey(&self) -> &PublicKey {
+            self.remote_pubkey
+                .as_ref()
+                .expect("handshake completed without authenticating the peer")
+        }
+
+        /// Finish writing the frame currently being sent (if any)
+        fn poll_send_frame(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+            while self.send_frame_pos < self.send_frame.len() {
+                let io_handler = Pin::new(&mut self.io_handler);
+
+                match io_handler.poll_write(cx, &self.send_frame[self.send_frame_pos..]) {
+                    Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                    Poll::Ready(Ok(n)) => self.send_frame_pos += n,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<IoHandler: AsyncRead + AsyncWrite + Unpin> AsyncRead for AsyncSecretConnection<IoHandler> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            data: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            let this = &mut *self;
+
+            while this.recv_buffer.is_empty() {
+                let mut frame = ReadBuf::new(&mut this.recv_frame[this.recv_frame_pos..]);
+
+                match Pin::new(&mut this.io_handler).poll_read(cx, &mut frame) {
+                    Poll::Ready(Ok(())) => (),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+
+                match frame.filled().len() {
+                    // The peer closed the connection at a frame boundary
+                    0 if this.recv_frame_pos == 0 => return Poll::Ready(Ok(())),
+                    0 => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                    n => this.recv_frame_pos += n,
+                }
+
+                if this.recv_frame_pos == SEALED_FRAME_SIZE {
+                    this.recv_frame_pos = 0;
+                    this.ciphers.open(&mut this.recv_frame, &mut this.recv_buffer)?;
+                }
+            }
+
+            let n = cmp::min(data.remaining(), this.recv_buffer.len());
+            data.put_slice(&this.recv_buffer[..n]);
+            this.recv_buffer.drain(..n);
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<IoHandler: AsyncRead + AsyncWrite + Unpin> AsyncWrite for AsyncSecretConnection<IoHandler> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            data: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if data.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let this = &mut *self;
+
+            // Only one frame is buffered at a time, so the previous one has
+            // to go before this one can be sealed
+            match this.poll_send_frame(cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+
+            let chunk = &data[..cmp::min(data.len(), DATA_MAX_SIZE)];
+            this.send_frame = this.ciphers.seal(chunk)?;
+            this.send_frame_pos = 0;
+
+            Poll::Ready(Ok(chunk.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            match self.poll_send_frame(cx) {
+                Poll::Ready(Ok(())) => Pin::new(&mut self.io_handler).poll_flush(cx),
+                other => other,
+            }
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            match self.poll_send_frame(cx) {
+                Poll::Ready(Ok(())) => Pin::new(&mut self.io_handler).poll_shutdown(cx),
+                other => other,
+            }
+        }
+    }
+
+    /// Future for the handshake: sends our ephemeral key and waits for the
+    /// peer's, then exchanges authentication messages over the encrypted
+    /// connection
+    pub struct Handshake<IoHandler> {
+        /// Underlying I/O handler (until the key exchange is complete)
+        io_handler: Option<IoHandler>,
+
+        /// Key we prove our identity with
+        identity_key: IdentityKey,
+
+        /// Our ephemeral key pair
+        local_eph_secret: StaticSecret,
+        local_eph_pubkey: EphemeralPublic,
+
+        /// How much of our key has been sent
+        sent: usize,
+
+        /// The peer's ephemeral key, and how much of it has arrived
+        remote_eph_pubkey: [u8; EPHEMERAL_KEY_SIZE],
+        received: usize,
+
+        /// Once the key exchange is complete: the connection, the challenge
+        /// both sides sign, and our authentication message (length-prefixed)
+        connection: Option<AsyncSecretConnection<IoHandler>>,
+        challenge: [u8; CHALLENGE_SIZE],
+        auth_msg: Vec<u8>,
+
+        /// How much of our authentication message has been sent
+        auth_sent: usize,
+
+        /// The peer's authentication message, as it arrives
+        remote_auth_msg: BytesMut,
+    }
+
+    impl<IoHandler: AsyncRead + AsyncWrite + Unpin> Handshake<IoHandler> {
+        /// Exchange ephemeral keys, then set up the encrypted connection
+        fn poll_key_exchange(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+            let io_handler = self.io_handler
+                .as_mut()
+                .expect("handshake polled after completion");
+
+            while self.sent < EPHEMERAL_KEY_SIZE {
+                let local_eph_pubkey = &self.local_eph_pubkey.as_bytes()[self.sent..];
+
+                match Pin::new(&mut *io_handler).poll_write(cx, local_eph_pubkey) {
+                    Poll::Ready(Ok(0)) => {
+                        return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()))
+                    }
+                    Poll::Ready(Ok(n)) => self.sent += n,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            while self.received < EPHEMERAL_KEY_SIZE {
+                let mut buf = ReadBuf::new(&mut self.remote_eph_pubkey[self.received..]);
+
+                match Pin::new(&mut *io_handler).poll_read(cx, &mut buf) {
+                    Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                        return Poll::Ready(Err(err!(
+                            HandshakeError,
+                            "couldn't read remote ephemeral key: connection closed"
+                        )));
+                    }
+                    Poll::Ready(Ok(())) => self.received += buf.filled().len(),
+                    Poll::Ready(Err(e)) => {
+                        return Poll::Ready(Err(err!(
+                            HandshakeError,
+                            "couldn't read remote ephemeral key: {}",
+                            e
+                        )));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            let (ciphers, challenge) = key_exchange(
+                &self.local_eph_secret,
+                &self.local_eph_pubkey,
+                &self.remote_eph_pubkey,
+            )?;
+
+            self.auth_msg = auth_sig_message(&self.identity_key, &challenge)?;
+            self.challenge = challenge;
+            self.connection = Some(AsyncSecretConnection {
+                io_handler: self.io_handler.take().unwrap(),
+                remote_eph_pubkey: self.remote_eph_pubkey,
+                remote_pubkey: None,
+                ciphers,
+                recv_buffer: vec![],
+                recv_frame: vec![0u8; SEALED_FRAME_SIZE],
+                recv_frame_pos: 0,
+                send_frame: vec![],
+                send_frame_pos: 0,
+            });
+
+            Poll::Ready(Ok(()))
+        }
+
+        /// Send our authentication message, then read and check the peer's
+        fn poll_authenticate(&mut self, cx: &mut Context) -> Poll<Result<PublicKey, Error>> {
+            let connection = self.connection.as_mut().unwrap();
+
+            while self.auth_sent < self.auth_msg.len() {
+                match Pin::new(&mut *connection).poll_write(cx, &self.auth_msg[self.auth_sent..]) {
+                    Poll::Ready(Ok(n)) => self.auth_sent += n,
+                    Poll::Ready(Err(e)) => {
+                        return Poll::Ready(Err(err!(
+                            HandshakeError,
+                            "couldn't send our authentication: {}",
+                            e
+                        )));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            ready!(Pin::new(&mut *connection).poll_flush(cx))
+                .map_err(|e| err!(HandshakeError, "couldn't send our authentication: {}", e))?;
+
+            // Read a byte at a time, so nothing the peer sends after its
+            // authentication is taken from the connection
+            let mut codec = RpcCodec::new(MAX_AUTH_SIG_MSG_SIZE);
+
+            loop {
+                let decoded = codec.decode(&mut self.remote_auth_msg).map_err(|e| {
+                    err!(HandshakeError, "couldn't read peer authentication: {}", e)
+                })?;
+
+                if let Some(remote_auth_msg) = decoded {
+                    return Poll::Ready(verify_peer(&self.challenge, &remote_auth_msg));
+                }
+
+                let mut byte = [0u8];
+                let mut buf = ReadBuf::new(&mut byte);
+
+                match Pin::new(&mut *connection).poll_read(cx, &mut buf) {
+                    Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                        return Poll::Ready(Err(err!(
+                            HandshakeError,
+                            "couldn't read peer authentication: connection closed"
+                        )));
+                    }
+                    Poll::Ready(Ok(())) => self.remote_auth_msg.extend_from_slice(&byte),
+                    Poll::Ready(Err(e)) => {
+                        return Poll::Ready(Err(err!(
+                            HandshakeError,
+                            "couldn't read peer authentication: {}",
+                            e
+                        )));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+
+    impl<IoHandler: AsyncRead + AsyncWrite + Unpin> Future for Handshake<IoHandler> {
+        type Output = Result<AsyncSecretConnection<IoHandler>, Error>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+            let this = &mut *self;
+
+            if this.connection.is_none() {
+                ready!(this.poll_key_exchange(cx))?;
+            }
+
+            let remote_pubkey = ready!(this.poll_authenticate(cx))?;
+            let mut connection = this.connection.take().unwrap();
+            connection.remote_pubkey = Some(remote_pubkey);
+
+            Poll::Ready(Ok(connection))
+        }
+    }
+}
+
+/// Ciphers for each direction of an established connection, with the
+/// number of frames each has processed (used as their nonces)
+struct FrameCiphers {
+    /// Cipher used to open frames received from the peer
+    recv_cipher: ChaCha20Poly1305,
+
+    /// Cipher used to seal frames sent to the peer
+    send_cipher: ChaCha20Poly1305,
+
+    /// Number of frames received thus far (used as the receive nonce)
+    recv_nonce: u64,
+
+    /// Number of frames sent thus far (used as the send nonce)
+    send_nonce: u64,
+}
+
+impl FrameCiphers {
+    /// Decrypt a frame received from the peer in place, appending the data
+    /// it carries to the given buffer
+    fn open(&mut self, sealed_frame: &mut [u8], recv_buffer: &mut Vec<u8>) -> io::Result<()> {
+        let (frame, tag) = sealed_frame.split_at_mut(TOTAL_FRAME_SIZE);
+        let nonce = next_nonce(&mut self.recv_nonce)?;
+
+        self.recv_cipher
+            .decrypt_in_place_detached(&nonce, b"", frame, Tag::from_slice(tag))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "frame decryption failed"))?;
+
+        let mut len_bytes = [0u8; DATA_LEN_SIZE];
+        len_bytes.copy_from_slice(&frame[..DATA_LEN_SIZE]);
+        let data_len = u32::from_le_bytes(len_bytes) as usize;
+
+        if data_len > DATA_MAX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame length too large: {}", data_len),
+            ));
+        }
+
+        recv_buffer.extend_from_slice(&frame[DATA_LEN_SIZE..DATA_LEN_SIZE + data_len]);
+        Ok(())
+    }
+
+    /// Encrypt a frame containing at most `DATA_MAX_SIZE` bytes, ready to
+    /// be sent to the peer
+    fn seal(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        debug_assert!(chunk.len() <= DATA_MAX_SIZE);
+
+        let mut sealed_frame = vec![0u8; SEALED_FRAME_SIZE];
+        sealed_frame[..DATA_LEN_SIZE].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+        sealed_frame[DATA_LEN_SIZE..DATA_LEN_SIZE + chunk.len()].copy_from_slice(chunk);
+
+        let nonce = next_nonce(&mut self.send_nonce)?;
+        let tag = self.send_cipher
+            .encrypt_in_place_detached(&nonce, b"", &mut sealed_frame[..TOTAL_FRAME_SIZE])
+            .map_err(|_| io::Error::other("frame encryption failed"))?;
+
+        sealed_frame[TOTAL_FRAME_SIZE..].copy_from_slice(&tag);
+        Ok(sealed_frame)
+    }
+}
+
+/// Generate our ephemeral X25519 key pair for a handshake
+fn ephemeral_keypair() -> Result<(StaticSecret, EphemeralPublic), Error> {
+    let mut seed = [0u8; EPHEMERAL_KEY_SIZE];
+    OsRng::new()
+        .map_err(|e| err!(HandshakeError, "couldn't initialize RNG: {}", e))?
+        .fill_bytes(&mut seed);
+
+    let local_eph_secret = StaticSecret::from(seed);
+    seed.clear();
+
+    let local_eph_pubkey = EphemeralPublic::from(&local_eph_secret);
+    Ok((local_eph_secret, local_eph_pubkey))
+}
+
+/// Complete the key exchange once the peer's ephemeral key has arrived,
+/// deriving the ciphers and the authentication challenge
+fn key_exchange(
+    local_eph_secret: &StaticSecret,
+    local_eph_pubkey: &EphemeralPublic,
+    remote_eph_pubkey: &[u8; EPHEMERAL_KEY_SIZE],
+) -> Result<(FrameCiphers, [u8; CHALLENGE_SIZE]), Error> {
+    ensure!(
+        remote_eph_pubkey != local_eph_pubkey.as_bytes(),
+        HandshakeError,
+        "remote ephemeral key is identical to our own (reflection attack?)"
+    );
+
+    let shared_secret =
+        local_eph_secret.diffie_hellman(&EphemeralPublic::from(*remote_eph_pubkey));
+
+    ensure!(
+        shared_secret.was_contributory(),
+        HandshakeError,
+        "malformed remote ephemeral key (low order point)"
+    );
+
+    let local_is_least = local_eph_pubkey.as_bytes() < remote_eph_pubkey;
+    let (recv_cipher, send_cipher, challenge) =
+        derive_keys(shared_secret.as_bytes(), local_is_least);
+
+    let ciphers = FrameCiphers {
+        recv_cipher,
+        send_cipher,
+        recv_nonce: 0,
+        send_nonce: 0,
+    };
+
+    Ok((ciphers, challenge))
+}
+
+/// Our authentication message: our identity key and its signature over the
+/// challenge, length-prefixed for sending
+fn auth_sig_message(
+    identity_key: &IdentityKey,
+    challenge: &[u8; CHALLENGE_SIZE],
+) -> Result<Vec<u8>, Error> {
+    let public_key = identity_key
+        .public_key()
+        .map_err(|e| err!(HandshakeError, "couldn't obtain our identity key: {}", e))?;
+
+    let signature = identity_key
+        .sign(challenge)
+        .map_err(|e| err!(HandshakeError, "couldn't sign the challenge: {}", e))?;
+
+    let auth_msg = AuthSigMessage {
+        public_key: PublicKey::from(public_key),
+        signature: signature.as_bytes().to_vec(),
+    };
+
+    Ok(auth_msg.to_vec())
+}
+
+/// Check the peer's authentication message (without its length prefix),
+/// returning its identity key if it signed the challenge
+fn verify_peer(challenge: &[u8; CHALLENGE_SIZE], auth_msg: &[u8]) -> Result<PublicKey, Error> {
+    let auth_msg = AuthSigMessage::decode(auth_msg)
+        .map_err(|e| err!(HandshakeError, "malformed peer authentication: {}", e))?;
+
+    let signature = Signature::from_bytes(&auth_msg.signature)
+        .map_err(|e| err!(HandshakeError, "malformed peer signature: {}", e))?;
+
+    auth_msg
+        .public_key
+        .verify(challenge, &signature.into())
+        .map_err(|_| err!(HandshakeError, "peer failed to authenticate: bad signature"))?;
+
+    Ok(auth_msg.public_key)
+}
+
+/// Peer ID for the given identity key: the hex-encoded first 20 bytes of
+/// its SHA-256 hash, as used for Tendermint node IDs
+pub fn peer_id(public_key: &PublicKey) -> String {
+    Sha256::digest(public_key.as_bytes())[..PEER_ID_SIZE]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Derive the receive and send ciphers, and the authentication challenge,
+/// from the X25519 shared secret.
+///
+/// The peer whose ephemeral public key sorts lowest receives with the first
+/// derived key and sends with the second, and vice versa.
+fn derive_keys(
+    shared_secret: &[u8],
+    local_is_least: bool,
+) -> (ChaCha20Poly1305, ChaCha20Poly1305, [u8; CHALLENGE_SIZE]) {
+    let mut okm = ClearOnDrop::new(vec![0u8; 64 + CHALLENGE_SIZE]);
+
+    Hkdf::<Sha256>::new(None, shared_secret)
+        .expand(HKDF_INFO, okm.as_mut())
+        .expect("96 bytes is a valid HKDF-SHA256 output length");
+
+    let mut challenge = [0u8; CHALLENGE_SIZE];
+    challenge.copy_from_slice(&okm[64..]);
+
+    let first = ChaCha20Poly1305::new(Key::from_slice(&okm[..32]));
+    let second = ChaCha20Poly1305::new(Key::from_slice(&okm[32..64]));
+
+    if local_is_least {
+        (first, second, challenge)
+    } else {
+        (second, first, challenge)
+    }
+}
+
+/// Compute the nonce for the next frame, incrementing the given counter
+fn next_nonce(counter: &mut u64) -> io::Result<Nonce> {
+    let mut nonce = Nonce::default();
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+
+    *counter = counter
+        .checked_add(1)
+        .ok_or_else(|| io::Error::other("nonce overflow"))?;
+
+    Ok(nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    fn identity(seed: u8) -> IdentityKey {
+        IdentityKey::from_seed(&[seed; IDENTITY_SEED_SIZE]).unwrap()
+    }
+
+    fn public_key(identity_key: &IdentityKey) -> PublicKey {
+        PublicKey::from(identity_key.public_key().unwrap())
+    }
+
+    fn public_key_of<IoHandler: Read + Write>(
+        connection: &SecretConnection<IoHandler>,
+    ) -> PublicKey {
+        connection.remote_pubkey().clone()
+    }
+
+    #[test]
+    fn handshake_authenticates_both_ends() {
+        let (kms_socket, validator_socket) = UnixStream::pair().unwrap();
+        let validator = thread::spawn(move || {
+            let mut connection = SecretConnection::new(validator_socket, &identity(2)).unwrap();
+            let mut greeting = [0u8; 5];
+            connection.read_exact(&mut greeting).unwrap();
+            (public_key_of(&connection), greeting)
+        });
+
+        let mut kms = SecretConnection::new(kms_socket, &identity(1)).unwrap();
+        kms.write_all(b"hello").unwrap();
+
+        let (validator_saw, greeting) = validator.join().unwrap();
+        assert_eq!(kms.remote_pubkey(), &public_key(&identity(2)));
+        assert_eq!(validator_saw, public_key(&identity(1)));
+        assert_eq!(&greeting, b"hello");
+    }
+
+    #[test]
+    fn peer_signing_the_wrong_challenge_is_rejected() {
+        let (kms_socket, mut validator_socket) = UnixStream::pair().unwrap();
+        let validator = thread::spawn(move || {
+            let (eph_secret, eph_pubkey) = ephemeral_keypair().unwrap();
+            validator_socket.write_all(eph_pubkey.as_bytes()).unwrap();
+
+            let mut remote_eph_pubkey = [0u8; EPHEMERAL_KEY_SIZE];
+            validator_socket.read_exact(&mut remote_eph_pubkey).unwrap();
+
+            let (ciphers, _) = key_exchange(&eph_secret, &eph_pubkey, &remote_eph_pubkey).unwrap();
+            let mut connection = SecretConnection {
+                io_handler: validator_socket,
+                remote_eph_pubkey,
+                remote_pubkey: None,
+                ciphers,
+                recv_buffer: vec![],
+            };
+
+            let forged = auth_sig_message(&identity(2), &[0u8; CHALLENGE_SIZE]).unwrap();
+            connection.write_all(&forged).unwrap();
+        });
+
+        match SecretConnection::new(kms_socket, &identity(1)) {
+            Err(Error::HandshakeError { .. }) => (),
+            Err(other) => panic!("expected HandshakeError, got {:?}", other),
+            Ok(_) => panic!("expected HandshakeError, got a connection"),
+        }
+
+        validator.join().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_handshake_authenticates_against_blocking_peer() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let validator = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            public_key_of(&SecretConnection::new(socket, &identity(2)).unwrap())
+        });
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let socket = std::net::TcpStream::connect(addr).unwrap();
+        socket.set_nonblocking(true).unwrap();
+
+        let _guard = runtime.enter();
+        let socket = tokio::net::TcpStream::from_std(socket).unwrap();
+        let handshake = AsyncSecretConnection::handshake(socket, identity(1)).unwrap();
+        let connection = runtime.block_on(handshake).unwrap();
+
+        assert_eq!(connection.remote_pubkey(), &public_key(&identity(2)));
+        assert_eq!(validator.join().unwrap(), public_key(&identity(1)));
+    }
+}
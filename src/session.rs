@@ -1,35 +1,869 @@
 //! A session with a validator node
 
-use std::io::Write;
-use std::net::TcpStream;
-use std::sync::Arc;
+use socket2::{self, Domain, Protocol, SockRef, TcpKeepalive, Type};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use codec;
+use config::{Chains, ProxyConfig, Transport, ValidatorConfig};
 use error::Error;
-use ed25519::Keyring;
+use ed25519::SharedKeyring;
+use handler::{Reply, RequestHandler};
+use metrics;
+use secret_connection::{self, SecretConnection};
+use socket::Socket;
+use socks5;
+use state::SignState;
 
-/// A (soon-to-be-encrypted) session with a validator node
+/// How often to check whether we've been asked to shut down (in milliseconds)
+pub const SHUTDOWN_POLL_MS: u64 = 500;
+
+/// What to check when the validator can't be reached
+pub const CONNECT_HINT: &str =
+    "check that the validator is running and its priv_validator_laddr is this address";
+
+/// An encrypted session with a validator node
 pub struct Session {
-    /// TCP connection to a validator node
-    socket: TcpStream,
+    /// Secret Connection to a validator node
+    connection: SecretConnection<Box<dyn Socket>>,
+
+    /// Signs requests and decides what to send back
+    handler: RequestHandler,
 
-    /// Keyring of signature keys
-    keyring: Arc<Keyring>,
+    /// Flag which is set when the client has been asked to shut down
+    shutdown: Arc<AtomicBool>,
+
+    /// Maximum time to wait for data from the validator (if any)
+    read_timeout: Option<Duration>,
+
+    /// Largest request the validator may send (in bytes)
+    max_request_bytes: usize,
 }
 
 impl Session {
-    /// Create a new session with the validator at the given address/port
-    pub fn new(addr: &str, port: u16, keyring: Arc<Keyring>) -> Result<Self, Error> {
-        debug!("Connecting to {}:{}...", addr, port);
+    /// Create a new session with the validator described by the given
+    /// config, at the given one of its endpoints (see
+    /// `ValidatorConfig::endpoints`)
+    pub fn new(
+        label: &str,
+        config: &ValidatorConfig,
+        endpoint: &str,
+        keyring: SharedKeyring,
+        state: Arc<Mutex<SignState>>,
+        chains: Arc<Chains>,
+        shutdown: Arc<AtomicBool>,
+    ) -> Result<Self, Error> {
+        let addr = endpoint;
+        let port = config.port;
+        let read_timeout = config.read_timeout();
+        let handler = RequestHandler::new(label, config, endpoint, keyring, state, chains)?;
+
+        debug!(
+            validator = label, addr = addr, port = port, event = "connecting";
+            "connecting to {}...", addr
+        );
+
+        let socket = match config.transport {
+            Transport::Tcp => connect_tcp(label, config, addr)?,
+            Transport::Unix => connect_unix(config)?,
+        };
+
+        socket.set_read_timeout(read_timeout)?;
+        socket.set_write_timeout(config.write_timeout())?;
+
+        let identity_key = secret_connection::identity_key(config.secret_key.as_deref())?;
+        let connection = SecretConnection::new(socket, &identity_key)?;
+
+        let remote_eph_pubkey = connection
+            .remote_eph_pubkey()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        debug!(
+            validator = label, addr = addr, port = port, event = "connected",
+            remote_eph_pubkey = remote_eph_pubkey.as_str();
+            "established secret connection"
+        );
+
+        let peer_id = secret_connection::peer_id(connection.remote_pubkey());
+
+        if let Some(peer_ids) = config.peer_ids() {
+            ensure!(
+                peer_ids.contains(&peer_id),
+                UntrustedPeer,
+                "validator {}: peer {} is not in peer-ids",
+                label,
+                peer_id
+            );
+        }
+
+        debug!(
+            validator = label, addr = addr, port = port, event = "peer_authenticated",
+            peer_id = peer_id.as_str();
+            "authenticated peer {}", peer_id
+        );
+
+        metrics::connection_opened();
+
+        Ok(Self {
+            connection,
+            handler,
+            shutdown,
+            read_timeout,
+            max_request_bytes: config.max_request_bytes(),
+        })
+    }
+
+    /// Where the validator is connected from: `host:port` for TCP, or the
+    /// socket path for Unix domain sockets
+    pub fn peer_addr(&self) -> &str {
+        self.handler.peer_addr()
+    }
+
+    /// Handle incoming requests from the validator until we're asked to shut
+    /// down. If the validator closes the connection between requests, the
+    /// error is `ConnectionClosed`.
+    pub fn handle_requests(&mut self) -> Result<(), Error> {
+        let mut last_activity = Instant::now();
+
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                debug!(
+                    validator = self.handler.label(), peer = self.peer_addr(),
+                    event = "session_shutdown";
+                    "shutdown requested; closing session"
+                );
+                return Ok(());
+            }
+
+            if !self.wait_for_request()? {
+                if let Some(ping) = self.handler.check_idle(last_activity)? {
+                    self.send_response(&ping)?;
+                }
+
+                continue;
+            }
+
+            let msg = codec::decode(&mut self.connection, self.max_request_bytes)?;
+
+            match self.handler.handle_message(&msg)? {
+                Reply::None => (),
+                Reply::Send(response) => self.send_response(&response)?,
+                Reply::SendAndClose(response, e) => {
+                    self.send_response(&response)?;
+                    return Err(e);
+                }
+            }
+
+            last_activity = Instant::now();
+        }
+    }
+
+    /// Send an encoded response, making sure all of it has been handed to
+    /// the socket (rather than sitting in a buffer) before returning. A write
+    /// which times out means the validator has stopped reading, so the
+    /// connection is treated as dead.
+    fn send_response(&mut self, response: &[u8]) -> Result<(), Error> {
+        let result = self.connection
+            .write_all(response)
+            .and_then(|()| self.connection.flush());
+
+        result.map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => err!(
+                Timeout,
+                "validator {} stopped reading responses (write timed out)",
+                self.handler.label()
+            ),
+            _ => e.into(),
+        })
+    }
+
+    /// Wait up to `SHUTDOWN_POLL_MS` for the validator to send something,
+    /// returning `true` if there's data (or EOF) ready to be read.
+    ///
+    /// The polling timeout is only in effect while waiting between requests,
+    /// so it can never interrupt a partially read message.
+    fn wait_for_request(&self) -> Result<bool, Error> {
+        if self.connection.has_buffered_data() {
+            return Ok(true);
+        }
+
+        let socket = self.connection.io_handler();
+        socket.set_read_timeout(Some(Duration::from_millis(SHUTDOWN_POLL_MS)))?;
+        let peek_result = socket.wait_readable();
+        socket.set_read_timeout(self.read_timeout)?;
+
+        match peek_result {
+            Ok(_) => Ok(true),
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Connect to the validator at the given host over TCP, directly or through
+/// its proxy (if any)
+fn connect_tcp(
+    label: &str,
+    config: &ValidatorConfig,
+    addr: &str,
+) -> Result<Box<dyn Socket>, Error> {
+    let socket = match config.proxy {
+        Some(ref proxy) => connect_proxy(label, config, proxy, addr)?,
+        None => connect_host(label, config, addr, config.port).map_err(|failure| match failure {
+            ConnectFailure::Unresolved(e) => err!(IoError, "couldn't resolve {}: {}", addr, e),
+            ConnectFailure::NoAddresses => {
+                err!(IoError, "{} didn't resolve to any addresses", addr)
+            }
+            ConnectFailure::TimedOut(tried) => err!(
+                Timeout,
+                "timed out connecting to {}; tried {} ({})",
+                config.host_port(addr),
+                tried,
+                CONNECT_HINT
+            ),
+            ConnectFailure::Failed(tried) => err!(
+                IoError,
+                "couldn't connect to {}; tried {} ({})",
+                config.host_port(addr),
+                tried,
+                CONNECT_HINT
+            ),
+            ConnectFailure::Error(e) => e,
+        })?,
+    };
+
+    // Requests and responses are tiny, so don't let Nagle's algorithm
+    // hold them back waiting for more data
+    socket
+        .set_nodelay(true)
+        .map_err(|e| err!(IoError, "couldn't set TCP_NODELAY: {}", e))?;
+
+    if let Some(idle) = config.keepalive() {
+        SockRef::from(&socket)
+            .set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))
+            .map_err(|e| err!(IoError, "couldn't enable TCP keepalive: {}", e))?;
+    }
+
+    Ok(Box::new(socket))
+}
 
-        let mut socket = TcpStream::connect(format!("{}:{}", addr, port))?;
-        socket.write_all(b"HELLO\n")?;
+/// Connect to the given proxy, then have it connect onwards to the
+/// validator at the given host
+fn connect_proxy(
+    label: &str,
+    config: &ValidatorConfig,
+    proxy: &ProxyConfig,
+    addr: &str,
+) -> Result<TcpStream, Error> {
+    let mut socket = connect_host(label, config, proxy.host(), proxy.port).map_err(|failure| {
+        match failure {
+            ConnectFailure::Error(e) => e,
+            failure => err!(
+                ProxyError,
+                "couldn't connect to proxy {}: {}",
+                proxy.host_port(),
+                failure
+            ),
+        }
+    })?;
 
-        Ok(Self { socket, keyring })
+    // The handshake is part of connecting, so is held to the same timeout;
+    // the session sets its own timeouts once connected
+    let connect_timeout = config.connect_timeout();
+    socket.set_read_timeout(connect_timeout)?;
+    socket.set_write_timeout(connect_timeout)?;
+
+    socks5::connect(&mut socket, proxy, addr, config.port, &config.host_port(addr))?;
+
+    debug!(
+        validator = label, addr = addr, port = config.port, event = "proxy_connected",
+        proxy = proxy.host_port().as_str();
+        "connected to {} through proxy {}", config.host_port(addr), proxy.host_port()
+    );
+
+    Ok(socket)
+}
+
+/// Why no TCP connection could be made to a host
+enum ConnectFailure {
+    /// The hostname couldn't be resolved
+    Unresolved(io::Error),
+
+    /// The hostname resolved to nothing
+    NoAddresses,
+
+    /// Every address timed out (listed with why each failed)
+    TimedOut(String),
+
+    /// Every address failed (listed with why each failed)
+    Failed(String),
+
+    /// Some other error, which explains itself
+    Error(Error),
+}
+
+impl fmt::Display for ConnectFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectFailure::Unresolved(e) => write!(f, "couldn't resolve it: {}", e),
+            ConnectFailure::NoAddresses => write!(f, "it didn't resolve to any addresses"),
+            ConnectFailure::TimedOut(tried) => write!(f, "timed out; tried {}", tried),
+            ConnectFailure::Failed(tried) => write!(f, "tried {}", tried),
+            ConnectFailure::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Connect to the given host and port over TCP, trying each address the
+/// hostname resolves to in turn
+fn connect_host(
+    label: &str,
+    config: &ValidatorConfig,
+    addr: &str,
+    port: u16,
+) -> Result<TcpStream, ConnectFailure> {
+    let candidates = (addr, port)
+        .to_socket_addrs()
+        .map_err(ConnectFailure::Unresolved)?;
+
+    let connect_timeout = config.connect_timeout();
+    let mut failures = vec![];
+    let mut timed_out = 0;
+
+    for candidate in candidates {
+        debug!(
+            validator = label, addr = addr, port = port, event = "connect_attempt",
+            ip:% = candidate.ip();
+            "trying {}", candidate
+        );
+
+        let result = match config.bind_addr {
+            Some(local_ip) if local_ip.is_ipv4() != candidate.is_ipv4() => {
+                failures.push(format!("{} (not the same IP version as bind-addr)", candidate));
+                continue;
+            }
+            Some(local_ip) => {
+                let socket = bind_socket(local_ip, candidate).map_err(ConnectFailure::Error)?;
+
+                match connect_timeout {
+                    Some(timeout) => socket.connect_timeout(&candidate.into(), timeout),
+                    None => socket.connect(&candidate.into()),
+                }.map(|()| socket.into())
+            }
+            None => match connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&candidate, timeout),
+                None => TcpStream::connect(candidate),
+            },
+        };
+
+        match result {
+            Ok(socket) => {
+                debug!(
+                    validator = label, addr = addr, port = port, event = "connect_succeeded",
+                    ip:% = candidate.ip();
+                    "connected to {}", candidate
+                );
+                return Ok(socket);
+            }
+            Err(e) => {
+                debug!(
+                    validator = label, addr = addr, port = port, event = "connect_failed",
+                    ip:% = candidate.ip();
+                    "couldn't connect to {}: {}", candidate, e
+                );
+
+                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock {
+                    timed_out += 1;
+                }
+
+                failures.push(format!("{} ({})", candidate, e));
+            }
+        }
     }
 
-    /// Handle incoming requests from the validator
-    pub fn handle_requests(&self) -> Result<(), Error> {
-        // TODO: actually do stuff
-        Ok(())
+    Err(if failures.is_empty() {
+        ConnectFailure::NoAddresses
+    } else if timed_out == failures.len() {
+        ConnectFailure::TimedOut(failures.join(", "))
+    } else {
+        ConnectFailure::Failed(failures.join(", "))
+    })
+}
+
+/// Create a TCP socket for connecting to the given address, bound to the
+/// given local IP (with any free port)
+fn bind_socket(local_ip: IpAddr, remote: SocketAddr) -> Result<socket2::Socket, Error> {
+    let socket = socket2::Socket::new(
+        Domain::for_address(remote),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    socket
+        .bind(&SocketAddr::new(local_ip, 0).into())
+        .map_err(|e| err!(BindError, "couldn't bind to {}: {}", local_ip, e))?;
+
+    Ok(socket)
+}
+
+/// Connect to the validator over a Unix domain socket
+#[cfg(unix)]
+fn connect_unix(config: &ValidatorConfig) -> Result<Box<dyn Socket>, Error> {
+    let path = config
+        .socket_path
+        .as_ref()
+        .ok_or_else(|| err!(ConfigError, "socket-path is required with the unix transport"))?;
+
+    let socket = UnixStream::connect(path)
+        .map_err(|e| {
+            err!(
+                IoError,
+                "couldn't connect to {}: {} ({})",
+                path.display(),
+                e,
+                CONNECT_HINT
+            )
+        })?;
+
+    Ok(Box::new(socket))
+}
+
+/// Unix domain sockets aren't available on this platform
+#[cfg(not(unix))]
+fn connect_unix(_config: &ValidatorConfig) -> Result<Box<dyn Socket>, Error> {
+    fail!(ConfigError, "the unix transport isn't supported on this platform");
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Dropping the socket alone doesn't always get the close to the
+        // validator promptly, so shut it down explicitly. If the validator
+        // has already gone, there's nothing left to shut down.
+        if let Err(e) = self.connection.io_handler().shutdown(Shutdown::Both) {
+            if e.kind() != io::ErrorKind::NotConnected {
+                warn!(
+                    validator = self.handler.label(), peer = self.peer_addr(),
+                    event = "socket_shutdown_failed";
+                    "couldn't shut down connection: {}", e
+                );
+            }
+        }
+
+        metrics::connection_closed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical;
+    use ed25519::{self, Keyring};
+    use rpc::{
+        BlockId, ErrorCode, ErrorResponse, HelloRequest, Request, Response, SignVoteRequest, Vote,
+        VOTE_TYPE_PREVOTE,
+    };
+    use secret_connection::IdentityKey;
+    use signatory::ed25519::{FromSeed, Signature as Ed25519Signature, Signer};
+    use std::io::Read;
+    use std::net::TcpListener;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::{env, fs, process, thread};
+    use toml;
+
+    /// Largest response the mock validator accepts (in bytes)
+    const MAX_RESPONSE_SIZE: usize = 1024;
+
+    /// Run a session in the background with a validator listening on a
+    /// loopback port, which is configured with the given settings (TOML).
+    /// Returns the validator's listener, the flag which shuts the session
+    /// down, and the session's outcome once it ends.
+    fn start_session(
+        validator_config: &str,
+    ) -> (TcpListener, Arc<AtomicBool>, thread::JoinHandle<Result<(), Error>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config: ValidatorConfig =
+            toml::from_str(&format!("port = {}\n{}", port, validator_config)).unwrap();
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let session_shutdown = Arc::clone(&shutdown);
+
+        let session = thread::spawn(move || {
+            Session::new(
+                "test",
+                &config,
+                "127.0.0.1",
+                SharedKeyring::new(Keyring::from_signers(vec![])?),
+                Arc::new(Mutex::new(SignState::default())),
+                Arc::new(Chains::new()),
+                session_shutdown,
+            )?.handle_requests()
+        });
+
+        (listener, shutdown, session)
+    }
+
+    /// Identity key of the mock validator
+    fn validator_identity() -> IdentityKey {
+        IdentityKey::from_seed(&[7; 32]).unwrap()
+    }
+
+    /// Accept the KMS's connection and complete the handshake with it, as
+    /// the validator
+    fn accept(listener: &TcpListener) -> SecretConnection<TcpStream> {
+        let (socket, _) = listener.accept().unwrap();
+        SecretConnection::new(socket, &validator_identity()).unwrap()
+    }
+
+    /// Say hello to the KMS and check that it answers
+    fn say_hello<IoHandler: Read + Write>(connection: &mut SecretConnection<IoHandler>) {
+        let hello = Request::Hello(HelloRequest {
+            protocol_version: 1,
+            software_version: String::new(),
+        });
+
+        connection.write_all(&hello.to_vec()).unwrap();
+
+        match Response::read(connection, MAX_RESPONSE_SIZE).unwrap() {
+            Response::Hello(_) => (),
+            other => panic!("expected Hello, got {:?}", other),
+        }
+    }
+
+    /// How long the mock validator waits for the KMS to connect (in seconds)
+    const ACCEPT_TIMEOUT_SECS: u64 = 5;
+
+    /// Mock validator which listens on a Unix socket in the temporary
+    /// directory, for sessions using the unix transport
+    #[cfg(unix)]
+    struct MockValidator {
+        listener: UnixListener,
+        path: PathBuf,
+
+        /// Why the session started with this validator ended, if it failed
+        session_error: Arc<Mutex<Option<String>>>,
+    }
+
+    #[cfg(unix)]
+    impl MockValidator {
+        fn bind(name: &str) -> Self {
+            let filename = format!("kms-session-{}-{}.sock", name, process::id());
+            let path = env::temp_dir().join(filename);
+            let _ = fs::remove_file(&path);
+
+            let listener = UnixListener::bind(&path).unwrap();
+            listener.set_nonblocking(true).unwrap();
+
+            MockValidator {
+                listener,
+                path,
+                session_error: Arc::new(Mutex::new(None)),
+            }
+        }
+
+        /// Run a session with this validator in the background, signing
+        /// with the given keyring. Returns the flag which shuts the session
+        /// down, and the session's outcome once it ends.
+        fn start_session(
+            &self,
+            keyring: Keyring,
+        ) -> (Arc<AtomicBool>, thread::JoinHandle<Result<(), Error>>) {
+            let path = self.path.display().to_string();
+            let config: ValidatorConfig =
+                toml::from_str(&format!("transport = \"unix\"\nsocket-path = {:?}\n", path))
+                    .unwrap();
+
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let session_shutdown = Arc::clone(&shutdown);
+            let session_error = Arc::clone(&self.session_error);
+
+            let session = thread::spawn(move || {
+                let result = Session::new(
+                    "test",
+                    &config,
+                    &path,
+                    SharedKeyring::new(keyring),
+                    Arc::new(Mutex::new(SignState::default())),
+                    Arc::new(Chains::new()),
+                    session_shutdown,
+                ).and_then(|mut session| session.handle_requests());
+
+                if let Err(ref e) = result {
+                    *session_error.lock().unwrap() = Some(e.to_string());
+                }
+
+                result
+            });
+
+            (shutdown, session)
+        }
+
+        /// Accept the KMS's connection and complete the handshake with it.
+        /// Panics if the KMS doesn't connect within `ACCEPT_TIMEOUT_SECS`,
+        /// or its session fails first, rather than waiting forever.
+        fn accept(&self) -> SecretConnection<UnixStream> {
+            let deadline = Instant::now() + Duration::from_secs(ACCEPT_TIMEOUT_SECS);
+
+            let socket = loop {
+                match self.listener.accept() {
+                    Ok((socket, _)) => break socket,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(e) => panic!("mock validator couldn't accept a connection: {}", e),
+                }
+
+                let session_error = self.session_error.lock().unwrap().clone();
+
+                if session_error.is_some() || Instant::now() >= deadline {
+                    panic!(
+                        "KMS did not connect within {}s: {}",
+                        ACCEPT_TIMEOUT_SECS,
+                        session_error.unwrap_or_else(|| "its session is still running".to_owned())
+                    );
+                }
+
+                thread::sleep(Duration::from_millis(10));
+            };
+
+            socket.set_nonblocking(false).unwrap();
+            SecretConnection::new(socket, &validator_identity()).unwrap()
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for MockValidator {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    #[cfg(all(unix, feature = "dalek-provider"))]
+    #[test]
+    fn mock_validator_gets_a_vote_signed_over_a_unix_socket() {
+        let validator = MockValidator::bind("sign-vote");
+        let keyring = Keyring::from_seed_bytes(&[("signer", &[3u8; 32])]).unwrap();
+        let public_key = keyring.default_pubkey(None).unwrap();
+        let (shutdown, session) = validator.start_session(keyring);
+
+        let mut connection = validator.accept();
+        say_hello(&mut connection);
+
+        let prevote = || Vote {
+            height: 1,
+            round: 0,
+            vote_type: VOTE_TYPE_PREVOTE,
+            block_id: BlockId::default(),
+            timestamp: "2018-01-01T00:00:00.000Z".to_owned(),
+        };
+        let request = Request::SignVote(SignVoteRequest {
+            public_key: public_key.as_bytes().to_vec(),
+            algorithm: public_key.algorithm(),
+            chain_id: "test-chain".parse().unwrap(),
+            vote: prevote(),
+        });
+        connection.write_all(&request.to_vec()).unwrap();
+
+        let response = Response::read(&mut connection, MAX_RESPONSE_SIZE).unwrap();
+        let signature: ed25519::Signature = match response {
+            Response::Sign(response) => {
+                Ed25519Signature::from_bytes(&response.signature).unwrap().into()
+            }
+            other => panic!("expected Sign response, got {:?}", other),
+        };
+
+        let sign_bytes = canonical::canonical_vote_bytes("test-chain", &prevote());
+        public_key.verify(&sign_bytes, &signature).unwrap();
+
+        shutdown.store(true, Ordering::SeqCst);
+        session.join().unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[should_panic(expected = "KMS did not connect within 5s: couldn't connect to")]
+    fn mock_validator_reports_why_the_kms_did_not_connect() {
+        let validator = MockValidator::bind("no-connect");
+        fs::remove_file(&validator.path).unwrap();
+
+        let _session = validator.start_session(Keyring::from_signers(vec![]).unwrap());
+        validator.accept();
+    }
+
+    #[test]
+    fn first_request_is_read_after_handshake_without_peer_ids() {
+        let (listener, shutdown, session) = start_session("");
+        let mut connection = accept(&listener);
+        say_hello(&mut connection);
+
+        // Hang on to the connection, so the session can only end by
+        // shutting down
+        shutdown.store(true, Ordering::SeqCst);
+        session.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn listed_peer_is_accepted() {
+        let public_key = validator_identity().public_key().unwrap();
+        let peer_id = secret_connection::peer_id(&public_key.into());
+        let (listener, shutdown, session) = start_session(&format!("peer-ids = [{:?}]", peer_id));
+        let mut connection = accept(&listener);
+        say_hello(&mut connection);
+
+        shutdown.store(true, Ordering::SeqCst);
+        session.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn unlisted_peer_is_rejected() {
+        let (listener, _, session) =
+            start_session("peer-ids = [\"0000000000000000000000000000000000000000\"]");
+        let _connection = accept(&listener);
+
+        match session.join().unwrap() {
+            Err(Error::UntrustedPeer { .. }) => (),
+            other => panic!("expected UntrustedPeer, got {:?}", other),
+        }
+    }
+
+    /// How a `TestSocket` treats writes
+    #[derive(Clone, Copy)]
+    enum WriteMode {
+        /// Writes go straight through
+        Normal,
+
+        /// Only one byte is accepted per write
+        OneByte,
+
+        /// Nothing is ever accepted, as if the validator had stopped reading;
+        /// writes fail with the given error (as a socket's write timeout does)
+        Stalled(io::ErrorKind),
+    }
+
+    /// TCP socket whose writes can be made to misbehave once the handshake
+    /// is done
+    struct TestSocket {
+        inner: TcpStream,
+        mode: Arc<Mutex<WriteMode>>,
+    }
+
+    impl Read for TestSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for TestSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match *self.mode.lock().unwrap() {
+                WriteMode::Normal => self.inner.write(buf),
+                WriteMode::OneByte => self.inner.write(&buf[..buf.len().min(1)]),
+                WriteMode::Stalled(kind) => Err(kind.into()),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl Socket for TestSocket {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.inner.set_read_timeout(timeout)
+        }
+
+        fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.inner.set_write_timeout(timeout)
+        }
+
+        fn wait_readable(&self) -> io::Result<()> {
+            self.inner.wait_readable()
+        }
+
+        fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+            self.inner.shutdown(how)
+        }
+    }
+
+    /// Set up a session over a `TestSocket`, returning it along with the
+    /// switch for its write mode and the validator's end of the connection
+    fn session_over_test_socket() -> (Session, Arc<Mutex<WriteMode>>, SecretConnection<TcpStream>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let validator = thread::spawn(move || accept(&listener));
+
+        let mode = Arc::new(Mutex::new(WriteMode::Normal));
+        let socket: Box<dyn Socket> = Box::new(TestSocket {
+            inner: TcpStream::connect(addr).unwrap(),
+            mode: Arc::clone(&mode),
+        });
+
+        let identity_key = secret_connection::identity_key(None).unwrap();
+        let connection = SecretConnection::new(socket, &identity_key).unwrap();
+        let handler = RequestHandler::new(
+            "test",
+            &toml::from_str("").unwrap(),
+            "127.0.0.1",
+            SharedKeyring::new(Keyring::from_signers(vec![]).unwrap()),
+            Arc::new(Mutex::new(SignState::default())),
+            Arc::new(Chains::new()),
+        ).unwrap();
+
+        let session = Session {
+            connection,
+            handler,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            read_timeout: None,
+            max_request_bytes: MAX_RESPONSE_SIZE,
+        };
+
+        (session, mode, validator.join().unwrap())
+    }
+
+    /// Response long enough to take several frames
+    fn long_response() -> Response {
+        Response::Error(ErrorResponse {
+            code: ErrorCode::InvalidRequest,
+            message: "x".repeat(3000),
+        })
+    }
+
+    #[test]
+    fn response_written_a_byte_at_a_time_arrives_intact() {
+        let (mut session, mode, mut validator) = session_over_test_socket();
+        *mode.lock().unwrap() = WriteMode::OneByte;
+
+        session.send_response(&long_response().to_vec()).unwrap();
+
+        match Response::read(&mut validator, 4096).unwrap() {
+            Response::Error(error) => assert_eq!(error.message, "x".repeat(3000)),
+            other => panic!("expected Error response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn response_to_a_validator_which_stopped_reading_times_out() {
+        for &kind in &[io::ErrorKind::WouldBlock, io::ErrorKind::TimedOut] {
+            let (mut session, mode, _validator) = session_over_test_socket();
+            *mode.lock().unwrap() = WriteMode::Stalled(kind);
+
+            match session.send_response(&long_response().to_vec()) {
+                Err(Error::Timeout { .. }) => (),
+                other => panic!("expected Timeout for {:?}, got {:?}", kind, other),
+            }
+        }
     }
 }
@@ -0,0 +1,65 @@
+//! Sockets a validator connection can be made over: TCP, or a Unix domain
+//! socket when the validator runs on the same host
+
+use socket2::SockRef;
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Connected stream socket
+pub trait Socket: Read + Write + Send {
+    /// Set the maximum time a read may block for (`None` means forever)
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Set the maximum time a write may block for (`None` means forever)
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Block (subject to the read timeout) until there's data or EOF to be
+    /// read, without consuming anything
+    fn wait_readable(&self) -> io::Result<()>;
+
+    /// Shut down the reading and/or writing halves of the connection
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn wait_readable(&self) -> io::Result<()> {
+        SockRef::from(self).peek(&mut [MaybeUninit::uninit()])?;
+        Ok(())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn wait_readable(&self) -> io::Result<()> {
+        SockRef::from(self).peek(&mut [MaybeUninit::uninit()])?;
+        Ok(())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+}
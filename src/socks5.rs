@@ -0,0 +1,265 @@
+//! Client side of the SOCKS5 protocol (RFC 1928), with username/password
+//! authentication (RFC 1929), for reaching validators through a proxy
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+
+use config::ProxyConfig;
+use error::Error;
+use session::CONNECT_HINT;
+
+/// SOCKS protocol version
+const VERSION: u8 = 5;
+
+/// Version of the username/password authentication subnegotiation
+const AUTH_VERSION: u8 = 1;
+
+/// Authentication method: none
+const METHOD_NONE: u8 = 0;
+
+/// Authentication method: username and password
+const METHOD_PASSWORD: u8 = 2;
+
+/// Authentication method the proxy picks when it accepts none of those offered
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+
+/// Request command: open a TCP connection
+const CMD_CONNECT: u8 = 1;
+
+/// Address types
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Ask the proxy `socket` is connected to to connect it onwards to
+/// `host:port` (`target` being the same, for messages). Hostnames are sent
+/// as they are, for the proxy to resolve.
+///
+/// Anything going wrong with the proxy itself is a `ProxyError`; the proxy
+/// reporting that the target couldn't be reached is an `IoError` (or
+/// `Timeout`), as it would be had the KMS connected directly.
+pub fn connect(
+    socket: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+    target: &str,
+) -> Result<(), Error> {
+    let proxy_addr = proxy.host_port();
+
+    handshake(socket, proxy, host, port, target).map_err(|e| match e {
+        Failure::Io(e) if e.kind() == io::ErrorKind::WouldBlock
+            || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            err!(ProxyError, "timed out waiting for proxy {} to respond", proxy_addr)
+        }
+        Failure::Io(e) => err!(
+            ProxyError,
+            "SOCKS5 handshake with proxy {} failed: {}",
+            proxy_addr,
+            e
+        ),
+        Failure::Proxy(description) => err!(
+            ProxyError,
+            "proxy {} {}",
+            proxy_addr,
+            description
+        ),
+        Failure::Target(6) => err!(
+            Timeout,
+            "proxy {} timed out connecting to {} ({})",
+            proxy_addr,
+            target,
+            CONNECT_HINT
+        ),
+        Failure::Target(reply) => err!(
+            IoError,
+            "proxy {} couldn't connect to {}: {} ({})",
+            proxy_addr,
+            target,
+            reply_description(reply),
+            CONNECT_HINT
+        ),
+    })
+}
+
+/// Ways the handshake can fail
+enum Failure {
+    /// Talking to the proxy failed
+    Io(io::Error),
+
+    /// The proxy misbehaved or refused us (completing "proxy X ...")
+    Proxy(String),
+
+    /// The proxy couldn't reach the target, with its reply code
+    Target(u8),
+}
+
+impl From<io::Error> for Failure {
+    fn from(other: io::Error) -> Self {
+        Failure::Io(other)
+    }
+}
+
+/// Negotiate authentication, then request the connection
+fn handshake(
+    socket: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+    target: &str,
+) -> Result<(), Failure> {
+    let credentials = proxy.credentials();
+
+    // Only offer the method we intend to use, so a proxy requiring a
+    // password isn't silently connected to without one (or vice versa)
+    let method = if credentials.is_some() {
+        METHOD_PASSWORD
+    } else {
+        METHOD_NONE
+    };
+
+    socket.write_all(&[VERSION, 1, method])?;
+
+    let mut choice = [0u8; 2];
+    socket.read_exact(&mut choice)?;
+
+    if choice[0] != VERSION {
+        return Err(Failure::Proxy(format!(
+            "isn't a SOCKS5 proxy (it replied with version {})",
+            choice[0]
+        )));
+    }
+
+    match (choice[1], credentials) {
+        (METHOD_NONE, None) => (),
+        (METHOD_PASSWORD, Some((username, password))) => {
+            authenticate(socket, username, password)?
+        }
+        (METHOD_UNACCEPTABLE, None) => {
+            return Err(Failure::Proxy("requires authentication".to_owned()));
+        }
+        (METHOD_UNACCEPTABLE, Some(_)) => {
+            return Err(Failure::Proxy(
+                "doesn't accept username/password authentication".to_owned(),
+            ));
+        }
+        (other, _) => {
+            return Err(Failure::Proxy(format!(
+                "chose an authentication method which wasn't offered ({})",
+                other
+            )));
+        }
+    }
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(Failure::Proxy(format!(
+                    "can't be sent {} (hostnames are limited to 255 bytes)",
+                    target
+                )));
+            }
+
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+
+    request.extend_from_slice(&[(port >> 8) as u8, port as u8]);
+    socket.write_all(&request)?;
+
+    // Reply: version, reply code, reserved, then the proxy's bound address
+    let mut reply = [0u8; 4];
+    socket.read_exact(&mut reply)?;
+
+    if reply[0] != VERSION {
+        return Err(Failure::Proxy(format!(
+            "sent a malformed reply (version {})",
+            reply[0]
+        )));
+    }
+
+    match reply[1] {
+        0 => (),
+        // Network or host unreachable, connection refused, or TTL expired
+        3..=6 => return Err(Failure::Target(reply[1])),
+        code => {
+            return Err(Failure::Proxy(format!(
+                "refused to connect to {}: {}",
+                target,
+                reply_description(code)
+            )));
+        }
+    }
+
+    // The bound address is of no use to us, but must be read past so it
+    // isn't taken for the start of the Secret Connection handshake
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            socket.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        other => {
+            return Err(Failure::Proxy(format!(
+                "sent a reply with an unknown address type ({})",
+                other
+            )));
+        }
+    };
+
+    let mut bound_addr = vec![0u8; addr_len + 2];
+    socket.read_exact(&mut bound_addr)?;
+
+    Ok(())
+}
+
+/// Send our username and password and check the proxy accepts them
+fn authenticate(socket: &mut TcpStream, username: &str, password: &str) -> Result<(), Failure> {
+    let mut request = vec![AUTH_VERSION, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    socket.write_all(&request)?;
+
+    let mut status = [0u8; 2];
+    socket.read_exact(&mut status)?;
+
+    if status[1] != 0 {
+        return Err(Failure::Proxy(format!(
+            "rejected the username and password for {}",
+            username
+        )));
+    }
+
+    Ok(())
+}
+
+/// What a SOCKS5 reply code means
+fn reply_description(code: u8) -> String {
+    match code {
+        1 => "general SOCKS server failure".to_owned(),
+        2 => "connection not allowed by ruleset".to_owned(),
+        3 => "network unreachable".to_owned(),
+        4 => "host unreachable".to_owned(),
+        5 => "connection refused".to_owned(),
+        6 => "TTL expired".to_owned(),
+        7 => "command not supported".to_owned(),
+        8 => "address type not supported".to_owned(),
+        other => format!("unknown reply code {}", other),
+    }
+}
@@ -0,0 +1,542 @@
+//! Signing state used to prevent double signing
+//!
+//! Tracks the last (height, round, step) signed on each chain. Signing
+//! anything which doesn't strictly advance this mark is refused, since a
+//! validator which signs the same height/round/step twice can be slashed.
+//! Votes within the same height and round must also not go back in time, to
+//! guard against replayed or clock-skewed requests.
+//!
+//! State is persisted either to a single file covering every chain, or to a
+//! directory with one file per chain (see `StateStore`). Either kind of file
+//! records the version of its format, so files written by an older KMS can
+//! be upgraded when they're loaded.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use error::Error;
+
+/// Step for block proposals
+pub const STEP_PROPOSAL: i8 = 1;
+
+/// Step for prevotes
+pub const STEP_PREVOTE: i8 = 2;
+
+/// Step for precommits
+pub const STEP_PRECOMMIT: i8 = 3;
+
+/// Version of the state file format written by this KMS. Files without a
+/// `version` are version 1, from before the format was versioned.
+pub const STATE_FILE_VERSION: u64 = 2;
+
+/// Position in the consensus process
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct HeightRoundStep {
+    /// Block height
+    pub height: i64,
+
+    /// Consensus round
+    pub round: i64,
+
+    /// Step within the round
+    pub step: i8,
+}
+
+/// Time a consensus message was created, as seconds and nanoseconds since
+/// the Unix epoch
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch
+    pub seconds: i64,
+
+    /// Nanoseconds within the second
+    pub nanos: i64,
+}
+
+/// Last thing signed on a chain
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+struct LastSigned {
+    /// Position in the consensus process
+    #[serde(flatten)]
+    hrs: HeightRoundStep,
+
+    /// Timestamp of the signed message (only tracked for votes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
+}
+
+/// Contents of a state file, tagged with the version of its format
+#[derive(Serialize, Deserialize)]
+struct VersionedState<T> {
+    /// Version of the state file format
+    version: u64,
+
+    /// The state itself
+    #[serde(flatten)]
+    state: T,
+}
+
+/// State of every chain, as kept in a single state file
+#[derive(Serialize, Deserialize)]
+struct AllChains<C> {
+    /// Last thing signed on each chain, keyed by chain ID
+    chains: C,
+}
+
+/// High-water marks of what has been signed on each chain
+#[derive(Debug, Default)]
+pub struct SignState {
+    /// Where the state is persisted
+    storage: Storage,
+
+    /// Last position signed on each chain, keyed by chain ID
+    chains: BTreeMap<String, LastSigned>,
+}
+
+/// Where signing state is persisted
+#[derive(Debug, Default)]
+enum Storage {
+    /// Only kept in memory
+    #[default]
+    Memory,
+
+    /// A single file holding the state of every chain
+    File(PathBuf),
+
+    /// A directory with one file per chain, loaded on first use
+    Dir(StateStore),
+}
+
+impl SignState {
+    /// Load the signing state from the given file, or begin with an empty
+    /// state if the file does not exist yet. If no path is given, the state
+    /// is only kept in memory.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(p) => p,
+            None => return Ok(Self::default()),
+        };
+
+        let chains = if path.exists() {
+            read_state(path, |chains| AllChains { chains })?.chains
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            storage: Storage::File(path.to_owned()),
+            chains,
+        })
+    }
+
+    /// Keep the signing state in the given directory, with one file per
+    /// chain, creating the directory if it doesn't exist yet
+    pub fn in_dir(dir: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            storage: Storage::Dir(StateStore::open(dir)?),
+            chains: BTreeMap::new(),
+        })
+    }
+
+    /// Is the state written somewhere, so it survives a restart?
+    pub fn is_persisted(&self) -> bool {
+        match self.storage {
+            Storage::Memory => false,
+            Storage::File(_) | Storage::Dir(_) => true,
+        }
+    }
+
+    /// Ensure signing at the given position (and, for votes, time) on the
+    /// given chain would not regress or duplicate a previous signature
+    pub fn check(
+        &mut self,
+        chain_id: &str,
+        hrs: HeightRoundStep,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), Error> {
+        if let Storage::Dir(ref store) = self.storage {
+            if !self.chains.contains_key(chain_id) {
+                if let Some(last) = store.load(chain_id)? {
+                    self.chains.insert(chain_id.to_owned(), last);
+                }
+            }
+        }
+
+        if let Some(last) = self.chains.get(chain_id) {
+            ensure!(
+                hrs > last.hrs,
+                DoubleSign,
+                "refusing to sign {}/{}/{} on {}: already signed {}/{}/{}",
+                hrs.height,
+                hrs.round,
+                hrs.step,
+                chain_id,
+                last.hrs.height,
+                last.hrs.round,
+                last.hrs.step
+            );
+
+            if let (Some(timestamp), Some(last_timestamp)) = (timestamp, last.timestamp) {
+                ensure!(
+                    hrs.height != last.hrs.height || hrs.round != last.hrs.round
+                        || timestamp >= last_timestamp,
+                    TimestampRegression,
+                    "refusing to sign {}/{}/{} on {}: timestamp {}.{:09} is earlier than \
+                     previously signed {}.{:09}",
+                    hrs.height,
+                    hrs.round,
+                    hrs.step,
+                    chain_id,
+                    timestamp.seconds,
+                    timestamp.nanos,
+                    last_timestamp.seconds,
+                    last_timestamp.nanos
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Last position signed on the given chain, if anything has been signed
+    /// on it yet. Unlike `check`, this never changes the state (per-chain
+    /// files which haven't been loaded yet are read but not cached).
+    pub fn last_signed(&self, chain_id: &str) -> Result<Option<HeightRoundStep>, Error> {
+        if let Some(last) = self.chains.get(chain_id) {
+            return Ok(Some(last.hrs));
+        }
+
+        match self.storage {
+            Storage::Dir(ref store) => Ok(store.load(chain_id)?.map(|last| last.hrs)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Record a successful signature at the given position, persisting the
+    /// updated state
+    pub fn record(
+        &mut self,
+        chain_id: &str,
+        hrs: HeightRoundStep,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), Error> {
+        let last = LastSigned { hrs, timestamp };
+        self.chains.insert(chain_id.to_owned(), last);
+
+        match self.storage {
+            Storage::Memory => Ok(()),
+            Storage::File(ref path) => write_atomically(
+                path,
+                &encode_state(&AllChains {
+                    chains: &self.chains,
+                }),
+            ),
+            Storage::Dir(ref store) => store.save(chain_id, &last),
+        }
+    }
+}
+
+/// Directory of per-chain state files (e.g. `cosmoshub-3.json`), so each
+/// chain's state can be inspected or removed without touching the others
+#[derive(Debug)]
+pub struct StateStore {
+    /// Directory containing the state files
+    dir: PathBuf,
+}
+
+impl StateStore {
+    /// Open the given state directory, creating it if necessary
+    pub fn open(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir).map_err(|e| {
+            err!(
+                ConfigError,
+                "couldn't create state directory {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+
+        Ok(Self {
+            dir: dir.to_owned(),
+        })
+    }
+
+    /// Load the last position signed on the given chain, if anything has
+    /// been signed on it yet. A corrupt file is an error rather than being
+    /// treated as empty, which would reset the high-water mark.
+    fn load(&self, chain_id: &str) -> Result<Option<LastSigned>, Error> {
+        let path = self.chain_path(chain_id)?;
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        read_state(&path, |last: LastSigned| last).map(Some)
+    }
+
+    /// Persist the last position signed on the given chain
+    fn save(&self, chain_id: &str, last: &LastSigned) -> Result<(), Error> {
+        write_atomically(&self.chain_path(chain_id)?, &encode_state(last))
+    }
+
+    /// Path of the state file for the given chain. Chain IDs come from the
+    /// validator, so they're checked to be safe to use as filenames.
+    fn chain_path(&self, chain_id: &str) -> Result<PathBuf, Error> {
+        ensure!(
+            !chain_id.is_empty() && !chain_id.starts_with('.')
+                && chain_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'),
+            RpcError,
+            "chain ID can't be used as a state filename: {:?}",
+            chain_id
+        );
+
+        Ok(self.dir.join(format!("{}.json", chain_id)))
+    }
+}
+
+/// Read a state file, upgrading it in place if it's in an older version of
+/// the format. Version 1 files hold just the state as `V1`, which `upgrade`
+/// converts. Files written by a newer KMS are refused, since rewriting them
+/// could lose whatever they record which this KMS doesn't understand.
+fn read_state<V1, T>(path: &Path, upgrade: fn(V1) -> T) -> Result<T, Error>
+where
+    V1: DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+{
+    let corrupt =
+        |e: &dyn Display| err!(ConfigError, "corrupt state file {}: {}", path.display(), e);
+
+    let file = File::open(path)?;
+    let value: Value = serde_json::from_reader(file).map_err(|e| corrupt(&e))?;
+
+    // A version 1 file holding every chain's state may have a chain called
+    // "version", but its value is never a number
+    match value.get("version").and_then(Value::as_u64) {
+        Some(STATE_FILE_VERSION) => serde_json::from_value::<VersionedState<T>>(value)
+            .map(|versioned| versioned.state)
+            .map_err(|e| corrupt(&e)),
+        Some(version) if version > STATE_FILE_VERSION => {
+            fail!(
+                ConfigError,
+                "state file {} is version {}, but this KMS only understands up to version {} \
+                 (was it written by a newer KMS?); refusing to load it",
+                path.display(),
+                version,
+                STATE_FILE_VERSION
+            );
+        }
+        Some(version) => Err(corrupt(&format!("unknown version {}", version))),
+        None => {
+            let state = upgrade(serde_json::from_value(value).map_err(|e| corrupt(&e))?);
+            write_atomically(path, &encode_state(&state))?;
+
+            info!(
+                event = "state_file_upgraded";
+                "upgraded state file {} to version {}", path.display(), STATE_FILE_VERSION
+            );
+
+            Ok(state)
+        }
+    }
+}
+
+/// Encode state in the current version of the state file format
+fn encode_state<T: Serialize>(state: &T) -> Vec<u8> {
+    serde_json::to_vec(&VersionedState {
+        version: STATE_FILE_VERSION,
+        state,
+    }).unwrap()
+}
+
+/// Atomically replace the contents of a file (via temp file + rename)
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut tmp_file = File::create(&tmp_path)?;
+    tmp_file.write_all(data)?;
+    tmp_file.sync_all()?;
+
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    const CHAIN_ID: &str = "test-chain";
+
+    /// Path in the temp directory for a test's state file or directory
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("kms-state-{}-{}", name, process::id()))
+    }
+
+    /// Version of the state file at the given path, if it has one
+    fn file_version(path: &Path) -> Option<u64> {
+        let value: Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        value.get("version").and_then(Value::as_u64)
+    }
+
+    fn hrs(height: i64, round: i64, step: i8) -> HeightRoundStep {
+        HeightRoundStep {
+            height,
+            round,
+            step,
+        }
+    }
+
+    fn timestamp(seconds: i64, nanos: i64) -> Option<Timestamp> {
+        Some(Timestamp { seconds, nanos })
+    }
+
+    #[test]
+    fn earlier_timestamp_in_the_same_round_is_refused() {
+        let mut state = SignState::default();
+        state
+            .record(CHAIN_ID, hrs(10, 1, STEP_PREVOTE), timestamp(1000, 500))
+            .unwrap();
+
+        match state.check(CHAIN_ID, hrs(10, 1, STEP_PRECOMMIT), timestamp(1000, 499)) {
+            Err(Error::TimestampRegression { .. }) => (),
+            other => panic!("expected TimestampRegression, got {:?}", other),
+        }
+
+        // The same time, or a later one, is fine
+        state
+            .check(CHAIN_ID, hrs(10, 1, STEP_PRECOMMIT), timestamp(1000, 500))
+            .unwrap();
+        state
+            .check(CHAIN_ID, hrs(10, 1, STEP_PRECOMMIT), timestamp(1001, 0))
+            .unwrap();
+    }
+
+    #[test]
+    fn earlier_timestamp_in_a_later_round_is_allowed() {
+        let mut state = SignState::default();
+        state
+            .record(CHAIN_ID, hrs(10, 1, STEP_PRECOMMIT), timestamp(1000, 0))
+            .unwrap();
+
+        state
+            .check(CHAIN_ID, hrs(10, 2, STEP_PREVOTE), timestamp(999, 0))
+            .unwrap();
+        state
+            .check(CHAIN_ID, hrs(11, 0, STEP_PREVOTE), timestamp(999, 0))
+            .unwrap();
+    }
+
+    #[test]
+    fn same_or_earlier_position_is_a_double_sign() {
+        let mut state = SignState::default();
+        state.record(CHAIN_ID, hrs(10, 1, STEP_PREVOTE), None).unwrap();
+
+        for &position in &[
+            hrs(10, 1, STEP_PREVOTE),
+            hrs(10, 1, STEP_PROPOSAL),
+            hrs(9, 5, STEP_PRECOMMIT),
+        ] {
+            match state.check(CHAIN_ID, position, None) {
+                Err(Error::DoubleSign { .. }) => (),
+                other => panic!("expected DoubleSign, got {:?}", other),
+            }
+        }
+
+        // Other chains are tracked separately
+        state
+            .check("other-chain", hrs(1, 0, STEP_PREVOTE), None)
+            .unwrap();
+    }
+
+    #[test]
+    fn version_1_state_file_is_upgraded() {
+        let path = temp_path("v1-file.json");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"test-chain":{"height":10,"round":1,"step":2,"#,
+                r#""timestamp":{"seconds":1000,"nanos":0}}}"#
+            ),
+        ).unwrap();
+
+        let state = SignState::load(Some(&path)).unwrap();
+        assert_eq!(file_version(&path), Some(STATE_FILE_VERSION));
+
+        // Reloading the upgraded file gives the same state
+        let reloaded = SignState::load(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        for mut state in [state, reloaded] {
+            assert_eq!(state.last_signed(CHAIN_ID).unwrap(), Some(hrs(10, 1, STEP_PREVOTE)));
+
+            match state.check(CHAIN_ID, hrs(10, 1, STEP_PRECOMMIT), timestamp(999, 0)) {
+                Err(Error::TimestampRegression { .. }) => (),
+                other => panic!("expected TimestampRegression, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn version_1_chain_file_is_upgraded() {
+        let dir = temp_path("v1-dir");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.json", CHAIN_ID));
+        fs::write(&path, r#"{"height":10,"round":1,"step":2}"#).unwrap();
+
+        let mut state = SignState::in_dir(&dir).unwrap();
+        let result = state.check(CHAIN_ID, hrs(10, 1, STEP_PREVOTE), None);
+        let version = file_version(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(Error::DoubleSign { .. }) => (),
+            other => panic!("expected DoubleSign, got {:?}", other),
+        }
+
+        assert_eq!(version, Some(STATE_FILE_VERSION));
+    }
+
+    #[test]
+    fn newer_state_files_are_refused() {
+        let path = temp_path("v3-file.json");
+        let contents = r#"{"version":3,"chains":{}}"#;
+        fs::write(&path, contents).unwrap();
+
+        let result = SignState::load(Some(&path));
+        let unchanged = fs::read_to_string(&path).unwrap() == contents;
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(Error::ConfigError { .. }) => (),
+            other => panic!("expected ConfigError, got {:?}", other),
+        }
+
+        assert!(unchanged, "a newer state file was rewritten");
+
+        let dir = temp_path("v3-dir");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.json", CHAIN_ID));
+        fs::write(&path, r#"{"version":3,"height":10,"round":1,"step":2}"#).unwrap();
+
+        let result = SignState::in_dir(&dir)
+            .unwrap()
+            .check(CHAIN_ID, hrs(11, 0, STEP_PREVOTE), None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(Error::ConfigError { .. }) => (),
+            other => panic!("expected ConfigError, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Supervises the clients for all configured validators, which share a
+//! single keyring unless they name a keyring of their own
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use client::Client;
+use config::{Chains, ValidatorConfig};
+use ed25519::SharedKeyring;
+use error::Error;
+use state::SignState;
+
+/// Owns the client threads and watches for any which exit unexpectedly
+pub struct ClientSupervisor {
+    /// Keyring shared by clients which don't name one
+    keyring: SharedKeyring,
+
+    /// Separate keyrings, by name, for clients which name one
+    named_keyrings: BTreeMap<String, SharedKeyring>,
+
+    /// Settings for particular chains, shared by all clients
+    chains: Arc<Chains>,
+
+    /// Clients which haven't yet been joined
+    clients: Vec<Client>,
+}
+
+impl ClientSupervisor {
+    /// Spawn a client for each of the given validators. Validators with
+    /// their own `state-file` use it, while the rest share the state kept in
+    /// `state_dir` (or just in memory, if not given). Likewise validators which name a keyring are
+    /// given that one of `named_keyrings`, while the rest share `keyring`.
+    pub fn spawn(
+        validators: BTreeMap<String, ValidatorConfig>,
+        keyring: SharedKeyring,
+        named_keyrings: BTreeMap<String, SharedKeyring>,
+        state_dir: Option<&Path>,
+        chains: Chains,
+    ) -> Result<Self, Error> {
+        let chains = Arc::new(chains);
+        let mut supervisor = Self {
+            keyring,
+            named_keyrings,
+            chains,
+            clients: vec![],
+        };
+
+        // Validators without their own state file share one state (kept in
+        // memory if there's no state_dir), so whatever the storage, no two
+        // of them can sign the same height/round/step on a chain
+        let shared_state = Arc::new(Mutex::new(match state_dir {
+            Some(dir) => SignState::in_dir(dir)?,
+            None => SignState::default(),
+        }));
+
+        for (label, config) in validators {
+            let state = state_for(&label, &config, &shared_state)?;
+            let keyring = supervisor.keyring_for(&label, &config)?;
+            let chains = Arc::clone(&supervisor.chains);
+            let client = Client::spawn(label, config, keyring, state, chains)?;
+            supervisor.clients.push(client);
+        }
+
+        Ok(supervisor)
+    }
+
+    /// Keyring the given validator signs with
+    fn keyring_for(&self, label: &str, config: &ValidatorConfig) -> Result<SharedKeyring, Error> {
+        match config.keyring {
+            Some(ref name) => self.named_keyrings.get(name).cloned().ok_or_else(|| {
+                err!(ConfigError, "[{}] no such keyring: {:?}", label, name)
+            }),
+            None => Ok(self.keyring.clone()),
+        }
+    }
+
+    /// Check for clients which exited without being asked to, respawning
+    /// those configured with `respawn-on-exit` and reaping the rest. Clients
+    /// stopped by an error reconnecting can't fix are never respawned.
+    pub fn check_clients(&mut self) {
+        let clients = mem::take(&mut self.clients);
+
+        for client in clients {
+            if !client.is_finished() || client.is_shutting_down() {
+                self.clients.push(client);
+                continue;
+            }
+
+            let label = client.label().to_owned();
+            let config = client.config().clone();
+            let state = client.state();
+
+            match client.join() {
+                Ok(()) if config.respawn_on_exit.unwrap_or(false) => {
+                    warn!(
+                        validator = label.as_str(), event = "client_exited";
+                        "client exited unexpectedly; respawning"
+                    );
+
+                    let chains = Arc::clone(&self.chains);
+                    let result = self.keyring_for(&label, &config).and_then(|keyring| {
+                        Client::spawn(label.clone(), config, keyring, state, chains)
+                    });
+
+                    match result {
+                        Ok(new_client) => self.clients.push(new_client),
+                        Err(e) => error!(
+                            validator = label.as_str(), event = "respawn_failed";
+                            "couldn't respawn client: {}", e
+                        ),
+                    }
+                }
+                Ok(()) => warn!(
+                    validator = label.as_str(), event = "client_exited";
+                    "client exited unexpectedly"
+                ),
+                Err(e) => error!(
+                    validator = label.as_str(), event = "client_failed";
+                    "client stopped: {}", e
+                ),
+            }
+        }
+    }
+
+    /// Have all clients exited?
+    pub fn is_finished(&self) -> bool {
+        self.clients.iter().all(|client| client.is_finished())
+    }
+
+    /// Ask all clients to shut down
+    pub fn shutdown_all(&self) {
+        for client in &self.clients {
+            client.shutdown();
+        }
+    }
+
+    /// Wait for all clients to finish
+    pub fn join_all(self) {
+        for client in self.clients {
+            // Any errors were logged when they stopped the client
+            client.join().ok();
+        }
+    }
+}
+
+/// Signing state for the given validator: the one in its own `state-file`,
+/// if it has one, or else the state shared by validators without one
+fn state_for(
+    label: &str,
+    config: &ValidatorConfig,
+    shared_state: &Arc<Mutex<SignState>>,
+) -> Result<Arc<Mutex<SignState>>, Error> {
+    let state = match config.state_file {
+        Some(ref state_file) => {
+            let state = SignState::load(Some(state_file)).map_err(|e| {
+                err!(ConfigError, "[{}] couldn't load signing state: {}", label, e)
+            })?;
+            Arc::new(Mutex::new(state))
+        }
+        None => Arc::clone(shared_state),
+    };
+
+    if !state.lock().unwrap().is_persisted() {
+        warn!(
+            validator = label, event = "state_not_persisted";
+            "no state-file or state-dir: signing state is only kept in memory, so double \
+             signing can't be prevented across restarts"
+        );
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::{HeightRoundStep, STEP_PREVOTE};
+    use toml;
+
+    #[test]
+    fn validators_without_a_state_file_share_state_in_memory() {
+        let shared_state = Arc::new(Mutex::new(SignState::default()));
+        let config: ValidatorConfig = toml::from_str("port = 26658").unwrap();
+
+        let a = state_for("a", &config, &shared_state).unwrap();
+        let b = state_for("b", &config, &shared_state).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+
+        let hrs = HeightRoundStep {
+            height: 1,
+            round: 0,
+            step: STEP_PREVOTE,
+        };
+
+        a.lock().unwrap().check("test-chain", hrs, None).unwrap();
+        a.lock().unwrap().record("test-chain", hrs, None).unwrap();
+
+        let result = b.lock().unwrap().check("test-chain", hrs, None);
+        match result {
+            Err(Error::DoubleSign { .. }) => (),
+            other => panic!("expected DoubleSign, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Counts of requests received from validators, by type
+//!
+//! Besides being exported as the `kms_requests_total` metric, the counts are
+//! summarized in the log every so often, which shows the mix of traffic
+//! without logging every request.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use metrics;
+
+/// How often to log a summary of requests received (in seconds)
+pub const SUMMARY_INTERVAL_SECS: u64 = 60;
+
+/// Kinds of request which are counted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RequestType {
+    /// Sign an arbitrary message
+    Sign,
+
+    /// Sign a consensus vote
+    SignVote,
+
+    /// Sign a block proposal
+    SignProposal,
+
+    /// Keepalive
+    Ping,
+
+    /// Obtain the public key
+    PubKey,
+}
+
+impl RequestType {
+    /// Name of this request type, as used in the metric's `type` label
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RequestType::Sign => "sign",
+            RequestType::SignVote => "sign_vote",
+            RequestType::SignProposal => "sign_proposal",
+            RequestType::Ping => "ping",
+            RequestType::PubKey => "pubkey",
+        }
+    }
+}
+
+/// Requests of each type received since the last summary, indexed by
+/// `RequestType`
+static COUNTS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Count a request of the given type
+pub fn record(request_type: RequestType) {
+    COUNTS[request_type as usize].fetch_add(1, Ordering::Relaxed);
+    metrics::request_received(request_type.as_str());
+}
+
+/// Start a background thread which logs how many requests of each type were
+/// received every `SUMMARY_INTERVAL_SECS`. The thread spends nearly all its
+/// time asleep, so it doesn't compete with the clients.
+pub fn spawn_summary_logger() -> io::Result<()> {
+    let interval = Duration::from_secs(SUMMARY_INTERVAL_SECS);
+
+    thread::Builder::new()
+        .name("request-summary".to_owned())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            log_summary(interval);
+        })?;
+
+    Ok(())
+}
+
+/// Log the requests received since the last summary (if there were any),
+/// resetting the counts
+fn log_summary(interval: Duration) {
+    let [sign, sign_vote, sign_proposal, ping, pubkey] =
+        [0, 1, 2, 3, 4].map(|i| COUNTS[i].swap(0, Ordering::Relaxed));
+
+    if sign + sign_vote + sign_proposal + ping + pubkey == 0 {
+        return;
+    }
+
+    info!(
+        event = "request_summary", sign = sign, sign_vote = sign_vote,
+        sign_proposal = sign_proposal, ping = ping, pubkey = pubkey;
+        "requests in the last {}s: {} sign, {} vote, {} proposal, {} ping, {} pubkey",
+        interval.as_secs(), sign, sign_vote, sign_proposal, ping, pubkey
+    );
+}
@@ -0,0 +1,121 @@
+//! Best-effort delivery of signing events to a webhook, for alerting
+//!
+//! Each signature is described by a small JSON event (the same as an audit
+//! log entry) which a background thread POSTs to the configured URL. Events
+//! wait in a bounded queue, and are dropped if it's full, so a slow webhook
+//! never holds up signing. When the `webhook` feature is disabled, recording
+//! events is a no-op.
+
+#[cfg(feature = "webhook")]
+pub use self::http_webhook::*;
+
+#[cfg(not(feature = "webhook"))]
+pub use self::noop_webhook::*;
+
+#[cfg(feature = "webhook")]
+mod http_webhook {
+    use serde_json;
+    use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+    use std::sync::OnceLock;
+    use std::thread;
+    use ureq;
+
+    use audit::AuditEntry;
+    use config::WebhookConfig;
+    use ed25519::PublicKey;
+    use error::Error;
+    use metrics;
+    use state::HeightRoundStep;
+
+    /// Queue of events waiting to be sent (only present if configured)
+    static QUEUE: OnceLock<SyncSender<AuditEntry>> = OnceLock::new();
+
+    /// Start the thread which sends events to the configured webhook
+    pub fn start(config: &WebhookConfig) -> Result<(), Error> {
+        let (sender, receiver) = mpsc::sync_channel(config.queue_size());
+        let agent = ureq::AgentBuilder::new().timeout(config.timeout()).build();
+        let url = config.url.clone();
+
+        thread::Builder::new()
+            .name("webhook".to_owned())
+            .spawn(move || deliver(&agent, &url, &receiver))?;
+
+        ensure!(
+            QUEUE.set(sender).is_ok(),
+            ConfigError,
+            "webhook already started"
+        );
+
+        info!("sending signing events to webhook at {}", config.url);
+        Ok(())
+    }
+
+    /// Queue an event describing a signature made for the given validator,
+    /// if a webhook is configured. Never blocks.
+    pub fn record(
+        validator: &str,
+        chain_id: Option<&str>,
+        hrs: Option<HeightRoundStep>,
+        public_key: &PublicKey,
+    ) {
+        let queue = match QUEUE.get() {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        match queue.try_send(AuditEntry::new(validator, chain_id, hrs, public_key)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                metrics::webhook_event_dropped()
+            }
+        }
+    }
+
+    /// Send queued events one at a time. Failures are logged when the
+    /// webhook starts failing and when it recovers, rather than for every
+    /// event.
+    fn deliver(agent: &ureq::Agent, url: &str, receiver: &Receiver<AuditEntry>) {
+        let mut failing = false;
+
+        for event in receiver {
+            let body = serde_json::to_string(&event).unwrap();
+
+            let result = agent
+                .post(url)
+                .set("Content-Type", "application/json")
+                .send_string(&body);
+
+            match result {
+                Ok(_) if failing => {
+                    info!(event = "webhook_recovered"; "webhook is accepting events again");
+                    failing = false;
+                }
+                Ok(_) => (),
+                Err(e) if !failing => {
+                    warn!(
+                        event = "webhook_failed";
+                        "couldn't send event to webhook: {}", e
+                    );
+                    failing = true;
+                }
+                Err(e) => debug!("couldn't send event to webhook: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "webhook"))]
+mod noop_webhook {
+    use ed25519::PublicKey;
+    use state::HeightRoundStep;
+
+    /// Queue an event describing a signature (no-op)
+    #[inline]
+    pub fn record(
+        _validator: &str,
+        _chain_id: Option<&str>,
+        _hrs: Option<HeightRoundStep>,
+        _public_key: &PublicKey,
+    ) {
+    }
+}